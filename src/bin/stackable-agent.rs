@@ -1,61 +1,163 @@
 use std::collections::HashMap;
 use std::env;
 use std::ffi::OsString;
+use std::fs;
 use std::io::ErrorKind;
 use std::path::PathBuf;
+use std::process;
+
+use anyhow::Context;
 
 use kubelet::config::{Config, ServerConfig};
 use kubelet::Kubelet;
-use log::{error, info};
+use log::{error, info, warn};
+use nix::sys::stat::{umask, Mode};
 use tokio::fs::{create_dir_all, File};
+use tokio::signal::unix::{signal, SignalKind};
 
-use stackable_agent::config::AgentConfig;
+use stackable_agent::config::{dropin, AgentConfig};
 use stackable_agent::fsext::check_dir_is_writable;
-use stackable_agent::provider::StackableProvider;
+use stackable_agent::provider::error::StackableError;
+use stackable_agent::provider::shutdown::ShutdownHandle;
+use stackable_agent::provider::{next_api_server_retry_delay, validation, StackableProvider};
+use stackable_agent::tls;
 use stackable_config::{ConfigBuilder, ConfigOption};
 
+/// Environment variable which points to the merged config file if there are drop-in files
+const MERGED_CONFIG_FILE_VARIABLE: &str = "STACKABLE_AGENT_MERGED_CONFIG_FILE";
+
 mod built_info {
     // The file has been placed there by the build script.
     include!(concat!(env!("OUT_DIR"), "/built.rs"));
 }
 
-pub fn print_startup_string(
+/// Returns the version of the agent together with the information about the build.
+fn version_string(
     pkg_version: &str,
     git_version: Option<&str>,
     target: &str,
     built_time: &str,
     rustc_version: &str,
-) {
+) -> String {
     let git_information = match git_version {
         None => "".to_string(),
         Some(git) => format!(" (Git information: {})", git),
     };
-    info!("Starting the Stackable Agent");
-    info!(
-        "This is version {}{}, built for {} by {} at {}",
+    format!(
+        "version {}{}, built for {} by {} at {}",
         pkg_version, git_information, target, rustc_version, built_time
     )
 }
 
+/// Returns the version string of this build, see [`version_string`].
+fn build_version_string() -> String {
+    version_string(
+        built_info::PKG_VERSION,
+        built_info::GIT_VERSION,
+        built_info::TARGET,
+        built_info::BUILT_TIME_UTC,
+        built_info::RUSTC_VERSION,
+    )
+}
+
+pub fn print_startup_string(version_string: &str) {
+    info!("Starting the Stackable Agent");
+    info!("This is {}", version_string)
+}
+
+/// Validates the pods in the given YAML files against the constraints of the agent.
+///
+/// All violations are printed and the process exits with a non-zero exit code if there is at
+/// least one violation.
+fn validate(files: &[OsString]) -> anyhow::Result<()> {
+    if files.is_empty() {
+        anyhow::bail!("Usage: stackable-agent validate <pod.yaml>...");
+    }
+
+    let mut valid = true;
+    for file in files.iter().map(PathBuf::from) {
+        let content = fs::read_to_string(&file)
+            .with_context(|| format!("The file [{}] could not be read.", file.display()))?;
+        let pod = validation::parse_pod(&content)
+            .with_context(|| format!("The file [{}] contains no valid pod.", file.display()))?;
+
+        let violations = validation::validate_pod(&pod);
+        if violations.is_empty() {
+            println!("{}: OK", file.display());
+        } else {
+            valid = false;
+            for violation in violations {
+                println!("{}: {}", file.display(), violation);
+            }
+        }
+    }
+
+    if !valid {
+        process::exit(1);
+    }
+
+    Ok(())
+}
+
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
     // Initialize the logger
-    env_logger::init();
+    stackable_agent::logging::init()?;
+
+    let args = env::args_os().collect::<Vec<OsString>>();
+
+    // The subcommand `validate` checks pod manifests offline and is handled before the
+    // configuration is parsed because it does not require any agent options.
+    if args.get(1).map_or(false, |arg| arg == "validate") {
+        return validate(&args[2..]);
+    }
+
+    // The version is printed without parsing the configuration, so that it can also be queried
+    // on nodes where the agent is not configured yet.
+    if args
+        .get(1)
+        .map_or(false, |arg| arg == "--version" || arg == "version")
+    {
+        println!("Stackable Agent {}", build_version_string());
+        return Ok(());
+    }
+
+    // The config file and the drop-in files are merged into a temporary file if there are
+    // drop-in files, see `stackable_agent::config::dropin`.
+    let merged_config_file = dropin::write_merged_config_file("CONFIG_FILE")
+        .expect("Error merging the config drop-in files!");
+    let config_file_variable = match &merged_config_file {
+        Some(merged_config_file) => {
+            env::set_var(MERGED_CONFIG_FILE_VARIABLE, merged_config_file);
+            MERGED_CONFIG_FILE_VARIABLE
+        }
+        None => "CONFIG_FILE",
+    };
+
+    let agent_config: AgentConfig = ConfigBuilder::build(args, config_file_variable)
+        .expect("Error initializing Configuration!");
 
-    let agent_config: AgentConfig =
-        ConfigBuilder::build(env::args_os().collect::<Vec<OsString>>(), "CONFIG_FILE")
-            .expect("Error initializing Configuration!");
+    if let Some(merged_config_file) = &merged_config_file {
+        let _ = fs::remove_file(merged_config_file);
+    }
+
+    if agent_config.print_config {
+        print!("{}", agent_config.get_effective_configuration());
+        return Ok(());
+    }
+
+    // The umask is set before any directory is created, so that the permissions of the
+    // created files and directories do not depend on the environment the agent was started in.
+    // Otherwise the umask of the environment, e.g. of the systemd unit of the agent, applies.
+    if let Some(mask) = agent_config.umask {
+        umask(Mode::from_bits_truncate(mask));
+    }
 
     // Make sure to only print diagnostic information once we are actually trying to start
-    print_startup_string(
-        built_info::PKG_VERSION,
-        built_info::GIT_VERSION,
-        built_info::TARGET,
-        built_info::BUILT_TIME_UTC,
-        built_info::RUSTC_VERSION,
-    );
+    print_startup_string(&build_version_string());
 
     check_optional_files(&agent_config).await;
+    check_server_key_pair(&agent_config);
     create_missing_directories(&agent_config).await;
     check_configured_directories(&agent_config).await;
 
@@ -72,7 +174,7 @@ async fn main() -> anyhow::Result<()> {
     );
 
     // Convert node tags to string in the form of key=value,key=value,...
-    // TODO: check for commas in the key value pairs themselves https://github.com/stackabletech/agent/issues/195
+    // The tags are validated as Kubernetes labels, so they do not contain commas.
     let node_labels = agent_config
         .tags
         .iter()
@@ -95,7 +197,7 @@ async fn main() -> anyhow::Result<()> {
     info!("args: {:?}", env::args());
 
     let server_config = ServerConfig {
-        addr: agent_config.server_ip_address,
+        addr: agent_config.server_bind_address,
         port: agent_config.server_port,
         cert_file: agent_config.server_cert_file.clone(),
         private_key_file: agent_config.server_key_file.clone(),
@@ -110,7 +212,7 @@ async fn main() -> anyhow::Result<()> {
         server_config,
         data_dir: agent_config.data_directory.to_owned(),
         plugins_dir: plugins_directory.to_owned(),
-        node_labels: agent_config.tags.to_owned(),
+        node_labels: agent_config.tag_labels(),
         max_pods: 110,
         bootstrap_file: agent_config.bootstrap_file.to_owned(),
         allow_local_modules: false,
@@ -120,25 +222,98 @@ async fn main() -> anyhow::Result<()> {
     // Bootstrap a kubernetes config, if no valid config is found
     // This also generates certificates for the webserver the krustlet
     // runs
-    let kubeconfig = kubelet::bootstrap(
-        &krustlet_config,
-        &krustlet_config.bootstrap_file,
-        notify_bootstrap,
-    )
-    .await?;
+    let kubeconfig = match bootstrap(&agent_config, &krustlet_config).await {
+        Ok(kubeconfig) => kubeconfig,
+        Err(error) => {
+            error!("{:#}", error);
+            process::exit(1);
+        }
+    };
 
-    let provider = StackableProvider::new(
+    let provider = match StackableProvider::new(
         kube::Client::new(kubeconfig.clone()),
         &agent_config,
         krustlet_config.max_pods,
     )
     .await
-    .expect("Error initializing provider.");
+    {
+        Ok(provider) => provider,
+        Err(error @ StackableError::DbusConnectionError { .. }) => {
+            report_dbus_connection_error(&agent_config, &error);
+            process::exit(1);
+        }
+        Err(error) => {
+            error!("Error initializing provider: {}", error);
+            process::exit(1);
+        }
+    };
 
     provider.cleanup(&krustlet_config.node_name).await;
 
+    if let Some(admin_socket) = &agent_config.admin_socket {
+        provider.serve_admin_socket(admin_socket);
+    }
+
+    let shutdown_handle = provider.shutdown_handle();
+    let mut sigterm = signal(SignalKind::terminate())?;
+    let mut sigint = signal(SignalKind::interrupt())?;
+
     let kubelet = Kubelet::new(provider, kubeconfig, krustlet_config).await?;
-    kubelet.start().await
+
+    let signal_name = tokio::select! {
+        result = kubelet.start() => return result,
+        _ = sigterm.recv() => "SIGTERM",
+        _ = sigint.recv() => "SIGINT",
+    };
+
+    shut_down(&shutdown_handle, signal_name, &agent_config).await;
+
+    Ok(())
+}
+
+/// Shuts the agent down gracefully after it received the given signal.
+///
+/// The in-flight downloads are cancelled first, so that no package is installed anymore. The
+/// managed units are only stopped if this is configured, otherwise the services keep running.
+/// The units are not removed in either case, so that they are adopted again on the next start,
+/// see `stackable_agent::provider::shutdown`.
+async fn shut_down(shutdown_handle: &ShutdownHandle, signal_name: &str, config: &AgentConfig) {
+    info!("{} received, shutting down", signal_name);
+    shutdown_handle.cancel_downloads();
+    if config.stop_units_on_shutdown {
+        shutdown_handle
+            .stop_units(config.shutdown_grace_period)
+            .await;
+    } else {
+        info!(
+            "The systemd units keep running and are adopted again on the next start (option [{}] \
+            not set)",
+            AgentConfig::STOP_UNITS_ON_SHUTDOWN.name
+        );
+    }
+    info!("Shutdown completed");
+    log::logger().flush();
+}
+
+/// Logs an actionable message if the agent cannot connect to D-Bus.
+fn report_dbus_connection_error(config: &AgentConfig, error: &StackableError) {
+    let (mode, systemctl) = if config.session {
+        ("session mode", "systemctl --user")
+    } else {
+        ("system mode", "systemctl")
+    };
+
+    error!(
+        "{}. The agent runs in {} (option [{}]). Check that systemd is running and that \
+        `{} status` works for the user running the agent. In session mode, the agent \
+        must run within a user session, i.e. with `XDG_RUNTIME_DIR` or \
+        `DBUS_SESSION_BUS_ADDRESS` set. In system mode, the agent must be permitted to \
+        access the system bus, usually by running as root.",
+        error,
+        mode,
+        AgentConfig::SESSION_SYSTEMD.name,
+        systemctl
+    );
 }
 
 fn export_env(var_name: &str, var_value: &str) {
@@ -150,6 +325,137 @@ fn notify_bootstrap(message: String) {
     info!("Successfully bootstrapped TLS certificate: {}", message);
 }
 
+/// Causes of a failed bootstrapping which can be resolved by the operator
+#[derive(Debug)]
+enum BootstrapFailure {
+    /// The API server is not reachable, e.g. because it is not started yet
+    Unreachable,
+    /// The API server rejected the bootstrap token
+    Unauthorized,
+    /// The bootstrap token is not permitted to request a certificate
+    Forbidden,
+    /// The bootstrap file or another required file does not exist
+    MissingFile,
+    /// The kubeconfig or the certificate files cannot be written
+    PermissionDenied,
+    /// Any other failure
+    Other,
+}
+
+impl BootstrapFailure {
+    /// Determines the failure from the chain of the given bootstrap error.
+    fn from_error(error: &anyhow::Error) -> BootstrapFailure {
+        for cause in error.chain() {
+            if let Some(kube::Error::Api(response)) = cause.downcast_ref::<kube::Error>() {
+                match response.code {
+                    401 => return BootstrapFailure::Unauthorized,
+                    403 => return BootstrapFailure::Forbidden,
+                    _ => {}
+                }
+            }
+            if let Some(io_error) = cause.downcast_ref::<std::io::Error>() {
+                match io_error.kind() {
+                    ErrorKind::NotFound => return BootstrapFailure::MissingFile,
+                    ErrorKind::PermissionDenied => return BootstrapFailure::PermissionDenied,
+                    _ => {}
+                }
+            }
+        }
+
+        let unreachable = error.chain().any(|cause| {
+            matches!(
+                cause.downcast_ref::<kube::Error>(),
+                Some(kube_error) if !matches!(kube_error, kube::Error::Api(_))
+            )
+        });
+        if unreachable {
+            BootstrapFailure::Unreachable
+        } else {
+            BootstrapFailure::Other
+        }
+    }
+
+    /// Returns a hint how the failure can be resolved.
+    fn hint(&self, config: &AgentConfig) -> String {
+        let bootstrap_file = config.bootstrap_file.to_string_lossy();
+        match self {
+            BootstrapFailure::Unreachable => format!(
+                "The bootstrapping failed because the API server is not reachable. Check the \
+                server URL in the bootstrap file [{}] (option [{}]) and the network connection \
+                to the API server.",
+                bootstrap_file,
+                AgentConfig::BOOTSTRAP_FILE.name
+            ),
+            BootstrapFailure::Unauthorized => format!(
+                "The bootstrapping failed because the API server rejected the credentials. \
+                Check that the token in the bootstrap file [{}] (option [{}]) is valid and not \
+                expired.",
+                bootstrap_file,
+                AgentConfig::BOOTSTRAP_FILE.name
+            ),
+            BootstrapFailure::Forbidden => String::from(
+                "The bootstrapping failed because the bootstrap token is not permitted to \
+                request a certificate. Check that the token belongs to the group \
+                system:bootstrappers and that this group may create \
+                CertificateSigningRequests.",
+            ),
+            BootstrapFailure::MissingFile => format!(
+                "The bootstrapping failed because a file does not exist. If the agent is not \
+                registered yet, i.e. there is no kubeconfig in the environment variable \
+                KUBECONFIG or in ~/.kube/config, then the bootstrap file [{}] (option [{}]) \
+                must exist.",
+                bootstrap_file,
+                AgentConfig::BOOTSTRAP_FILE.name
+            ),
+            BootstrapFailure::PermissionDenied => format!(
+                "The bootstrapping failed because a file cannot be accessed. Check that the \
+                user running the agent may write the kubeconfig and the files in the options \
+                [{}] and [{}].",
+                AgentConfig::SERVER_CERT_FILE.name,
+                AgentConfig::SERVER_KEY_FILE.name
+            ),
+            BootstrapFailure::Other => String::from("The bootstrapping failed."),
+        }
+    }
+}
+
+/// Bootstraps the kubeconfig and the server certificate if they do not exist yet, see
+/// [`kubelet::bootstrap`].
+///
+/// If the API server is not reachable then the bootstrapping is retried with an exponential
+/// backoff like the initial connection to the API server, see the options [`api-server-retries`]
+/// and [`api-server-retry-interval`]. The delay is capped, see [`next_api_server_retry_delay`]. Other failures are not retried. The returned error
+/// contains a hint how the failure can be resolved.
+///
+/// [`api-server-retries`]: AgentConfig::API_SERVER_RETRIES
+/// [`api-server-retry-interval`]: AgentConfig::API_SERVER_RETRY_INTERVAL
+async fn bootstrap(agent_config: &AgentConfig, config: &Config) -> anyhow::Result<kube::Config> {
+    let retries = agent_config.api_server_retries;
+    let mut attempt = 0;
+    let mut delay = agent_config.api_server_retry_interval;
+
+    loop {
+        match kubelet::bootstrap(config, &config.bootstrap_file, notify_bootstrap).await {
+            Ok(kubeconfig) => return Ok(kubeconfig),
+            Err(error) => match BootstrapFailure::from_error(&error) {
+                BootstrapFailure::Unreachable if attempt < retries => {
+                    attempt += 1;
+                    warn!(
+                        "The API server is not reachable for the bootstrapping, retrying in {} seconds (retry {} of {}): {:#}",
+                        delay.as_secs(),
+                        attempt,
+                        retries,
+                        error
+                    );
+                    tokio::time::sleep(delay).await;
+                    delay = next_api_server_retry_delay(delay);
+                }
+                failure => return Err(error.context(failure.hint(agent_config))),
+            },
+        }
+    }
+}
+
 /// Checks if the optional files can be opened if they exist. An error
 /// is logged if they cannot be opened.
 async fn check_optional_files(config: &AgentConfig) {
@@ -171,6 +477,25 @@ async fn check_optional_files(config: &AgentConfig) {
     }
 }
 
+/// Checks if the server certificate and private key form a valid pair
+/// if both files exist. An error is logged if they do not.
+///
+/// The files do not exist before the bootstrapping, in which case they
+/// are created as a valid pair.
+fn check_server_key_pair(config: &AgentConfig) {
+    if config.server_cert_file.is_file() && config.server_key_file.is_file() {
+        if let Err(error) = tls::check_key_pair(&config.server_cert_file, &config.server_key_file) {
+            error!(
+                "The files specified in the configuration options [{}] and [{}] \
+                cannot be used by the webserver. {:#}",
+                AgentConfig::SERVER_CERT_FILE.name,
+                AgentConfig::SERVER_KEY_FILE.name,
+                error
+            );
+        }
+    }
+}
+
 /// Creates the directories where write access is required and which do
 /// not exist yet.
 ///
@@ -0,0 +1,237 @@
+//! Config drop-in files
+//!
+//! In addition to the config file given in the environment variable `CONFIG_FILE`, the files
+//! with the extension `.conf` in the drop-in directory are read in lexical order. The directory
+//! defaults to [`DEFAULT_DROPIN_DIRECTORY`] and can be changed with the environment variable
+//! [`DROPIN_DIRECTORY_VARIABLE`]. Values in later files override the values of earlier files,
+//! whereas the values of options which can be specified multiple times are accumulated.
+//!
+//! The files are merged into one temporary config file which is then parsed like a single
+//! config file, so that the command line still takes precedence.
+use std::collections::HashSet;
+use std::env;
+use std::fs;
+use std::io;
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+use std::process;
+
+use log::info;
+
+use super::AgentConfig;
+
+/// Environment variable which overrides the drop-in directory
+pub const DROPIN_DIRECTORY_VARIABLE: &str = "CONFIG_DROPIN_DIRECTORY";
+
+/// Drop-in directory which is used if [`DROPIN_DIRECTORY_VARIABLE`] is not set
+pub const DEFAULT_DROPIN_DIRECTORY: &str = "/etc/stackable/stackable-agent/conf.d";
+
+/// Extension of the config files in the drop-in directory
+const DROPIN_EXTENSION: &str = "conf";
+
+/// Number of names which are tried for the merged config file
+const MAX_TEMPORARY_FILE_ATTEMPTS: u32 = 100;
+
+/// Merges the config file in the given environment variable with the drop-in files into a
+/// temporary file.
+///
+/// [`None`] is returned if there are no drop-in files, so that the config file can be used
+/// directly. Otherwise the caller must remove the returned file after it was parsed.
+pub fn write_merged_config_file(config_file_variable: &str) -> io::Result<Option<PathBuf>> {
+    let dropin_directory = env::var_os(DROPIN_DIRECTORY_VARIABLE)
+        .map(PathBuf::from)
+        .unwrap_or_else(|| PathBuf::from(DEFAULT_DROPIN_DIRECTORY));
+
+    let dropin_files = dropin_files(&dropin_directory)?;
+    if dropin_files.is_empty() {
+        return Ok(None);
+    }
+
+    let mut contents = Vec::new();
+    if let Some(config_file) = env::var_os(config_file_variable) {
+        contents.push(fs::read_to_string(config_file)?);
+    }
+    for dropin_file in &dropin_files {
+        info!(
+            "Reading config drop-in file [{}]",
+            dropin_file.to_string_lossy()
+        );
+        contents.push(fs::read_to_string(dropin_file)?);
+    }
+
+    let list_options = AgentConfig::get_options()
+        .into_iter()
+        .filter(|option| option.list)
+        .map(|option| option.name)
+        .collect::<HashSet<_>>();
+
+    let (merged_config_file, mut file) = create_temporary_file()?;
+    io::Write::write_all(
+        &mut file,
+        merge_config_files(&contents, &list_options).as_bytes(),
+    )?;
+
+    Ok(Some(merged_config_file))
+}
+
+/// Creates a new temporary file which is only accessible by the current user.
+///
+/// The names of the files in the temporary directory are predictable, so an existing file with
+/// the same name is never opened because it could be a symbolic link which was placed there by
+/// another user. Another name is tried instead.
+fn create_temporary_file() -> io::Result<(PathBuf, fs::File)> {
+    let mut attempt = 0;
+    loop {
+        let path = env::temp_dir().join(format!(
+            "stackable-agent-{}-{}.conf",
+            process::id(),
+            attempt
+        ));
+
+        match fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .mode(0o600)
+            .open(&path)
+        {
+            Ok(file) => return Ok((path, file)),
+            Err(error)
+                if error.kind() == io::ErrorKind::AlreadyExists
+                    && attempt < MAX_TEMPORARY_FILE_ATTEMPTS =>
+            {
+                attempt += 1
+            }
+            Err(error) => return Err(error),
+        }
+    }
+}
+
+/// Returns the drop-in files in the given directory in lexical order.
+///
+/// A missing directory is treated like an empty one.
+fn dropin_files(directory: &Path) -> io::Result<Vec<PathBuf>> {
+    let entries = match fs::read_dir(directory) {
+        Ok(entries) => entries,
+        Err(error) if error.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(error) => return Err(error),
+    };
+
+    let mut files = Vec::new();
+    for entry in entries {
+        let path = entry?.path();
+        if path.is_file()
+            && path
+                .extension()
+                .map_or(false, |ext| ext == DROPIN_EXTENSION)
+        {
+            files.push(path);
+        }
+    }
+    files.sort();
+
+    Ok(files)
+}
+
+/// Merges the given config files in the format `--parameter=value`.
+///
+/// A parameter in a later file replaces the same parameter of earlier files unless it is
+/// contained in `list_options`, in which case the values of all files are kept. Empty lines
+/// are dropped.
+fn merge_config_files(contents: &[String], list_options: &HashSet<&str>) -> String {
+    let mut lines: Vec<(&str, &str)> = Vec::new();
+
+    for line in contents.iter().flat_map(|content| content.lines()) {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+
+        let name = line
+            .trim_start_matches('-')
+            .split('=')
+            .next()
+            .unwrap_or_default();
+
+        if !list_options.contains(name) {
+            lines.retain(|(existing_name, _)| *existing_name != name);
+        }
+        lines.push((name, line));
+    }
+
+    lines
+        .into_iter()
+        .map(|(_, line)| format!("{}\n", line))
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use indoc::indoc;
+
+    #[test]
+    fn later_files_should_override_earlier_values_and_accumulate_list_values() {
+        let contents = vec![
+            String::from(indoc! {"
+                --package-directory=/opt/stackable/packages
+                --tag=region=eu
+
+                --session
+            "}),
+            String::from(indoc! {"
+                --package-directory=/var/lib/stackable/packages
+                --tag=rack=1
+            "}),
+        ];
+
+        let list_options = ["tag"].iter().cloned().collect::<HashSet<_>>();
+
+        assert_eq!(
+            indoc! {"
+                --tag=region=eu
+                --session
+                --package-directory=/var/lib/stackable/packages
+                --tag=rack=1
+            "},
+            merge_config_files(&contents, &list_options)
+        );
+    }
+
+    #[test]
+    fn existing_temporary_files_should_not_be_opened() {
+        let existing_file =
+            env::temp_dir().join(format!("stackable-agent-{}-0.conf", process::id()));
+        fs::write(&existing_file, "existing").unwrap();
+
+        let result = create_temporary_file();
+
+        let existing_content = fs::read_to_string(&existing_file).unwrap();
+        fs::remove_file(&existing_file).unwrap();
+        let (path, _) = result.unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_ne!(existing_file, path);
+        assert_eq!("existing", existing_content);
+    }
+
+    #[test]
+    fn dropin_files_should_be_returned_in_lexical_order() {
+        let directory = env::temp_dir().join(format!("stackable-agent-dropin-{}", process::id()));
+        fs::create_dir_all(&directory).unwrap();
+        for file in &["20-tags.conf", "10-base.conf", "README"] {
+            fs::write(directory.join(file), "").unwrap();
+        }
+
+        let files = dropin_files(&directory);
+        fs::remove_dir_all(&directory).unwrap();
+
+        assert_eq!(
+            vec![
+                directory.join("10-base.conf"),
+                directory.join("20-tags.conf")
+            ],
+            files.unwrap()
+        );
+        assert!(dropin_files(&directory).unwrap().is_empty());
+    }
+}
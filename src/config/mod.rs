@@ -1,19 +1,28 @@
 use anyhow::anyhow;
 use std::collections::hash_map::RandomState;
 use std::collections::{HashMap, HashSet};
-use std::net::IpAddr;
+use std::env;
+use std::fmt;
+use std::fs;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
 use std::path::{Path, PathBuf};
+use std::process;
 use std::str::FromStr;
+use std::time::Duration;
 
-use log::{debug, error, info, trace};
+use log::{debug, error, info, trace, warn};
 use nix::ifaddrs;
 use nix::net::if_::InterfaceFlags;
 use nix::sys::socket::SockAddr;
 use stackable_config::{ConfigOption, Configurable, Configuration};
 use thiserror::Error;
+use url::Url;
 
 use crate::config::AgentConfigError::{ArgumentParseError, WrongArgumentCount};
 use crate::fsext::{is_valid_file_path, normalize_path};
+use crate::provider::systemdmanager::systemdunit::{is_valid_log_namespace, is_valid_user_name};
+
+pub mod dropin;
 
 #[derive(Error, Debug)]
 pub enum AgentConfigError {
@@ -23,6 +32,489 @@ pub enum AgentConfigError {
     ArgumentParseError { name: String },
 }
 
+/// A taint which is added to the node of the agent
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct NodeTaint {
+    pub key: String,
+    pub value: String,
+    pub effect: String,
+}
+
+impl NodeTaint {
+    const EFFECTS: [&'static str; 3] = ["NoSchedule", "PreferNoSchedule", "NoExecute"];
+}
+
+impl FromStr for NodeTaint {
+    type Err = anyhow::Error;
+
+    /// Parses a taint in the format `key[=value]:effect` like `kubectl taint` does.
+    fn from_str(taint: &str) -> Result<Self, Self::Err> {
+        let (key_value, effect) = taint
+            .rsplit_once(':')
+            .ok_or_else(|| anyhow!("The taint [{}] does not contain an effect.", taint))?;
+        let (key, value) = key_value.split_once('=').unwrap_or((key_value, ""));
+
+        if key.is_empty() {
+            return Err(anyhow!("The taint [{}] does not contain a key.", taint));
+        }
+        if !NodeTaint::EFFECTS.contains(&effect) {
+            return Err(anyhow!(
+                "The effect of the taint [{}] must be one of {:?}.",
+                taint,
+                NodeTaint::EFFECTS
+            ));
+        }
+
+        Ok(NodeTaint {
+            key: key.to_string(),
+            value: value.to_string(),
+            effect: effect.to_string(),
+        })
+    }
+}
+
+impl fmt::Display for NodeTaint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.value.is_empty() {
+            write!(f, "{}:{}", self.key, self.effect)
+        } else {
+            write!(f, "{}={}:{}", self.key, self.value, self.effect)
+        }
+    }
+}
+
+/// Resources of the node which are reserved and not allocatable by pods
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct ReservedResources {
+    /// CPU in millicores
+    pub cpu_millis: u64,
+    /// Memory in bytes
+    pub memory_bytes: u64,
+}
+
+impl FromStr for ReservedResources {
+    type Err = anyhow::Error;
+
+    /// Parses a comma-separated list of reservations in the format
+    /// `cpu=<quantity>,memory=<quantity>` like the Kubelet does, e.g. `cpu=500m,memory=1Gi`.
+    ///
+    /// An empty value reserves nothing.
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let mut reserved_resources = ReservedResources::default();
+
+        for reservation in value.split(',').map(str::trim).filter(|r| !r.is_empty()) {
+            let (resource, quantity) = reservation.split_once('=').ok_or_else(|| {
+                anyhow!(
+                    "The reservation [{}] must be in the format <resource>=<quantity>.",
+                    reservation
+                )
+            })?;
+
+            let invalid_quantity = || {
+                anyhow!(
+                    "The quantity [{}] of the resource [{}] is invalid.",
+                    quantity,
+                    resource
+                )
+            };
+
+            match resource {
+                "cpu" => {
+                    reserved_resources.cpu_millis =
+                        parse_cpu_quantity(quantity).ok_or_else(invalid_quantity)?
+                }
+                "memory" => {
+                    reserved_resources.memory_bytes =
+                        parse_memory_quantity(quantity).ok_or_else(invalid_quantity)?
+                }
+                _ => {
+                    return Err(anyhow!(
+                        "The resource [{}] cannot be reserved. Supported are [cpu] and [memory].",
+                        resource
+                    ))
+                }
+            }
+        }
+
+        Ok(reserved_resources)
+    }
+}
+
+impl fmt::Display for ReservedResources {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "cpu={}m,memory={}", self.cpu_millis, self.memory_bytes)
+    }
+}
+
+/// Source of the ip address which is registered as the node's ip
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum NodeIpSource {
+    /// First address of the first non-loopback interface which is up
+    FirstNonLoopback,
+    /// Given address which does not have to be assigned to a local interface, e.g. the public
+    /// address of a cloud instance
+    Address(IpAddr),
+    /// First address of the given interface
+    Interface(String),
+    /// Address which is printed by the given shell command
+    Command(String),
+    /// Address which is contained in the given file
+    File(PathBuf),
+}
+
+impl NodeIpSource {
+    /// Retrieves the ip address from this source.
+    ///
+    /// Commands and files are read once when the agent starts, so a changed address is only
+    /// registered after a restart.
+    pub fn resolve(&self) -> anyhow::Result<IpAddr> {
+        match self {
+            NodeIpSource::FirstNonLoopback => AgentConfig::get_default_ipaddress()
+                .ok_or_else(|| anyhow!("No non-loopback interface with an address was found.")),
+            NodeIpSource::Address(ip) => Ok(*ip),
+            NodeIpSource::Interface(interface_name) => ifaddrs::getifaddrs()?
+                .filter(|ifaddr| &ifaddr.interface_name == interface_name)
+                .filter_map(|ifaddr| match ifaddr.address {
+                    Some(SockAddr::Inet(inet_addr)) => Some(inet_addr.to_std().ip()),
+                    _ => None,
+                })
+                .find(|ip| !is_link_local(ip))
+                .ok_or_else(|| {
+                    anyhow!(
+                        "The interface [{}] does not exist or has no address assigned which is \
+                        not link-local.",
+                        interface_name
+                    )
+                }),
+            NodeIpSource::Command(command) => {
+                let output = process::Command::new("sh")
+                    .arg("-c")
+                    .arg(command)
+                    .output()?;
+                if !output.status.success() {
+                    return Err(anyhow!(
+                        "The command [{}] failed with {}: {}",
+                        command,
+                        output.status,
+                        String::from_utf8_lossy(&output.stderr).trim()
+                    ));
+                }
+                parse_ip_address(&String::from_utf8_lossy(&output.stdout))
+            }
+            NodeIpSource::File(path) => parse_ip_address(&fs::read_to_string(path)?),
+        }
+    }
+}
+
+impl FromStr for NodeIpSource {
+    type Err = anyhow::Error;
+
+    /// Parses a source in the format `first-non-loopback`, `address:<ip>`,
+    /// `interface:<name>`, `command:<command>`, or `file:<path>`.
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        if value == "first-non-loopback" {
+            return Ok(NodeIpSource::FirstNonLoopback);
+        }
+
+        let (kind, argument) = value
+            .split_once(':')
+            .filter(|(_, argument)| !argument.is_empty())
+            .ok_or_else(|| {
+                anyhow!(
+                    "The node ip source [{}] must be [first-non-loopback] or in the format <kind>:<argument>.",
+                    value
+                )
+            })?;
+
+        match kind {
+            "address" => Ok(NodeIpSource::Address(parse_ip_address(argument)?)),
+            "interface" => Ok(NodeIpSource::Interface(argument.to_string())),
+            "command" => Ok(NodeIpSource::Command(argument.to_string())),
+            "file" if Path::new(argument).is_absolute() => {
+                Ok(NodeIpSource::File(PathBuf::from(argument)))
+            }
+            "file" => Err(anyhow!("The path [{}] must be absolute.", argument)),
+            _ => Err(anyhow!(
+                "The kind [{}] of the node ip source is invalid. Supported are [address], [interface], [command], and [file].",
+                kind
+            )),
+        }
+    }
+}
+
+impl fmt::Display for NodeIpSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            NodeIpSource::FirstNonLoopback => write!(f, "first-non-loopback"),
+            NodeIpSource::Address(ip) => write!(f, "address:{}", ip),
+            NodeIpSource::Interface(interface_name) => write!(f, "interface:{}", interface_name),
+            NodeIpSource::Command(command) => write!(f, "command:{}", command),
+            NodeIpSource::File(path) => write!(f, "file:{}", path.to_string_lossy()),
+        }
+    }
+}
+
+/// Checks if the given address is link-local, e.g. `fe80::1`. Link-local addresses are only
+/// reachable on the local network segment, so they are not registered as the node's ip.
+fn is_link_local(ip: &IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => ip.is_link_local(),
+        IpAddr::V6(ip) => (ip.segments()[0] & 0xffc0) == 0xfe80,
+    }
+}
+
+/// Parses an ip address which can be registered as the node's ip, e.g. from the output of a
+/// command or the content of a file. Surrounding whitespace like a trailing newline is ignored.
+fn parse_ip_address(value: &str) -> anyhow::Result<IpAddr> {
+    let value = value.trim();
+    match IpAddr::from_str(value) {
+        Ok(ip) if ip.is_unspecified() => Err(anyhow!(
+            "The unspecified address [{}] cannot be registered as the node's ip.",
+            value
+        )),
+        Ok(ip) => Ok(ip),
+        Err(_) => Err(anyhow!("The value [{}] is not a valid ip address.", value)),
+    }
+}
+
+/// Value of the option `umask` which keeps the umask the agent was started with
+pub const UMASK_INHERIT: &str = "inherit";
+
+/// Parses a umask in octal notation like `0027` or `27`.
+pub fn parse_umask(value: &str) -> Option<u32> {
+    u32::from_str_radix(value, 8)
+        .ok()
+        .filter(|umask| *umask <= 0o777)
+}
+
+/// Checks if the given template for the names of the package directories contains the
+/// placeholders `{{product}}` and `{{version}}` and yields a single directory name. Names
+/// starting with an underscore are reserved for the agent, e.g. `_download`.
+fn is_valid_package_directory_template(template: &str) -> bool {
+    let without_placeholders = template
+        .replace("{{product}}", "")
+        .replace("{{version}}", "");
+
+    template.contains("{{product}}")
+        && template.contains("{{version}}")
+        && !template.starts_with('_')
+        && !without_placeholders.contains(&['/', '{', '}'][..])
+}
+
+/// Parses a CPU quantity in cores like `2` or `0.5`, or in millicores like `500m`, and returns
+/// it in millicores.
+pub fn parse_cpu_quantity(quantity: &str) -> Option<u64> {
+    if let Some(millis) = quantity.strip_suffix('m') {
+        return millis.parse().ok();
+    }
+
+    let (cores, fraction) = quantity.split_once('.').unwrap_or((quantity, ""));
+    if fraction.len() > 3 || !fraction.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    let fraction_millis = format!("{:0<3}", fraction).parse::<u64>().ok()?;
+
+    cores
+        .parse::<u64>()
+        .ok()?
+        .checked_mul(1000)?
+        .checked_add(fraction_millis)
+}
+
+/// Parses a memory quantity in bytes with an optional decimal suffix like `k`, `M`, or `G`, or
+/// a binary suffix like `Ki`, `Mi`, or `Gi`, and returns it in bytes.
+pub fn parse_memory_quantity(quantity: &str) -> Option<u64> {
+    let digits_end = quantity
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or_else(|| quantity.len());
+    let (number, suffix) = quantity.split_at(digits_end);
+
+    let factor: u64 = match suffix {
+        "" => 1,
+        "k" => 1_000,
+        "M" => 1_000_000,
+        "G" => 1_000_000_000,
+        "T" => 1_000_000_000_000,
+        "Ki" => 1 << 10,
+        "Mi" => 1 << 20,
+        "Gi" => 1 << 30,
+        "Ti" => 1 << 40,
+        _ => return None,
+    };
+
+    number.parse::<u64>().ok()?.checked_mul(factor)
+}
+
+/// Checks that the given ip address is one of the given local addresses.
+///
+/// The unspecified addresses `0.0.0.0` and `::` are accepted because they bind to all
+/// interfaces.
+fn check_local_ip_address(ip: &IpAddr, local_addresses: &[IpAddr]) -> anyhow::Result<()> {
+    if ip.is_unspecified() || local_addresses.contains(ip) {
+        Ok(())
+    } else {
+        Err(anyhow::anyhow!(
+            "The ip address [{}] of the parameter [{}] is not assigned to a local interface. \
+            Available addresses: [{}]",
+            ip,
+            AgentConfig::SERVER_IP_ADDRESS.name,
+            local_addresses
+                .iter()
+                .map(IpAddr::to_string)
+                .collect::<Vec<_>>()
+                .join(", ")
+        ))
+    }
+}
+
+/// Returns the unspecified address of the family of the given address, i.e. `0.0.0.0` or `::`.
+fn unspecified_address(ip: &IpAddr) -> IpAddr {
+    match ip {
+        IpAddr::V4(_) => Ipv4Addr::UNSPECIFIED.into(),
+        IpAddr::V6(_) => Ipv6Addr::UNSPECIFIED.into(),
+    }
+}
+
+/// Parses a comma-separated list of pod CIDRs.
+///
+/// An empty value results in an empty list. At most one IPv4 and one IPv6 CIDR may be given,
+/// like in Kubernetes for dual-stack nodes.
+pub fn parse_pod_cidrs(value: &str) -> Result<Vec<String>, anyhow::Error> {
+    let pod_cidrs = value
+        .split(',')
+        .map(str::trim)
+        .filter(|pod_cidr| !pod_cidr.is_empty())
+        .map(|pod_cidr| {
+            let (address, prefix_length) = pod_cidr.split_once('/').ok_or_else(|| {
+                anyhow!(
+                    "The pod CIDR [{}] does not contain a prefix length.",
+                    pod_cidr
+                )
+            })?;
+            let address = IpAddr::from_str(address)
+                .map_err(|_| anyhow!("The pod CIDR [{}] contains an invalid address.", pod_cidr))?;
+            let max_prefix_length = if address.is_ipv4() { 32 } else { 128 };
+            match u8::from_str(prefix_length) {
+                Ok(prefix_length) if prefix_length <= max_prefix_length => {
+                    Ok((address, pod_cidr.to_string()))
+                }
+                _ => Err(anyhow!(
+                    "The prefix length of the pod CIDR [{}] must be between 0 and {}.",
+                    pod_cidr,
+                    max_prefix_length
+                )),
+            }
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    match pod_cidrs.as_slice() {
+        [] | [_] => {}
+        [(first, _), (second, _)] if first.is_ipv4() != second.is_ipv4() => {}
+        _ => {
+            return Err(anyhow!(
+                "The pod CIDRs [{}] must contain at most one IPv4 and one IPv6 CIDR.",
+                value
+            ))
+        }
+    }
+
+    Ok(pod_cidrs
+        .into_iter()
+        .map(|(_, pod_cidr)| pod_cidr)
+        .collect())
+}
+
+/// Checks if the given string is a valid key of a Kubernetes label.
+///
+/// A key consists of an optional prefix and a name separated by a slash. The prefix must be a
+/// DNS subdomain with at most 253 characters and the name must be valid according to
+/// [`is_valid_label_value`] but must not be empty.
+pub fn is_valid_label_key(key: &str) -> bool {
+    let (prefix, name) = match key.rsplit_once('/') {
+        Some((prefix, name)) => (Some(prefix), name),
+        None => (None, key),
+    };
+
+    let valid_prefix = prefix.map_or(true, |prefix| {
+        prefix.len() <= 253
+            && prefix.split('.').all(|part| {
+                !part.is_empty()
+                    && part.len() <= 63
+                    && part
+                        .chars()
+                        .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-')
+                    && !part.starts_with('-')
+                    && !part.ends_with('-')
+            })
+    });
+
+    valid_prefix && !name.is_empty() && is_valid_label_value(name)
+}
+
+/// Checks if the given string is a valid value of a Kubernetes label.
+///
+/// A value is either empty or consists of at most 63 alphanumeric characters, `-`, `_`, or `.`
+/// and starts and ends with an alphanumeric character.
+pub fn is_valid_label_value(value: &str) -> bool {
+    value.is_empty()
+        || (value.len() <= 63
+            && value
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.')
+            && value.starts_with(|c: char| c.is_ascii_alphanumeric())
+            && value.ends_with(|c: char| c.is_ascii_alphanumeric()))
+}
+
+/// Resolves the references in the value of the hostname option.
+///
+/// A value starting with `@` denotes the path of a file which contains the hostname, e.g.
+/// `@/etc/nodename`. Otherwise references to environment variables in the form `${NAME}` are
+/// replaced with their values. An error is returned if a referenced file or variable does not
+/// exist or if the resulting hostname is empty.
+pub fn resolve_hostname(value: &str) -> Result<String, anyhow::Error> {
+    let hostname = if let Some(path) = value.strip_prefix('@') {
+        fs::read_to_string(path)
+            .map_err(|error| anyhow!("The hostname file [{}] could not be read: {}", path, error))?
+            .trim()
+            .to_string()
+    } else {
+        let mut hostname = String::new();
+        let mut remainder = value;
+        while let Some(start) = remainder.find("${") {
+            let (prefix, reference) = remainder.split_at(start);
+            let end = reference.find('}').ok_or_else(|| {
+                anyhow!(
+                    "The reference [{}] in the hostname [{}] is not terminated with \"}}\".",
+                    reference,
+                    value
+                )
+            })?;
+            let name = &reference[2..end];
+            let variable = env::var(name).map_err(|_| {
+                anyhow!(
+                    "The environment variable [{}] referenced in the hostname [{}] is not set.",
+                    name,
+                    value
+                )
+            })?;
+            hostname.push_str(prefix);
+            hostname.push_str(&variable);
+            remainder = &reference[end + 1..];
+        }
+        hostname.push_str(remainder);
+        hostname
+    };
+
+    if hostname.is_empty() {
+        Err(anyhow!(
+            "The hostname [{}] resolves to an empty string.",
+            value
+        ))
+    } else {
+        Ok(hostname)
+    }
+}
+
 #[derive(Clone)]
 pub struct AgentConfig {
     pub hostname: String,
@@ -32,12 +524,48 @@ pub struct AgentConfig {
     pub bootstrap_file: PathBuf,
     pub data_directory: PathBuf,
     pub server_ip_address: IpAddr,
+    pub server_bind_address: IpAddr,
+    pub node_ip_source: Option<NodeIpSource>,
     pub server_port: u16,
     pub server_cert_file: PathBuf,
     pub server_key_file: PathBuf,
     pub tags: HashMap<String, String>,
+    pub labels: HashMap<String, String>,
+    pub taints: Vec<NodeTaint>,
     pub session: bool,
-    pub pod_cidr: String,
+    pub no_arch_taints: bool,
+    pub keep_failed_services: bool,
+    pub allow_multiple_containers: bool,
+    pub pod_cidrs: Vec<String>,
+    pub print_config: bool,
+    pub config_generations: usize,
+    pub adoption_readiness_timeout: Duration,
+    pub admin_socket: Option<PathBuf>,
+    pub system_unit_directory: PathBuf,
+    pub user_unit_directory: PathBuf,
+    pub repository_connect_timeout: Duration,
+    pub repository_request_timeout: Duration,
+    pub repository_pool_idle_timeout: Duration,
+    pub shutdown_grace_period: Duration,
+    pub stop_units_on_shutdown: bool,
+    pub cleanup_grace_period: Duration,
+    pub service_check_interval: Duration,
+    pub max_concurrent_installs: usize,
+    pub max_download_retries: u32,
+    pub api_server_retries: u32,
+    pub api_server_retry_interval: Duration,
+    pub host_path_allowed: Vec<PathBuf>,
+    pub allowed_products: Vec<String>,
+    pub allowed_sysctls: Vec<String>,
+    pub default_service_user: Option<String>,
+    pub log_namespace: Option<String>,
+    pub secrets_backend_url: Option<Url>,
+    pub secrets_backend_token_file: Option<PathBuf>,
+    pub system_reserved: ReservedResources,
+    pub kube_reserved: ReservedResources,
+    pub umask: Option<u32>,
+    pub unit_name_max_length: usize,
+    pub package_directory_template: String,
 }
 
 impl AgentConfig {
@@ -47,7 +575,7 @@ impl AgentConfig {
         required: false,
         takes_argument: true,
         help:
-            "The hostname to register the node under in Kubernetes - defaults to system hostname.",
+            "The hostname to register the node under in Kubernetes - defaults to system hostname. The value can reference a file with @<path> or environment variables with ${NAME}.",
         documentation: include_str!("config_documentation/hostname.adoc"),
         list: false,
     };
@@ -82,6 +610,26 @@ impl AgentConfig {
         list: false,
     };
 
+    pub const SERVER_BIND_ALL_INTERFACES: ConfigOption = ConfigOption {
+        name: "server-bind-all-interfaces",
+        default: None,
+        required: false,
+        takes_argument: false,
+        help: "If this option is specified, the server listens on all interfaces instead of only on the address which is registered as the node's ip.",
+        documentation: include_str!("config_documentation/server_bind_all_interfaces.adoc"),
+        list: false,
+    };
+
+    pub const NODE_IP_SOURCE: ConfigOption = ConfigOption {
+        name: "node-ip-source",
+        default: Some(""),
+        required: false,
+        takes_argument: true,
+        help: "The source of the ip address which is registered as the node's ip, e.g. address:203.0.113.10, interface:eth1, command:<command>, file:<path>, or first-non-loopback. If no source is given then the address of server-bind-ip is registered.",
+        documentation: include_str!("config_documentation/node_ip_source.adoc"),
+        list: false,
+    };
+
     pub const SERVER_CERT_FILE: ConfigOption = ConfigOption {
         name: "server-cert-file",
         default: Some("/etc/stackable/stackable-agent/secret/agent.crt"),
@@ -123,6 +671,17 @@ impl AgentConfig {
         list: false,
     };
 
+    pub const PACKAGE_DIR_TEMPLATE: ConfigOption = ConfigOption {
+        name: "package-directory-template",
+        default: Some("{{product}}-{{version}}"),
+        required: false,
+        takes_argument: true,
+        help:
+            "The template for the names of the directories into which the packages are installed.",
+        documentation: include_str!("config_documentation/package_directory_template.adoc"),
+        list: false,
+    };
+
     pub const CONFIG_DIR: ConfigOption = ConfigOption {
         name: "config-directory",
         default: Some("/etc/stackable/serviceconfig"),
@@ -133,6 +692,16 @@ impl AgentConfig {
         list: false,
     };
 
+    pub const CONFIG_GENERATIONS: ConfigOption = ConfigOption {
+        name: "config-generations",
+        default: Some("5"),
+        required: false,
+        takes_argument: true,
+        help: "The number of configuration directories which are kept per service.",
+        documentation: include_str!("config_documentation/config_generations.adoc"),
+        list: false,
+    };
+
     pub const LOG_DIR: ConfigOption = ConfigOption {
         name: "log-directory",
         default: Some("/var/log/stackable/servicelogs"),
@@ -163,6 +732,26 @@ impl AgentConfig {
         list: true
     };
 
+    pub const LABEL: ConfigOption = ConfigOption {
+        name: "label",
+        default: None,
+        required: false,
+        takes_argument: true,
+        help: "A \"key=value\" pair that should be assigned to the node as label. This can be specified multiple times to assign additional labels.",
+        documentation: include_str!("config_documentation/label.adoc"),
+        list: true
+    };
+
+    pub const TAINT: ConfigOption = ConfigOption {
+        name: "taint",
+        default: None,
+        required: false,
+        takes_argument: true,
+        help: "A taint in the format \"key[=value]:effect\" that should be added to the node. This can be specified multiple times to add additional taints.",
+        documentation: include_str!("config_documentation/taint.adoc"),
+        list: true
+    };
+
     pub const SESSION_SYSTEMD: ConfigOption = ConfigOption {
         name: "session",
         default: None,
@@ -173,67 +762,406 @@ impl AgentConfig {
         list: false
     };
 
+    pub const NO_ARCH_TAINTS: ConfigOption = ConfigOption {
+        name: "no-arch-taints",
+        default: None,
+        required: false,
+        takes_argument: false,
+        help: "If this option is specified, the taints on the architecture \"stackable-linux\" are not added to the node.",
+        documentation: include_str!("config_documentation/no_arch_taints.adoc"),
+        list: false
+    };
+
+    pub const KEEP_FAILED_SERVICES: ConfigOption = ConfigOption {
+        name: "keep-failed-services",
+        default: None,
+        required: false,
+        takes_argument: false,
+        help: "If this option is specified, the systemd units and configs of failed pods are kept until the pods are deleted.",
+        documentation: include_str!("config_documentation/keep_failed_services.adoc"),
+        list: false
+    };
+
+    pub const ALLOW_MULTIPLE_CONTAINERS: ConfigOption = ConfigOption {
+        name: "allow-multiple-containers",
+        default: None,
+        required: false,
+        takes_argument: false,
+        help: "If this option is specified, pods with more than one container are processed best-effort instead of being rejected.",
+        documentation: include_str!("config_documentation/allow_multiple_containers.adoc"),
+        list: false
+    };
+
+    pub const SYSTEM_UNIT_DIR: ConfigOption = ConfigOption {
+        name: "system-unit-directory",
+        default: Some("/lib/systemd/system"),
+        required: false,
+        takes_argument: true,
+        help:
+            "The directory in which the systemd unit files are written if the system bus is used.",
+        documentation: include_str!("config_documentation/system_unit_directory.adoc"),
+        list: false,
+    };
+
+    pub const USER_UNIT_DIR: ConfigOption = ConfigOption {
+        name: "user-unit-directory",
+        default: Some("~/.config/systemd/user"),
+        required: false,
+        takes_argument: true,
+        help:
+            "The directory in which the systemd unit files are written if the session bus is used.",
+        documentation: include_str!("config_documentation/user_unit_directory.adoc"),
+        list: false,
+    };
+
+    pub const REPOSITORY_CONNECT_TIMEOUT: ConfigOption = ConfigOption {
+        name: "repository-connect-timeout",
+        default: Some("10"),
+        required: false,
+        takes_argument: true,
+        help: "The number of seconds to wait for a connection to a repository, 0 disables the timeout.",
+        documentation: include_str!("config_documentation/repository_connect_timeout.adoc"),
+        list: false
+    };
+
+    pub const REPOSITORY_REQUEST_TIMEOUT: ConfigOption = ConfigOption {
+        name: "repository-request-timeout",
+        default: Some("300"),
+        required: false,
+        takes_argument: true,
+        help: "The number of seconds a request to a repository may take in total, 0 disables the timeout.",
+        documentation: include_str!("config_documentation/repository_request_timeout.adoc"),
+        list: false
+    };
+
+    pub const REPOSITORY_POOL_IDLE_TIMEOUT: ConfigOption = ConfigOption {
+        name: "repository-pool-idle-timeout",
+        default: Some("90"),
+        required: false,
+        takes_argument: true,
+        help: "The number of seconds an idle connection to a repository is kept open for reuse, 0 disables the reuse of connections.",
+        documentation: include_str!("config_documentation/repository_pool_idle_timeout.adoc"),
+        list: false
+    };
+
     pub const POD_CIDR: ConfigOption = ConfigOption {
         name: "pod-cidr",
         default: Some(""),
         required: false,
         takes_argument: true,
-        help: "An IP range in CIDR notation which designates the range that pods assigned to this node should have their ip addresses in.",
+        help: "An IP range in CIDR notation which designates the range that pods assigned to this node should have their ip addresses in. An IPv4 and an IPv6 range can be separated by a comma for dual-stack nodes.",
         documentation: include_str!("config_documentation/pod_cidr.adoc"),
         list: false
     };
 
-    /// Returns the directory in which the `server_cert_file` is
-    /// located.
-    ///
-    /// If `server_cert_file` contains only a file name then
-    /// `Path::new("")` is returned.
-    ///
-    /// # Panics
-    ///
-    /// Panics if `server_cert_file` does not contain a file name. An
-    /// [`AgentConfig`] which was created by
-    /// [`Configurable::parse_values`] always contains a valid file
-    /// name.
-    pub fn server_cert_file_dir(&self) -> &Path {
-        self.server_cert_file
-            .parent()
-            .expect("server_cert_file should contain a file")
-    }
+    pub const ADOPTION_READINESS_TIMEOUT: ConfigOption = ConfigOption {
+        name: "adoption-readiness-timeout",
+        default: Some("0"),
+        required: false,
+        takes_argument: true,
+        help: "The number of seconds to wait for the TCP ports of adopted services to accept connections, 0 disables the check.",
+        documentation: include_str!("config_documentation/adoption_readiness_timeout.adoc"),
+        list: false
+    };
 
-    /// Returns the directory in which the `server_key_file` is located.
-    ///
-    /// If `server_key_file` contains only a file name then
-    /// `Path::new("")` is returned.
-    ///
-    /// # Panics
-    ///
-    /// Panics if `server_key_file` does not contain a file name. An
-    /// [`AgentConfig`] which was created by
-    /// [`Configurable::parse_values`] always contains a valid file
-    /// name.
-    pub fn server_key_file_dir(&self) -> &Path {
-        self.server_key_file
-            .parent()
-            .expect("server_key_file should contain a file")
-    }
+    pub const API_SERVER_RETRIES: ConfigOption = ConfigOption {
+        name: "api-server-retries",
+        default: Some("5"),
+        required: false,
+        takes_argument: true,
+        help: "The number of times the connection to the API server is retried on startup.",
+        documentation: include_str!("config_documentation/api_server_retries.adoc"),
+        list: false,
+    };
 
-    fn get_options() -> HashSet<ConfigOption> {
-        [
-            AgentConfig::HOSTNAME,
-            AgentConfig::DATA_DIR,
-            AgentConfig::SERVER_IP_ADDRESS,
-            AgentConfig::SERVER_CERT_FILE,
-            AgentConfig::SERVER_KEY_FILE,
-            AgentConfig::SERVER_PORT,
-            AgentConfig::PACKAGE_DIR,
-            AgentConfig::CONFIG_DIR,
-            AgentConfig::LOG_DIR,
-            AgentConfig::NO_CONFIG,
-            AgentConfig::TAG,
-            AgentConfig::BOOTSTRAP_FILE,
-            AgentConfig::SESSION_SYSTEMD,
-            AgentConfig::POD_CIDR,
+    pub const API_SERVER_RETRY_INTERVAL: ConfigOption = ConfigOption {
+        name: "api-server-retry-interval",
+        default: Some("5"),
+        required: false,
+        takes_argument: true,
+        help: "The number of seconds to wait before the first retry to connect to the API server, the interval is doubled with every retry.",
+        documentation: include_str!("config_documentation/api_server_retry_interval.adoc"),
+        list: false,
+    };
+
+    pub const MAX_CONCURRENT_INSTALLS: ConfigOption = ConfigOption {
+        name: "max-concurrent-installs",
+        default: Some("2"),
+        required: false,
+        takes_argument: true,
+        help: "The number of packages which are downloaded or installed at the same time.",
+        documentation: include_str!("config_documentation/max_concurrent_installs.adoc"),
+        list: false,
+    };
+
+    pub const MAX_DOWNLOAD_RETRIES: ConfigOption = ConfigOption {
+        name: "max-download-retries",
+        default: Some("0"),
+        required: false,
+        takes_argument: true,
+        help: "The number of times a failed download of a package is retried before the pod fails, 0 retries indefinitely.",
+        documentation: include_str!("config_documentation/max_download_retries.adoc"),
+        list: false,
+    };
+
+    pub const SHUTDOWN_GRACE_PERIOD: ConfigOption = ConfigOption {
+        name: "shutdown-grace-period",
+        default: Some("30"),
+        required: false,
+        takes_argument: true,
+        help: "The number of seconds to wait for the managed units to stop when the agent receives SIGTERM or SIGINT.",
+        documentation: include_str!("config_documentation/shutdown_grace_period.adoc"),
+        list: false
+    };
+
+    pub const STOP_UNITS_ON_SHUTDOWN: ConfigOption = ConfigOption {
+        name: "stop-units-on-shutdown",
+        default: None,
+        required: false,
+        takes_argument: false,
+        help: "If this option is specified, the systemd units of the pods are stopped when the agent receives SIGTERM or SIGINT.",
+        documentation: include_str!("config_documentation/stop_units_on_shutdown.adoc"),
+        list: false
+    };
+
+    pub const CLEANUP_GRACE_PERIOD: ConfigOption = ConfigOption {
+        name: "cleanup-grace-period",
+        default: Some("5"),
+        required: false,
+        takes_argument: true,
+        help: "The number of seconds to wait on startup before systemd units without corresponding pods are removed.",
+        documentation: include_str!("config_documentation/cleanup_grace_period.adoc"),
+        list: false
+    };
+
+    pub const SERVICE_CHECK_INTERVAL: ConfigOption = ConfigOption {
+        name: "service-check-interval",
+        default: Some("10"),
+        required: false,
+        takes_argument: true,
+        help: "The number of seconds between the checks of the states of the running services.",
+        documentation: include_str!("config_documentation/service_check_interval.adoc"),
+        list: false,
+    };
+
+    pub const HOST_PATH_ALLOWED: ConfigOption = ConfigOption {
+        name: "host-path-allowed",
+        default: None,
+        required: false,
+        takes_argument: true,
+        help: "An absolute directory below which hostPath volumes are allowed. This can be specified multiple times to allow additional directories.",
+        documentation: include_str!("config_documentation/host_path_allowed.adoc"),
+        list: true
+    };
+
+    pub const ALLOWED_PRODUCT: ConfigOption = ConfigOption {
+        name: "allowed-product",
+        default: None,
+        required: false,
+        takes_argument: true,
+        help: "The name of a product which may run on this node, e.g. kafka. This can be specified multiple times to allow additional products. If no product is given then all products are allowed.",
+        documentation: include_str!("config_documentation/allowed_product.adoc"),
+        list: true
+    };
+
+    pub const ALLOWED_SYSCTL: ConfigOption = ConfigOption {
+        name: "allowed-sysctl",
+        default: None,
+        required: false,
+        takes_argument: true,
+        help: "The name of a kernel parameter which pods may set with sysctls, e.g. vm.max_map_count. A trailing * allows all parameters with the given prefix. This can be specified multiple times to allow additional parameters. If no parameter is given then pods must not set sysctls.",
+        documentation: include_str!("config_documentation/allowed_sysctl.adoc"),
+        list: true
+    };
+
+    pub const DEFAULT_SERVICE_USER: ConfigOption = ConfigOption {
+        name: "default-service-user",
+        default: Some(""),
+        required: false,
+        takes_argument: true,
+        help: "The user which runs the services of pods without a runAsUserName in their security context. If no user is given then these services run as root.",
+        documentation: include_str!("config_documentation/default_service_user.adoc"),
+        list: false
+    };
+
+    pub const LOG_NAMESPACE: ConfigOption = ConfigOption {
+        name: "log-namespace",
+        default: Some(""),
+        required: false,
+        takes_argument: true,
+        help: "The journal namespace to which the services of the pods log. If no namespace is given then the services log to the default journal.",
+        documentation: include_str!("config_documentation/log_namespace.adoc"),
+        list: false
+    };
+
+    pub const ADMIN_SOCKET: ConfigOption = ConfigOption {
+        name: "admin-socket",
+        default: Some(""),
+        required: false,
+        takes_argument: true,
+        help: "The path of a Unix domain socket for the local admin interface, which is disabled if no path is given.",
+        documentation: include_str!("config_documentation/admin_socket.adoc"),
+        list: false
+    };
+
+    pub const SECRETS_BACKEND_URL: ConfigOption = ConfigOption {
+        name: "secrets-backend-url",
+        default: Some(""),
+        required: false,
+        takes_argument: true,
+        help: "The base URL of a backend which resolves the secrets referenced in config files with {{secret:<path>}}.",
+        documentation: include_str!("config_documentation/secrets_backend_url.adoc"),
+        list: false
+    };
+
+    pub const SECRETS_BACKEND_TOKEN_FILE: ConfigOption = ConfigOption {
+        name: "secrets-backend-token-file",
+        default: Some(""),
+        required: false,
+        takes_argument: true,
+        help: "The path of a file which contains the bearer token for the secrets backend.",
+        documentation: include_str!("config_documentation/secrets_backend_token_file.adoc"),
+        list: false,
+    };
+
+    pub const SYSTEM_RESERVED: ConfigOption = ConfigOption {
+        name: "system-reserved",
+        default: Some(""),
+        required: false,
+        takes_argument: true,
+        help:
+            "The resources which are reserved for the operating system, e.g. cpu=500m,memory=1Gi.",
+        documentation: include_str!("config_documentation/system_reserved.adoc"),
+        list: false,
+    };
+
+    pub const KUBE_RESERVED: ConfigOption = ConfigOption {
+        name: "kube-reserved",
+        default: Some(""),
+        required: false,
+        takes_argument: true,
+        help: "The resources which are reserved for the agent, e.g. cpu=100m,memory=256Mi.",
+        documentation: include_str!("config_documentation/kube_reserved.adoc"),
+        list: false,
+    };
+
+    pub const UMASK: ConfigOption = ConfigOption {
+        name: "umask",
+        default: Some(UMASK_INHERIT),
+        required: false,
+        takes_argument: true,
+        help: "The umask of the agent process in octal notation which determines the permissions of the created files and directories, or \"inherit\" to keep the umask the agent was started with.",
+        documentation: include_str!("config_documentation/umask.adoc"),
+        list: false,
+    };
+
+    pub const UNIT_NAME_MAX_LENGTH: ConfigOption = ConfigOption {
+        name: "unit-name-max-length",
+        default: Some("255"),
+        required: false,
+        takes_argument: true,
+        help: "The maximum length of the names of the systemd units including the type suffix, longer names are shortened. The value must be between 64 and 255.",
+        documentation: include_str!("config_documentation/unit_name_max_length.adoc"),
+        list: false,
+    };
+
+    pub const PRINT_CONFIG: ConfigOption = ConfigOption {
+        name: "print-config",
+        default: None,
+        required: false,
+        takes_argument: false,
+        help: "If this option is specified, the resolved configuration is printed and the agent exits.",
+        documentation: include_str!("config_documentation/print_config.adoc"),
+        list: false
+    };
+
+    /// Returns the directory in which the `server_cert_file` is
+    /// located.
+    ///
+    /// If `server_cert_file` contains only a file name then
+    /// `Path::new("")` is returned.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `server_cert_file` does not contain a file name. An
+    /// [`AgentConfig`] which was created by
+    /// [`Configurable::parse_values`] always contains a valid file
+    /// name.
+    pub fn server_cert_file_dir(&self) -> &Path {
+        self.server_cert_file
+            .parent()
+            .expect("server_cert_file should contain a file")
+    }
+
+    /// Returns the directory in which the `server_key_file` is located.
+    ///
+    /// If `server_key_file` contains only a file name then
+    /// `Path::new("")` is returned.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `server_key_file` does not contain a file name. An
+    /// [`AgentConfig`] which was created by
+    /// [`Configurable::parse_values`] always contains a valid file
+    /// name.
+    pub fn server_key_file_dir(&self) -> &Path {
+        self.server_key_file
+            .parent()
+            .expect("server_key_file should contain a file")
+    }
+
+    fn get_options() -> HashSet<ConfigOption> {
+        [
+            AgentConfig::HOSTNAME,
+            AgentConfig::DATA_DIR,
+            AgentConfig::SERVER_IP_ADDRESS,
+            AgentConfig::SERVER_BIND_ALL_INTERFACES,
+            AgentConfig::NODE_IP_SOURCE,
+            AgentConfig::SERVER_CERT_FILE,
+            AgentConfig::SERVER_KEY_FILE,
+            AgentConfig::SERVER_PORT,
+            AgentConfig::PACKAGE_DIR,
+            AgentConfig::PACKAGE_DIR_TEMPLATE,
+            AgentConfig::CONFIG_DIR,
+            AgentConfig::CONFIG_GENERATIONS,
+            AgentConfig::LOG_DIR,
+            AgentConfig::NO_CONFIG,
+            AgentConfig::TAG,
+            AgentConfig::LABEL,
+            AgentConfig::TAINT,
+            AgentConfig::BOOTSTRAP_FILE,
+            AgentConfig::SESSION_SYSTEMD,
+            AgentConfig::NO_ARCH_TAINTS,
+            AgentConfig::KEEP_FAILED_SERVICES,
+            AgentConfig::ALLOW_MULTIPLE_CONTAINERS,
+            AgentConfig::SYSTEM_UNIT_DIR,
+            AgentConfig::USER_UNIT_DIR,
+            AgentConfig::REPOSITORY_CONNECT_TIMEOUT,
+            AgentConfig::REPOSITORY_REQUEST_TIMEOUT,
+            AgentConfig::REPOSITORY_POOL_IDLE_TIMEOUT,
+            AgentConfig::MAX_CONCURRENT_INSTALLS,
+            AgentConfig::MAX_DOWNLOAD_RETRIES,
+            AgentConfig::API_SERVER_RETRIES,
+            AgentConfig::API_SERVER_RETRY_INTERVAL,
+            AgentConfig::POD_CIDR,
+            AgentConfig::PRINT_CONFIG,
+            AgentConfig::ADOPTION_READINESS_TIMEOUT,
+            AgentConfig::SHUTDOWN_GRACE_PERIOD,
+            AgentConfig::STOP_UNITS_ON_SHUTDOWN,
+            AgentConfig::CLEANUP_GRACE_PERIOD,
+            AgentConfig::SERVICE_CHECK_INTERVAL,
+            AgentConfig::ADMIN_SOCKET,
+            AgentConfig::HOST_PATH_ALLOWED,
+            AgentConfig::ALLOWED_PRODUCT,
+            AgentConfig::ALLOWED_SYSCTL,
+            AgentConfig::DEFAULT_SERVICE_USER,
+            AgentConfig::LOG_NAMESPACE,
+            AgentConfig::SECRETS_BACKEND_URL,
+            AgentConfig::SECRETS_BACKEND_TOKEN_FILE,
+            AgentConfig::SYSTEM_RESERVED,
+            AgentConfig::KUBE_RESERVED,
+            AgentConfig::UMASK,
+            AgentConfig::UNIT_NAME_MAX_LENGTH,
         ]
         .iter()
         .cloned()
@@ -307,6 +1235,72 @@ impl AgentConfig {
         })
     }
 
+    /// Returns the node labels which are derived from the tags.
+    ///
+    /// Tags which are overridden by a label are omitted, so that the value of the label takes
+    /// precedence independent of the order in which the labels are applied to the node.
+    pub fn tag_labels(&self) -> HashMap<String, String> {
+        self.tags
+            .iter()
+            .filter(|(key, _)| !self.labels.contains_key(*key))
+            .map(|(key, value)| (key.to_owned(), value.to_owned()))
+            .collect()
+    }
+
+    /// Helper method to retrieve the "key=value" pairs of a list option.
+    ///
+    /// Malformed pairs are not ignored but an ArgumentParseError is added to the error list, so
+    /// that config parsing fails instead of behaving unpredictably. The pairs are assigned to the
+    /// node as labels, therefore keys and values must follow the syntax of Kubernetes labels.
+    /// This also ensures that they do not contain commas which would corrupt the comma-separated
+    /// list of node labels. If a key is specified multiple times then the last value wins.
+    fn get_key_value_pairs(
+        parsed_values: &HashMap<ConfigOption, Option<Vec<String>>>,
+        option: &ConfigOption,
+        error_list: &mut Vec<AgentConfigError>,
+    ) -> HashMap<String, String> {
+        let mut pairs = HashMap::new();
+        if let Some(Some(values)) = parsed_values.get(option) {
+            for value in values {
+                let split: Vec<&str> = value.split('=').collect();
+                if split.len() != 2 {
+                    error!(
+                        "The value [{}] of the parameter [{}] is not a \"key=value\" pair.",
+                        value, option.name
+                    );
+                    error_list.push(ArgumentParseError {
+                        name: option.name.to_string(),
+                    });
+                    continue;
+                }
+
+                let (key, label_value) = (split[0], split[1]);
+                if !is_valid_label_key(key) || !is_valid_label_value(label_value) {
+                    error!(
+                        "The value [{}] of the parameter [{}] is not a valid Kubernetes label. \
+                        Keys consist of an optional DNS subdomain prefix and a name, values may \
+                        be empty. Names and values contain at most 63 alphanumeric characters, \
+                        '-', '_', or '.', and start and end with an alphanumeric character.",
+                        value, option.name
+                    );
+                    error_list.push(ArgumentParseError {
+                        name: option.name.to_string(),
+                    });
+                    continue;
+                }
+
+                if let Some(previous_value) = pairs.insert(key.to_string(), label_value.to_string())
+                {
+                    warn!(
+                        "The key [{}] of the parameter [{}] is specified multiple times. The value [{}] replaces the value [{}].",
+                        key, option.name, label_value, previous_value
+                    );
+                }
+            }
+        }
+        pairs
+    }
+
     /// This tries to find the first non loopback interface with an ip address assigned.
     /// This should usually be the default interface.
     fn get_default_ipaddress() -> Option<IpAddr> {
@@ -343,6 +1337,19 @@ impl AgentConfig {
         }
     }
 
+    /// Returns the ip addresses which are assigned to the local interfaces.
+    fn get_local_ipaddresses() -> nix::Result<Vec<IpAddr>> {
+        Ok(ifaddrs::getifaddrs()?
+            .filter_map(|ifaddr| {
+                if let Some(SockAddr::Inet(inet_addr)) = ifaddr.address {
+                    Some(inet_addr.to_std().ip())
+                } else {
+                    None
+                }
+            })
+            .collect())
+    }
+
     fn default_hostname() -> anyhow::Result<String> {
         hostname::get()?
             .into_string()
@@ -370,6 +1377,232 @@ impl AgentConfig {
         }
         doc_string
     }
+
+    /// Returns the directory in which the systemd unit files are written, depending on whether
+    /// the session or the system bus is used.
+    pub fn unit_directory(&self) -> &Path {
+        if self.session {
+            &self.user_unit_directory
+        } else {
+            &self.system_unit_directory
+        }
+    }
+
+    /// Returns the resolved values of all options in a human readable form.
+    ///
+    /// The options are listed in alphabetical order with one option per line. Options which
+    /// can be specified multiple times are listed once per value.
+    pub fn get_effective_configuration(&self) -> String {
+        let mut tags = self
+            .tags
+            .iter()
+            .map(|(key, value)| format!("{}={}", key, value))
+            .collect::<Vec<_>>();
+        tags.sort();
+
+        let mut labels = self
+            .labels
+            .iter()
+            .map(|(key, value)| format!("{}={}", key, value))
+            .collect::<Vec<_>>();
+        labels.sort();
+
+        let mut values = vec![
+            (AgentConfig::HOSTNAME, self.hostname.to_owned()),
+            (
+                AgentConfig::DATA_DIR,
+                self.data_directory.to_string_lossy().into(),
+            ),
+            (
+                AgentConfig::BOOTSTRAP_FILE,
+                self.bootstrap_file.to_string_lossy().into(),
+            ),
+            (
+                AgentConfig::SERVER_IP_ADDRESS,
+                self.server_ip_address.to_string(),
+            ),
+            (
+                AgentConfig::SERVER_BIND_ALL_INTERFACES,
+                self.server_bind_address.is_unspecified().to_string(),
+            ),
+            (
+                AgentConfig::NODE_IP_SOURCE,
+                self.node_ip_source
+                    .as_ref()
+                    .map(NodeIpSource::to_string)
+                    .unwrap_or_default(),
+            ),
+            (
+                AgentConfig::SERVER_CERT_FILE,
+                self.server_cert_file.to_string_lossy().into(),
+            ),
+            (
+                AgentConfig::SERVER_KEY_FILE,
+                self.server_key_file.to_string_lossy().into(),
+            ),
+            (AgentConfig::SERVER_PORT, self.server_port.to_string()),
+            (
+                AgentConfig::PACKAGE_DIR,
+                self.parcel_directory.to_string_lossy().into(),
+            ),
+            (
+                AgentConfig::PACKAGE_DIR_TEMPLATE,
+                self.package_directory_template.to_owned(),
+            ),
+            (
+                AgentConfig::CONFIG_DIR,
+                self.config_directory.to_string_lossy().into(),
+            ),
+            (
+                AgentConfig::CONFIG_GENERATIONS,
+                self.config_generations.to_string(),
+            ),
+            (
+                AgentConfig::LOG_DIR,
+                self.log_directory.to_string_lossy().into(),
+            ),
+            (AgentConfig::SESSION_SYSTEMD, self.session.to_string()),
+            (AgentConfig::NO_ARCH_TAINTS, self.no_arch_taints.to_string()),
+            (
+                AgentConfig::KEEP_FAILED_SERVICES,
+                self.keep_failed_services.to_string(),
+            ),
+            (
+                AgentConfig::ALLOW_MULTIPLE_CONTAINERS,
+                self.allow_multiple_containers.to_string(),
+            ),
+            (
+                AgentConfig::SYSTEM_UNIT_DIR,
+                self.system_unit_directory.to_string_lossy().into(),
+            ),
+            (
+                AgentConfig::USER_UNIT_DIR,
+                self.user_unit_directory.to_string_lossy().into(),
+            ),
+            (
+                AgentConfig::REPOSITORY_CONNECT_TIMEOUT,
+                self.repository_connect_timeout.as_secs().to_string(),
+            ),
+            (
+                AgentConfig::REPOSITORY_REQUEST_TIMEOUT,
+                self.repository_request_timeout.as_secs().to_string(),
+            ),
+            (
+                AgentConfig::REPOSITORY_POOL_IDLE_TIMEOUT,
+                self.repository_pool_idle_timeout.as_secs().to_string(),
+            ),
+            (
+                AgentConfig::MAX_CONCURRENT_INSTALLS,
+                self.max_concurrent_installs.to_string(),
+            ),
+            (
+                AgentConfig::MAX_DOWNLOAD_RETRIES,
+                self.max_download_retries.to_string(),
+            ),
+            (
+                AgentConfig::API_SERVER_RETRIES,
+                self.api_server_retries.to_string(),
+            ),
+            (
+                AgentConfig::API_SERVER_RETRY_INTERVAL,
+                self.api_server_retry_interval.as_secs().to_string(),
+            ),
+            (AgentConfig::POD_CIDR, self.pod_cidrs.join(",")),
+            (
+                AgentConfig::ADOPTION_READINESS_TIMEOUT,
+                self.adoption_readiness_timeout.as_secs().to_string(),
+            ),
+            (
+                AgentConfig::SHUTDOWN_GRACE_PERIOD,
+                self.shutdown_grace_period.as_secs().to_string(),
+            ),
+            (
+                AgentConfig::STOP_UNITS_ON_SHUTDOWN,
+                self.stop_units_on_shutdown.to_string(),
+            ),
+            (
+                AgentConfig::CLEANUP_GRACE_PERIOD,
+                self.cleanup_grace_period.as_secs().to_string(),
+            ),
+            (
+                AgentConfig::SERVICE_CHECK_INTERVAL,
+                self.service_check_interval.as_secs().to_string(),
+            ),
+            (
+                AgentConfig::ADMIN_SOCKET,
+                self.admin_socket
+                    .as_ref()
+                    .map(|path| path.to_string_lossy().into_owned())
+                    .unwrap_or_default(),
+            ),
+            (
+                AgentConfig::DEFAULT_SERVICE_USER,
+                self.default_service_user.to_owned().unwrap_or_default(),
+            ),
+            (
+                AgentConfig::LOG_NAMESPACE,
+                self.log_namespace.to_owned().unwrap_or_default(),
+            ),
+            (
+                AgentConfig::SECRETS_BACKEND_URL,
+                self.secrets_backend_url
+                    .as_ref()
+                    .map(Url::to_string)
+                    .unwrap_or_default(),
+            ),
+            (
+                AgentConfig::SECRETS_BACKEND_TOKEN_FILE,
+                self.secrets_backend_token_file
+                    .as_ref()
+                    .map(|path| path.to_string_lossy().into_owned())
+                    .unwrap_or_default(),
+            ),
+            (
+                AgentConfig::SYSTEM_RESERVED,
+                self.system_reserved.to_string(),
+            ),
+            (AgentConfig::KUBE_RESERVED, self.kube_reserved.to_string()),
+            (
+                AgentConfig::UMASK,
+                self.umask
+                    .map(|umask| format!("{:04o}", umask))
+                    .unwrap_or_else(|| String::from(UMASK_INHERIT)),
+            ),
+            (
+                AgentConfig::UNIT_NAME_MAX_LENGTH,
+                self.unit_name_max_length.to_string(),
+            ),
+        ];
+        values.extend(tags.into_iter().map(|tag| (AgentConfig::TAG, tag)));
+        values.extend(labels.into_iter().map(|label| (AgentConfig::LABEL, label)));
+        values.extend(
+            self.taints
+                .iter()
+                .map(|taint| (AgentConfig::TAINT, taint.to_string())),
+        );
+        values.extend(self.host_path_allowed.iter().map(|path| {
+            (
+                AgentConfig::HOST_PATH_ALLOWED,
+                path.to_string_lossy().into(),
+            )
+        }));
+        values.extend(
+            self.allowed_products
+                .iter()
+                .map(|product| (AgentConfig::ALLOWED_PRODUCT, product.to_owned())),
+        );
+        values.extend(
+            self.allowed_sysctls
+                .iter()
+                .map(|sysctl| (AgentConfig::ALLOWED_SYSCTL, sysctl.to_owned())),
+        );
+        values.sort_by_key(|(option, _)| option.name);
+
+        values
+            .into_iter()
+            .map(|(option, value)| format!("{}: {}\n", option.name, value))
+            .collect()
+    }
 }
 
 impl Configurable for AgentConfig {
@@ -385,27 +1618,141 @@ impl Configurable for AgentConfig {
     fn parse_values(
         parsed_values: HashMap<ConfigOption, Option<Vec<String>>, RandomState>,
     ) -> Result<Self, anyhow::Error> {
+        let mut error_list = vec![];
+
         // Parse hostname or lookup local hostname
         let final_hostname =
-            AgentConfig::get_exactly_one_string(&parsed_values, &AgentConfig::HOSTNAME)
-                .unwrap_or_else(|_| {
-                    AgentConfig::default_hostname()
-                        .unwrap_or_else(|_| panic!("Unable to get hostname!"))
-                });
+            match AgentConfig::get_exactly_one_string(&parsed_values, &AgentConfig::HOSTNAME) {
+                Ok(hostname) => resolve_hostname(&hostname).map_err(|error| {
+                    error!("Unable to resolve hostname: {}", error);
+                    error_list.push(ArgumentParseError {
+                        name: AgentConfig::HOSTNAME.name.to_string(),
+                    });
+                    error
+                }),
+                Err(_) => AgentConfig::default_hostname().map_err(|error| {
+                    error!("Unable to get hostname: {}", error);
+                    error_list.push(ArgumentParseError {
+                        name: AgentConfig::HOSTNAME.name.to_string(),
+                    });
+                    error
+                }),
+            };
 
-        // Parse IP Address or lookup default
-        let final_ip = if let Ok(ip) =
+        // Parse IP Address
+        let server_ip = if let Ok(ip) =
             AgentConfig::get_exactly_one_string(&parsed_values, &AgentConfig::SERVER_IP_ADDRESS)
         {
-            IpAddr::from_str(&ip)
-                .unwrap_or_else(|_| panic!("Couldn't parse {} as a valid ip address!", ip))
+            let ip = IpAddr::from_str(&ip)
+                .unwrap_or_else(|_| panic!("Couldn't parse {} as a valid ip address!", ip));
+
+            // Otherwise the webserver would fail later on with a rather cryptic error
+            match AgentConfig::get_local_ipaddresses() {
+                Ok(local_addresses) => {
+                    if let Err(error) = check_local_ip_address(&ip, &local_addresses) {
+                        error!("{}", error);
+                        error_list.push(ArgumentParseError {
+                            name: AgentConfig::SERVER_IP_ADDRESS.name.to_string(),
+                        });
+                    }
+                }
+                Err(err) => warn!(
+                    "The ip address {} could not be checked because the interface addresses could not be retrieved: {}",
+                    ip, err
+                ),
+            }
+            Some(ip)
         } else {
-            AgentConfig::get_default_ipaddress()
-                .expect("Error getting default ip address, please specify it explicitly!")
+            None
         };
-        info!("Selected {} as local address to listen on.", final_ip);
 
-        let mut error_list = vec![];
+        // An empty source registers the address of server-bind-ip as the node's ip
+        let final_node_ip_source = AgentConfig::get_with_default(
+            &parsed_values,
+            &AgentConfig::NODE_IP_SOURCE,
+            error_list.as_mut(),
+        )
+        .and_then(|source: String| {
+            if source.is_empty() {
+                Ok(None)
+            } else {
+                NodeIpSource::from_str(&source).map(Some).map_err(|error| {
+                    error!("{}", error);
+                    error_list.push(ArgumentParseError {
+                        name: AgentConfig::NODE_IP_SOURCE.name.to_string(),
+                    });
+                    error
+                })
+            }
+        });
+
+        let bind_all_interfaces = parsed_values
+            .get(&AgentConfig::SERVER_BIND_ALL_INTERFACES)
+            .expect(
+                "No value for server-bind-all-interfaces parameter found in parsed values, this should not happen!",
+            )
+            .is_some();
+
+        let final_addresses = if let Ok(Some(source)) = &final_node_ip_source {
+            match source.resolve() {
+                Ok(node_ip) => {
+                    // The node's ip is not necessarily assigned to a local interface, e.g. if it
+                    // is the public address of a cloud instance, so the server listens on all
+                    // interfaces then.
+                    let bind_address = server_ip.unwrap_or_else(|| {
+                        let is_local = AgentConfig::get_local_ipaddresses()
+                            .map(|local_addresses| local_addresses.contains(&node_ip))
+                            .unwrap_or_default();
+                        if is_local {
+                            node_ip
+                        } else {
+                            unspecified_address(&node_ip)
+                        }
+                    });
+
+                    if bind_all_interfaces {
+                        Ok((node_ip, unspecified_address(&bind_address)))
+                    } else {
+                        Ok((node_ip, bind_address))
+                    }
+                }
+                Err(error) => {
+                    error!(
+                        "Unable to get the node's ip from the source [{}]: {}",
+                        source, error
+                    );
+                    error_list.push(ArgumentParseError {
+                        name: AgentConfig::NODE_IP_SOURCE.name.to_string(),
+                    });
+                    Err(error)
+                }
+            }
+        } else {
+            let final_ip = server_ip.unwrap_or_else(|| {
+                NodeIpSource::FirstNonLoopback
+                    .resolve()
+                    .expect("Error getting default ip address, please specify it explicitly!")
+            });
+
+            // The unspecified address cannot be registered as the node's ip, so the server
+            // listens on all interfaces and the default address is registered instead.
+            if final_ip.is_unspecified() {
+                let node_ip = NodeIpSource::FirstNonLoopback.resolve().expect(
+                    "Error getting default ip address, please specify it explicitly with server-bind-ip!",
+                );
+                Ok((node_ip, final_ip))
+            } else if bind_all_interfaces {
+                Ok((final_ip, unspecified_address(&final_ip)))
+            } else {
+                Ok((final_ip, final_ip))
+            }
+        };
+        if let Ok((final_ip, final_bind_address)) = &final_addresses {
+            info!(
+                "Selected {} as local address to listen on and {} as the node's ip.",
+                final_bind_address, final_ip
+            );
+        }
 
         // Parse directory/file parameters
         // PathBuf::from_str returns an infallible as Error, so cannot fail, hence unwrap is save
@@ -444,6 +1791,25 @@ impl Configurable for AgentConfig {
         .map(|path: PathBuf| normalize_path(&path));
 
         // Parse parcel directory
+        let final_config_generations = AgentConfig::get_with_default(
+            &parsed_values,
+            &AgentConfig::CONFIG_GENERATIONS,
+            error_list.as_mut(),
+        )
+        .and_then(|config_generations: usize| {
+            if config_generations >= 1 {
+                Ok(config_generations)
+            } else {
+                error_list.push(ArgumentParseError {
+                    name: AgentConfig::CONFIG_GENERATIONS.name.to_string(),
+                });
+                Err(anyhow!(
+                    "Error for parameter: {}",
+                    AgentConfig::CONFIG_GENERATIONS.name
+                ))
+            }
+        });
+
         let final_package_dir = AgentConfig::get_with_default(
             &parsed_values,
             &AgentConfig::PACKAGE_DIR,
@@ -451,13 +1817,124 @@ impl Configurable for AgentConfig {
         )
         .map(|path: PathBuf| normalize_path(&path));
 
+        let final_package_dir_template = AgentConfig::get_with_default(
+            &parsed_values,
+            &AgentConfig::PACKAGE_DIR_TEMPLATE,
+            error_list.as_mut(),
+        )
+        .and_then(|template: String| {
+            if is_valid_package_directory_template(&template) {
+                Ok(template)
+            } else {
+                error!(
+                    "The value [{}] of the parameter [{}] is not a valid directory name template. It must contain the placeholders {{{{product}}}} and {{{{version}}}}, must not contain a slash, and must not start with an underscore.",
+                    template,
+                    AgentConfig::PACKAGE_DIR_TEMPLATE.name
+                );
+                error_list.push(ArgumentParseError {
+                    name: AgentConfig::PACKAGE_DIR_TEMPLATE.name.to_string(),
+                });
+                Err(anyhow!(
+                    "Error for parameter: {}",
+                    AgentConfig::PACKAGE_DIR_TEMPLATE.name
+                ))
+            }
+        });
+
         // Parse pod cidr
-        let final_pod_cidr: Result<String, anyhow::Error> = AgentConfig::get_with_default(
+        // Parse systemd unit directories
+        let final_system_unit_dir = AgentConfig::get_with_default(
             &parsed_values,
-            &AgentConfig::POD_CIDR,
+            &AgentConfig::SYSTEM_UNIT_DIR,
+            error_list.as_mut(),
+        )
+        .map(|path: PathBuf| normalize_path(&path));
+
+        let final_user_unit_dir = AgentConfig::get_with_default(
+            &parsed_values,
+            &AgentConfig::USER_UNIT_DIR,
+            error_list.as_mut(),
+        )
+        .map(|path: String| {
+            let expanded_path = shellexpand::tilde(&path).into_owned();
+            normalize_path(Path::new(&expanded_path))
+        });
+
+        // Parse repository timeouts
+        let final_repository_connect_timeout = AgentConfig::get_with_default(
+            &parsed_values,
+            &AgentConfig::REPOSITORY_CONNECT_TIMEOUT,
+            error_list.as_mut(),
+        )
+        .map(Duration::from_secs);
+
+        let final_repository_request_timeout = AgentConfig::get_with_default(
+            &parsed_values,
+            &AgentConfig::REPOSITORY_REQUEST_TIMEOUT,
+            error_list.as_mut(),
+        )
+        .map(Duration::from_secs);
+
+        let final_repository_pool_idle_timeout = AgentConfig::get_with_default(
+            &parsed_values,
+            &AgentConfig::REPOSITORY_POOL_IDLE_TIMEOUT,
+            error_list.as_mut(),
+        )
+        .map(Duration::from_secs);
+
+        let final_max_concurrent_installs = AgentConfig::get_with_default(
+            &parsed_values,
+            &AgentConfig::MAX_CONCURRENT_INSTALLS,
+            error_list.as_mut(),
+        )
+        .and_then(|max_concurrent_installs: usize| {
+            if max_concurrent_installs >= 1 {
+                Ok(max_concurrent_installs)
+            } else {
+                error_list.push(ArgumentParseError {
+                    name: AgentConfig::MAX_CONCURRENT_INSTALLS.name.to_string(),
+                });
+                Err(anyhow!(
+                    "Error for parameter: {}",
+                    AgentConfig::MAX_CONCURRENT_INSTALLS.name
+                ))
+            }
+        });
+
+        let final_max_download_retries = AgentConfig::get_with_default(
+            &parsed_values,
+            &AgentConfig::MAX_DOWNLOAD_RETRIES,
+            error_list.as_mut(),
+        );
+
+        let final_api_server_retries = AgentConfig::get_with_default(
+            &parsed_values,
+            &AgentConfig::API_SERVER_RETRIES,
             error_list.as_mut(),
         );
 
+        let final_api_server_retry_interval = AgentConfig::get_with_default(
+            &parsed_values,
+            &AgentConfig::API_SERVER_RETRY_INTERVAL,
+            error_list.as_mut(),
+        )
+        .map(Duration::from_secs);
+
+        let final_pod_cidrs = AgentConfig::get_with_default(
+            &parsed_values,
+            &AgentConfig::POD_CIDR,
+            error_list.as_mut(),
+        )
+        .and_then(|pod_cidrs: String| {
+            parse_pod_cidrs(&pod_cidrs).map_err(|error| {
+                error!("{}", error);
+                error_list.push(ArgumentParseError {
+                    name: AgentConfig::POD_CIDR.name.to_string(),
+                });
+                error
+            })
+        });
+
         // Parse cert file
         let final_server_cert_file = AgentConfig::get_with_default(
             &parsed_values,
@@ -492,30 +1969,271 @@ impl Configurable for AgentConfig {
             }
         }
 
+        let final_adoption_readiness_timeout = AgentConfig::get_with_default(
+            &parsed_values,
+            &AgentConfig::ADOPTION_READINESS_TIMEOUT,
+            error_list.as_mut(),
+        )
+        .map(Duration::from_secs);
+
+        let final_shutdown_grace_period = AgentConfig::get_with_default(
+            &parsed_values,
+            &AgentConfig::SHUTDOWN_GRACE_PERIOD,
+            error_list.as_mut(),
+        )
+        .map(Duration::from_secs);
+
+        let final_cleanup_grace_period = AgentConfig::get_with_default(
+            &parsed_values,
+            &AgentConfig::CLEANUP_GRACE_PERIOD,
+            error_list.as_mut(),
+        )
+        .map(Duration::from_secs);
+
+        let final_service_check_interval = AgentConfig::get_with_default(
+            &parsed_values,
+            &AgentConfig::SERVICE_CHECK_INTERVAL,
+            error_list.as_mut(),
+        )
+        .and_then(|service_check_interval: u64| {
+            if service_check_interval >= 1 {
+                Ok(Duration::from_secs(service_check_interval))
+            } else {
+                error_list.push(ArgumentParseError {
+                    name: AgentConfig::SERVICE_CHECK_INTERVAL.name.to_string(),
+                });
+                Err(anyhow!(
+                    "Error for parameter: {}",
+                    AgentConfig::SERVICE_CHECK_INTERVAL.name
+                ))
+            }
+        });
+
+        // An empty path disables the admin interface
+        let final_admin_socket = AgentConfig::get_with_default(
+            &parsed_values,
+            &AgentConfig::ADMIN_SOCKET,
+            error_list.as_mut(),
+        )
+        .map(|path: String| {
+            if path.is_empty() {
+                None
+            } else {
+                Some(normalize_path(Path::new(&path)))
+            }
+        });
+
+        // An empty user name keeps running the services as root
+        let final_default_service_user = AgentConfig::get_with_default(
+            &parsed_values,
+            &AgentConfig::DEFAULT_SERVICE_USER,
+            error_list.as_mut(),
+        )
+        .and_then(|user_name: String| {
+            if user_name.is_empty() {
+                Ok(None)
+            } else if is_valid_user_name(&user_name) {
+                Ok(Some(user_name))
+            } else {
+                error!(
+                    "The value [{}] of parameter {} is not a valid user name.",
+                    user_name,
+                    AgentConfig::DEFAULT_SERVICE_USER.name
+                );
+                error_list.push(ArgumentParseError {
+                    name: AgentConfig::DEFAULT_SERVICE_USER.name.to_string(),
+                });
+                Err(anyhow!(
+                    "Error for parameter: {}",
+                    AgentConfig::DEFAULT_SERVICE_USER.name
+                ))
+            }
+        });
+
+        // An empty namespace selects the default journal
+        let final_log_namespace = AgentConfig::get_with_default(
+            &parsed_values,
+            &AgentConfig::LOG_NAMESPACE,
+            error_list.as_mut(),
+        )
+        .and_then(|log_namespace: String| {
+            if log_namespace.is_empty() {
+                Ok(None)
+            } else if is_valid_log_namespace(&log_namespace) {
+                Ok(Some(log_namespace))
+            } else {
+                error!(
+                    "The value [{}] of parameter {} is not a valid journal namespace.",
+                    log_namespace,
+                    AgentConfig::LOG_NAMESPACE.name
+                );
+                error_list.push(ArgumentParseError {
+                    name: AgentConfig::LOG_NAMESPACE.name.to_string(),
+                });
+                Err(anyhow!(
+                    "Error for parameter: {}",
+                    AgentConfig::LOG_NAMESPACE.name
+                ))
+            }
+        });
+
+        // An empty URL disables the resolution of secrets
+        let final_secrets_backend_url = AgentConfig::get_with_default(
+            &parsed_values,
+            &AgentConfig::SECRETS_BACKEND_URL,
+            error_list.as_mut(),
+        )
+        .and_then(|url: String| {
+            if url.is_empty() {
+                Ok(None)
+            } else {
+                match Url::parse(&url) {
+                    Ok(url) if url.scheme() == "http" || url.scheme() == "https" => Ok(Some(url)),
+                    _ => {
+                        error!(
+                            "The value [{}] of parameter {} is not a valid HTTP or HTTPS URL.",
+                            url,
+                            AgentConfig::SECRETS_BACKEND_URL.name
+                        );
+                        error_list.push(ArgumentParseError {
+                            name: AgentConfig::SECRETS_BACKEND_URL.name.to_string(),
+                        });
+                        Err(anyhow!(
+                            "Error for parameter: {}",
+                            AgentConfig::SECRETS_BACKEND_URL.name
+                        ))
+                    }
+                }
+            }
+        });
+
+        // An empty path sends the requests to the secrets backend without a token
+        let final_secrets_backend_token_file = AgentConfig::get_with_default(
+            &parsed_values,
+            &AgentConfig::SECRETS_BACKEND_TOKEN_FILE,
+            error_list.as_mut(),
+        )
+        .map(|path: String| {
+            if path.is_empty() {
+                None
+            } else {
+                Some(normalize_path(Path::new(&path)))
+            }
+        });
+
+        let mut parse_reserved_resources = |option: &ConfigOption| {
+            AgentConfig::get_with_default(&parsed_values, option, error_list.as_mut()).and_then(
+                |value: String| {
+                    ReservedResources::from_str(&value).map_err(|error| {
+                        error!("{}", error);
+                        error_list.push(ArgumentParseError {
+                            name: option.name.to_string(),
+                        });
+                        error
+                    })
+                },
+            )
+        };
+        let final_system_reserved = parse_reserved_resources(&AgentConfig::SYSTEM_RESERVED);
+        let final_kube_reserved = parse_reserved_resources(&AgentConfig::KUBE_RESERVED);
+
+        let final_umask =
+            AgentConfig::get_with_default(&parsed_values, &AgentConfig::UMASK, error_list.as_mut())
+                .and_then(|value: String| {
+                    if value == UMASK_INHERIT {
+                        return Ok(None);
+                    }
+                    parse_umask(&value).map(Some).ok_or_else(|| {
+                        error!(
+                            "The value [{}] of the parameter [{}] is neither [{}] nor an octal umask between 0000 and 0777.",
+                            value,
+                            AgentConfig::UMASK.name,
+                            UMASK_INHERIT
+                        );
+                        error_list.push(ArgumentParseError {
+                            name: AgentConfig::UMASK.name.to_string(),
+                        });
+                        anyhow!("Error for parameter: {}", AgentConfig::UMASK.name)
+                    })
+                });
+
+        let final_unit_name_max_length = AgentConfig::get_with_default(
+            &parsed_values,
+            &AgentConfig::UNIT_NAME_MAX_LENGTH,
+            error_list.as_mut(),
+        )
+        .and_then(|unit_name_max_length: usize| {
+            if (64..=255).contains(&unit_name_max_length) {
+                Ok(unit_name_max_length)
+            } else {
+                error_list.push(ArgumentParseError {
+                    name: AgentConfig::UNIT_NAME_MAX_LENGTH.name.to_string(),
+                });
+                Err(anyhow!(
+                    "Error for parameter: {}",
+                    AgentConfig::UNIT_NAME_MAX_LENGTH.name
+                ))
+            }
+        });
+
         let final_port = AgentConfig::get_with_default(
             &parsed_values,
             &AgentConfig::SERVER_PORT,
             error_list.as_mut(),
         );
 
-        let mut final_tags: HashMap<String, String> = HashMap::new();
-        if let Some(Some(tags)) = parsed_values.get(&AgentConfig::TAG) {
-            for tag in tags {
-                let split: Vec<&str> = tag.split('=').collect();
-                if split.len() == 2 {
-                    // This might panic, but really shouldn't, as we've checked the size of the array
-                    final_tags.insert(split[0].to_string(), split[1].to_string());
+        let final_tags =
+            AgentConfig::get_key_value_pairs(&parsed_values, &AgentConfig::TAG, &mut error_list);
+
+        let final_labels =
+            AgentConfig::get_key_value_pairs(&parsed_values, &AgentConfig::LABEL, &mut error_list);
+
+        let mut final_taints = Vec::new();
+        if let Some(Some(taints)) = parsed_values.get(&AgentConfig::TAINT) {
+            for taint in taints {
+                match NodeTaint::from_str(taint) {
+                    Ok(taint) => final_taints.push(taint),
+                    Err(error) => {
+                        error!("{}", error);
+                        error_list.push(ArgumentParseError {
+                            name: AgentConfig::TAINT.name.to_string(),
+                        });
+                    }
+                }
+            }
+        }
+
+        // Relative paths are rejected because they would be resolved against the working
+        // directory of the agent.
+        let mut final_host_path_allowed = Vec::new();
+        if let Some(Some(paths)) = parsed_values.get(&AgentConfig::HOST_PATH_ALLOWED) {
+            for path in paths {
+                let path = normalize_path(Path::new(path));
+                if path.is_absolute() {
+                    final_host_path_allowed.push(path);
                 } else {
-                    // We want to avoid any "unpredictable" behavior like ignoring a malformed
-                    // key=value pair with just a log message -> so we panic if this can't be
-                    // parsed
+                    error!(
+                        "The value [{}] of parameter {} must be an absolute path.",
+                        path.to_string_lossy(),
+                        AgentConfig::HOST_PATH_ALLOWED.name
+                    );
                     error_list.push(ArgumentParseError {
-                        name: AgentConfig::TAG.name.to_string(),
+                        name: AgentConfig::HOST_PATH_ALLOWED.name.to_string(),
                     });
                 }
             }
         }
 
+        let final_allowed_products = match parsed_values.get(&AgentConfig::ALLOWED_PRODUCT) {
+            Some(Some(products)) => products.to_owned(),
+            _ => Vec::new(),
+        };
+
+        let final_allowed_sysctls = match parsed_values.get(&AgentConfig::ALLOWED_SYSCTL) {
+            Some(Some(sysctls)) => sysctls.to_owned(),
+            _ => Vec::new(),
+        };
+
         // The first unwrap defaults to none in case the option is not se
 
         let final_session = parsed_values
@@ -525,6 +2243,41 @@ impl Configurable for AgentConfig {
             )
             .is_some();
 
+        let final_no_arch_taints = parsed_values
+            .get(&AgentConfig::NO_ARCH_TAINTS)
+            .expect(
+                "No value for no-arch-taints parameter found in parsed values, this should not happen!",
+            )
+            .is_some();
+
+        let final_keep_failed_services = parsed_values
+            .get(&AgentConfig::KEEP_FAILED_SERVICES)
+            .expect(
+                "No value for keep-failed-services parameter found in parsed values, this should not happen!",
+            )
+            .is_some();
+
+        let final_stop_units_on_shutdown = parsed_values
+            .get(&AgentConfig::STOP_UNITS_ON_SHUTDOWN)
+            .expect(
+                "No value for stop-units-on-shutdown parameter found in parsed values, this should not happen!",
+            )
+            .is_some();
+
+        let final_allow_multiple_containers = parsed_values
+            .get(&AgentConfig::ALLOW_MULTIPLE_CONTAINERS)
+            .expect(
+                "No value for allow-multiple-containers parameter found in parsed values, this should not happen!",
+            )
+            .is_some();
+
+        let final_print_config = parsed_values
+            .get(&AgentConfig::PRINT_CONFIG)
+            .expect(
+                "No value for print-config parameter found in parsed values, this should not happen!",
+            )
+            .is_some();
+
         // Panic if we encountered any errors during parsing of the values
         if !error_list.is_empty() {
             panic!(
@@ -539,20 +2292,309 @@ impl Configurable for AgentConfig {
         // These unwraps are ok to panic, if one of them barfs then something went horribly wrong
         // above, as we should have paniced in a "controlled fashion" from the conditional block
         // right before this
+        let (final_ip, final_bind_address) = final_addresses.unwrap();
         Ok(AgentConfig {
-            hostname: final_hostname,
+            hostname: final_hostname.unwrap(),
             parcel_directory: final_package_dir.unwrap(),
             config_directory: final_config_dir.unwrap(),
             data_directory: final_data_dir.unwrap(),
             log_directory: final_log_dir.unwrap(),
             bootstrap_file: final_bootstrap_file.unwrap(),
             server_ip_address: final_ip,
+            server_bind_address: final_bind_address,
+            node_ip_source: final_node_ip_source.unwrap(),
             server_port: final_port.unwrap(),
             server_cert_file: final_server_cert_file.unwrap(),
             server_key_file: final_server_key_file.unwrap(),
             tags: final_tags,
+            labels: final_labels,
+            taints: final_taints,
             session: final_session,
-            pod_cidr: final_pod_cidr.unwrap(),
+            no_arch_taints: final_no_arch_taints,
+            keep_failed_services: final_keep_failed_services,
+            allow_multiple_containers: final_allow_multiple_containers,
+            pod_cidrs: final_pod_cidrs.unwrap(),
+            print_config: final_print_config,
+            config_generations: final_config_generations.unwrap(),
+            adoption_readiness_timeout: final_adoption_readiness_timeout.unwrap(),
+            admin_socket: final_admin_socket.unwrap(),
+            secrets_backend_url: final_secrets_backend_url.unwrap(),
+            secrets_backend_token_file: final_secrets_backend_token_file.unwrap(),
+            system_reserved: final_system_reserved.unwrap(),
+            kube_reserved: final_kube_reserved.unwrap(),
+            umask: final_umask.unwrap(),
+            unit_name_max_length: final_unit_name_max_length.unwrap(),
+            package_directory_template: final_package_dir_template.unwrap(),
+            system_unit_directory: final_system_unit_dir.unwrap(),
+            user_unit_directory: final_user_unit_dir.unwrap(),
+            repository_connect_timeout: final_repository_connect_timeout.unwrap(),
+            repository_request_timeout: final_repository_request_timeout.unwrap(),
+            repository_pool_idle_timeout: final_repository_pool_idle_timeout.unwrap(),
+            shutdown_grace_period: final_shutdown_grace_period.unwrap(),
+            stop_units_on_shutdown: final_stop_units_on_shutdown,
+            cleanup_grace_period: final_cleanup_grace_period.unwrap(),
+            service_check_interval: final_service_check_interval.unwrap(),
+            max_concurrent_installs: final_max_concurrent_installs.unwrap(),
+            max_download_retries: final_max_download_retries.unwrap(),
+            api_server_retries: final_api_server_retries.unwrap(),
+            api_server_retry_interval: final_api_server_retry_interval.unwrap(),
+            host_path_allowed: final_host_path_allowed,
+            allowed_products: final_allowed_products,
+            allowed_sysctls: final_allowed_sysctls,
+            default_service_user: final_default_service_user.unwrap(),
+            log_namespace: final_log_namespace.unwrap(),
         })
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use rstest::rstest;
+
+    #[rstest]
+    #[case("maintenance=true:NoSchedule", Some(("maintenance", "true", "NoSchedule")))]
+    #[case("maintenance:NoExecute", Some(("maintenance", "", "NoExecute")))]
+    #[case("stackable.tech/disk=ssd:PreferNoSchedule", Some(("stackable.tech/disk", "ssd", "PreferNoSchedule")))]
+    #[case("maintenance=true", None)]
+    #[case("maintenance=true:NoStart", None)]
+    #[case("=true:NoSchedule", None)]
+    fn test_parse_node_taint(#[case] taint: &str, #[case] expected: Option<(&str, &str, &str)>) {
+        let expected = expected.map(|(key, value, effect)| NodeTaint {
+            key: key.to_string(),
+            value: value.to_string(),
+            effect: effect.to_string(),
+        });
+
+        let parsed = NodeTaint::from_str(taint).ok();
+
+        assert_eq!(expected, parsed);
+        if let Some(parsed) = parsed {
+            assert_eq!(taint, parsed.to_string());
+        }
+    }
+
+    #[rstest]
+    #[case("", Some((0, 0)))]
+    #[case("cpu=500m,memory=1Gi", Some((500, 1 << 30)))]
+    #[case("memory=512M, cpu=1.5", Some((1500, 512_000_000)))]
+    #[case("cpu=2", Some((2000, 0)))]
+    #[case("cpu=0.0005", None)]
+    #[case("memory=1GB", None)]
+    #[case("pid=1000", None)]
+    #[case("cpu", None)]
+    fn test_parse_reserved_resources(#[case] value: &str, #[case] expected: Option<(u64, u64)>) {
+        let expected = expected.map(|(cpu_millis, memory_bytes)| ReservedResources {
+            cpu_millis,
+            memory_bytes,
+        });
+
+        assert_eq!(expected, ReservedResources::from_str(value).ok());
+    }
+
+    #[rstest]
+    #[case("first-non-loopback", true)]
+    #[case("address:203.0.113.10", true)]
+    #[case("address:2001:db8::10", true)]
+    #[case("interface:eth1", true)]
+    #[case(
+        "command:curl -s http://169.254.169.254/latest/meta-data/public-ipv4",
+        true
+    )]
+    #[case("file:/etc/stackable/node-ip", true)]
+    #[case("address:0.0.0.0", false)]
+    #[case("address:eth1", false)]
+    #[case("file:node-ip", false)]
+    #[case("interface:", false)]
+    #[case("dns:node.example.com", false)]
+    #[case("eth1", false)]
+    fn test_parse_node_ip_source(#[case] value: &str, #[case] valid: bool) {
+        let parsed = NodeIpSource::from_str(value).ok();
+
+        assert_eq!(valid, parsed.is_some());
+        if let Some(parsed) = parsed {
+            assert_eq!(value, parsed.to_string());
+        }
+    }
+
+    #[test]
+    fn node_ip_should_be_resolved_from_commands_and_files() {
+        let file = env::temp_dir().join(format!("stackable-agent-node-ip-{}", process::id()));
+        fs::write(&file, "203.0.113.10\n").unwrap();
+        let from_file = NodeIpSource::File(file.to_owned()).resolve().ok();
+        fs::remove_file(&file).unwrap();
+
+        let from_command = NodeIpSource::Command(String::from("echo 2001:db8::10"))
+            .resolve()
+            .ok();
+        let from_failing_command = NodeIpSource::Command(String::from("exit 1")).resolve().ok();
+
+        assert_eq!(Some(IpAddr::from([203, 0, 113, 10])), from_file);
+        assert_eq!(
+            Some(IpAddr::from([0x2001, 0xdb8, 0, 0, 0, 0, 0, 0x10])),
+            from_command
+        );
+        assert_eq!(None, from_failing_command);
+    }
+
+    #[rstest]
+    #[case("fe80::1", true)]
+    #[case("febf::1", true)]
+    #[case("169.254.0.1", true)]
+    #[case("fec0::1", false)]
+    #[case("2001:db8::10", false)]
+    #[case("203.0.113.10", false)]
+    fn test_is_link_local(#[case] ip: &str, #[case] expected: bool) {
+        assert_eq!(expected, is_link_local(&IpAddr::from_str(ip).unwrap()));
+    }
+
+    #[rstest]
+    #[case("", Some(vec![]))]
+    #[case("10.244.0.0/24", Some(vec!["10.244.0.0/24"]))]
+    #[case("10.244.0.0/24, fd00:10:244::/64", Some(vec!["10.244.0.0/24", "fd00:10:244::/64"]))]
+    #[case("10.244.0.0", None)]
+    #[case("10.244.0.0/33", None)]
+    #[case("fd00:10:244::/129", None)]
+    #[case("10.244.0/24", None)]
+    #[case("10.244.0.0/24,10.245.0.0/24", None)]
+    #[case("10.244.0.0/24,fd00:10:244::/64,10.245.0.0/24", None)]
+    fn test_parse_pod_cidrs(#[case] value: &str, #[case] expected: Option<Vec<&str>>) {
+        let expected =
+            expected.map(|pod_cidrs| pod_cidrs.into_iter().map(String::from).collect::<Vec<_>>());
+
+        assert_eq!(expected, parse_pod_cidrs(value).ok());
+    }
+
+    #[rstest]
+    #[case("0022", Some(0o022))]
+    #[case("27", Some(0o027))]
+    #[case("0777", Some(0o777))]
+    #[case("1000", None)]
+    #[case("0089", None)]
+    #[case("", None)]
+    fn test_parse_umask(#[case] value: &str, #[case] expected: Option<u32>) {
+        assert_eq!(expected, parse_umask(value));
+    }
+
+    #[rstest]
+    #[case("{{product}}-{{version}}", true)]
+    #[case("{{product}}_{{version}}", true)]
+    #[case("{{version}}.{{product}}", true)]
+    #[case("{{product}}", false)]
+    #[case("{{product}}/{{version}}", false)]
+    #[case("_{{product}}-{{version}}", false)]
+    #[case("{{product}}-{{version}}-{{os}}", false)]
+    fn test_package_directory_template(#[case] template: &str, #[case] expected: bool) {
+        assert_eq!(expected, is_valid_package_directory_template(template));
+    }
+
+    #[rstest]
+    #[case("region", true)]
+    #[case("stackable.tech/rack", true)]
+    #[case("kubernetes.io/os", true)]
+    #[case("a.b-c_d", true)]
+    #[case("", false)]
+    #[case("stackable.tech/", false)]
+    #[case("/rack", false)]
+    #[case("Stackable.tech/rack", false)]
+    #[case("a/b/c", false)]
+    #[case("-region", false)]
+    #[case("region,rack", false)]
+    fn test_is_valid_label_key(#[case] key: &str, #[case] expected: bool) {
+        assert_eq!(expected, is_valid_label_key(key));
+    }
+
+    #[rstest]
+    #[case("", true)]
+    #[case("eu-central-1", true)]
+    #[case("V1.2_3", true)]
+    #[case("eu,us", false)]
+    #[case("eu ", false)]
+    #[case("eu-", false)]
+    #[case(
+        "a123456789b123456789c123456789d123456789e123456789f123456789xyz1",
+        false
+    )]
+    fn test_is_valid_label_value(#[case] value: &str, #[case] expected: bool) {
+        assert_eq!(expected, is_valid_label_value(value));
+    }
+
+    #[test]
+    fn key_value_pairs_should_be_validated() {
+        let values = vec![
+            String::from("region=eu"),
+            String::from("rack=1"),
+            String::from("rack=2"),
+            String::from("zone=a,b"),
+            String::from("invalid"),
+        ];
+        let mut parsed_values = HashMap::new();
+        parsed_values.insert(AgentConfig::TAG, Some(values));
+        let mut error_list = Vec::new();
+
+        let pairs =
+            AgentConfig::get_key_value_pairs(&parsed_values, &AgentConfig::TAG, &mut error_list);
+
+        let mut expected_pairs = HashMap::new();
+        expected_pairs.insert(String::from("region"), String::from("eu"));
+        expected_pairs.insert(String::from("rack"), String::from("2"));
+        assert_eq!(expected_pairs, pairs);
+        assert_eq!(2, error_list.len());
+    }
+
+    #[rstest]
+    #[case("node-1", Some("node-1"))]
+    #[case("${STACKABLE_AGENT_TEST_NODE}", Some("node-1"))]
+    #[case("${STACKABLE_AGENT_TEST_NODE}.example.com", Some("node-1.example.com"))]
+    #[case("${STACKABLE_AGENT_TEST_UNSET}", None)]
+    #[case("${STACKABLE_AGENT_TEST_NODE", None)]
+    #[case("@/nonexistent/nodename", None)]
+    fn test_resolve_hostname(#[case] value: &str, #[case] expected: Option<&str>) {
+        env::set_var("STACKABLE_AGENT_TEST_NODE", "node-1");
+
+        assert_eq!(expected.map(String::from), resolve_hostname(value).ok());
+    }
+
+    #[rstest]
+    #[case::local_address("192.168.1.2", true)]
+    #[case::unspecified_address("0.0.0.0", true)]
+    #[case::foreign_address("192.168.1.3", false)]
+    fn ip_address_should_be_assigned_to_local_interface(
+        #[case] ip: &str,
+        #[case] expected_valid: bool,
+    ) {
+        let local_addresses = vec![
+            IpAddr::from_str("127.0.0.1").unwrap(),
+            IpAddr::from_str("192.168.1.2").unwrap(),
+        ];
+
+        assert_eq!(
+            expected_valid,
+            check_local_ip_address(&IpAddr::from_str(ip).unwrap(), &local_addresses).is_ok()
+        );
+    }
+
+    #[rstest]
+    #[case("192.168.1.2", "0.0.0.0")]
+    #[case("fe80::1", "::")]
+    fn server_should_bind_all_interfaces_of_the_address_family(
+        #[case] ip: &str,
+        #[case] expected_bind_address: &str,
+    ) {
+        assert_eq!(
+            IpAddr::from_str(expected_bind_address).unwrap(),
+            unspecified_address(&IpAddr::from_str(ip).unwrap())
+        );
+    }
+
+    #[test]
+    fn hostname_should_be_read_from_file() {
+        let file = env::temp_dir().join(format!("stackable-agent-nodename-{}", std::process::id()));
+        fs::write(&file, "node-2\n").unwrap();
+
+        let hostname = resolve_hostname(&format!("@{}", file.to_string_lossy()));
+        fs::remove_file(&file).unwrap();
+
+        assert_eq!(String::from("node-2"), hostname.unwrap());
+    }
+}
@@ -7,6 +7,7 @@ use std::io;
 use std::path::{Component, Path, PathBuf};
 
 use anyhow::{anyhow, Result};
+use nix::fcntl::{self, RenameFlags};
 use nix::{libc::O_TMPFILE, unistd};
 use tokio::fs::OpenOptions;
 
@@ -41,6 +42,20 @@ pub fn change_owner(path: &Path, uid: &Uid) -> Result<()> {
     Ok(unistd::chown(path, Some(uid.0), None)?)
 }
 
+/// Changes the ownership of the file or directory at `path` to the user
+/// with the given `user_name` and its primary group.
+///
+/// # Errors
+///
+/// An error is returned if the user does not exist or if the ownership
+/// cannot be changed.
+pub fn change_owner_to_user(path: &Path, user_name: &str) -> Result<()> {
+    let user = unistd::User::from_name(user_name)
+        .map_err(|err| anyhow!("Could not retrieve user [{}]. {}", user_name, err))?
+        .ok_or_else(|| anyhow!("The user [{}] does not exist.", user_name))?;
+    Ok(unistd::chown(path, Some(user.uid), Some(user.gid))?)
+}
+
 /// Changes the ownership of the file or directory at `path` recursively to be
 /// owned by the given `uid`.
 ///
@@ -66,6 +81,15 @@ where
     Ok(())
 }
 
+/// Exchanges the files or directories at the given paths atomically.
+///
+/// This allows to replace a directory which is in use with a new one. Both paths must exist and
+/// reside on the same filesystem.
+pub fn exchange(path1: &Path, path2: &Path) -> io::Result<()> {
+    fcntl::renameat2(None, path1, None, path2, RenameFlags::RENAME_EXCHANGE)
+        .map_err(|errno| io::Error::from_raw_os_error(errno as i32))
+}
+
 /// Checks if the given directory exists and is writable by the current
 /// process.
 ///
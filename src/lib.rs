@@ -1,3 +1,5 @@
 pub mod config;
 pub mod fsext;
+pub mod logging;
 pub mod provider;
+pub mod tls;
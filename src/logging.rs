@@ -0,0 +1,172 @@
+//! Logger whose filter can be changed at runtime
+//!
+//! The agent logs with [`env_logger`] and the filter is initially taken from the environment
+//! variable `RUST_LOG`. The filter can be replaced while the agent is running, e.g. via the
+//! admin interface, so that the log level of a single module can be raised to reproduce an issue
+//! without restarting the agent. Filters use the same syntax as `RUST_LOG`, e.g.
+//! `info,stackable_agent::provider::systemdmanager=trace`.
+use std::env;
+use std::sync::{PoisonError, RwLock, RwLockReadGuard};
+
+use anyhow::{anyhow, Result};
+use env_logger::{Builder, Logger};
+use lazy_static::lazy_static;
+use log::{LevelFilter, Log, Metadata, Record, SetLoggerError};
+use regex::Regex;
+
+/// Environment variable which contains the initial filter
+const FILTER_VARIABLE: &str = "RUST_LOG";
+
+/// Environment variable which defines whether the output is colored
+const WRITE_STYLE_VARIABLE: &str = "RUST_LOG_STYLE";
+
+lazy_static! {
+    static ref LOGGER: ReloadableLogger =
+        ReloadableLogger::new(&env::var(FILTER_VARIABLE).unwrap_or_default());
+}
+
+/// Logger which delegates to an [`env_logger::Logger`] which can be replaced
+struct ReloadableLogger {
+    state: RwLock<LoggerState>,
+}
+
+/// Current logger together with the filter it was built from
+struct LoggerState {
+    logger: Logger,
+    filter: String,
+}
+
+impl ReloadableLogger {
+    fn new(filter: &str) -> Self {
+        ReloadableLogger {
+            state: RwLock::new(LoggerState {
+                logger: build_logger(filter),
+                filter: String::from(filter),
+            }),
+        }
+    }
+
+    /// Returns the current state; a poisoned lock is ignored because the state is always
+    /// replaced as a whole.
+    fn state(&self) -> RwLockReadGuard<LoggerState> {
+        self.state.read().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn replace(&self, filter: &str) {
+        let logger = build_logger(filter);
+        let max_level = logger.filter();
+
+        let mut state = self.state.write().unwrap_or_else(PoisonError::into_inner);
+        *state = LoggerState {
+            logger,
+            filter: String::from(filter),
+        };
+        log::set_max_level(max_level);
+    }
+}
+
+impl Log for ReloadableLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        self.state().logger.enabled(metadata)
+    }
+
+    fn log(&self, record: &Record) {
+        self.state().logger.log(record)
+    }
+
+    fn flush(&self) {
+        self.state().logger.flush()
+    }
+}
+
+/// Builds a logger with the given filter which writes to stderr like [`env_logger::init`].
+fn build_logger(filter: &str) -> Logger {
+    let mut builder = Builder::new();
+    builder.parse_filters(filter);
+    if let Ok(write_style) = env::var(WRITE_STYLE_VARIABLE) {
+        builder.parse_write_style(&write_style);
+    }
+    builder.build()
+}
+
+/// Initializes the global logger with the filter from the environment variable `RUST_LOG`.
+///
+/// An error is returned if a logger was already set.
+pub fn init() -> Result<(), SetLoggerError> {
+    log::set_logger(&*LOGGER)?;
+    log::set_max_level(LOGGER.state().logger.filter());
+    Ok(())
+}
+
+/// Returns the current filter of the global logger.
+pub fn filter() -> String {
+    LOGGER.state().filter.to_owned()
+}
+
+/// Replaces the filter of the global logger.
+///
+/// An error is returned if the filter is invalid, in which case the current filter is kept.
+pub fn set_filter(filter: &str) -> Result<()> {
+    validate_filter(filter)?;
+    LOGGER.replace(filter);
+    Ok(())
+}
+
+/// Checks if the given filter is valid.
+///
+/// [`env_logger`] ignores invalid directives and only prints a warning, so they are rejected
+/// beforehand to report them to the caller.
+fn validate_filter(filter: &str) -> Result<()> {
+    let (directives, regex) = match filter.split_once('/') {
+        Some((directives, regex)) => (directives, Some(regex)),
+        None => (filter, None),
+    };
+
+    for directive in directives.split(',').map(str::trim) {
+        let mut parts = directive.split('=');
+        let (_, level, rest) = (parts.next(), parts.next().map(str::trim), parts.next());
+
+        if rest.is_some() {
+            return Err(anyhow!(
+                "The log directive [{}] contains more than one '='.",
+                directive
+            ));
+        }
+        if let Some(level) = level.filter(|level| !level.is_empty()) {
+            level.parse::<LevelFilter>().map_err(|_| {
+                anyhow!(
+                    "The log level [{}] in the directive [{}] is invalid.",
+                    level,
+                    directive
+                )
+            })?;
+        }
+    }
+
+    if let Some(regex) = regex {
+        Regex::new(regex)
+            .map_err(|error| anyhow!("The log message filter is invalid: {}", error))?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use rstest::rstest;
+
+    #[rstest]
+    #[case("", true)]
+    #[case("debug", true)]
+    #[case("stackable_agent", true)]
+    #[case("info,stackable_agent::provider::systemdmanager=trace", true)]
+    #[case("stackable_agent=", true)]
+    #[case("warn,kube=off/D-Bus call", true)]
+    #[case("stackable_agent=verbose", false)]
+    #[case("stackable_agent=debug=trace", false)]
+    #[case("info/[", false)]
+    fn filters_should_be_validated(#[case] filter: &str, #[case] valid: bool) {
+        assert_eq!(valid, validate_filter(filter).is_ok());
+    }
+}
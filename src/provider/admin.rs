@@ -0,0 +1,611 @@
+//! Local admin interface
+//!
+//! The admin interface listens on a Unix domain socket and provides information about the
+//! systemd units which are managed by the Stackable Agent. It is meant for debugging on the node
+//! without knowing the naming scheme of the units.
+//!
+//! Requests and responses are JSON objects which are terminated by a newline. The following
+//! requests are supported:
+//!
+//! * `{"command":"list"}` returns all managed units:
+//!   `{"units":[{"pod":"default/kafka-0","container":"kafka",...}]}`
+//! * `{"command":"inspect","unit":"<unit name>"}` returns the given unit:
+//!   `{"unit":{"pod":"default/kafka-0","container":"kafka",...}}`
+//! * `{"command":"journal","unit":"<unit name>","tail":<entries>,"follow":<bool>}` returns the
+//!   journal entries of the current run of the given unit in the
+//!   [journal export format](https://systemd.io/JOURNAL_EXPORT_FORMATS/) with all fields, so
+//!   that they can be ingested by log shippers. `tail` and `follow` are optional and behave like
+//!   in `kubectl logs`. The connection is closed after the entries were sent.
+//! * `{"command":"packages"}` returns the packages which are installed in the parcel directory
+//!   with their hashes and disk sizes and the repositories which provide them:
+//!   `{"packages":[{"directory":"kafka-2.7","sha256":"...","size":...,"repositories":[...],
+//!   "verified":true}],"unavailableRepositories":[]}`
+//! * `{"command":"transitions","pod":"<namespace>/<name>"}` returns the current state of the
+//!   given pod in the state machine of the agent and its recent transitions with their reasons:
+//!   `{"pod":"default/kafka-0","state":"WaitingConfigMap","transitions":[{"state":"Initializing",
+//!   "reason":null,"time":"..."},...]}`
+//! * `{"command":"maintenance","enabled":<bool>}` enables or disables the maintenance mode and
+//!   returns whether it is enabled: `{"maintenance":true}`. In maintenance mode, the node is
+//!   marked as unschedulable and new pods are rejected with the reason `NodeInMaintenance`,
+//!   while the services of the running pods are kept. `enabled` is optional, so that the mode
+//!   can be queried without changing it.
+//! * `{"command":"logFilter","filter":"<filter>"}` replaces the log filter of the agent, see
+//!   [`logging`], and returns the current filter: `{"filter":"info"}`. `filter` is optional, so
+//!   that the current filter can be queried without changing it.
+//!
+//! If a request cannot be processed then an error is returned: `{"error":"<message>"}`
+use std::fs;
+use std::os::unix::fs::{DirBuilderExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::Ordering;
+
+use anyhow::{anyhow, Context};
+use kubelet::container::ContainerKey;
+use kubelet::pod::PodKey;
+use log::{debug, info, warn};
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::unix::OwnedWriteHalf;
+use tokio::net::{UnixListener, UnixStream};
+use tokio::sync::mpsc;
+use tokio::task;
+
+use super::kubernetes::node;
+use super::repository::inventory::{self, Inventory};
+use super::systemdmanager::journal_reader;
+use super::systemdmanager::manager::SystemdManager;
+use super::systemdmanager::service::ServiceState;
+use super::systemdmanager::systemdunit::SystemDUnit;
+use super::transitions::StateTransition;
+use super::{ContainerHandle, ProviderState};
+use crate::logging;
+
+/// Request sent by a client
+#[derive(Debug, Deserialize)]
+#[serde(tag = "command", rename_all = "camelCase")]
+enum Request {
+    List,
+    Inspect {
+        unit: String,
+    },
+    Journal {
+        unit: String,
+        #[serde(default)]
+        tail: Option<usize>,
+        #[serde(default)]
+        follow: bool,
+    },
+    Packages,
+    Transitions {
+        pod: String,
+    },
+    LogFilter {
+        #[serde(default)]
+        filter: Option<String>,
+    },
+    Maintenance {
+        #[serde(default)]
+        enabled: Option<bool>,
+    },
+}
+
+/// Response sent to the client for a request
+#[derive(Debug, Serialize)]
+#[serde(untagged)]
+enum Response {
+    Units {
+        units: Vec<UnitInfo>,
+    },
+    Unit {
+        unit: UnitInfo,
+    },
+    Packages(Inventory),
+    Transitions {
+        pod: String,
+        state: String,
+        transitions: Vec<StateTransition>,
+    },
+    LogFilter {
+        filter: String,
+    },
+    Maintenance {
+        maintenance: bool,
+    },
+    Error {
+        error: String,
+    },
+}
+
+/// Information about a managed unit
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct UnitInfo {
+    pod: String,
+    container: String,
+    init_container: bool,
+    unit: String,
+    state: Option<String>,
+    invocation_id: Option<String>,
+    previous_invocation_id: Option<String>,
+    ports: Vec<String>,
+}
+
+/// Binds the admin interface to the given socket path and serves incoming connections.
+///
+/// An existing file at the given path is replaced. The socket is only accessible by the user
+/// running the agent. This function only returns if the socket cannot be bound.
+pub async fn serve(socket_path: PathBuf, provider_state: ProviderState) -> anyhow::Result<()> {
+    let socket_path = socket_path.as_path();
+
+    if socket_path.exists() {
+        fs::remove_file(socket_path).with_context(|| {
+            format!(
+                "The existing file [{}] could not be removed.",
+                socket_path.to_string_lossy()
+            )
+        })?;
+    }
+
+    let listener = bind_private_socket(socket_path).with_context(|| {
+        format!(
+            "The admin socket [{}] could not be bound.",
+            socket_path.to_string_lossy()
+        )
+    })?;
+
+    info!(
+        "Admin interface listening on [{}]",
+        socket_path.to_string_lossy()
+    );
+
+    loop {
+        match listener.accept().await {
+            Ok((stream, _)) => {
+                let provider_state = provider_state.clone();
+                tokio::spawn(async move {
+                    if let Err(error) = handle_connection(stream, &provider_state).await {
+                        debug!("Connection to the admin interface failed: {}", error);
+                    }
+                });
+            }
+            Err(error) => warn!("Connection to the admin interface not accepted: {}", error),
+        }
+    }
+}
+
+/// Binds a Unix domain socket at the given path which is only accessible by the user running
+/// the agent.
+///
+/// The socket is bound in a private directory and moved to the given path after its permissions
+/// were restricted, so that no other user can connect in between. Changing the umask instead
+/// would affect the files which are created concurrently by other threads.
+fn bind_private_socket(socket_path: &Path) -> anyhow::Result<UnixListener> {
+    let file_name = socket_path
+        .file_name()
+        .ok_or_else(|| anyhow!("The path must denote a file."))?;
+    let private_directory =
+        socket_path.with_file_name(format!(".{}.bind", file_name.to_string_lossy()));
+
+    // A directory which remained from a crashed agent is removed.
+    if private_directory.exists() {
+        fs::remove_dir_all(&private_directory)?;
+    }
+    fs::DirBuilder::new()
+        .mode(0o700)
+        .create(&private_directory)?;
+
+    let private_socket = private_directory.join(file_name);
+    let result = UnixListener::bind(&private_socket).and_then(|listener| {
+        fs::set_permissions(&private_socket, fs::Permissions::from_mode(0o600))?;
+        fs::rename(&private_socket, socket_path)?;
+        Ok(listener)
+    });
+
+    if let Err(error) = fs::remove_dir_all(&private_directory) {
+        warn!(
+            "The directory [{}] could not be removed: {}",
+            private_directory.to_string_lossy(),
+            error
+        );
+    }
+
+    Ok(result?)
+}
+
+/// Answers the requests of a client until the connection is closed.
+async fn handle_connection(
+    stream: UnixStream,
+    provider_state: &ProviderState,
+) -> anyhow::Result<()> {
+    let (reader, mut writer) = stream.into_split();
+    let mut lines = BufReader::new(reader).lines();
+
+    while let Some(line) = lines.next_line().await? {
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let response = match serde_json::from_str::<Request>(&line) {
+            Ok(Request::Journal { unit, tail, follow }) => {
+                debug!("Admin request for the journal of unit [{}] received", unit);
+                match export_journal(&unit, tail, follow, provider_state, &mut writer).await {
+                    Ok(()) => return Ok(()),
+                    Err(error) => Response::Error {
+                        error: error.to_string(),
+                    },
+                }
+            }
+            Ok(request) => {
+                debug!("Admin request received: {:?}", request);
+                handle_request(request, provider_state).await
+            }
+            Err(error) => Response::Error {
+                error: format!("Invalid request: {}", error),
+            },
+        };
+
+        let mut response_line = serde_json::to_string(&response)?;
+        response_line.push('\n');
+        writer.write_all(response_line.as_bytes()).await?;
+    }
+
+    Ok(())
+}
+
+async fn handle_request(request: Request, provider_state: &ProviderState) -> Response {
+    match request {
+        Request::List => Response::Units {
+            units: managed_units(provider_state).await,
+        },
+        Request::Inspect { unit } => match managed_units(provider_state)
+            .await
+            .into_iter()
+            .find(|info| info.unit == unit)
+        {
+            Some(info) => Response::Unit { unit: info },
+            None => Response::Error {
+                error: format!("Unit [{}] is not managed by the agent.", unit),
+            },
+        },
+        Request::Packages => match inventory::inventory(
+            provider_state.client.clone(),
+            provider_state.parcel_directory.to_owned(),
+            &provider_state.package_directory_template,
+            &provider_state.repository_client,
+        )
+        .await
+        {
+            Ok(inventory) => Response::Packages(inventory),
+            Err(error) => Response::Error {
+                error: format!("The installed packages could not be listed: {}", error),
+            },
+        },
+        Request::Transitions { pod } => match pod_transitions(&pod, provider_state).await {
+            Some(transitions) => Response::Transitions {
+                pod,
+                state: transitions
+                    .last()
+                    .map(|transition| transition.state.to_owned())
+                    .unwrap_or_default(),
+                transitions,
+            },
+            None => Response::Error {
+                error: format!(
+                    "No transitions of pod [{}] recorded. The pod must be given as \
+                    <namespace>/<name>.",
+                    pod
+                ),
+            },
+        },
+        Request::LogFilter { filter } => match filter {
+            Some(filter) => match logging::set_filter(&filter) {
+                Ok(()) => {
+                    info!("Log filter changed to [{}]", filter);
+                    Response::LogFilter { filter }
+                }
+                Err(error) => Response::Error {
+                    error: error.to_string(),
+                },
+            },
+            None => Response::LogFilter {
+                filter: logging::filter(),
+            },
+        },
+        Request::Maintenance { enabled } => match enabled {
+            Some(enabled) => match set_maintenance(enabled, provider_state).await {
+                Ok(()) => Response::Maintenance {
+                    maintenance: enabled,
+                },
+                Err(error) => Response::Error {
+                    error: format!(
+                        "The node [{}] could not be marked as {}: {}",
+                        provider_state.node_name,
+                        if enabled {
+                            "unschedulable"
+                        } else {
+                            "schedulable"
+                        },
+                        error
+                    ),
+                },
+            },
+            None => Response::Maintenance {
+                maintenance: provider_state.maintenance.load(Ordering::SeqCst),
+            },
+        },
+        Request::Journal { .. } => Response::Error {
+            error: String::from("The journal must be exported separately."),
+        },
+    }
+}
+
+/// Writes the journal entries of the current run of the given unit in the export format.
+///
+/// The journal is read in a blocking task and the entries are passed through a channel, so
+/// that the reader stops when the client closes the connection. An error is returned if the
+/// unit is not managed by the agent or its journal cannot be read. In this case nothing was
+/// written yet.
+async fn export_journal(
+    unit: &str,
+    tail: Option<usize>,
+    follow: bool,
+    provider_state: &ProviderState,
+    writer: &mut OwnedWriteHalf,
+) -> anyhow::Result<()> {
+    let container_handle = {
+        let handles = provider_state.handles.read().await;
+        handles
+            .iter()
+            .flat_map(|(_, pod_handle)| pod_handle.values())
+            .find(|container_handle| container_handle.service_unit == unit)
+            .map(ContainerHandle::to_owned)
+    }
+    .ok_or_else(|| anyhow!("Unit [{}] is not managed by the agent.", unit))?;
+
+    let systemd_service = &container_handle.systemd_service;
+    let invocation_id = systemd_service.invocation_id().await?;
+    let syslog_identifier = systemd_service
+        .syslog_identifier()
+        .await
+        .unwrap_or_else(|error| {
+            debug!("{}", error);
+            None
+        });
+    let log_namespace = systemd_service
+        .log_namespace()
+        .await
+        .unwrap_or_else(|error| {
+            debug!("{}", error);
+            None
+        });
+
+    let (sender, mut receiver) = mpsc::channel::<Vec<u8>>(16);
+
+    let export = task::spawn_blocking(move || {
+        journal_reader::export_entries(
+            &invocation_id,
+            syslog_identifier.as_deref(),
+            log_namespace.as_deref(),
+            tail,
+            follow,
+            |entry| {
+                sender
+                    .blocking_send(entry)
+                    .map_err(|_| anyhow!("The client closed the connection."))
+            },
+            || sender.is_closed(),
+        )
+    });
+
+    let mut written = false;
+    while let Some(entry) = receiver.recv().await {
+        if writer.write_all(&entry).await.is_err() {
+            break;
+        }
+        written = true;
+    }
+    drop(receiver);
+
+    match export.await? {
+        // The error can only be reported to the client if no entries were sent.
+        Err(error) if !written => Err(error),
+        Err(error) => {
+            debug!(
+                "Journal of unit [{}] could not be exported: {}",
+                unit, error
+            );
+            Ok(())
+        }
+        Ok(()) => Ok(()),
+    }
+}
+
+/// Enables or disables the maintenance mode.
+///
+/// New pods are rejected before the node is marked as unschedulable, so that no pod is
+/// accepted in between. If the node cannot be updated then the previous mode is restored.
+async fn set_maintenance(enabled: bool, provider_state: &ProviderState) -> anyhow::Result<()> {
+    let previous = provider_state.maintenance.swap(enabled, Ordering::SeqCst);
+
+    if let Err(error) = node::set_unschedulable(
+        provider_state.client.clone(),
+        &provider_state.node_name,
+        enabled,
+    )
+    .await
+    {
+        provider_state.maintenance.store(previous, Ordering::SeqCst);
+        return Err(error.into());
+    }
+
+    info!(
+        "Maintenance mode {}",
+        if enabled { "enabled" } else { "disabled" }
+    );
+    Ok(())
+}
+
+/// Returns the recorded transitions of the given pod in the form `<namespace>/<name>`.
+async fn pod_transitions(
+    pod: &str,
+    provider_state: &ProviderState,
+) -> Option<Vec<StateTransition>> {
+    let (namespace, name) = pod.split_once('/')?;
+    let pod_key = PodKey::new(namespace, name);
+
+    let transitions = provider_state.transitions.read().await;
+    transitions
+        .get(&pod_key)
+        .map(|transitions| transitions.iter().cloned().collect())
+}
+
+/// Collects the information about all units in the pod handles.
+///
+/// The handles are copied first, so that the lock is not held while systemd is queried.
+async fn managed_units(provider_state: &ProviderState) -> Vec<UnitInfo> {
+    let handles = {
+        let handles = provider_state.handles.read().await;
+        handles
+            .iter()
+            .flat_map(|(pod_key, pod_handle)| {
+                pod_handle
+                    .iter()
+                    .map(move |(container_key, container_handle)| {
+                        (
+                            pod_key.to_owned(),
+                            container_key.to_owned(),
+                            container_handle.to_owned(),
+                        )
+                    })
+            })
+            .collect::<Vec<_>>()
+    };
+
+    let mut units = Vec::new();
+    for (pod_key, container_key, container_handle) in handles {
+        units.push(
+            unit_info(
+                &provider_state.systemd_manager,
+                &pod_key,
+                &container_key,
+                &container_handle,
+            )
+            .await,
+        );
+    }
+    units.sort_by(|a, b| a.unit.cmp(&b.unit));
+
+    units
+}
+
+async fn unit_info(
+    systemd_manager: &SystemdManager,
+    pod_key: &PodKey,
+    container_key: &ContainerKey,
+    container_handle: &ContainerHandle,
+) -> UnitInfo {
+    let systemd_service = &container_handle.systemd_service;
+
+    let state = systemd_service
+        .service_state()
+        .await
+        .map(|state| {
+            match state {
+                ServiceState::Created => "created",
+                ServiceState::Started => "started",
+                ServiceState::Succeeded => "succeeded",
+                ServiceState::Failed => "failed",
+            }
+            .to_string()
+        })
+        .ok();
+
+    // The ports are only recorded in the unit files which are written by the agent.
+    let ports = match systemd_manager
+        .unit_file_content(&container_handle.service_unit)
+        .await
+    {
+        Ok(Some(unit_file_content)) => SystemDUnit::ports_from_unit_file(&unit_file_content),
+        Ok(None) => Vec::new(),
+        Err(error) => {
+            debug!("{}", error);
+            Vec::new()
+        }
+    };
+
+    UnitInfo {
+        pod: format!("{}/{}", pod_key.namespace(), pod_key.name()),
+        container: container_key.name().to_string(),
+        init_container: container_key.is_init(),
+        unit: container_handle.service_unit.to_owned(),
+        state,
+        invocation_id: systemd_service.invocation_id().await.ok(),
+        previous_invocation_id: container_handle.previous_invocation_id.to_owned(),
+        ports,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn requests_should_be_parsed() {
+        assert!(matches!(
+            serde_json::from_str(r#"{"command":"list"}"#),
+            Ok(Request::List)
+        ));
+        assert!(matches!(
+            serde_json::from_str(r#"{"command":"inspect","unit":"default-test-test.service"}"#),
+            Ok(Request::Inspect { unit }) if unit == "default-test-test.service"
+        ));
+        assert!(matches!(
+            serde_json::from_str(r#"{"command":"journal","unit":"default-test-test.service","tail":10}"#),
+            Ok(Request::Journal { unit, tail: Some(10), follow: false }) if unit == "default-test-test.service"
+        ));
+        assert!(matches!(
+            serde_json::from_str(r#"{"command":"packages"}"#),
+            Ok(Request::Packages)
+        ));
+        assert!(matches!(
+            serde_json::from_str(r#"{"command":"transitions","pod":"default/test"}"#),
+            Ok(Request::Transitions { pod }) if pod == "default/test"
+        ));
+        assert!(matches!(
+            serde_json::from_str(r#"{"command":"logFilter","filter":"debug"}"#),
+            Ok(Request::LogFilter { filter: Some(filter) }) if filter == "debug"
+        ));
+        assert!(matches!(
+            serde_json::from_str(r#"{"command":"logFilter"}"#),
+            Ok(Request::LogFilter { filter: None })
+        ));
+        assert!(matches!(
+            serde_json::from_str(r#"{"command":"maintenance","enabled":true}"#),
+            Ok(Request::Maintenance {
+                enabled: Some(true)
+            })
+        ));
+        assert!(serde_json::from_str::<Request>(r#"{"command":"restart"}"#).is_err());
+    }
+
+    #[test]
+    fn responses_should_be_serialized_as_json_objects() {
+        let response = Response::Units {
+            units: vec![UnitInfo {
+                pod: String::from("default/test"),
+                container: String::from("test"),
+                init_container: false,
+                unit: String::from("default-test-test.service"),
+                state: Some(String::from("started")),
+                invocation_id: None,
+                previous_invocation_id: None,
+                ports: vec![String::from("8080/TCP http")],
+            }],
+        };
+
+        assert_eq!(
+            r#"{"units":[{"pod":"default/test","container":"test","initContainer":false,"unit":"default-test-test.service","state":"started","invocationId":null,"previousInvocationId":null,"ports":["8080/TCP http"]}]}"#,
+            serde_json::to_string(&response).unwrap()
+        );
+    }
+}
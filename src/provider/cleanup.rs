@@ -1,19 +1,24 @@
 //! Initial cleanup
 //!
-//! On startup the systemd units in the `system-stackable` slice are compared to the pods assigned
-//! to this node. If a systemd unit is as expected then it is kept and the Stackable Agent will
-//! take ownership again in the `Starting` stage.  If there is no corresponding pod or the systemd
-//! unit differs from the pod specification then it is removed and the Stackable Agent will create
-//! a new systemd unit in the `CreatingService` stage.
+//! On startup the systemd units in the `system-stackable` slice and its nested pod slices, and
+//! the socket and timer units which activate them, are compared to the pods assigned to this
+//! node. If a systemd unit is as expected then it is kept
+//! and the Stackable Agent will take ownership again in the `Starting` stage.  If there is no
+//! corresponding pod or the systemd unit differs from the pod specification then it is removed
+//! and the Stackable Agent will create a new systemd unit in the `CreatingService` stage.
 //!
 //! The cleanup stage is implemented as part of the [`StackableProvider`] because the expected
 //! content of a systemd unit file can only be determined with the directories configured in the
 //! provider.
 //!
+//! While the cleanup runs, the node is tainted with [`NOT_READY_TAINT`], so that no new pods are
+//! scheduled onto it before the existing units are adopted or removed.
+//!
 //! The cleanup code resides in a separate module because the amount of code justifies it and the
 //! log output is more meaningful. It makes it clearer whether a systemd unit is removed in the
 //! cleanup stage or in the normal process.
 use std::collections::HashMap;
+use std::sync::atomic::Ordering;
 
 use anyhow::Context;
 use k8s_openapi::api::core::v1::Pod as KubePod;
@@ -22,19 +27,33 @@ use kube::Api;
 use kubelet::pod::Pod;
 use kubelet::provider::Provider;
 use log::{debug, error, info, warn};
-use tokio::fs::{read_to_string, remove_file};
+use tokio::time;
 
+use super::kubernetes::node;
 use super::systemdmanager::systemdunit::SystemDUnit;
 use super::systemdmanager::systemdunit::STACKABLE_SLICE;
-use super::StackableProvider;
+use super::{StackableProvider, NOT_READY_TAINT};
 
 impl StackableProvider {
     /// Removes systemd units without corresponding pods.
     ///
-    /// The systemd units in the `system-stackable` slice are compared with the pods assigned to
-    /// this node and all units without corresponding pods or which differ from the pod
-    /// specifications are removed.
+    /// The systemd units in the `system-stackable` slice and its nested pod slices are compared
+    /// with the pods assigned to this node and all units without corresponding pods or which
+    /// differ from the pod specifications are removed.
+    ///
+    /// The node is tainted with [`NOT_READY_TAINT`] until the cleanup is completed.
     pub async fn cleanup(&self, node_name: &str) {
+        let client = self.shared.client.clone();
+        node::add_taint(client.clone(), node_name, NOT_READY_TAINT).await;
+
+        self.cleanup_units(node_name).await;
+
+        self.cleanup_completed.store(true, Ordering::SeqCst);
+        node::remove_taint(client, node_name, NOT_READY_TAINT).await;
+    }
+
+    /// Removes the systemd units without corresponding pods, see [`StackableProvider::cleanup`].
+    async fn cleanup_units(&self, node_name: &str) {
         let systemd_manager = &self.shared.systemd_manager;
 
         if let Err(error) = systemd_manager.reload().await {
@@ -45,8 +64,8 @@ impl StackableProvider {
             return;
         }
 
-        let units_in_slice = match systemd_manager.slice_content(STACKABLE_SLICE).await {
-            Ok(units_in_slice) => units_in_slice,
+        let units_in_slice = match self.units_in_slice(STACKABLE_SLICE).await {
+            Ok(units_in_slice) => self.with_trigger_units(units_in_slice).await,
             Err(error) => {
                 debug!(
                     "Skipping the cleanup stage because no systemd units were found in the slice \
@@ -57,43 +76,48 @@ impl StackableProvider {
             }
         };
 
-        let pods = match self.assigned_pods(node_name).await {
-            Ok(pods) => pods.items,
-            Err(error) => {
-                error!(
-                    "The assigned pods could not be retrieved. All systemd units in the slice [{}] \
-                    will be removed. {}",
-                    STACKABLE_SLICE, error
-                );
-                Vec::new()
-            }
-        };
+        let mut units_from_pods = self.units_from_assigned_pods(node_name).await;
 
-        let mut units_from_pods = HashMap::new();
-        for pod in pods {
-            let pod_terminating = pod.metadata.deletion_timestamp.is_some();
-
-            match self.units_from_pod(&pod).await {
-                Ok(units) => {
-                    for (unit_name, content) in units {
-                        units_from_pods.insert(unit_name, (content, pod_terminating));
-                    }
-                }
-                Err(error) => warn!(
-                    "Systemd units could not be generated for pod [{}/{}]. {}",
-                    pod.namespace().unwrap_or_else(|| String::from("default")),
-                    pod.name(),
-                    error
-                ),
-            }
+        // Recently created pods may not be listed yet directly after a restart, so the pods are
+        // listed again after the grace period before units are considered orphaned.
+        let orphaned_units = units_in_slice
+            .iter()
+            .filter(|unit_name| !units_from_pods.contains_key(*unit_name))
+            .count();
+        if orphaned_units > 0 && !self.cleanup_grace_period.is_zero() {
+            info!(
+                "{} systemd units without corresponding pods found. Waiting {} seconds for the \
+                pods to become visible before the units are removed.",
+                orphaned_units,
+                self.cleanup_grace_period.as_secs()
+            );
+            time::sleep(self.cleanup_grace_period).await;
+            units_from_pods = self.units_from_assigned_pods(node_name).await;
         }
 
-        let mut unit_removed = false;
+        let mut units_to_remove = Vec::new();
 
         for unit_name in &units_in_slice {
             let remove_unit = match units_from_pods.get(unit_name) {
-                Some((expected_content, pod_terminating)) => {
-                    match self.unit_file_content(unit_name).await {
+                Some((None, pod_terminating)) => {
+                    // Transient units have no unit file which could be compared.
+                    if *pod_terminating {
+                        info!(
+                            "The transient systemd unit [{}] will be removed because the \
+                            corresponding pod is terminating.",
+                            unit_name
+                        );
+                    } else {
+                        info!(
+                            "The transient systemd unit [{}] will be kept because a \
+                            corresponding pod exists.",
+                            unit_name
+                        );
+                    }
+                    *pod_terminating
+                }
+                Some((Some(expected_content), pod_terminating)) => {
+                    match systemd_manager.unit_file_content(unit_name).await {
                         Ok(Some(content)) if &content == expected_content && !pod_terminating => {
                             info!(
                                 "The systemd unit [{}] will be kept because a corresponding pod \
@@ -151,16 +175,116 @@ impl StackableProvider {
             };
 
             if remove_unit {
-                self.remove_unit(unit_name).await;
-                unit_removed = true;
+                units_to_remove.push(unit_name.as_str());
             }
         }
 
-        if unit_removed {
-            let _ = systemd_manager.reload().await;
+        if let Err(error) = systemd_manager.remove_units(&units_to_remove).await {
+            warn!("{}", error);
         }
     }
 
+    /// Creates the systemd units of all pods assigned to the given node in memory.
+    ///
+    /// A mapping from the unit names to their content and the information if the pod is
+    /// terminating is returned. Transient units have no content. If the pods cannot be listed
+    /// then an empty map is returned.
+    async fn units_from_assigned_pods(
+        &self,
+        node_name: &str,
+    ) -> HashMap<String, (Option<String>, bool)> {
+        let pods = match self.assigned_pods(node_name).await {
+            Ok(pods) => pods.items,
+            Err(error) => {
+                error!(
+                    "The assigned pods could not be retrieved. All systemd units in the slice [{}] \
+                    will be removed. {}",
+                    STACKABLE_SLICE, error
+                );
+                Vec::new()
+            }
+        };
+
+        let mut units_from_pods = HashMap::new();
+        for pod in pods {
+            let pod_terminating = pod.metadata.deletion_timestamp.is_some();
+
+            match self.units_from_pod(&pod).await {
+                Ok(units) => {
+                    for (unit_name, content) in units {
+                        units_from_pods.insert(unit_name, (content, pod_terminating));
+                    }
+                }
+                Err(error) => warn!(
+                    "Systemd units could not be generated for pod [{}/{}]. {}",
+                    pod.namespace().unwrap_or_else(|| String::from("default")),
+                    pod.name(),
+                    error
+                ),
+            }
+        }
+
+        units_from_pods
+    }
+
+    /// Returns the units assigned to the given slice including the units in nested slices.
+    ///
+    /// The nested slices themselves are also contained in the result. Nested slices are
+    /// returned before the units they contain.
+    async fn units_in_slice(&self, slice: &str) -> anyhow::Result<Vec<String>> {
+        let systemd_manager = &self.shared.systemd_manager;
+
+        let mut units = Vec::new();
+        let mut slices_to_visit = systemd_manager
+            .slice_content(slice)
+            .await?
+            .into_iter()
+            .rev()
+            .collect::<Vec<_>>();
+
+        while let Some(unit) = slices_to_visit.pop() {
+            if unit.ends_with(".slice") {
+                match systemd_manager.slice_content(&unit).await {
+                    Ok(content) => slices_to_visit.extend(content.into_iter().rev()),
+                    Err(error) => warn!(
+                        "The content of the slice [{}] could not be determined. {}",
+                        unit, error
+                    ),
+                }
+            }
+            units.push(unit);
+        }
+
+        Ok(units)
+    }
+
+    /// Adds the socket and timer units which activate the given service units.
+    ///
+    /// Socket and timer units are not assigned to the slices of the pods, so they are looked up
+    /// by the names of the service units. A trigger unit is placed before its service unit.
+    async fn with_trigger_units(&self, units: Vec<String>) -> Vec<String> {
+        let systemd_manager = &self.shared.systemd_manager;
+
+        let mut units_with_triggers = Vec::new();
+        for unit in units {
+            if let Some(name) = unit.strip_suffix(".service") {
+                for trigger_unit in &[format!("{}.socket", name), format!("{}.timer", name)] {
+                    match systemd_manager.fragment_path(trigger_unit).await {
+                        Ok(Some(_)) => units_with_triggers.push(trigger_unit.to_owned()),
+                        Ok(None) => {}
+                        Err(error) => warn!(
+                            "The file path of the systemd unit [{}] could not be determined. {}",
+                            trigger_unit, error
+                        ),
+                    }
+                }
+            }
+            units_with_triggers.push(unit);
+        }
+
+        units_with_triggers
+    }
+
     /// Returns a list of all pods assigned to the given node.
     async fn assigned_pods(&self, node_name: &str) -> anyhow::Result<ObjectList<KubePod>> {
         let client = &self.shared.client;
@@ -175,71 +299,100 @@ impl StackableProvider {
         })
     }
 
-    /// Creates the systemd unit files for the given pod in memory.
+    /// Creates the systemd unit files for the given pod in memory including the units of the
+    /// init containers, the socket or timer units, and the slice unit file of the pod.
     ///
-    /// A mapping from systemd unit file names to the file content is returned.
-    async fn units_from_pod(&self, kubepod: &KubePod) -> anyhow::Result<HashMap<String, String>> {
+    /// A mapping from systemd unit file names to the file content is returned. The content of
+    /// transient units is [`None`] because they are not started from unit files.
+    async fn units_from_pod(
+        &self,
+        kubepod: &KubePod,
+    ) -> anyhow::Result<HashMap<String, Option<String>>> {
         let systemd_manager = &self.shared.systemd_manager;
 
         let mut units = HashMap::new();
         let pod = Pod::from(kubepod.to_owned());
         let pod_state = self.initialize_pod_state(&pod).await?;
 
-        for container in pod.containers() {
-            let unit = SystemDUnit::new(
-                systemd_manager.is_user_mode(),
-                &pod_state,
-                &self.shared.kubeconfig_path,
-                &pod,
-                &container,
-            )?;
-            units.insert(unit.get_name(), unit.get_unit_file_content());
+        let slice = SystemDUnit::new_pod_slice(&pod, pod_state.unit_name_max_length);
+        units.insert(slice.get_name(), Some(slice.get_unit_file_content()));
+
+        let transient = SystemDUnit::is_transient(&pod)?;
+
+        for (container_key, unit) in SystemDUnit::new_all(
+            systemd_manager.is_user_mode(),
+            &pod_state,
+            &self.shared.kubeconfig_path,
+            &pod,
+        )? {
+            if let Some(container) = pod.containers().iter().find(|container| {
+                container_key.is_app() && container.name() == container_key.name()
+            }) {
+                if let Some(trigger) = SystemDUnit::new_trigger(&pod, container, &unit)? {
+                    units.insert(trigger.get_name(), Some(trigger.get_unit_file_content()));
+                }
+            }
+
+            let content = if transient {
+                None
+            } else {
+                Some(unit.get_unit_file_content())
+            };
+            units.insert(unit.get_name(), content);
         }
 
         Ok(units)
     }
+}
 
-    /// Returns the content of the given systemd unit file.
-    async fn unit_file_content(&self, unit_name: &str) -> anyhow::Result<Option<String>> {
-        let systemd_manager = &self.shared.systemd_manager;
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::provider::systemdmanager::systemdunit::UNIT_NAME_MAX;
+    use crate::provider::test::TestPod;
 
-        let file_path_result = systemd_manager
-            .fragment_path(unit_name)
-            .await
-            .with_context(|| {
-                format!(
-                    "The file path of the unit [{}] could not be determined.",
-                    unit_name
-                )
-            });
-
-        match file_path_result {
-            Ok(Some(file_path)) => {
-                let file_content = read_to_string(&file_path)
-                    .await
-                    .with_context(|| format!("The file [{}] could not be read.", file_path))?;
-                Ok(Some(file_content))
-            }
-            Ok(None) => Ok(None),
-            Err(error) => Err(error),
-        }
+    /// Returns the slices which systemd creates implicitly as parents of the given slice, i.e.
+    /// one slice for every prefix of the name which ends before a dash.
+    fn implicit_parent_slices(slice: &str) -> Vec<String> {
+        let name = slice.strip_suffix(".slice").unwrap_or(slice);
+        name.match_indices('-')
+            .map(|(index, _)| format!("{}.slice", &name[..index]))
+            .collect()
     }
 
-    /// Stops, disables and removes the given systemd unit.
-    async fn remove_unit(&self, unit_name: &str) {
-        let systemd_manager = &self.shared.systemd_manager;
+    #[test]
+    fn pods_in_the_same_namespace_should_not_share_a_nested_slice() {
+        let slices = ["kafka-0", "kafka-1"]
+            .iter()
+            .map(|pod_name| {
+                let pod = format!(
+                    "
+                    apiVersion: v1
+                    kind: Pod
+                    metadata:
+                      name: {}
+                      namespace: kafka-brokers
+                    spec:
+                      containers:
+                        - name: kafka
+                    ",
+                    pod_name
+                )
+                .parse::<TestPod>()
+                .unwrap();
+                SystemDUnit::pod_slice_name(&pod, UNIT_NAME_MAX)
+            })
+            .collect::<Vec<_>>();
 
-        if let Err(error) = systemd_manager.stop(unit_name).await {
-            warn!("{}", error);
-        }
-        if let Err(error) = systemd_manager.disable(unit_name).await {
-            warn!("{}", error);
-        }
-        if let Ok(Some(file_path)) = systemd_manager.fragment_path(unit_name).await {
-            debug!("Removing file [{}].", file_path);
-            if let Err(error) = remove_file(file_path).await {
-                warn!("{}", error);
-            }
+        assert_ne!(slices[0], slices[1]);
+
+        // The cleanup only considers the content of the stackable slice, so a parent slice of
+        // the pod slices below it would be removed together with the units of all its pods.
+        for slice in &slices {
+            assert_eq!(
+                vec![String::from("system.slice"), String::from(STACKABLE_SLICE)],
+                implicit_parent_slices(slice)
+            );
         }
     }
 }
@@ -0,0 +1,240 @@
+//! DNS settings of pods
+//!
+//! Services use the resolver of the node unless the pod specifies a `dnsConfig` or the
+//! `dnsPolicy` `None`. In this case a `resolv.conf` is written to the service directory and
+//! mounted over `/etc/resolv.conf` in the mount namespace of the service units, see
+//! [`SystemDUnit::new`].
+//!
+//! The settings are merged like in Kubernetes: With the policy `None` only the `dnsConfig` is
+//! used, otherwise it is merged into the `resolv.conf` of the node. The node resolver takes the
+//! place of the cluster DNS because the services run in the host network.
+//!
+//! [`SystemDUnit::new`]: crate::provider::systemdmanager::systemdunit::SystemDUnit::new
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use kubelet::pod::Pod;
+
+/// Name of the generated resolver configuration in the service directory
+pub const RESOLV_CONF_FILE_NAME: &str = "resolv.conf";
+
+/// Resolver configuration of the node
+const NODE_RESOLV_CONF: &str = "/etc/resolv.conf";
+
+/// Maximum number of name servers which are used by the resolver, see `MAXNS` in glibc
+const MAX_NAMESERVERS: usize = 3;
+
+/// Settings of a `resolv.conf` file
+#[derive(Debug, Default, Eq, PartialEq)]
+struct ResolvConf {
+    nameservers: Vec<String>,
+    searches: Vec<String>,
+    options: Vec<(String, Option<String>)>,
+}
+
+impl ResolvConf {
+    /// Parses the `nameserver`, `search`, `domain`, and `options` lines of the given content.
+    ///
+    /// Other lines and comments are ignored.
+    fn parse(content: &str) -> Self {
+        let mut resolv_conf = ResolvConf::default();
+
+        for line in content.lines() {
+            let mut fields = line.split_whitespace();
+            match fields.next() {
+                Some("nameserver") => resolv_conf.nameservers.extend(fields.map(String::from)),
+                // The last search or domain line takes precedence in the resolver.
+                Some("search") | Some("domain") => {
+                    resolv_conf.searches = fields.map(String::from).collect()
+                }
+                Some("options") => {
+                    resolv_conf
+                        .options
+                        .extend(fields.map(|option| match option.split_once(':') {
+                            Some((name, value)) => (String::from(name), Some(String::from(value))),
+                            None => (String::from(option), None),
+                        }))
+                }
+                _ => {}
+            }
+        }
+
+        resolv_conf
+    }
+
+    /// Merges the DNS config of the given pod into these settings like the Kubelet does it.
+    fn merge(&mut self, pod: &Pod) {
+        let dns_config = match pod
+            .as_kube_pod()
+            .spec
+            .as_ref()
+            .and_then(|spec| spec.dns_config.as_ref())
+        {
+            Some(dns_config) => dns_config,
+            None => return,
+        };
+
+        for nameserver in dns_config.nameservers.iter().flatten() {
+            if !self.nameservers.contains(nameserver) {
+                self.nameservers.push(nameserver.to_owned());
+            }
+        }
+        self.nameservers.truncate(MAX_NAMESERVERS);
+
+        for search in dns_config.searches.iter().flatten() {
+            if !self.searches.contains(search) {
+                self.searches.push(search.to_owned());
+            }
+        }
+
+        for option in dns_config.options.iter().flatten() {
+            if let Some(name) = &option.name {
+                self.options
+                    .retain(|(existing_name, _)| existing_name != name);
+                self.options
+                    .push((name.to_owned(), option.value.to_owned()));
+            }
+        }
+    }
+
+    fn to_file_content(&self) -> String {
+        let mut lines = self
+            .nameservers
+            .iter()
+            .map(|nameserver| format!("nameserver {}", nameserver))
+            .collect::<Vec<_>>();
+
+        if !self.searches.is_empty() {
+            lines.push(format!("search {}", self.searches.join(" ")));
+        }
+
+        if !self.options.is_empty() {
+            let options = self
+                .options
+                .iter()
+                .map(|(name, value)| match value {
+                    Some(value) => format!("{}:{}", name, value),
+                    None => name.to_owned(),
+                })
+                .collect::<Vec<_>>();
+            lines.push(format!("options {}", options.join(" ")));
+        }
+
+        lines
+            .into_iter()
+            .map(|line| format!("{}\n", line))
+            .collect()
+    }
+}
+
+/// Checks if the given pod overrides the DNS settings of the node.
+pub fn has_dns_override(pod: &Pod) -> bool {
+    pod.as_kube_pod().spec.as_ref().map_or(false, |spec| {
+        spec.dns_config.is_some() || spec.dns_policy.as_deref() == Some("None")
+    })
+}
+
+/// Returns the content of the `resolv.conf` for the services of the given pod which is derived
+/// from the given `resolv.conf` of the node.
+fn resolv_conf_content(pod: &Pod, node_resolv_conf: &str) -> String {
+    let dns_policy_none = pod
+        .as_kube_pod()
+        .spec
+        .as_ref()
+        .and_then(|spec| spec.dns_policy.as_deref())
+        == Some("None");
+
+    let mut resolv_conf = if dns_policy_none {
+        ResolvConf::default()
+    } else {
+        ResolvConf::parse(node_resolv_conf)
+    };
+    resolv_conf.merge(pod);
+
+    resolv_conf.to_file_content()
+}
+
+/// Writes the `resolv.conf` for the services of the given pod into the given directory if the
+/// pod overrides the DNS settings of the node.
+///
+/// A missing `resolv.conf` on the node is treated like an empty one.
+pub fn write_resolv_conf(pod: &Pod, directory: &Path) -> io::Result<()> {
+    if !has_dns_override(pod) {
+        return Ok(());
+    }
+
+    let node_resolv_conf = match fs::read_to_string(NODE_RESOLV_CONF) {
+        Ok(content) => content,
+        Err(error) if error.kind() == io::ErrorKind::NotFound => String::new(),
+        Err(error) => return Err(error),
+    };
+
+    fs::write(
+        directory.join(RESOLV_CONF_FILE_NAME),
+        resolv_conf_content(pod, &node_resolv_conf),
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::provider::test::TestPod;
+    use indoc::indoc;
+    use rstest::rstest;
+
+    const NODE_RESOLV_CONF_CONTENT: &str = indoc! {"
+        # Generated by NetworkManager
+        search example.com
+        nameserver 10.0.0.2
+        nameserver 10.0.0.3
+        options edns0 ndots:1
+    "};
+
+    #[rstest]
+    #[case::without_dns_config("ClusterFirst", "null", false, "")]
+    #[case::merged_with_node(
+        "ClusterFirst",
+        "{nameservers: [10.0.0.3, 10.0.0.4, 10.0.0.5], searches: [svc.example.com], options: [{name: ndots, value: '5'}, {name: rotate}]}",
+        true,
+        indoc! {"
+            nameserver 10.0.0.2
+            nameserver 10.0.0.3
+            nameserver 10.0.0.4
+            search example.com svc.example.com
+            options edns0 ndots:5 rotate
+        "}
+    )]
+    #[case::dns_policy_none("None", "{nameservers: [1.1.1.1]}", true, "nameserver 1.1.1.1\n")]
+    fn resolv_conf_should_be_derived_from_dns_config(
+        #[case] dns_policy: &str,
+        #[case] dns_config: &str,
+        #[case] expected_override: bool,
+        #[case] expected_content: &str,
+    ) {
+        let pod = format!(
+            "
+            apiVersion: v1
+            kind: Pod
+            metadata:
+              name: test
+            spec:
+              containers:
+                - name: test-container
+              dnsPolicy: {}
+              dnsConfig: {}
+            ",
+            dns_policy, dns_config
+        )
+        .parse::<TestPod>()
+        .unwrap();
+
+        assert_eq!(expected_override, has_dns_override(&pod));
+        if expected_override {
+            assert_eq!(
+                expected_content,
+                resolv_conf_content(&pod, NODE_RESOLV_CONF_CONTENT)
+            );
+        }
+    }
+}
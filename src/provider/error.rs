@@ -5,6 +5,7 @@ use thiserror::Error;
 use crate::provider::repository::package::Package;
 use reqwest::Url;
 use std::ffi::OsString;
+use std::path::PathBuf;
 
 #[derive(Error, Debug)]
 pub enum StackableError {
@@ -18,6 +19,43 @@ pub enum StackableError {
     RepositoryConversionError,
     #[error("Invalid content in pod object: {msg}")]
     PodValidationError { msg: String },
+    #[error(
+        "Only one container is supported in the PodSpec but the pod [{pod}] contains {count}."
+    )]
+    UnsupportedContainerCount { pod: String, count: usize },
+    #[error("The container of the pod [{pod}] has no image. An image like [kafka:2.7] is required which denotes the package.")]
+    MissingImage { pod: String },
+    #[error("The image of the pod [{pod}] is malformed: {msg}")]
+    MalformedImage { pod: String, msg: String },
+    #[error("The image [{image}] of the pod [{pod}] has no tag. The image tag is required because it denotes the version of the package.")]
+    MissingImageTag { pod: String, image: String },
+    #[error("The value [{value}] of the annotation [{annotation}] of the pod [{pod}] is invalid. A package like [kafka:2.7] is required.")]
+    MalformedPackageAnnotation {
+        pod: String,
+        annotation: String,
+        value: String,
+    },
+    #[error("The product [{product}] of the pod [{pod}] is not allowed on this node. The allowed products are {allowed_products:?}.")]
+    ProductNotAllowed {
+        pod: String,
+        product: String,
+        allowed_products: Vec<String>,
+    },
+    #[error("The package {package} is already installed with {installed} stripped path components but the pod [{pod}] requests {requested}. All pods which use the same package must strip the same number of path components.")]
+    StripComponentsConflict {
+        pod: String,
+        package: String,
+        installed: usize,
+        requested: usize,
+    },
+    #[error("The pod [{pod}] is not accepted because the node [{node}] is in maintenance mode.")]
+    NodeInMaintenance { pod: String, node: String },
+    #[error("The sysctl [{sysctl}] of the pod [{pod}] is not allowed on this node. The allowed sysctls are {allowed_sysctls:?}.")]
+    SysctlNotAllowed {
+        pod: String,
+        sysctl: String,
+        allowed_sysctls: Vec<String>,
+    },
     #[error("Kubernetes reported error: {source}")]
     KubeError {
         #[from]
@@ -35,10 +73,28 @@ pub enum StackableError {
     TemplateError(#[from] TemplateError),
     #[error("A required CRD has not been registered: {missing_crds:?}")]
     CrdMissing { missing_crds: Vec<String> },
+    #[error("The request to [{url}] timed out")]
+    RepositoryTimeout { url: Url },
+    #[error("The package {package} could not be downloaded within {retries} retries")]
+    DownloadRetriesExhausted { package: Package, retries: u32 },
     #[error("Package {package} not found in repository")]
     PackageNotFound { package: Package },
+    #[error("The repository [{repository}] required by the pod is not registered")]
+    RepositoryNotFound { repository: String },
+    #[error(
+        "Package {package} is not provided by the repository [{repository}] required by the pod"
+    )]
+    PackageNotInRepository {
+        package: Package,
+        repository: String,
+    },
     #[error("{msg}")]
     RuntimeError { msg: String },
+    #[error("Could not create a connection to the systemd {bus} bus: {source}")]
+    DbusConnectionError {
+        bus: &'static str,
+        source: zbus::Error,
+    },
     #[error("Unable to parse data for {target} from non-UTF8 String: {original:?}")]
     DirectoryParseError { target: String, original: OsString },
     #[error("An error ocurred trying to write Config Map {config_map} to file {target_file}")]
@@ -46,10 +102,14 @@ pub enum StackableError {
         target_file: String,
         config_map: String,
     },
+    #[error("The host path {path:?} {msg}")]
+    HostPathError { path: PathBuf, msg: String },
     #[error(
         "The following config maps were specified in a pod but not found: {missing_config_maps:?}"
     )]
     MissingConfigMapsError { missing_config_maps: Vec<String> },
+    #[error("The secret [{path}] could not be resolved: {msg}")]
+    SecretResolutionError { path: String, msg: String },
     #[error("Object is missing key: {key}")]
     MissingObjectKey { key: &'static str },
 }
@@ -2,9 +2,13 @@
 
 use std::str::FromStr;
 
+use k8s_openapi::api::core::v1::ResourceRequirements;
 use kubelet::pod::Pod;
 use strum::{Display, EnumString, EnumVariantNames};
 
+use crate::provider::error::StackableError;
+use crate::provider::error::StackableError::PodValidationError;
+
 /// Restart policy for all containers within the pod.
 #[derive(Clone, Debug, Display, EnumString, EnumVariantNames, Eq, PartialEq)]
 pub enum RestartPolicy {
@@ -29,6 +33,100 @@ pub fn restart_policy(pod: &Pod) -> RestartPolicy {
         .unwrap_or_default()
 }
 
+/// Quality of service class of a pod
+///
+/// The class is derived from the resource requests and limits of the
+/// containers as described in the
+/// [Kubernetes documentation](https://kubernetes.io/docs/tasks/configure-pod-container/quality-service-pod/).
+#[derive(Clone, Debug, Display, Eq, PartialEq)]
+pub enum QosClass {
+    /// All containers have memory and CPU limits and the requests, if
+    /// specified, are equal to the limits.
+    Guaranteed,
+    /// At least one container has a memory or CPU request or limit but
+    /// the criteria for `Guaranteed` are not met.
+    Burstable,
+    /// No container has memory or CPU requests or limits.
+    BestEffort,
+}
+
+/// Returns the quality of service class of the pod.
+pub fn qos_class(pod: &Pod) -> QosClass {
+    const COMPUTE_RESOURCES: &[&str] = &["cpu", "memory"];
+
+    let resources = pod
+        .as_kube_pod()
+        .spec
+        .iter()
+        .flat_map(|spec| &spec.containers)
+        .map(|container| container.resources.to_owned().unwrap_or_default())
+        .collect::<Vec<_>>();
+
+    let has_compute_resources = |resources: &ResourceRequirements| {
+        COMPUTE_RESOURCES.iter().any(|resource| {
+            resources
+                .limits
+                .iter()
+                .chain(resources.requests.iter())
+                .any(|quantities| quantities.contains_key(*resource))
+        })
+    };
+
+    let is_guaranteed = |resources: &ResourceRequirements| {
+        COMPUTE_RESOURCES.iter().all(|resource| {
+            let limit = resources
+                .limits
+                .as_ref()
+                .and_then(|limits| limits.get(*resource));
+            let request = resources
+                .requests
+                .as_ref()
+                .and_then(|requests| requests.get(*resource));
+            limit.is_some() && (request.is_none() || request == limit)
+        })
+    };
+
+    if !resources.iter().any(has_compute_resources) {
+        QosClass::BestEffort
+    } else if resources.iter().all(is_guaranteed) {
+        QosClass::Guaranteed
+    } else {
+        QosClass::Burstable
+    }
+}
+
+/// Returns the value of the annotation with the given key or `None` if
+/// the pod is not annotated with this key.
+pub fn annotation<'a>(pod: &'a Pod, key: &str) -> Option<&'a str> {
+    pod.as_kube_pod()
+        .metadata
+        .annotations
+        .as_ref()
+        .and_then(|annotations| annotations.get(key))
+        .map(String::as_str)
+}
+
+/// Returns the value of the annotation with the given key as boolean or
+/// `None` if the pod is not annotated with this key.
+///
+/// An error is returned if the value is neither `true` nor `false`.
+pub fn boolean_annotation(pod: &Pod, key: &str) -> Result<Option<bool>, StackableError> {
+    annotation(pod, key)
+        .map(|value| match value {
+            "true" => Ok(true),
+            "false" => Ok(false),
+            _ => Err(PodValidationError {
+                msg: format!(
+                    "The validation of the pod [{}] failed. The value [{}] of the annotation [{}] must be either true or false.",
+                    pod.name(),
+                    value,
+                    key
+                ),
+            }),
+        })
+        .transpose()
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -67,4 +165,98 @@ mod test {
     ) {
         assert_eq!(expected_restart_policy, restart_policy(&pod));
     }
+
+    #[rstest]
+    #[case::best_effort(
+        "
+            apiVersion: v1
+            kind: Pod
+            metadata:
+              name: test
+            spec:
+              containers:
+                - name: test-container
+        ",
+        QosClass::BestEffort
+    )]
+    #[case::burstable(
+        "
+            apiVersion: v1
+            kind: Pod
+            metadata:
+              name: test
+            spec:
+              containers:
+                - name: test-container
+                  resources:
+                    requests:
+                      memory: 1Gi
+        ",
+        QosClass::Burstable
+    )]
+    #[case::guaranteed(
+        "
+            apiVersion: v1
+            kind: Pod
+            metadata:
+              name: test
+            spec:
+              containers:
+                - name: test-container
+                  resources:
+                    limits:
+                      cpu: 500m
+                      memory: 1Gi
+                    requests:
+                      memory: 1Gi
+        ",
+        QosClass::Guaranteed
+    )]
+    fn should_return_qos_class(#[case] pod: TestPod, #[case] expected_qos_class: QosClass) {
+        assert_eq!(expected_qos_class, qos_class(&pod));
+    }
+
+    #[test]
+    fn should_return_annotation() {
+        let pod = "
+            apiVersion: v1
+            kind: Pod
+            metadata:
+              name: test
+              annotations:
+                key: value
+            spec:
+              containers:
+                - name: test-container
+        "
+        .parse::<TestPod>()
+        .unwrap();
+
+        assert_eq!(Some("value"), annotation(&pod, "key"));
+        assert_eq!(None, annotation(&pod, "other-key"));
+    }
+
+    #[test]
+    fn should_return_boolean_annotation() {
+        let pod = r#"
+            apiVersion: v1
+            kind: Pod
+            metadata:
+              name: test
+              annotations:
+                enabled: "true"
+                disabled: "false"
+                invalid: "yes"
+            spec:
+              containers:
+                - name: test-container
+        "#
+        .parse::<TestPod>()
+        .unwrap();
+
+        assert_eq!(Some(true), boolean_annotation(&pod, "enabled").unwrap());
+        assert_eq!(Some(false), boolean_annotation(&pod, "disabled").unwrap());
+        assert_eq!(None, boolean_annotation(&pod, "other-key").unwrap());
+        assert!(boolean_annotation(&pod, "invalid").is_err());
+    }
 }
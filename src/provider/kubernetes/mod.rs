@@ -1,4 +1,5 @@
 //! Utility functions for Kubernetes
 
 pub mod accessor;
+pub mod node;
 pub mod status;
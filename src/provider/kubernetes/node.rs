@@ -0,0 +1,210 @@
+//! Functions for patching the node
+
+use k8s_openapi::api::core::v1::{Node, Taint};
+use kube::api::{Patch, PatchParams};
+use kube::{Api, Client};
+use log::{debug, info, warn};
+use serde_json::json;
+use tokio::time::{self, Duration};
+
+/// Number of attempts to find the registered node
+const NODE_LOOKUP_ATTEMPTS: u32 = 10;
+
+/// Delay between the attempts to find the registered node
+const NODE_LOOKUP_INTERVAL: Duration = Duration::from_secs(3);
+
+/// Sets the pod CIDRs of the node for dual-stack networking.
+///
+/// The node builder of the Krustlet only supports a single pod CIDR, so the node is registered
+/// without one and the CIDRs are patched afterwards. The node is looked up repeatedly because
+/// it is registered after the provider built it. Kubernetes does not allow to change the pod
+/// CIDRs once they are set, so existing ones which differ are only reported.
+pub async fn patch_pod_cidrs(client: Client, node_name: String, pod_cidrs: Vec<String>) {
+    let api: Api<Node> = Api::all(client);
+
+    for _ in 0..NODE_LOOKUP_ATTEMPTS {
+        time::sleep(NODE_LOOKUP_INTERVAL).await;
+
+        let node = match api.get(&node_name).await {
+            Ok(node) => node,
+            Err(error) => {
+                debug!(
+                    "Node [{}] not found yet, retrying to set the pod CIDRs: {}",
+                    node_name, error
+                );
+                continue;
+            }
+        };
+
+        let current_pod_cidrs = node
+            .spec
+            .and_then(|spec| spec.pod_cidrs)
+            .unwrap_or_default();
+
+        if current_pod_cidrs == pod_cidrs {
+            debug!("Pod CIDRs of node [{}] are already set", node_name);
+        } else if !current_pod_cidrs.is_empty() {
+            warn!(
+                "Pod CIDRs of node [{}] cannot be changed from {:?} to {:?}. \
+                Delete the node to register it with the new pod CIDRs.",
+                node_name, current_pod_cidrs, pod_cidrs
+            );
+        } else {
+            let patch = json!({
+                "spec": {
+                    "podCIDR": pod_cidrs[0],
+                    "podCIDRs": pod_cidrs,
+                }
+            });
+            match api
+                .patch(&node_name, &PatchParams::default(), &Patch::Merge(patch))
+                .await
+            {
+                Ok(_) => info!("Pod CIDRs of node [{}] set to {:?}", node_name, pod_cidrs),
+                Err(error) => warn!(
+                    "Pod CIDRs of node [{}] could not be set: {}",
+                    node_name, error
+                ),
+            }
+        }
+        return;
+    }
+
+    warn!(
+        "Node [{}] was not found, so the pod CIDRs {:?} could not be set",
+        node_name, pod_cidrs
+    );
+}
+
+/// Marks the node as unschedulable or schedulable like `kubectl cordon` and `kubectl uncordon`.
+pub async fn set_unschedulable(
+    client: Client,
+    node_name: &str,
+    unschedulable: bool,
+) -> Result<(), kube::Error> {
+    let api: Api<Node> = Api::all(client);
+    let patch = json!({
+        "spec": {
+            "unschedulable": unschedulable,
+        }
+    });
+    api.patch(node_name, &PatchParams::default(), &Patch::Merge(patch))
+        .await?;
+    info!(
+        "Node [{}] marked as {}",
+        node_name,
+        if unschedulable {
+            "unschedulable"
+        } else {
+            "schedulable"
+        }
+    );
+    Ok(())
+}
+
+/// Number of attempts to update the taints of the node
+const TAINT_UPDATE_ATTEMPTS: u32 = 5;
+
+/// Adds a taint with the given key and the effect `NoSchedule` to the node if the node is
+/// registered and the taint is not set yet.
+pub async fn add_taint(client: Client, node_name: &str, key: &str) {
+    update_taints(client, node_name, key, |taints| {
+        if taints.iter().any(|taint| taint.key == key) {
+            false
+        } else {
+            taints.push(Taint {
+                key: String::from(key),
+                effect: String::from("NoSchedule"),
+                ..Default::default()
+            });
+            true
+        }
+    })
+    .await
+}
+
+/// Removes the taints with the given key from the node if the node is registered.
+pub async fn remove_taint(client: Client, node_name: &str, key: &str) {
+    update_taints(client, node_name, key, |taints| {
+        let taint_count = taints.len();
+        taints.retain(|taint| taint.key != key);
+        taints.len() != taint_count
+    })
+    .await
+}
+
+/// Updates the taints of the node with the given function which returns whether the taints were
+/// changed.
+///
+/// The taints are replaced as a whole, so the patch contains the resource version of the node
+/// and is retried if the node was modified concurrently. Errors are only logged.
+async fn update_taints<F>(client: Client, node_name: &str, key: &str, update: F)
+where
+    F: Fn(&mut Vec<Taint>) -> bool,
+{
+    let api: Api<Node> = Api::all(client);
+
+    for attempt in 1..=TAINT_UPDATE_ATTEMPTS {
+        let node = match api.get(node_name).await {
+            Ok(node) => node,
+            Err(kube::Error::Api(error)) if error.code == 404 => {
+                debug!(
+                    "Node [{}] is not registered yet, so the taint [{}] is not updated",
+                    node_name, key
+                );
+                return;
+            }
+            Err(error) => {
+                debug!(
+                    "Node [{}] could not be retrieved to update the taint [{}] (attempt {} of {}): {}",
+                    node_name, key, attempt, TAINT_UPDATE_ATTEMPTS, error
+                );
+                time::sleep(NODE_LOOKUP_INTERVAL).await;
+                continue;
+            }
+        };
+
+        let mut taints = node.spec.and_then(|spec| spec.taints).unwrap_or_default();
+        if !update(&mut taints) {
+            debug!(
+                "Taint [{}] of node [{}] is already up to date",
+                key, node_name
+            );
+            return;
+        }
+
+        let patch = json!({
+            "metadata": {
+                "resourceVersion": node.metadata.resource_version,
+            },
+            "spec": {
+                "taints": taints,
+            }
+        });
+        match api
+            .patch(node_name, &PatchParams::default(), &Patch::Merge(patch))
+            .await
+        {
+            Ok(_) => {
+                info!("Taint [{}] of node [{}] updated", key, node_name);
+                return;
+            }
+            Err(kube::Error::Api(error)) if error.code == 409 => debug!(
+                "Node [{}] was modified concurrently, retrying to update the taint [{}]",
+                node_name, key
+            ),
+            Err(error) => {
+                debug!(
+                    "Taint [{}] of node [{}] could not be updated (attempt {} of {}): {}",
+                    key, node_name, attempt, TAINT_UPDATE_ATTEMPTS, error
+                );
+                time::sleep(NODE_LOOKUP_INTERVAL).await;
+            }
+        }
+    }
+
+    warn!(
+        "Taint [{}] of node [{}] could not be updated after {} attempts",
+        key, node_name, TAINT_UPDATE_ATTEMPTS
+    );
+}
@@ -1,7 +1,14 @@
 //! Functions for patching the pod status
+//!
+//! Failed patches are retried a few times with an increasing delay, so that a short outage of
+//! the API server does not leave a stale status until the next change.
+
+use std::fmt;
+use std::future::Future;
 
 use anyhow::anyhow;
-use k8s_openapi::api::core::v1::Pod as KubePod;
+use k8s_openapi::api::core::v1::{ContainerStateTerminated, ContainerStateWaiting, Pod as KubePod};
+use k8s_openapi::apimachinery::pkg::apis::meta::v1::Time;
 use kube::{
     api::{Patch, PatchParams},
     Api, Client,
@@ -10,7 +17,72 @@ use kubelet::{
     container::{ContainerKey, Status},
     pod::Pod,
 };
-use log::warn;
+use log::{debug, warn};
+use tokio::time::{self, Duration};
+
+use crate::provider::systemdmanager::service::SystemdService;
+
+/// Number of retries of a failed patch
+const PATCH_RETRIES: u32 = 3;
+
+/// Delay before the first retry of a failed patch which is doubled with every further retry
+const PATCH_RETRY_DELAY: Duration = Duration::from_millis(500);
+
+/// Runs the given patch and retries it with an exponential backoff if it fails.
+///
+/// The error of the last attempt is returned if all retries fail.
+async fn with_retries<F, Fut, T, E>(description: &str, mut patch: F) -> Result<T, E>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, E>>,
+    E: fmt::Display,
+{
+    let mut attempt = 0;
+    let mut delay = PATCH_RETRY_DELAY;
+
+    loop {
+        match patch().await {
+            Err(error) if attempt < PATCH_RETRIES => {
+                attempt += 1;
+                debug!(
+                    "Patching {} failed, retrying in {} ms (retry {} of {}): {}",
+                    description,
+                    delay.as_millis(),
+                    attempt,
+                    PATCH_RETRIES,
+                    error
+                );
+                time::sleep(delay).await;
+                delay *= 2;
+            }
+            result => return result,
+        }
+    }
+}
+
+/// Applies the given JSON patch to the status of the pod.
+async fn apply_status_patch(
+    client: &Client,
+    pod: &Pod,
+    patch: json_patch::Patch,
+) -> anyhow::Result<()> {
+    let api: Api<KubePod> = Api::namespaced(client.clone(), pod.namespace());
+
+    let api = &api;
+    let patch = &patch;
+
+    with_retries(&format!("status of pod [{}]", pod.name()), || async move {
+        api.patch_status(
+            pod.name(),
+            &PatchParams::default(),
+            &Patch::<()>::Json(patch.clone()),
+        )
+        .await
+    })
+    .await?;
+
+    Ok(())
+}
 
 /// Patches the pod status with the given container status.
 ///
@@ -23,8 +95,12 @@ pub async fn patch_container_status(
 ) {
     let api: Api<KubePod> = Api::namespaced(client.clone(), pod.namespace());
 
-    if let Err(error) =
-        kubelet::container::patch_container_status(&api, pod, container_key, status).await
+    let api = &api;
+
+    if let Err(error) = with_retries(&format!("status of container [{}]", container_key), || {
+        kubelet::container::patch_container_status(api, pod, container_key, status)
+    })
+    .await
     {
         warn!(
             "Status of container [{}] in pod [{}] could not be patched. {}",
@@ -35,15 +111,38 @@ pub async fn patch_container_status(
     }
 }
 
-/// Patches the restart count of a container.
-pub async fn patch_restart_count(
-    client: &Client,
-    pod: &Pod,
-    container_key: &ContainerKey,
-    restart_count: u32,
-) -> anyhow::Result<()> {
+/// Sets the phase of the given pod to `Failed` with the given reason and message.
+///
+/// This is used for pods which cannot be processed at all, so that the reason is shown by
+/// `kubectl describe pod`. If the patching fails then a warning is logged.
+pub async fn patch_pod_failed(client: &Client, pod: &Pod, reason: &str, message: &str) {
     let api: Api<KubePod> = Api::namespaced(client.clone(), pod.namespace());
 
+    let api = &api;
+    let patch = &serde_json::json!({
+        "status": {
+            "phase": "Failed",
+            "reason": reason,
+            "message": message,
+        }
+    });
+
+    if let Err(error) = with_retries(&format!("status of pod [{}]", pod.name()), || async move {
+        api.patch_status(pod.name(), &PatchParams::default(), &Patch::Merge(patch))
+            .await
+    })
+    .await
+    {
+        warn!(
+            "Status of pod [{}] could not be patched. {}",
+            pod.name(),
+            error
+        );
+    }
+}
+
+/// Returns the JSON pointer to the status of the given container in the pod.
+fn container_status_path(pod: &Pod, container_key: &ContainerKey) -> anyhow::Result<String> {
     let index = pod
         .container_status_index(container_key)
         .ok_or_else(|| anyhow!("Container not found"))?;
@@ -54,19 +153,165 @@ pub async fn patch_restart_count(
         "container"
     };
 
+    Ok(format!("/status/{}Statuses/{}", container_type, index))
+}
+
+/// Patches the restart count of a container.
+pub async fn patch_restart_count(
+    client: &Client,
+    pod: &Pod,
+    container_key: &ContainerKey,
+    restart_count: u32,
+) -> anyhow::Result<()> {
+    let container_status_path = container_status_path(pod, container_key)?;
+
     let patch = json_patch::Patch(vec![json_patch::PatchOperation::Replace(
         json_patch::ReplaceOperation {
-            path: format!("/status/{}Statuses/{}/restartCount", container_type, index),
+            path: format!("{}/restartCount", container_status_path),
             value: restart_count.into(),
         },
     )]);
 
-    api.patch_status(
-        pod.name(),
-        &PatchParams::default(),
-        &Patch::<()>::Json(patch),
-    )
-    .await?;
+    apply_status_patch(client, pod, patch).await
+}
 
-    Ok(())
+/// Patches the terminated state of a container with the exit status of its service.
+///
+/// [`Status::Terminated`] only distinguishes between success and failure, so the terminated
+/// state must already be set with [`patch_container_status`]. It is then replaced with the exit
+/// code, the signal, and the timestamps of the last run of the service.
+pub async fn patch_exit_status(
+    client: &Client,
+    pod: &Pod,
+    container_key: &ContainerKey,
+    reason: &str,
+    systemd_service: &SystemdService,
+) -> anyhow::Result<()> {
+    let exit_status = systemd_service.exit_status().await?;
+
+    let message = match exit_status.signal {
+        Some(signal) => format!("Killed by signal {}", signal),
+        None => format!("Exited with code {}", exit_status.exit_code),
+    };
+
+    let terminated = ContainerStateTerminated {
+        exit_code: exit_status.exit_code,
+        signal: exit_status.signal,
+        reason: Some(String::from(reason)),
+        message: Some(message),
+        started_at: exit_status.started_at.map(Time),
+        finished_at: exit_status.finished_at.map(Time),
+        container_id: None,
+    };
+
+    let container_status_path = container_status_path(pod, container_key)?;
+
+    let patch = json_patch::Patch(vec![json_patch::PatchOperation::Replace(
+        json_patch::ReplaceOperation {
+            path: format!("{}/state/terminated", container_status_path),
+            value: serde_json::to_value(terminated)?,
+        },
+    )]);
+
+    apply_status_patch(client, pod, patch).await
+}
+
+/// Patches the state of a container to waiting with the given reason and message.
+///
+/// [`Status::Waiting`] does not contain a reason, so the state is replaced directly. This is
+/// used to report services which wait for a restart, e.g. with the reason `CrashLoopBackOff`.
+pub async fn patch_waiting_state(
+    client: &Client,
+    pod: &Pod,
+    container_key: &ContainerKey,
+    reason: &str,
+    message: &str,
+) -> anyhow::Result<()> {
+    let waiting = ContainerStateWaiting {
+        reason: Some(String::from(reason)),
+        message: Some(String::from(message)),
+    };
+
+    let container_status_path = container_status_path(pod, container_key)?;
+
+    let patch = json_patch::Patch(vec![
+        json_patch::PatchOperation::Replace(json_patch::ReplaceOperation {
+            path: format!("{}/state", container_status_path),
+            value: serde_json::json!({ "waiting": waiting }),
+        }),
+        json_patch::PatchOperation::Replace(json_patch::ReplaceOperation {
+            path: format!("{}/ready", container_status_path),
+            value: false.into(),
+        }),
+    ]);
+
+    apply_status_patch(client, pod, patch).await
+}
+
+/// Patches the image and the image ID of a container with the installed package.
+///
+/// The image is the package in the form `<product>:<version>`. The image ID additionally
+/// contains the SHA256 hash of the archive from which the package was installed, so that it
+/// can be verified which build of a version is running.
+pub async fn patch_image(
+    client: &Client,
+    pod: &Pod,
+    container_key: &ContainerKey,
+    image: &str,
+    image_id: &str,
+) -> anyhow::Result<()> {
+    let container_status_path = container_status_path(pod, container_key)?;
+
+    let patch = json_patch::Patch(vec![
+        json_patch::PatchOperation::Replace(json_patch::ReplaceOperation {
+            path: format!("{}/image", container_status_path),
+            value: image.into(),
+        }),
+        json_patch::PatchOperation::Replace(json_patch::ReplaceOperation {
+            path: format!("{}/imageID", container_status_path),
+            value: image_id.into(),
+        }),
+    ]);
+
+    apply_status_patch(client, pod, patch).await
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::cell::Cell;
+
+    #[tokio::test(start_paused = true)]
+    async fn failed_patches_should_be_retried() {
+        let attempts = Cell::new(0);
+
+        let result = with_retries("test", || {
+            attempts.set(attempts.get() + 1);
+            let attempt = attempts.get();
+            async move {
+                if attempt < 3 {
+                    Err("unavailable")
+                } else {
+                    Ok(attempt)
+                }
+            }
+        })
+        .await;
+
+        assert_eq!(Ok(3), result);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn retries_should_be_bounded() {
+        let attempts = Cell::new(0);
+
+        let result: Result<(), _> = with_retries("test", || {
+            attempts.set(attempts.get() + 1);
+            async { Err("unavailable") }
+        })
+        .await;
+
+        assert_eq!(Err("unavailable"), result);
+        assert_eq!(PATCH_RETRIES + 1, attempts.get());
+    }
 }
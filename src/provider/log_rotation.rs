@@ -0,0 +1,357 @@
+//! Size-based rotation of log files
+//!
+//! Services log to the journal, but some products additionally write their own log files into
+//! the log directory of the service, see [`PodState::get_service_log_directory`]. These files
+//! are rotated by the agent if the pod is annotated with [`LOG_MAX_SIZE_ANNOTATION`].
+//!
+//! Files are rotated by copying and truncating them like `copytruncate` in logrotate, because
+//! the services keep their log files open and would otherwise continue to write to the rotated
+//! file. The rotated files get the suffixes `.1`, `.2`, … with `.1` being the most recent one.
+//!
+//! [`PodState::get_service_log_directory`]: crate::provider::states::pod::PodState::get_service_log_directory
+use std::fs::{self, OpenOptions};
+use std::io;
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+
+use kubelet::pod::Pod;
+use log::debug;
+use nix::libc::O_NOFOLLOW;
+
+use crate::provider::error::StackableError;
+use crate::provider::error::StackableError::PodValidationError;
+use crate::provider::kubernetes::accessor::annotation;
+
+/// Annotation with the size from which on log files are rotated, e.g. `100Mi`
+pub const LOG_MAX_SIZE_ANNOTATION: &str = "stackable.tech/log-max-size";
+
+/// Annotation with the number of rotated files which are kept per log file
+pub const LOG_MAX_FILES_ANNOTATION: &str = "stackable.tech/log-max-files";
+
+/// Number of rotated files which are kept if [`LOG_MAX_FILES_ANNOTATION`] is not set
+const DEFAULT_MAX_FILES: usize = 5;
+
+/// Rotation settings of a pod
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct LogRotation {
+    /// Size in bytes from which on a log file is rotated
+    pub max_size: u64,
+    /// Number of rotated files which are kept per log file
+    pub max_files: usize,
+}
+
+impl LogRotation {
+    /// Reads the rotation settings from the annotations of the given pod.
+    ///
+    /// [`None`] is returned if the pod does not enable the rotation with
+    /// [`LOG_MAX_SIZE_ANNOTATION`].
+    pub fn from_pod(pod: &Pod) -> Result<Option<LogRotation>, StackableError> {
+        let max_size = match annotation(pod, LOG_MAX_SIZE_ANNOTATION) {
+            Some(value) => parse_size(value).ok_or_else(|| PodValidationError {
+                msg: format!(
+                    "The validation of the pod [{}] failed. The value [{}] of the annotation [{}] must be a positive size like 1000, 512Ki, 100Mi, or 1Gi.",
+                    pod.name(),
+                    value,
+                    LOG_MAX_SIZE_ANNOTATION
+                ),
+            })?,
+            None => return Ok(None),
+        };
+
+        let max_files = match annotation(pod, LOG_MAX_FILES_ANNOTATION) {
+            Some(value) => value
+                .parse::<usize>()
+                .ok()
+                .filter(|max_files| *max_files >= 1)
+                .ok_or_else(|| PodValidationError {
+                    msg: format!(
+                        "The validation of the pod [{}] failed. The value [{}] of the annotation [{}] must be a positive integer.",
+                        pod.name(),
+                        value,
+                        LOG_MAX_FILES_ANNOTATION
+                    ),
+                })?,
+            None => DEFAULT_MAX_FILES,
+        };
+
+        Ok(Some(LogRotation {
+            max_size,
+            max_files,
+        }))
+    }
+
+    /// Rotates all log files in the given directory and its subdirectories which exceed the
+    /// maximum size and returns them.
+    ///
+    /// Rotated files are not rotated again and symbolic links are skipped. A missing directory
+    /// is ignored because the service may not have written any log files yet.
+    pub fn rotate(&self, log_directory: &Path) -> io::Result<Vec<PathBuf>> {
+        let mut rotated_files = Vec::new();
+
+        let entries = match fs::read_dir(log_directory) {
+            Ok(entries) => entries,
+            Err(error) if error.kind() == io::ErrorKind::NotFound => return Ok(rotated_files),
+            Err(error) => return Err(error),
+        };
+
+        for entry in entries {
+            let entry = entry?;
+            let path = entry.path();
+            let file_type = entry.file_type()?;
+
+            if file_type.is_dir() {
+                rotated_files.extend(self.rotate(&path)?);
+            } else if file_type.is_file()
+                && !is_rotated_file(&path)
+                && entry.metadata()?.len() > self.max_size
+            {
+                self.rotate_file(&path)?;
+                rotated_files.push(path);
+            }
+        }
+
+        Ok(rotated_files)
+    }
+
+    /// Shifts the rotated files of the given log file, copies the log file to the first
+    /// rotated file, and truncates it.
+    ///
+    /// The log directory is writable by the service, so symbolic links are never followed.
+    /// Otherwise a service could make the agent overwrite or truncate arbitrary files. The log
+    /// file is opened only once and copied and truncated through this handle so that it cannot
+    /// be replaced in between. Log files which are symbolic links are rejected and a rotated file
+    /// is only created if no file or symbolic link exists at its path.
+    fn rotate_file(&self, file: &Path) -> io::Result<()> {
+        debug!("Rotating log file {:?}", file);
+
+        let mut log_file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .custom_flags(O_NOFOLLOW)
+            .open(file)?;
+        if !log_file.metadata()?.is_file() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("The log file {:?} is not a regular file.", file),
+            ));
+        }
+
+        let rotated_file = |index: usize| {
+            let mut file_name = file.as_os_str().to_owned();
+            file_name.push(format!(".{}", index));
+            PathBuf::from(file_name)
+        };
+        // Symbolic links are detected even if their target does not exist.
+        let exists = |path: &Path| fs::symlink_metadata(path).is_ok();
+
+        let oldest_file = rotated_file(self.max_files);
+        if exists(&oldest_file) {
+            fs::remove_file(&oldest_file)?;
+        }
+        for index in (1..self.max_files).rev() {
+            let source = rotated_file(index);
+            if exists(&source) {
+                fs::rename(&source, rotated_file(index + 1))?;
+            }
+        }
+
+        let mut first_rotated_file = OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .custom_flags(O_NOFOLLOW)
+            .open(rotated_file(1))?;
+        io::copy(&mut log_file, &mut first_rotated_file)?;
+        log_file.set_len(0)
+    }
+}
+
+/// Parses a size in bytes with an optional binary suffix like `Ki`, `Mi`, or `Gi`.
+///
+/// The suffixes `K`, `M`, and `G` are accepted as well and also denote powers of 1024.
+fn parse_size(value: &str) -> Option<u64> {
+    let digits_end = value
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or_else(|| value.len());
+    let (number, suffix) = value.split_at(digits_end);
+
+    let factor = match suffix {
+        "" => 1,
+        "K" | "Ki" => 1 << 10,
+        "M" | "Mi" => 1 << 20,
+        "G" | "Gi" => 1 << 30,
+        _ => return None,
+    };
+
+    number
+        .parse::<u64>()
+        .ok()
+        .and_then(|number| number.checked_mul(factor))
+        .filter(|size| *size > 0)
+}
+
+/// Checks if the given file is a rotated file, i.e. if its extension is a number.
+fn is_rotated_file(file: &Path) -> bool {
+    file.extension()
+        .and_then(|extension| extension.to_str())
+        .map_or(false, |extension| {
+            extension.chars().all(|c| c.is_ascii_digit())
+        })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::provider::test::TestPod;
+    use rstest::rstest;
+    use std::env;
+    use std::os::unix::fs::symlink;
+    use std::process;
+
+    #[rstest]
+    #[case("1000", Some(1000))]
+    #[case("512Ki", Some(512 * 1024))]
+    #[case("100M", Some(100 * 1024 * 1024))]
+    #[case("1Gi", Some(1024 * 1024 * 1024))]
+    #[case("0", None)]
+    #[case("-1", None)]
+    #[case("100MB", None)]
+    #[case("Mi", None)]
+    fn test_parse_size(#[case] value: &str, #[case] expected_size: Option<u64>) {
+        assert_eq!(expected_size, parse_size(value));
+    }
+
+    #[rstest]
+    #[case::disabled("{}", Ok(None))]
+    #[case::default_max_files(
+        r#"{"stackable.tech/log-max-size": "1Mi"}"#,
+        Ok(Some(LogRotation { max_size: 1024 * 1024, max_files: 5 }))
+    )]
+    #[case::max_files(
+        r#"{"stackable.tech/log-max-size": "1000", "stackable.tech/log-max-files": "2"}"#,
+        Ok(Some(LogRotation { max_size: 1000, max_files: 2 }))
+    )]
+    #[case::invalid_max_files(
+        r#"{"stackable.tech/log-max-size": "1000", "stackable.tech/log-max-files": "0"}"#,
+        Err(())
+    )]
+    fn log_rotation_should_be_read_from_annotations(
+        #[case] annotations: &str,
+        #[case] expected_rotation: Result<Option<LogRotation>, ()>,
+    ) {
+        let pod = format!(
+            "
+            apiVersion: v1
+            kind: Pod
+            metadata:
+              name: test
+              annotations: {}
+            spec:
+              containers:
+                - name: test-container
+            ",
+            annotations
+        )
+        .parse::<TestPod>()
+        .unwrap();
+
+        assert_eq!(
+            expected_rotation,
+            LogRotation::from_pod(&pod).map_err(|_| ())
+        );
+    }
+
+    #[test]
+    fn large_log_files_should_be_rotated() {
+        let log_directory =
+            env::temp_dir().join(format!("stackable-agent-log-rotation-{}", process::id()));
+        fs::create_dir_all(log_directory.join("gc")).unwrap();
+
+        let log_file = log_directory.join("server.log");
+        fs::write(&log_file, "new").unwrap();
+        fs::write(log_directory.join("server.log.1"), "old").unwrap();
+        fs::write(log_directory.join("server.log.2"), "oldest").unwrap();
+        fs::write(log_directory.join("gc").join("gc.log"), "gc").unwrap();
+        fs::write(log_directory.join("small.log"), "s").unwrap();
+
+        let rotation = LogRotation {
+            max_size: 1,
+            max_files: 2,
+        };
+        let mut rotated_files = rotation.rotate(&log_directory).unwrap();
+        rotated_files.sort();
+
+        let content = |file: &str| fs::read_to_string(log_directory.join(file)).unwrap();
+        let result = (
+            rotated_files.to_owned(),
+            content("server.log"),
+            content("server.log.1"),
+            content("server.log.2"),
+            content("gc/gc.log.1"),
+            content("small.log"),
+        );
+        fs::remove_dir_all(&log_directory).unwrap();
+
+        assert_eq!(
+            (
+                vec![log_directory.join("gc").join("gc.log"), log_file],
+                String::new(),
+                String::from("new"),
+                String::from("old"),
+                String::from("gc"),
+                String::from("s"),
+            ),
+            result
+        );
+    }
+
+    #[test]
+    fn symbolic_links_should_not_be_followed() {
+        let test_directory = env::temp_dir().join(format!(
+            "stackable-agent-log-rotation-symlinks-{}",
+            process::id()
+        ));
+        let log_directory = test_directory.join("log");
+        fs::create_dir_all(&log_directory).unwrap();
+
+        let target_file = test_directory.join("target");
+        fs::write(&target_file, "target").unwrap();
+        let linked_log_file = log_directory.join("linked.log");
+        symlink(&target_file, &linked_log_file).unwrap();
+        let log_file = log_directory.join("server.log");
+        fs::write(&log_file, "new").unwrap();
+        symlink(&target_file, log_directory.join("server.log.1")).unwrap();
+
+        let rotation = LogRotation {
+            max_size: 1,
+            max_files: 2,
+        };
+        let rotated_files = rotation.rotate(&log_directory).unwrap();
+        let rotate_linked_log_file_result = rotation.rotate_file(&linked_log_file);
+
+        let content = |file: &str| fs::read_to_string(log_directory.join(file)).unwrap();
+        let result = (
+            rotated_files.to_owned(),
+            rotate_linked_log_file_result.is_err(),
+            fs::read_to_string(&target_file).unwrap(),
+            content("server.log.1"),
+            fs::symlink_metadata(log_directory.join("server.log.2"))
+                .unwrap()
+                .file_type()
+                .is_symlink(),
+            log_directory.join("linked.log.1").exists(),
+        );
+        fs::remove_dir_all(&test_directory).unwrap();
+
+        assert_eq!(
+            (
+                vec![log_file],
+                true,
+                String::from("target"),
+                String::from("new"),
+                true,
+                false
+            ),
+            result
+        );
+    }
+}
@@ -2,11 +2,14 @@ use std::collections::HashMap;
 use std::convert::TryFrom;
 use std::env;
 use std::net::IpAddr;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
+use std::time::Duration;
 
 use anyhow::anyhow;
 use dirs::home_dir;
+use futures_util::future::join_all;
 use k8s_openapi::apiextensions_apiserver::pkg::apis::apiextensions::v1::CustomResourceDefinition;
 use kube::error::ErrorResponse;
 use kube::{Api, Client};
@@ -17,17 +20,32 @@ use kubelet::node::Builder;
 use kubelet::pod::state::prelude::*;
 use kubelet::pod::{Pod, PodKey};
 use kubelet::provider::Provider;
-use log::{debug, error};
-use tokio::{runtime::Runtime, sync::RwLock, task};
+use log::{debug, error, warn};
+use oci_distribution::Reference;
+use tokio::{
+    runtime::Runtime,
+    sync::{RwLock, Semaphore},
+    task,
+};
 
-use crate::config::AgentConfig;
+use crate::config::{AgentConfig, NodeTaint, ReservedResources};
 use crate::provider::error::StackableError;
 use crate::provider::error::StackableError::{
-    CrdMissing, KubeError, MissingObjectKey, PodValidationError,
+    CrdMissing, KubeError, MalformedImage, MalformedPackageAnnotation, MissingImage,
+    MissingImageTag, MissingObjectKey, NodeInMaintenance, ProductNotAllowed, SysctlNotAllowed,
+    UnsupportedContainerCount,
 };
+use crate::provider::kubernetes::accessor::annotation;
+use crate::provider::kubernetes::status::patch_pod_failed;
+use crate::provider::node_resources::NodeResources;
 use crate::provider::repository::package::Package;
+use crate::provider::repository::{RepositoryClient, RequestTimeouts, PACKAGE_ANNOTATION};
+use crate::provider::secrets::SecretsBackend;
+use crate::provider::shutdown::{ShutdownHandle, ShutdownSignal};
 use crate::provider::states::pod::PodState;
 use crate::provider::systemdmanager::manager::SystemdManager;
+use crate::provider::systemdmanager::systemdunit::SystemDUnit;
+use crate::provider::transitions::TransitionLog;
 
 use states::pod::{initializing::Initializing, terminated::Terminated};
 use systemdmanager::journal_reader;
@@ -38,17 +56,68 @@ pub struct StackableProvider {
     parcel_directory: PathBuf,
     config_directory: PathBuf,
     log_directory: PathBuf,
-    pod_cidr: String,
+    node_name: String,
+    pod_cidrs: Vec<String>,
+    node_labels: HashMap<String, String>,
+    node_taints: Vec<NodeTaint>,
+    /// Adds the taints on the architecture, so that only pods for the agent are scheduled
+    arch_taints: bool,
+    default_service_user: Option<String>,
+    /// Journal namespace to which the services log, see [`PodState::log_namespace`]
+    log_namespace: Option<String>,
+    /// Products which may run on this node; all products are allowed if it is empty
+    allowed_products: Vec<String>,
+    /// Kernel parameters which pods may set; a trailing `*` matches all parameters with the
+    /// given prefix
+    allowed_sysctls: Vec<String>,
+    /// Time to wait before units without corresponding pods are removed in the cleanup stage
+    cleanup_grace_period: Duration,
+    /// Resources which are reserved for the operating system
+    system_reserved: ReservedResources,
+    /// Resources which are reserved for the agent
+    kube_reserved: ReservedResources,
+    /// Whether the initial cleanup is completed, see [`NOT_READY_TAINT`]
+    cleanup_completed: AtomicBool,
 }
 
 pub const CRDS: &[&str] = &["repositories.stable.stackable.de"];
 
+/// Label of the node which contains the architecture of the host, e.g. `amd64` or `arm64`
+///
+/// The label `kubernetes.io/arch` is set to [`StackableProvider::ARCH`] for all hosts because
+/// it is also used in the taints, so pods which require a specific architecture must select
+/// this label instead.
+pub const ARCH_LABEL: &str = "stackable.tech/arch";
+
+/// Key of the taint which prevents the scheduling of pods onto the node until the initial
+/// cleanup is completed, see [`StackableProvider::cleanup`]
+pub const NOT_READY_TAINT: &str = "stackable.tech/agent-not-ready";
+
+/// Maximum delay between the retries to reach the API server on startup, so that a node which
+/// was offline for a while still registers soon after the API server becomes reachable
+pub const MAX_API_SERVER_RETRY_DELAY: Duration = Duration::from_secs(300);
+
+/// Returns the delay before the next retry to reach the API server on startup, i.e. the doubled
+/// given delay but at most [`MAX_API_SERVER_RETRY_DELAY`].
+pub fn next_api_server_retry_delay(delay: Duration) -> Duration {
+    delay.saturating_mul(2).min(MAX_API_SERVER_RETRY_DELAY)
+}
+
+mod admin;
 pub mod cleanup;
-mod error;
+mod dns;
+pub mod error;
 pub mod kubernetes;
+mod log_rotation;
+mod node_resources;
 mod repository;
+mod resource_limits;
+mod secrets;
+pub mod shutdown;
 mod states;
 pub mod systemdmanager;
+mod transitions;
+pub mod validation;
 
 mod built_info {
     // The file has been placed there by the build script.
@@ -63,6 +132,38 @@ pub struct ProviderState {
     systemd_manager: Arc<SystemdManager>,
     server_ip_address: IpAddr,
     kubeconfig_path: PathBuf,
+    config_generations: usize,
+    adoption_readiness_timeout: Duration,
+    /// HTTP client for the repositories which is shared by all downloads
+    repository_client: RepositoryClient,
+    /// Directory into which the packages are installed
+    parcel_directory: PathBuf,
+    /// Limits the number of packages which are downloaded or installed at the same time
+    install_permits: Arc<Semaphore>,
+    /// Number of retries of a failed download before the pod fails, 0 retries indefinitely
+    max_download_retries: u32,
+    /// Directories below which hostPath volumes are allowed
+    host_path_allowed: Vec<PathBuf>,
+    /// Interval between the checks of the running services
+    service_check_interval: Duration,
+    /// Keeps the units of failed pods until the pods are deleted
+    keep_failed_services: bool,
+    /// Backend which resolves the secrets referenced in config files
+    secrets_backend: Option<SecretsBackend>,
+    /// Processes pods with more than one container best-effort instead of rejecting them
+    allow_multiple_containers: bool,
+    /// Recent state transitions of the pods
+    transitions: Arc<RwLock<TransitionLog>>,
+    /// Template for the names of the package directories
+    package_directory_template: String,
+    /// Cancels long-running operations when the agent shuts down
+    shutdown_signal: ShutdownSignal,
+    /// Name of the node which runs the pods
+    node_name: String,
+    /// Rejects new pods while the running services are kept, see [`admin`]
+    maintenance: Arc<AtomicBool>,
+    /// Maximum length of the unit names, see [`SystemDUnit::pod_slice_name`]
+    unit_name_max_length: usize,
 }
 
 /// Contains handles for running pods.
@@ -105,6 +206,11 @@ impl PodHandleMap {
             .insert(container_key.to_owned(), container_handle.to_owned());
     }
 
+    /// Returns an iterator over all pod keys and their pod handles.
+    pub fn iter(&self) -> impl Iterator<Item = (&PodKey, &PodHandle)> {
+        self.handles.iter()
+    }
+
     /// Returns a reference to the container handle with the given pod and
     /// container key or [`None`] if not found.
     pub fn container_handle(
@@ -130,6 +236,18 @@ pub struct ContainerHandle {
 
     /// Proxy for the systemd service
     pub systemd_service: SystemdService,
+
+    /// Contains the name of the socket or timer unit which activates the service unit if the
+    /// service is not started directly.
+    pub trigger_unit: Option<String>,
+
+    /// Contains the invocation ID of the previous run of the service unit if it was restarted
+    /// while the agent monitored it.
+    pub previous_invocation_id: Option<String>,
+
+    /// Contains the service unit if it is started as transient unit with `systemd-run` instead
+    /// of a unit file.
+    pub transient_unit: Option<SystemDUnit>,
 }
 
 impl StackableProvider {
@@ -138,7 +256,14 @@ impl StackableProvider {
         agent_config: &AgentConfig,
         max_pods: u16,
     ) -> Result<Self, StackableError> {
-        let systemd_manager = Arc::new(SystemdManager::new(agent_config.session, max_pods).await?);
+        let systemd_manager = Arc::new(
+            SystemdManager::new(
+                agent_config.session,
+                agent_config.unit_directory().to_owned(),
+                max_pods,
+            )
+            .await?,
+        );
 
         let kubeconfig_path = find_kubeconfig().ok_or_else(|| StackableError::RuntimeError {
             msg: String::from(
@@ -153,6 +278,37 @@ impl StackableProvider {
             systemd_manager,
             server_ip_address: agent_config.server_ip_address,
             kubeconfig_path,
+            config_generations: agent_config.config_generations,
+            adoption_readiness_timeout: agent_config.adoption_readiness_timeout,
+            repository_client: RepositoryClient::new(
+                RequestTimeouts {
+                    connect: Some(agent_config.repository_connect_timeout)
+                        .filter(|timeout| !timeout.is_zero()),
+                    request: Some(agent_config.repository_request_timeout)
+                        .filter(|timeout| !timeout.is_zero()),
+                },
+                agent_config.repository_pool_idle_timeout,
+            )?,
+            parcel_directory: agent_config.parcel_directory.to_owned(),
+            install_permits: Arc::new(Semaphore::new(agent_config.max_concurrent_installs)),
+            max_download_retries: agent_config.max_download_retries,
+            host_path_allowed: agent_config.host_path_allowed.to_owned(),
+            service_check_interval: agent_config.service_check_interval,
+            keep_failed_services: agent_config.keep_failed_services,
+            allow_multiple_containers: agent_config.allow_multiple_containers,
+            transitions: Default::default(),
+            package_directory_template: agent_config.package_directory_template.to_owned(),
+            shutdown_signal: Default::default(),
+            node_name: agent_config.hostname.to_owned(),
+            maintenance: Default::default(),
+            unit_name_max_length: agent_config.unit_name_max_length,
+            secrets_backend: agent_config
+                .secrets_backend_url
+                .to_owned()
+                .map(|url| {
+                    SecretsBackend::new(url, agent_config.secrets_backend_token_file.to_owned())
+                })
+                .transpose()?,
         };
 
         let provider = StackableProvider {
@@ -160,9 +316,26 @@ impl StackableProvider {
             parcel_directory: agent_config.parcel_directory.to_owned(),
             config_directory: agent_config.config_directory.to_owned(),
             log_directory: agent_config.log_directory.to_owned(),
-            pod_cidr: agent_config.pod_cidr.to_owned(),
+            node_name: agent_config.hostname.to_owned(),
+            pod_cidrs: agent_config.pod_cidrs.to_owned(),
+            node_labels: agent_config.labels.to_owned(),
+            node_taints: agent_config.taints.to_owned(),
+            arch_taints: !agent_config.no_arch_taints,
+            default_service_user: agent_config.default_service_user.to_owned(),
+            log_namespace: agent_config.log_namespace.to_owned(),
+            allowed_products: agent_config.allowed_products.to_owned(),
+            allowed_sysctls: agent_config.allowed_sysctls.to_owned(),
+            cleanup_grace_period: agent_config.cleanup_grace_period,
+            system_reserved: agent_config.system_reserved,
+            kube_reserved: agent_config.kube_reserved,
+            cleanup_completed: AtomicBool::new(false),
         };
-        let missing_crds = provider.check_crds().await?;
+        let missing_crds = provider
+            .check_crds_with_retries(
+                agent_config.api_server_retries,
+                agent_config.api_server_retry_interval,
+            )
+            .await?;
         return if missing_crds.is_empty() {
             debug!("All required CRDS present!");
             Ok(provider)
@@ -172,39 +345,210 @@ impl StackableProvider {
         };
     }
 
-    fn get_package(pod: &Pod) -> Result<Package, StackableError> {
-        if let Some((container, [])) = pod.containers().split_first() {
-            container
-                .image()
-                .and_then(|maybe_ref| maybe_ref.ok_or_else(|| anyhow!("Image is required.")))
-                .and_then(Package::try_from)
-                .map_err(|err| PodValidationError {
-                    msg: format!(
-                        "Unable to get package reference from pod [{}]: {}",
-                        &pod.name(),
-                        &err
-                    ),
+    /// Starts the local admin interface on the given socket path in the background, see
+    /// [`admin`].
+    pub fn serve_admin_socket(&self, socket_path: &Path) {
+        let socket_path = socket_path.to_owned();
+        let provider_state = self.shared.clone();
+        task::spawn(async move {
+            if let Err(error) = admin::serve(socket_path, provider_state).await {
+                error!("The admin interface could not be started: {:?}", error);
+            }
+        });
+    }
+
+    /// Returns a handle to cancel the downloads and to stop the managed units when the agent
+    /// shuts down, see [`shutdown`].
+    pub fn shutdown_handle(&self) -> ShutdownHandle {
+        ShutdownHandle {
+            provider_state: self.shared.clone(),
+        }
+    }
+
+    /// Returns the package of the given pod.
+    ///
+    /// The package is taken from the annotation [`PACKAGE_ANNOTATION`] if it is set, otherwise it
+    /// is derived from the image of the container, e.g. `kafka:2.7`. In the first case, the image
+    /// is ignored and can be a placeholder.
+    ///
+    /// Pods with more than one container are rejected unless `allow_multiple_containers` is set.
+    /// In this case, the package is derived from the first container and the images of the other
+    /// containers are ignored.
+    fn get_package(pod: &Pod, allow_multiple_containers: bool) -> Result<Package, StackableError> {
+        let containers = pod.containers();
+        let container = match containers.as_slice() {
+            [container] => container,
+            [container, ..] if allow_multiple_containers => container,
+            _ => {
+                return Err(UnsupportedContainerCount {
+                    pod: pod.name().to_string(),
+                    count: containers.len(),
                 })
+            }
+        };
+
+        if let Some(value) = annotation(pod, PACKAGE_ANNOTATION) {
+            return Reference::try_from(value)
+                .ok()
+                .and_then(|reference| Package::try_from(reference).ok())
+                .ok_or_else(|| MalformedPackageAnnotation {
+                    pod: pod.name().to_string(),
+                    annotation: String::from(PACKAGE_ANNOTATION),
+                    value: String::from(value),
+                });
+        }
+
+        let reference = container
+            .image()
+            .map_err(|error| MalformedImage {
+                pod: pod.name().to_string(),
+                msg: error.to_string(),
+            })?
+            .ok_or_else(|| MissingImage {
+                pod: pod.name().to_string(),
+            })?;
+
+        let image = reference.whole();
+        Package::try_from(reference).map_err(|_| MissingImageTag {
+            pod: pod.name().to_string(),
+            image,
+        })
+    }
+
+    /// Checks if systemd units were already created for the given pod on this node.
+    ///
+    /// The slice of the pod is created together with its units and removed after them, so it
+    /// indicates that the pod was already admitted. If this cannot be determined then the pod
+    /// is considered new.
+    async fn has_units(&self, pod: &Pod) -> bool {
+        let slice = SystemDUnit::pod_slice_name(pod, self.shared.unit_name_max_length);
+        match self.shared.systemd_manager.fragment_path(&slice).await {
+            Ok(fragment_path) => fragment_path.is_some(),
+            Err(error) => {
+                debug!(
+                    "It could not be determined if the slice [{}] of pod [{}] exists: {}",
+                    slice,
+                    pod.name(),
+                    error
+                );
+                false
+            }
+        }
+    }
+
+    /// Returns the given package of the pod if its product is in the given list of allowed
+    /// products or if the list is empty, otherwise [`ProductNotAllowed`] is returned.
+    fn check_product_allowed(
+        pod: &Pod,
+        package: Package,
+        allowed_products: &[String],
+    ) -> Result<Package, StackableError> {
+        if allowed_products.is_empty() || allowed_products.contains(&package.product) {
+            Ok(package)
         } else {
-            Err(PodValidationError {
-                msg: String::from("Only one container is supported in the PodSpec."),
+            Err(ProductNotAllowed {
+                pod: pod.name().to_string(),
+                product: package.product,
+                allowed_products: allowed_products.to_vec(),
             })
         }
     }
 
+    /// Checks if all sysctls of the given pod are in the given list of allowed sysctls,
+    /// otherwise [`SysctlNotAllowed`] is returned for the first one which is not allowed.
+    ///
+    /// An allowed sysctl which ends with `*` matches all sysctls with the given prefix.
+    fn check_sysctls_allowed(pod: &Pod, allowed_sysctls: &[String]) -> Result<(), StackableError> {
+        let is_allowed = |name: &str| {
+            allowed_sysctls
+                .iter()
+                .any(|allowed| match allowed.strip_suffix('*') {
+                    Some(prefix) => name.starts_with(prefix),
+                    None => name == allowed,
+                })
+        };
+
+        match SystemDUnit::get_sysctls(pod)
+            .iter()
+            .find(|sysctl| !is_allowed(&sysctl.name))
+        {
+            Some(sysctl) => Err(SysctlNotAllowed {
+                pod: pod.name().to_string(),
+                sysctl: sysctl.name.to_owned(),
+                allowed_sysctls: allowed_sysctls.to_vec(),
+            }),
+            None => Ok(()),
+        }
+    }
+
+    /// Returns the reason which is shown in the pod status if the package of a pod cannot be
+    /// determined or is not allowed, or [`None`] if the given error is not related to the
+    /// package reference.
+    fn package_error_reason(error: &StackableError) -> Option<&'static str> {
+        match error {
+            UnsupportedContainerCount { .. } => Some("UnsupportedContainerCount"),
+            MissingImage { .. } => Some("MissingImage"),
+            MalformedImage { .. } => Some("MalformedImage"),
+            MissingImageTag { .. } => Some("MissingImageTag"),
+            MalformedPackageAnnotation { .. } => Some("MalformedPackageAnnotation"),
+            ProductNotAllowed { .. } => Some("ProductNotAllowed"),
+            _ => None,
+        }
+    }
+
+    /// Checks the CRDs like [`StackableProvider::check_crds`] but retries with an exponential
+    /// backoff if the API server is not reachable, e.g. because it is not started yet while the
+    /// node boots.
+    ///
+    /// Errors returned by the API server itself are not retried.
+    async fn check_crds_with_retries(
+        &self,
+        retries: u32,
+        interval: Duration,
+    ) -> Result<Vec<String>, StackableError> {
+        let mut attempt = 0;
+        let mut delay = interval;
+
+        loop {
+            match self.check_crds().await {
+                Err(KubeError { source })
+                    if attempt < retries && !matches!(source, kube::Error::Api(_)) =>
+                {
+                    attempt += 1;
+                    warn!(
+                        "The API server is not reachable, retrying in {} seconds (retry {} of {}): {}",
+                        delay.as_secs(),
+                        attempt,
+                        retries,
+                        source
+                    );
+                    tokio::time::sleep(delay).await;
+                    delay = next_api_server_retry_delay(delay);
+                }
+                result => return result,
+            }
+        }
+    }
+
     async fn check_crds(&self) -> Result<Vec<String>, StackableError> {
-        let mut missing_crds = vec![];
         let crds: Api<CustomResourceDefinition> = Api::all(self.shared.client.clone());
 
-        // Check all CRDS
-        for crd in CRDS.iter() {
+        // Check all CRDS concurrently
+        let results = join_all(CRDS.iter().map(|crd| {
             debug!("Checking if CRD [{}] is registered", crd);
-            match crds.get(crd).await {
+            let crds = &crds;
+            async move { (*crd, crds.get(crd).await) }
+        }))
+        .await;
+
+        let mut missing_crds = vec![];
+        for (crd, result) in results {
+            match result {
                 Err(kube::error::Error::Api(ErrorResponse { reason, .. }))
                     if reason == "NotFound" =>
                 {
                     error!("Missing required CRD: [{}]", crd);
-                    missing_crds.push(String::from(*crd))
+                    missing_crds.push(String::from(crd))
                 }
                 Err(e) => {
                     error!(
@@ -229,6 +573,20 @@ fn find_kubeconfig() -> Option<PathBuf> {
     env_var.or_else(default_path).filter(|path| path.exists())
 }
 
+/// Converts the given architecture as returned by [`env::consts::ARCH`] into the name which is
+/// used in Kubernetes and for container images, e.g. `x86_64` into `amd64`.
+///
+/// Architectures without a Kubernetes name are returned unchanged.
+fn kubernetes_architecture(rust_architecture: &str) -> &str {
+    match rust_architecture {
+        "x86_64" => "amd64",
+        "x86" => "386",
+        "aarch64" => "arm64",
+        "powerpc64" if cfg!(target_endian = "little") => "ppc64le",
+        architecture => architecture,
+    }
+}
+
 #[async_trait::async_trait]
 impl Provider for StackableProvider {
     type ProviderState = ProviderState;
@@ -244,10 +602,52 @@ impl Provider for StackableProvider {
 
     async fn node(&self, builder: &mut Builder) -> anyhow::Result<()> {
         builder.set_architecture(Self::ARCH);
-        builder.set_pod_cidr(&self.pod_cidr);
+        match self.pod_cidrs.as_slice() {
+            [] => builder.set_pod_cidr(""),
+            [pod_cidr] => builder.set_pod_cidr(pod_cidr),
+            pod_cidrs => {
+                // The builder only supports a single pod CIDR, so dual-stack CIDRs are patched
+                // after the node is registered.
+                builder.set_pod_cidr("");
+                tokio::spawn(kubernetes::node::patch_pod_cidrs(
+                    self.shared.client.clone(),
+                    self.node_name.to_owned(),
+                    pod_cidrs.to_vec(),
+                ));
+            }
+        }
         builder.set_kubelet_version(built_info::PKG_VERSION);
-        builder.add_taint("NoSchedule", "kubernetes.io/arch", Self::ARCH);
-        builder.add_taint("NoExecute", "kubernetes.io/arch", Self::ARCH);
+        if self.arch_taints {
+            builder.add_taint("NoSchedule", "kubernetes.io/arch", Self::ARCH);
+            builder.add_taint("NoExecute", "kubernetes.io/arch", Self::ARCH);
+        }
+        // The node is usually registered after the cleanup, in which case the taint was
+        // already removed, otherwise it is removed when the cleanup is completed.
+        if !self.cleanup_completed.load(Ordering::SeqCst) {
+            builder.add_taint("NoSchedule", NOT_READY_TAINT, "");
+        }
+        builder.add_label(ARCH_LABEL, kubernetes_architecture(env::consts::ARCH));
+        for (key, value) in &self.node_labels {
+            builder.add_label(key, value);
+        }
+        for taint in &self.node_taints {
+            builder.add_taint(&taint.effect, &taint.key, &taint.value);
+        }
+        match NodeResources::capacity() {
+            Ok(capacity) => {
+                let allocatable =
+                    capacity.allocatable(&[&self.system_reserved, &self.kube_reserved]);
+                builder.add_capacity("cpu", &capacity.cpu_quantity());
+                builder.add_capacity("memory", &capacity.memory_quantity());
+                builder.add_allocatable("cpu", &allocatable.cpu_quantity());
+                builder.add_allocatable("memory", &allocatable.memory_quantity());
+            }
+            Err(error) => warn!(
+                "The capacity of the node could not be determined, so the default values of \
+                the Krustlet are reported. {}",
+                error
+            ),
+        }
         Ok(())
     }
 
@@ -264,12 +664,76 @@ impl Provider for StackableProvider {
                 key: ".metadata.uid",
             }));
         };
+        // Pods which are already running were initialized before the maintenance mode was
+        // enabled, so only new pods are rejected.
+        if self.shared.maintenance.load(Ordering::SeqCst) {
+            let error = NodeInMaintenance {
+                pod: pod.name().to_string(),
+                node: self.node_name.to_owned(),
+            };
+            patch_pod_failed(
+                &self.shared.client,
+                pod,
+                "NodeInMaintenance",
+                &error.to_string(),
+            )
+            .await;
+            return Err(anyhow::Error::new(error));
+        }
+
         let parcel_directory = self.parcel_directory.clone();
         // TODO: make this configurable
         let download_directory = parcel_directory.join("_download");
         let log_directory = self.log_directory.clone();
 
-        let package = Self::get_package(pod)?;
+        // Pods which already have units on this node, e.g. after a restart of the agent, were
+        // admitted before and keep running even if the allowed products or sysctls were
+        // restricted in the meantime.
+        let has_units = self.has_units(pod).await;
+
+        let allow_multiple_containers = self.shared.allow_multiple_containers;
+        let package = match Self::get_package(pod, allow_multiple_containers).and_then(|package| {
+            if has_units {
+                Ok(package)
+            } else {
+                Self::check_product_allowed(pod, package, &self.allowed_products)
+            }
+        }) {
+            Ok(package) => package,
+            Err(error) => {
+                // Without a package the pod cannot run through the stages, so the reason is
+                // patched directly to make it visible with `kubectl describe pod`.
+                if let Some(reason) = Self::package_error_reason(&error) {
+                    patch_pod_failed(&self.shared.client, pod, reason, &error.to_string()).await;
+                }
+                return Err(anyhow::Error::new(error));
+            }
+        };
+
+        if !has_units {
+            if let Err(error) = Self::check_sysctls_allowed(pod, &self.allowed_sysctls) {
+                patch_pod_failed(
+                    &self.shared.client,
+                    pod,
+                    "SysctlNotAllowed",
+                    &error.to_string(),
+                )
+                .await;
+                return Err(anyhow::Error::new(error));
+            }
+        }
+
+        let container_count = pod.containers().len();
+        if container_count > 1 {
+            warn!(
+                "The pod [{}] contains {} containers which are processed best-effort because \
+                multiple containers are allowed. All containers run from the package [{}] and \
+                the images of the containers except the first one are ignored.",
+                pod.name(),
+                container_count,
+                package
+            );
+        }
 
         Ok(PodState {
             parcel_directory,
@@ -277,9 +741,17 @@ impl Provider for StackableProvider {
             log_directory,
             config_directory: self.config_directory.clone(),
             package_download_backoff_strategy: ExponentialBackoffStrategy::default(),
+            download_failures: 0,
             service_name,
             service_uid,
             package,
+            package_directory_template: self.shared.package_directory_template.clone(),
+            default_service_user: self.default_service_user.clone(),
+            log_namespace: self.log_namespace.clone(),
+            node_name: self.node_name.clone(),
+            node_ip: self.shared.server_ip_address,
+            unit_name_max_length: self.shared.unit_name_max_length,
+            secret_files: Vec::new(),
         })
     }
 
@@ -314,10 +786,34 @@ impl Provider for StackableProvider {
         })?;
 
         if let Ok(invocation_id) = container_handle.systemd_service.invocation_id().await {
+            // Units of previous versions of the agent do not set a syslog identifier, so the
+            // entries are only filtered by the invocation ID in this case.
+            let syslog_identifier = container_handle
+                .systemd_service
+                .syslog_identifier()
+                .await
+                .unwrap_or_else(|error| {
+                    debug!("{}", error);
+                    None
+                });
+            let log_namespace = container_handle
+                .systemd_service
+                .log_namespace()
+                .await
+                .unwrap_or_else(|error| {
+                    debug!("{}", error);
+                    None
+                });
+
             task::spawn_blocking(move || {
                 let result = Runtime::new()
                     .unwrap()
-                    .block_on(journal_reader::send_messages(&mut sender, &invocation_id));
+                    .block_on(journal_reader::send_messages(
+                        &mut sender,
+                        &invocation_id,
+                        syslog_identifier.as_deref(),
+                        log_namespace.as_deref(),
+                    ));
 
                 if let Err(error) = result {
                     match error.downcast_ref::<SendError>() {
@@ -341,12 +837,75 @@ impl Provider for StackableProvider {
 #[cfg(test)]
 pub mod test {
     use super::*;
+    use crate::provider::repository::package::DEFAULT_DIRECTORY_TEMPLATE;
+    use crate::provider::systemdmanager::systemdunit::UNIT_NAME_MAX;
     use rstest::rstest;
+    use std::net::Ipv4Addr;
     use std::ops::Deref;
     use std::str::FromStr;
 
     #[test]
-    fn try_to_get_package_from_complete_configuration() {
+    fn api_server_retry_delays_should_be_capped() {
+        let mut delay = Duration::from_secs(5);
+        let mut delays = Vec::new();
+        for _ in 0..8 {
+            delays.push(delay.as_secs());
+            delay = next_api_server_retry_delay(delay);
+        }
+
+        assert_eq!(vec![5, 10, 20, 40, 80, 160, 300, 300], delays);
+        assert_eq!(
+            MAX_API_SERVER_RETRY_DELAY,
+            next_api_server_retry_delay(Duration::MAX)
+        );
+    }
+
+    #[rstest]
+    #[case::all_products_allowed(&[], true)]
+    #[case::product_allowed(&["zookeeper", "kafka"], true)]
+    #[case::product_not_allowed(&["zookeeper"], false)]
+    fn products_should_be_checked_against_the_allowed_products(
+        #[case] allowed_products: &[&str],
+        #[case] expected_allowed: bool,
+    ) {
+        let pod = "
+            apiVersion: v1
+            kind: Pod
+            metadata:
+              name: test
+            spec:
+              containers:
+              - name: kafka
+                image: kafka:2.7
+        "
+        .parse::<TestPod>()
+        .unwrap();
+        let package = StackableProvider::get_package(&pod, false).unwrap();
+        let allowed_products = allowed_products
+            .iter()
+            .map(|product| String::from(*product))
+            .collect::<Vec<_>>();
+
+        let result = StackableProvider::check_product_allowed(&pod, package, &allowed_products);
+
+        assert_eq!(expected_allowed, result.is_ok());
+        if let Err(error) = result {
+            assert_eq!(
+                "The product [kafka] of the pod [test] is not allowed on this node. The allowed products are [\"zookeeper\"].",
+                error.to_string()
+            );
+        }
+    }
+
+    #[rstest]
+    #[case::no_sysctls_allowed(&[], Some("vm.max_map_count"))]
+    #[case::sysctls_allowed(&["vm.max_map_count", "net.ipv4.*", "net.core.somaxconn"], None)]
+    #[case::sysctl_not_allowed(&["net.ipv4.*"], Some("vm.max_map_count"))]
+    #[case::prefix_does_not_match(&["net.ipv4.*", "vm.max_map_count"], Some("net.core.somaxconn"))]
+    fn sysctls_should_be_checked_against_the_allowed_sysctls(
+        #[case] allowed_sysctls: &[&str],
+        #[case] expected_rejected_sysctl: Option<&str>,
+    ) {
         let pod = "
             apiVersion: v1
             kind: Pod
@@ -356,11 +915,78 @@ pub mod test {
               containers:
               - name: kafka
                 image: kafka:2.7
+              securityContext:
+                sysctls:
+                - name: vm.max_map_count
+                  value: '262144'
+                - name: net.ipv4.tcp_keepalive_time
+                  value: '600'
+                - name: net.core.somaxconn
+                  value: '1024'
         "
         .parse::<TestPod>()
         .unwrap();
+        let allowed_sysctls = allowed_sysctls
+            .iter()
+            .map(|sysctl| String::from(*sysctl))
+            .collect::<Vec<_>>();
+
+        let result = StackableProvider::check_sysctls_allowed(&pod, &allowed_sysctls);
+
+        match expected_rejected_sysctl {
+            None => assert!(result.is_ok()),
+            Some(expected_sysctl) => assert!(matches!(
+                result,
+                Err(SysctlNotAllowed { sysctl, .. }) if sysctl == expected_sysctl
+            )),
+        }
+    }
+
+    #[rstest]
+    #[case("x86_64", "amd64")]
+    #[case("aarch64", "arm64")]
+    #[case("arm", "arm")]
+    #[case("s390x", "s390x")]
+    #[case("riscv64", "riscv64")]
+    fn architectures_should_be_named_like_in_kubernetes(
+        #[case] rust_architecture: &str,
+        #[case] expected_architecture: &str,
+    ) {
+        assert_eq!(
+            expected_architecture,
+            kubernetes_architecture(rust_architecture)
+        );
+    }
 
-        let maybe_package = StackableProvider::get_package(&pod);
+    #[rstest]
+    #[case::from_image(
+        "
+            apiVersion: v1
+            kind: Pod
+            metadata:
+              name: test
+            spec:
+              containers:
+              - name: kafka
+                image: kafka:2.7
+        "
+    )]
+    #[case::from_annotation(
+        "
+            apiVersion: v1
+            kind: Pod
+            metadata:
+              name: test
+              annotations:
+                stackable.tech/package: kafka:2.7
+            spec:
+              containers:
+              - name: kafka
+                image: placeholder
+        "
+    )]
+    fn try_to_get_package_from_complete_configuration(#[case] pod: TestPod) {
+        let maybe_package = StackableProvider::get_package(&pod, false);
 
         if let Ok(package) = maybe_package {
             assert_eq!("kafka", package.product);
@@ -384,7 +1010,8 @@ pub mod test {
               - name: zookeeper
                 image: zookeeper:3.6.2
         ",
-        "Only one container is supported in the PodSpec."
+        "UnsupportedContainerCount",
+        "Only one container is supported in the PodSpec but the pod [test] contains 2."
     )]
     #[case(
         "
@@ -396,7 +1023,8 @@ pub mod test {
               containers:
               - name: kafka
         ",
-        "Unable to get package reference from pod [test]: Image is required."
+        "MissingImage",
+        "The container of the pod [test] has no image. An image like [kafka:2.7] is required which denotes the package."
     )]
     #[case(
         "
@@ -409,21 +1037,66 @@ pub mod test {
               - name: kafka
                 image: kafka
         ",
-        "Unable to get package reference from pod [test]: Tag is required."
+        "MissingImageTag",
+        "The image [kafka] of the pod [test] has no tag. The image tag is required because it denotes the version of the package."
+    )]
+    #[case(
+        "
+            apiVersion: v1
+            kind: Pod
+            metadata:
+              name: test
+              annotations:
+                stackable.tech/package: kafka
+            spec:
+              containers:
+              - name: kafka
+                image: kafka:2.7
+        ",
+        "MalformedPackageAnnotation",
+        "The value [kafka] of the annotation [stackable.tech/package] of the pod [test] is invalid. A package like [kafka:2.7] is required."
     )]
     fn try_to_get_package_from_insufficient_configuration(
         #[case] pod: TestPod,
+        #[case] expected_reason: &str,
         #[case] expected_err: &str,
     ) {
-        let maybe_package = StackableProvider::get_package(&pod);
+        let maybe_package = StackableProvider::get_package(&pod, false);
 
-        if let Err(PodValidationError { msg }) = maybe_package {
-            assert_eq!(expected_err, msg);
+        if let Err(error) = maybe_package {
+            assert_eq!(
+                Some(expected_reason),
+                StackableProvider::package_error_reason(&error)
+            );
+            assert_eq!(expected_err, error.to_string());
         } else {
-            panic!("PodValidationError expected but got {:?}", maybe_package);
+            panic!("Error expected but got {:?}", maybe_package);
         }
     }
 
+    #[test]
+    fn package_should_be_taken_from_first_container_if_multiple_containers_are_allowed() {
+        let pod: TestPod = "
+            apiVersion: v1
+            kind: Pod
+            metadata:
+              name: test
+            spec:
+              containers:
+              - name: kafka
+                image: kafka:2.7
+              - name: zookeeper
+                image: zookeeper:3.6.2
+        "
+        .parse()
+        .unwrap();
+
+        let package = StackableProvider::get_package(&pod, true).unwrap();
+
+        assert_eq!("kafka", package.product);
+        assert_eq!("2.7", package.version);
+    }
+
     /// Encapsulates a [`Pod`] with implementations for [`FromStr`] to
     /// deserialize from YAML and [`Deref`] to dereference into a [`Pod`].
     ///
@@ -454,8 +1127,7 @@ pub mod test {
         type Err = serde_yaml::Error;
 
         fn from_str(s: &str) -> Result<Self, Self::Err> {
-            let kube_pod: k8s_openapi::api::core::v1::Pod = serde_yaml::from_str(s)?;
-            Ok(TestPod(Pod::from(kube_pod)))
+            validation::parse_pod(s).map(TestPod)
         }
     }
 
@@ -466,4 +1138,32 @@ pub mod test {
             &self.0
         }
     }
+
+    /// Returns the state of the given pod with the package `test-1.0.0` in `/run` and the
+    /// default settings of the agent.
+    ///
+    /// Tests which depend on other values can override them with the struct update syntax.
+    pub fn test_pod_state(pod: &Pod) -> PodState {
+        PodState {
+            parcel_directory: PathBuf::from("/run"),
+            download_directory: PathBuf::new(),
+            config_directory: PathBuf::from("/etc"),
+            log_directory: PathBuf::from("/var/log"),
+            package_download_backoff_strategy: ExponentialBackoffStrategy::default(),
+            download_failures: 0,
+            service_name: format!("{}-{}", pod.namespace(), pod.name()),
+            service_uid: String::from("b3ca9d08-b97d-45bc-9da1-7b0156712ef1"),
+            package: Package {
+                product: String::from("test"),
+                version: String::from("1.0.0"),
+            },
+            package_directory_template: String::from(DEFAULT_DIRECTORY_TEMPLATE),
+            default_service_user: None,
+            log_namespace: None,
+            node_name: String::from("node-1"),
+            node_ip: Ipv4Addr::LOCALHOST.into(),
+            unit_name_max_length: UNIT_NAME_MAX,
+            secret_files: Vec::new(),
+        }
+    }
 }
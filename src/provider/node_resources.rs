@@ -0,0 +1,136 @@
+//! Capacity and allocatable resources of the node
+//!
+//! The capacity is read from the node and the resources which are reserved with the options
+//! `system-reserved` and `kube-reserved` are subtracted from it to get the allocatable
+//! resources, like the Kubelet does it. The scheduler only assigns pods to the node as long as
+//! their requests fit into the allocatable resources.
+use std::fs;
+use std::io;
+
+use crate::config::ReservedResources;
+
+/// File which lists the processors of the node
+const CPU_INFO_FILE: &str = "/proc/cpuinfo";
+
+/// File which contains the memory statistics of the node
+const MEM_INFO_FILE: &str = "/proc/meminfo";
+
+/// CPU and memory of the node
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct NodeResources {
+    /// CPU in millicores
+    pub cpu_millis: u64,
+    /// Memory in bytes
+    pub memory_bytes: u64,
+}
+
+impl NodeResources {
+    /// Reads the capacity of the node.
+    pub fn capacity() -> io::Result<NodeResources> {
+        let cpu_info = fs::read_to_string(CPU_INFO_FILE)?;
+        let mem_info = fs::read_to_string(MEM_INFO_FILE)?;
+
+        let memory_bytes = parse_mem_total(&mem_info).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("MemTotal not found in [{}]", MEM_INFO_FILE),
+            )
+        })?;
+
+        Ok(NodeResources {
+            cpu_millis: count_processors(&cpu_info) * 1000,
+            memory_bytes,
+        })
+    }
+
+    /// Returns the resources which remain for pods if the given resources are reserved.
+    pub fn allocatable(&self, reserved: &[&ReservedResources]) -> NodeResources {
+        reserved
+            .iter()
+            .fold(*self, |allocatable, reserved| NodeResources {
+                cpu_millis: allocatable.cpu_millis.saturating_sub(reserved.cpu_millis),
+                memory_bytes: allocatable
+                    .memory_bytes
+                    .saturating_sub(reserved.memory_bytes),
+            })
+    }
+
+    /// Returns the CPU as quantity in millicores, e.g. `3500m`.
+    pub fn cpu_quantity(&self) -> String {
+        format!("{}m", self.cpu_millis)
+    }
+
+    /// Returns the memory as quantity in kibibytes, e.g. `8048576Ki`, like the Kubelet reports
+    /// it.
+    pub fn memory_quantity(&self) -> String {
+        format!("{}Ki", self.memory_bytes / 1024)
+    }
+}
+
+/// Counts the processors in the given content of `/proc/cpuinfo`.
+fn count_processors(cpu_info: &str) -> u64 {
+    cpu_info
+        .lines()
+        .filter(|line| {
+            line.split(':')
+                .next()
+                .map_or(false, |key| key.trim() == "processor")
+        })
+        .count() as u64
+}
+
+/// Returns the total memory in bytes from the given content of `/proc/meminfo`.
+fn parse_mem_total(mem_info: &str) -> Option<u64> {
+    mem_info
+        .lines()
+        .find_map(|line| line.strip_prefix("MemTotal:"))
+        .and_then(|value| value.trim().strip_suffix("kB"))
+        .and_then(|kibibytes| kibibytes.trim().parse::<u64>().ok())
+        .map(|kibibytes| kibibytes * 1024)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use indoc::indoc;
+
+    #[test]
+    fn capacity_should_be_parsed_from_proc_files() {
+        let cpu_info = indoc! {"
+            processor	: 0
+            model name	: Intel(R) Xeon(R) CPU
+
+            processor	: 1
+            model name	: Intel(R) Xeon(R) CPU
+        "};
+        let mem_info = indoc! {"
+            MemTotal:        8048576 kB
+            MemFree:         1048576 kB
+        "};
+
+        assert_eq!(2, count_processors(cpu_info));
+        assert_eq!(Some(8048576 * 1024), parse_mem_total(mem_info));
+        assert_eq!(None, parse_mem_total("MemFree: 1048576 kB"));
+    }
+
+    #[test]
+    fn reserved_resources_should_be_subtracted() {
+        let capacity = NodeResources {
+            cpu_millis: 4000,
+            memory_bytes: 8 << 30,
+        };
+        let system_reserved = ReservedResources {
+            cpu_millis: 500,
+            memory_bytes: 1 << 30,
+        };
+        let kube_reserved = ReservedResources {
+            cpu_millis: 4000,
+            memory_bytes: 256 << 20,
+        };
+
+        let allocatable = capacity.allocatable(&[&system_reserved, &kube_reserved]);
+
+        assert_eq!("0m", allocatable.cpu_quantity());
+        assert_eq!("7077888Ki", allocatable.memory_quantity());
+    }
+}
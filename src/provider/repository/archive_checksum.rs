@@ -0,0 +1,134 @@
+//! Cached checksums of the downloaded archives
+//!
+//! Hashing a large archive takes a while, so the SHA256 hash of a downloaded archive is stored
+//! in a sidecar file next to it, see [`Package::get_archive_checksum_file_name`], and reused as
+//! long as the archive is unchanged. The sidecar also records the size and the modification time
+//! of the archive. If either of them differs or the sidecar is missing or malformed then the
+//! archive is hashed again.
+//!
+//! [`Package::get_archive_checksum_file_name`]: super::package::Package::get_archive_checksum_file_name
+use std::ffi::OsString;
+use std::fs::{self, File, Metadata};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+use log::{debug, warn};
+
+use super::stackablerepository::calculate_sha256;
+
+/// Extension which is appended to the archive to get the name of its sidecar file
+const SIDECAR_EXTENSION: &str = ".sha256";
+
+/// Returns the SHA256 hash of the given archive.
+///
+/// The hash is taken from the sidecar file if it matches the archive, otherwise it is
+/// calculated and stored in the sidecar file for later calls.
+pub fn sha256(archive: &Path) -> io::Result<String> {
+    let metadata = fs::metadata(archive)?;
+
+    if let Some(hash) = read_sidecar(archive, &metadata) {
+        debug!("Using the cached checksum of [{:?}]", archive);
+        return Ok(hash);
+    }
+
+    let hash = calculate_sha256(&mut File::open(archive)?)?;
+    store(archive, &hash);
+    Ok(hash)
+}
+
+/// Stores the given hash of the archive in its sidecar file.
+///
+/// The hash is only cached, so a sidecar which cannot be written is logged but not treated as
+/// error.
+pub fn store(archive: &Path, hash: &str) {
+    let result = fs::metadata(archive)
+        .and_then(|metadata| fingerprint(&metadata))
+        .and_then(|fingerprint| {
+            fs::write(
+                sidecar_path(archive),
+                format!("{} {}\n", hash.to_lowercase(), fingerprint),
+            )
+        });
+
+    if let Err(error) = result {
+        warn!(
+            "The checksum of [{:?}] could not be cached, so it is calculated again next time: {}",
+            archive, error
+        );
+    }
+}
+
+/// Returns the path of the sidecar file of the given archive.
+fn sidecar_path(archive: &Path) -> PathBuf {
+    let mut file_name = archive.file_name().map(OsString::from).unwrap_or_default();
+    file_name.push(SIDECAR_EXTENSION);
+    archive.with_file_name(file_name)
+}
+
+/// Returns the size and the modification time in nanoseconds of the given archive in the form
+/// `<size> <mtime>`.
+fn fingerprint(metadata: &Metadata) -> io::Result<String> {
+    let modified = metadata
+        .modified()?
+        .duration_since(UNIX_EPOCH)
+        .map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))?;
+    Ok(format!("{} {}", metadata.len(), modified.as_nanos()))
+}
+
+/// Reads the hash from the sidecar file of the given archive if the sidecar matches the
+/// archive.
+fn read_sidecar(archive: &Path, metadata: &Metadata) -> Option<String> {
+    let content = fs::read_to_string(sidecar_path(archive)).ok()?;
+    let (hash, recorded_fingerprint) = content.trim().split_once(' ')?;
+
+    if fingerprint(metadata).ok()? == recorded_fingerprint {
+        Some(String::from(hash))
+    } else {
+        debug!(
+            "The archive [{:?}] changed since its checksum was cached",
+            archive
+        );
+        None
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::env;
+    use std::process;
+
+    #[test]
+    fn checksum_should_be_cached_until_the_archive_changes() {
+        let directory = env::temp_dir().join(format!("stackable-agent-checksum-{}", process::id()));
+        fs::create_dir_all(&directory).unwrap();
+        let archive = directory.join("test-1.0.tar.gz");
+        fs::write(&archive, b"archive").unwrap();
+
+        let calculated_hash = sha256(&archive).unwrap();
+        let sidecar_written = sidecar_path(&archive).exists();
+
+        // A manipulated cache entry is returned as long as the archive is unchanged.
+        let sidecar = fs::read_to_string(sidecar_path(&archive)).unwrap();
+        let (_, fingerprint) = sidecar.split_once(' ').unwrap();
+        fs::write(sidecar_path(&archive), format!("cached {}", fingerprint)).unwrap();
+        let cached_hash = sha256(&archive).unwrap();
+
+        fs::write(&archive, b"changed archive").unwrap();
+        let recalculated_hash = sha256(&archive).unwrap();
+
+        fs::remove_dir_all(&directory).unwrap();
+
+        assert!(sidecar_written);
+        assert_eq!(
+            calculate_sha256(&mut &b"archive"[..]).unwrap(),
+            calculated_hash
+        );
+        assert_eq!("cached", cached_hash);
+        assert_eq!(
+            calculate_sha256(&mut &b"changed archive"[..]).unwrap(),
+            recalculated_hash
+        );
+    }
+}
@@ -0,0 +1,270 @@
+//! Inventory of the installed packages
+//!
+//! Packages are installed into directories named like `<product>-<version>` below the parcel
+//! directory, see [`Package::get_directory_name`], and the SHA256 hash of the archive from which a package was installed is stored
+//! next to its directory, see [`Package::get_checksum_file_name`]. The inventory lists these
+//! directories with their hashes and disk sizes and reconciles them with the registered
+//! repositories, so that packages which are not provided by any repository anymore can be
+//! identified.
+//!
+//! [`Package::get_directory_name`]: super::package::Package::get_directory_name
+//! [`Package::get_checksum_file_name`]: super::package::Package::get_checksum_file_name
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+
+use kube::Client;
+use log::warn;
+use serde::Serialize;
+use tokio::task;
+
+use super::{convert_to_repo_provider, retrieve_repositories, RepositoryClient};
+use crate::provider::error::StackableError;
+
+/// Extension of the files which contain the hashes of the installed archives
+const CHECKSUM_FILE_EXTENSION: &str = "sha256";
+
+/// Size of the blocks reported in [`MetadataExt::blocks`]
+const BLOCK_SIZE: u64 = 512;
+
+/// Installed packages and the repositories they were reconciled with
+#[derive(Clone, Debug, Default, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Inventory {
+    pub packages: Vec<InstalledPackage>,
+    /// Repositories whose metadata could not be retrieved, so that packages provided by them
+    /// are reported as unreferenced
+    pub unavailable_repositories: Vec<String>,
+}
+
+/// Package which is installed in the parcel directory
+#[derive(Clone, Debug, Default, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct InstalledPackage {
+    /// Name of the package directory, e.g. `<product>-<version>`
+    pub directory: String,
+    /// SHA256 hash of the archive from which the package was installed or [`None`] if the
+    /// checksum file is missing
+    pub sha256: Option<String>,
+    /// Disk usage of the package directory in bytes
+    pub size: u64,
+    /// Names of the repositories which provide the package
+    pub repositories: Vec<String>,
+    /// Whether the installed hash matches the hash of the package in one of the repositories
+    /// or [`None`] if the hashes are not available
+    pub verified: Option<bool>,
+}
+
+impl InstalledPackage {
+    /// Checks if the package is provided by at least one repository.
+    pub fn is_referenced(&self) -> bool {
+        !self.repositories.is_empty()
+    }
+}
+
+/// Lists the packages in the given parcel directory and reconciles them with the registered
+/// repositories. The names of the package directories are derived from the given template.
+///
+/// An error is returned if the parcel directory cannot be read or the repositories cannot be
+/// retrieved from the API server. Repositories whose metadata cannot be retrieved are skipped.
+pub async fn inventory(
+    client: Client,
+    parcel_directory: PathBuf,
+    package_directory_template: &str,
+    http_client: &RepositoryClient,
+) -> Result<Inventory, StackableError> {
+    let mut packages = task::spawn_blocking(move || scan_parcel_directory(&parcel_directory))
+        .await
+        .map_err(|error| StackableError::RuntimeError {
+            msg: error.to_string(),
+        })??;
+
+    let repositories = retrieve_repositories(client).await?;
+
+    // Hashes of the provided packages by their directory names and repositories
+    let mut provided_packages = BTreeMap::new();
+    let mut unavailable_repositories = Vec::new();
+
+    for repository in repositories.iter() {
+        if let Some(repo_provider) = convert_to_repo_provider(repository) {
+            let mut repo_provider = repo_provider.with_http_client(http_client.to_owned());
+            match repo_provider
+                .provided_packages(package_directory_template)
+                .await
+            {
+                Ok(hashes) => {
+                    for (directory, hash) in hashes {
+                        provided_packages
+                            .entry(directory)
+                            .or_insert_with(Vec::new)
+                            .push((repo_provider.name.to_owned(), hash));
+                    }
+                }
+                Err(error) => {
+                    warn!(
+                        "Repository [{}] skipped in the inventory because its metadata could not be retrieved: {}",
+                        repo_provider, error
+                    );
+                    unavailable_repositories.push(repo_provider.name.to_owned());
+                }
+            }
+        }
+    }
+
+    reconcile(&mut packages, &provided_packages);
+
+    Ok(Inventory {
+        packages,
+        unavailable_repositories,
+    })
+}
+
+/// Lists the package directories in the given parcel directory sorted by their names.
+///
+/// Directories starting with an underscore, like `_download`, are used by the agent itself and
+/// are skipped.
+fn scan_parcel_directory(parcel_directory: &Path) -> io::Result<Vec<InstalledPackage>> {
+    let mut packages = Vec::new();
+
+    for entry in fs::read_dir(parcel_directory)? {
+        let entry = entry?;
+        let directory = entry.file_name().to_string_lossy().into_owned();
+        if directory.starts_with('_') || !entry.file_type()?.is_dir() {
+            continue;
+        }
+
+        let checksum_file =
+            parcel_directory.join(format!("{}.{}", directory, CHECKSUM_FILE_EXTENSION));
+        let sha256 = match fs::read_to_string(&checksum_file) {
+            Ok(checksum) => Some(checksum.trim().to_owned()),
+            Err(error) if error.kind() == io::ErrorKind::NotFound => None,
+            Err(error) => return Err(error),
+        };
+
+        packages.push(InstalledPackage {
+            size: disk_usage(&entry.path())?,
+            directory,
+            sha256,
+            ..Default::default()
+        });
+    }
+
+    packages.sort_by(|a, b| a.directory.cmp(&b.directory));
+
+    Ok(packages)
+}
+
+/// Returns the disk usage of the given path in bytes including all files and directories below
+/// it. Symbolic links are not followed.
+fn disk_usage(path: &Path) -> io::Result<u64> {
+    let metadata = fs::symlink_metadata(path)?;
+    let mut size = metadata.blocks() * BLOCK_SIZE;
+
+    if metadata.is_dir() {
+        for entry in fs::read_dir(path)? {
+            size += disk_usage(&entry?.path())?;
+        }
+    }
+
+    Ok(size)
+}
+
+/// Adds the providing repositories to the given packages and verifies their hashes.
+///
+/// The given map contains the names of the repositories and the hashes of the provided
+/// packages by their directory names.
+fn reconcile(
+    packages: &mut [InstalledPackage],
+    provided_packages: &BTreeMap<String, Vec<(String, Option<String>)>>,
+) {
+    for package in packages {
+        let providers = provided_packages
+            .get(&package.directory)
+            .map(Vec::as_slice)
+            .unwrap_or_default();
+
+        package.repositories = providers
+            .iter()
+            .map(|(repository, _)| repository.to_owned())
+            .collect();
+
+        let provided_hashes = providers
+            .iter()
+            .filter_map(|(_, hash)| hash.as_deref())
+            .collect::<Vec<_>>();
+
+        package.verified = match &package.sha256 {
+            Some(installed_hash) if !provided_hashes.is_empty() => Some(
+                provided_hashes
+                    .iter()
+                    .any(|hash| hash.eq_ignore_ascii_case(installed_hash)),
+            ),
+            _ => None,
+        };
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn installed_packages_should_be_reconciled_with_repositories() {
+        let installed_package = |directory: &str, sha256: Option<&str>| InstalledPackage {
+            directory: String::from(directory),
+            sha256: sha256.map(String::from),
+            size: 4096,
+            ..Default::default()
+        };
+
+        let mut packages = vec![
+            installed_package("kafka-2.7", Some("abc")),
+            installed_package("kafka-2.8", Some("abc")),
+            installed_package("zookeeper-3.5", Some("abc")),
+            installed_package("zookeeper-3.6", None),
+        ];
+
+        let mut provided_packages = BTreeMap::new();
+        provided_packages.insert(
+            String::from("kafka-2.7"),
+            vec![
+                (String::from("old"), Some(String::from("def"))),
+                (String::from("stackable"), Some(String::from("ABC"))),
+            ],
+        );
+        provided_packages.insert(
+            String::from("kafka-2.8"),
+            vec![(String::from("stackable"), Some(String::from("def")))],
+        );
+        provided_packages.insert(
+            String::from("zookeeper-3.6"),
+            vec![(String::from("stackable"), Some(String::from("abc")))],
+        );
+
+        reconcile(&mut packages, &provided_packages);
+
+        let result = packages
+            .iter()
+            .map(|package| {
+                (
+                    package.directory.as_str(),
+                    package.is_referenced(),
+                    package.verified,
+                )
+            })
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            vec![
+                ("kafka-2.7", true, Some(true)),
+                ("kafka-2.8", true, Some(false)),
+                ("zookeeper-3.5", false, None),
+                ("zookeeper-3.6", true, None),
+            ],
+            result
+        );
+        assert_eq!(vec!["old", "stackable"], packages[0].repositories);
+    }
+}
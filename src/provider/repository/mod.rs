@@ -2,48 +2,148 @@
 
 use kube::api::{ListParams, ObjectList};
 use kube::{Api, Client};
+use kubelet::pod::Pod;
 use log::{debug, info, warn};
+use reqwest::{RequestBuilder, Url};
 use std::convert::TryFrom;
+use std::time::Duration;
 
 use crate::provider::error::StackableError;
+use crate::provider::error::StackableError::{PackageNotInRepository, RepositoryNotFound};
+use crate::provider::kubernetes::accessor::{annotation, boolean_annotation};
 use package::Package;
 use repository_spec::Repository;
 use stackablerepository::StackableRepoProvider;
 
+pub mod archive_checksum;
+pub mod inventory;
 pub mod package;
 pub mod repository_spec;
 pub mod stackablerepository;
 
+/// Annotation with the name of the repository from which the package of the pod must be
+/// downloaded
+pub const REPOSITORY_ANNOTATION: &str = "stackable.tech/repository";
+
+/// Annotation with the package of the pod, e.g. `kafka:2.7`, which takes precedence over the
+/// image of the container
+pub const PACKAGE_ANNOTATION: &str = "stackable.tech/package";
+
+/// Annotation which forces the package to be downloaded again
+/// even if it is already present, either `true` or `false` (default)
+pub const FORCE_REINSTALL_ANNOTATION: &str = "stackable.tech/force-reinstall";
+
+/// Timeouts for the HTTP requests to the repositories
+///
+/// `None` means that the requests do not time out.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct RequestTimeouts {
+    /// Timeout for establishing a connection
+    pub connect: Option<Duration>,
+    /// Timeout for a whole request from sending it until the response body is read
+    pub request: Option<Duration>,
+}
+
+/// HTTP client for the requests to the repositories
+///
+/// The client is created once and shared by all repositories, so that idle connections are kept
+/// alive in a pool and reused by subsequent requests to the same host instead of establishing a
+/// new connection for every download. Clones share the same pool.
+#[derive(Clone, Debug, Default)]
+pub struct RepositoryClient {
+    client: reqwest::Client,
+    /// Timeout which is applied to every request
+    request_timeout: Option<Duration>,
+}
+
+impl RepositoryClient {
+    /// Creates a client with the given timeouts.
+    ///
+    /// Idle connections are closed after the given pool idle timeout. If it is zero then
+    /// connections are not reused at all.
+    pub fn new(
+        timeouts: RequestTimeouts,
+        pool_idle_timeout: Duration,
+    ) -> Result<RepositoryClient, StackableError> {
+        let mut builder = reqwest::Client::builder();
+        if let Some(connect_timeout) = timeouts.connect {
+            builder = builder.connect_timeout(connect_timeout);
+        }
+        if pool_idle_timeout.is_zero() {
+            builder = builder.pool_max_idle_per_host(0);
+        } else {
+            builder = builder.pool_idle_timeout(pool_idle_timeout);
+        }
+
+        Ok(RepositoryClient {
+            client: builder.build()?,
+            request_timeout: timeouts.request,
+        })
+    }
+
+    /// Creates a GET request to the given URL which applies the request timeout.
+    pub fn get(&self, url: Url) -> RequestBuilder {
+        let request = self.client.get(url);
+        match self.request_timeout {
+            Some(request_timeout) => request.timeout(request_timeout),
+            None => request,
+        }
+    }
+}
+
 /// Searches for the given package in all registered repositories.
 ///
-/// The available repositories are retrieved from the API server and if
-/// the given package is provided by one of them then
-/// `Ok(Some(repository))` else `Ok(None)` is returned.
+/// The available repositories are retrieved from the API server and all
+/// repositories which provide the given package are returned, so that
+/// the download can fall back to the next one if it fails. An empty list
+/// is returned if no repository provides the package. The requests to
+/// the repositories are sent with the given HTTP client.
 ///
 /// If the repositories cannot be retrieved then `Err(error)` is
 /// returned.
 ///
-/// The repositories are sorted by their name to provide a deterministic
-/// behavior especially for tests.
-pub async fn find_repository(
+/// The repositories are searched and returned in the order of their
+/// priority and repositories with the same priority are sorted by their name to
+/// provide a deterministic behavior especially for tests.
+///
+/// If a `required_repository` is given then only this repository is
+/// searched and an error is returned if it is not registered or if it
+/// does not provide the package, see [`required_repository`].
+pub async fn find_repositories(
     client: Client,
     package: &Package,
-) -> Result<Option<StackableRepoProvider>, StackableError> {
+    required_repository: Option<&str>,
+    http_client: &RepositoryClient,
+) -> Result<Vec<StackableRepoProvider>, StackableError> {
     let repositories = retrieve_repositories(client).await?;
 
-    let mut repo_providers = repositories
+    let repo_providers = repositories
         .iter()
         .filter_map(convert_to_repo_provider)
+        .map(|repo_provider| repo_provider.with_http_client(http_client.to_owned()))
         .collect::<Vec<_>>();
 
-    repo_providers.sort_unstable_by_key(|repo_provider| repo_provider.name.to_owned());
+    let mut repo_providers = select_repo_providers(repo_providers, required_repository)?;
 
-    let maybe_repo_provider = choose_repo_provider(&mut repo_providers, package).await;
+    let providing_repo_providers = choose_repo_providers(&mut repo_providers, package).await;
 
-    if let Some(repo_provider) = &maybe_repo_provider {
+    if providing_repo_providers.is_empty() {
+        if let Some(repository) = required_repository {
+            return Err(PackageNotInRepository {
+                package: package.to_owned(),
+                repository: String::from(repository),
+            });
+        }
+    }
+
+    if !providing_repo_providers.is_empty() {
+        let repository_names = providing_repo_providers
+            .iter()
+            .map(|repo_provider| repo_provider.name.as_str())
+            .collect::<Vec<_>>();
         debug!(
-            "Package [{}] found in repository [{}]",
-            &package, &repo_provider
+            "Package [{}] found in the following repositories: {:?}",
+            &package, repository_names
         );
     } else {
         let repository_names = repo_providers
@@ -56,7 +156,55 @@ pub async fn find_repository(
         );
     }
 
-    Ok(maybe_repo_provider)
+    Ok(providing_repo_providers)
+}
+
+/// Returns the name of the repository which is required by the given pod.
+///
+/// The repository is set with the annotation [`REPOSITORY_ANNOTATION`].
+/// If several repositories provide the same version of a package, e.g.
+/// with different builds, then this ensures that the package is always
+/// downloaded from the same repository.
+pub fn required_repository(pod: &Pod) -> Option<&str> {
+    annotation(pod, REPOSITORY_ANNOTATION)
+}
+
+/// Checks if the package of the given pod must be downloaded again even if
+/// it is already present, see [`FORCE_REINSTALL_ANNOTATION`]. The package
+/// is only extracted again if the downloaded archive changed.
+///
+/// This is meant for development if a package is republished under the same
+/// version.
+pub fn force_reinstall(pod: &Pod) -> Result<bool, StackableError> {
+    Ok(boolean_annotation(pod, FORCE_REINSTALL_ANNOTATION)?.unwrap_or_default())
+}
+
+/// Returns the repository providers which are searched for a package
+/// sorted by their priorities in descending order and then by their
+/// names.
+///
+/// If a required repository is given then only its provider is returned
+/// or an error if there is none with this name.
+fn select_repo_providers(
+    mut repo_providers: Vec<StackableRepoProvider>,
+    required_repository: Option<&str>,
+) -> Result<Vec<StackableRepoProvider>, StackableError> {
+    if let Some(repository) = required_repository {
+        repo_providers.retain(|repo_provider| repo_provider.name == repository);
+        if repo_providers.is_empty() {
+            return Err(RepositoryNotFound {
+                repository: String::from(repository),
+            });
+        }
+    }
+
+    repo_providers.sort_unstable_by(|a, b| {
+        b.priority
+            .cmp(&a.priority)
+            .then_with(|| a.name.cmp(&b.name))
+    });
+
+    Ok(repo_providers)
 }
 
 /// Retrieves all Stackable repositories in the default namespace from
@@ -81,16 +229,62 @@ fn convert_to_repo_provider(repository: &Repository) -> Option<StackableRepoProv
 }
 
 /// Retrieves the provided packages for the given repository providers
-/// and returns the first provider which provides the given package or
-/// `None` if none provides it.
-async fn choose_repo_provider(
+/// and returns the providers which provide the given package in the
+/// given order.
+async fn choose_repo_providers(
     repo_providers: &mut [StackableRepoProvider],
     package: &Package,
-) -> Option<StackableRepoProvider> {
+) -> Vec<StackableRepoProvider> {
+    let mut providing_repo_providers = Vec::new();
     for repo_provider in repo_providers {
-        if let Ok(true) = repo_provider.provides_package(package.to_owned()).await {
-            return Some(repo_provider.to_owned());
+        match repo_provider.provides_package(package.to_owned()).await {
+            Ok(true) => providing_repo_providers.push(repo_provider.to_owned()),
+            Ok(false) => {}
+            Err(error) => warn!(
+                "Repository [{}] skipped because its metadata could not be retrieved: {}",
+                repo_provider, error
+            ),
         }
     }
-    None
+    providing_repo_providers
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use reqwest::Url;
+    use rstest::rstest;
+
+    #[rstest]
+    #[case::all_repositories(None, Ok(vec!["d", "a", "b", "c", "e"]))]
+    #[case::required_repository(Some("b"), Ok(vec!["b"]))]
+    #[case::unknown_repository(Some("f"), Err(()))]
+    fn repo_providers_should_be_selected(
+        #[case] required_repository: Option<&str>,
+        #[case] expected_names: Result<Vec<&str>, ()>,
+    ) {
+        let url = Url::parse("https://repo.stackable.tech/").unwrap();
+        let repo_providers = [("c", 0), ("e", -1), ("a", 0), ("d", 10), ("b", 0)]
+            .iter()
+            .map(|(name, priority)| {
+                StackableRepoProvider::new(name, &url)
+                    .unwrap()
+                    .with_priority(*priority)
+            })
+            .collect();
+
+        let names = select_repo_providers(repo_providers, required_repository)
+            .map(|repo_providers| {
+                repo_providers
+                    .into_iter()
+                    .map(|repo_provider| repo_provider.name)
+                    .collect::<Vec<_>>()
+            })
+            .map_err(|_| ());
+
+        assert_eq!(
+            expected_names.map(|names| names.into_iter().map(String::from).collect()),
+            names
+        );
+    }
 }
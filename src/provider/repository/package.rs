@@ -5,6 +5,16 @@ use anyhow::{anyhow, Result};
 use oci_distribution::Reference;
 use serde::{Deserialize, Serialize};
 
+/// Placeholder for the product in the templates of the package directory names
+const PRODUCT_PLACEHOLDER: &str = "{{product}}";
+
+/// Placeholder for the version in the templates of the package directory names
+const VERSION_PLACEHOLDER: &str = "{{version}}";
+
+/// Template for the names of the package directories which is used by default and for the
+/// names of the downloaded archives, e.g. `kafka-2.7`
+pub const DEFAULT_DIRECTORY_TEMPLATE: &str = "{{product}}-{{version}}";
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Package {
     pub product: String,
@@ -19,13 +29,47 @@ impl Package {
     /// Currently this assumes all archives to be in .tar.gz format, we might revisit this at
     /// a later stage.
     pub fn get_file_name(&self) -> String {
-        format!("{}.tar.gz", self.get_directory_name())
+        format!(
+            "{}.tar.gz",
+            self.get_directory_name(DEFAULT_DIRECTORY_TEMPLATE)
+        )
+    }
+
+    /// Derive the name of the file which holds the archive while it is being downloaded.
+    /// The file is only renamed to [`Package::get_file_name`] after the download has been
+    /// completed and verified, so that an interrupted download can be resumed later on.
+    pub fn get_partial_file_name(&self) -> String {
+        format!("{}.part", self.get_file_name())
+    }
+
+    /// Derive the name of the file which caches the SHA256 hash of the downloaded archive. It is
+    /// placed next to the archive, see [`super::archive_checksum`].
+    pub fn get_archive_checksum_file_name(&self) -> String {
+        format!("{}.sha256", self.get_file_name())
     }
 
-    /// Derive a standardized name for the folder that this package should be installed to.
+    /// Derive the name of the folder that this package should be installed to from the given
+    /// template, e.g. [`DEFAULT_DIRECTORY_TEMPLATE`], by replacing the placeholders
+    /// `{{product}}` and `{{version}}`.
     /// This helps avoiding duplicate binary installations due to different folder names.
-    pub fn get_directory_name(&self) -> String {
-        format!("{}-{}", self.product, self.version)
+    pub fn get_directory_name(&self, template: &str) -> String {
+        template
+            .replace(PRODUCT_PLACEHOLDER, &self.product)
+            .replace(VERSION_PLACEHOLDER, &self.version)
+    }
+
+    /// Derive the name of the file which holds the SHA256 hash of the archive from which the
+    /// package was installed. It is placed next to the installation folder whose name is derived
+    /// from the given template.
+    pub fn get_checksum_file_name(&self, template: &str) -> String {
+        format!("{}.sha256", self.get_directory_name(template))
+    }
+
+    /// Derive the name of the file which holds the number of leading path components which
+    /// were stripped from the entries of the archive when the package was installed. It is
+    /// placed next to the installation folder whose name is derived from the given template.
+    pub fn get_strip_components_file_name(&self, template: &str) -> String {
+        format!("{}.strip-components", self.get_directory_name(template))
     }
 }
 
@@ -82,4 +126,26 @@ mod test {
             panic!("Error expected but got {:?}", maybe_package);
         }
     }
+
+    #[test]
+    fn directory_name_should_be_derived_from_template() {
+        let package = Package {
+            product: String::from("kafka"),
+            version: String::from("2.7"),
+        };
+
+        assert_eq!(
+            "kafka-2.7",
+            package.get_directory_name(DEFAULT_DIRECTORY_TEMPLATE)
+        );
+        assert_eq!(
+            "2.7_kafka",
+            package.get_directory_name("{{version}}_{{product}}")
+        );
+        assert_eq!(
+            "kafka_2.7.sha256",
+            package.get_checksum_file_name("{{product}}_{{version}}")
+        );
+        assert_eq!("kafka-2.7.tar.gz", package.get_file_name());
+    }
 }
@@ -14,8 +14,15 @@ use serde::{Deserialize, Serialize};
 pub struct RepositorySpec {
     pub repo_type: RepoType,
     pub properties: HashMap<String, String>,
+    /// Repositories with a higher priority are searched first for a package, see
+    /// [`DEFAULT_PRIORITY`] if it is not set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub priority: Option<i32>,
 }
 
+/// Priority of repositories which do not specify one
+pub const DEFAULT_PRIORITY: i32 = 0;
+
 #[derive(Serialize, Deserialize, Clone, Debug, JsonSchema)]
 pub enum RepoType {
     StackableRepo,
@@ -1,20 +1,26 @@
 use std::collections::HashMap;
 use std::convert::TryFrom;
 use std::fmt;
-use std::fs::File;
+use std::fs::{self, File, OpenOptions};
 use std::hash::{Hash, Hasher};
-use std::io::{copy, Cursor, Write};
-use std::path::PathBuf;
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
 
 use crate::provider::error::StackableError;
-use crate::provider::error::StackableError::{PackageDownloadError, PackageNotFound};
+use crate::provider::error::StackableError::{
+    PackageDownloadError, PackageNotFound, RepositoryTimeout,
+};
+use crate::provider::repository::archive_checksum;
 use crate::provider::repository::package::Package;
-use crate::provider::repository::repository_spec::Repository;
+use crate::provider::repository::repository_spec::{Repository, DEFAULT_PRIORITY};
+use crate::provider::repository::RepositoryClient;
+use anyhow::anyhow;
 use kube::api::Meta;
-use log::{debug, trace, warn};
-use reqwest::header::{ACCEPT, CONTENT_TYPE};
-use reqwest::{Client, StatusCode};
+use log::{debug, info, trace, warn};
+use reqwest::header::{ACCEPT, CONTENT_RANGE, CONTENT_TYPE, RANGE};
+use reqwest::{Response, StatusCode};
 use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
 use url::Url;
 
 // These are the default content_types that we have seen in the wild
@@ -32,7 +38,9 @@ const DEFAULT_ALLOWED_CONTENT_TYPES: &[&str] = &[
 pub struct StackableRepoProvider {
     metadata_url: Url,
     pub name: String,
+    pub priority: i32,
     content: Option<RepositoryContent>,
+    http_client: RepositoryClient,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -75,10 +83,24 @@ impl StackableRepoProvider {
         Ok(StackableRepoProvider {
             metadata_url,
             name: String::from(name),
+            priority: DEFAULT_PRIORITY,
             content: None,
+            http_client: RepositoryClient::default(),
         })
     }
 
+    /// Sets the priority of this repository.
+    pub fn with_priority(mut self, priority: i32) -> Self {
+        self.priority = priority;
+        self
+    }
+
+    /// Sets the shared HTTP client for the requests to this repository.
+    pub fn with_http_client(mut self, http_client: RepositoryClient) -> Self {
+        self.http_client = http_client;
+        self
+    }
+
     pub async fn provides_package<T: Into<Package>>(
         &mut self,
         package: T,
@@ -96,6 +118,33 @@ impl StackableRepoProvider {
         Ok(false)
     }
 
+    /// Retrieves the metadata of the repository and returns the SHA256 hashes of all provided
+    /// packages by the names of their installation directories which are derived from the given
+    /// template, see [`Package::get_directory_name`]. The hash is `None` if the repository does
+    /// not provide one for the package.
+    pub async fn provided_packages(
+        &mut self,
+        package_directory_template: &str,
+    ) -> Result<HashMap<String, Option<String>>, StackableError> {
+        let metadata = self.get_repo_metadata().await?;
+
+        Ok(metadata
+            .packages
+            .values()
+            .flat_map(HashMap::values)
+            .map(|stackable_package| {
+                let package = Package {
+                    product: stackable_package.product.to_owned(),
+                    version: stackable_package.version.to_owned(),
+                };
+                (
+                    package.get_directory_name(package_directory_template),
+                    sha256_hash(&stackable_package.hashes).map(String::from),
+                )
+            })
+            .collect())
+    }
+
     async fn get_package(&mut self, package: Package) -> Result<StackablePackage, StackableError> {
         if self.content.is_none() {
             self.get_repo_metadata().await?;
@@ -125,25 +174,135 @@ impl StackableRepoProvider {
         let stackable_package = self.get_package(package.clone()).await?;
         let download_link = Url::parse(&stackable_package.link)?;
 
-        let client = Client::builder()
-            .build()
-            .map_err(|error| PackageDownloadError {
-                package: package.clone(),
-                download_link: download_link.clone(),
-                errormessage: format!("Unable to create http client: [{}]", error),
-            })?;
+        let target_file = target_path.join(package.get_file_name());
+        let partial_file = target_path.join(package.get_partial_file_name());
+
+        // If a previous download was interrupted, the partial file is still present and we try
+        // to resume the download from where it stopped
+        let resume_offset = match fs::metadata(&partial_file) {
+            Ok(metadata) if metadata.is_file() => metadata.len(),
+            _ => 0,
+        };
+
+        let mut response = StackableRepoProvider::request_package(
+            &self.http_client,
+            package,
+            &download_link,
+            resume_offset,
+        )
+        .await?;
+
+        if response.status() == StatusCode::RANGE_NOT_SATISFIABLE {
+            // The partial file is either already complete or does not match the file on the
+            // server anymore, either way we cannot resume and start over
+            debug!(
+                "Unable to resume download of package {} from offset {}, restarting download",
+                package, resume_offset
+            );
+            fs::remove_file(&partial_file)?;
+            response = StackableRepoProvider::request_package(
+                &self.http_client,
+                package,
+                &download_link,
+                0,
+            )
+            .await?;
+        }
+
+        // Servers which do not support range requests simply respond with the full content and
+        // status code 200, in which case the partial file is overwritten
+        let resumed = response.status() == StatusCode::PARTIAL_CONTENT;
+        if resumed {
+            let content_range = response
+                .headers()
+                .get(CONTENT_RANGE)
+                .and_then(|value| value.to_str().ok())
+                .unwrap_or_default();
+            if !content_range.starts_with(&format!("bytes {}-", resume_offset)) {
+                fs::remove_file(&partial_file)?;
+                return Err(PackageDownloadError {
+                    package: package.clone(),
+                    download_link,
+                    errormessage: format!(
+                        "Got unexpected 'content_range' header [{}] in response from webserver when resuming at offset {}.",
+                        content_range, resume_offset
+                    ),
+                });
+            }
+            info!(
+                "Resuming download of package {} at offset {}",
+                package, resume_offset
+            );
+        }
+
+        let mut out = if resumed {
+            OpenOptions::new().append(true).open(&partial_file)?
+        } else {
+            File::create(&partial_file)?
+        };
+
+        // All error cases return above, so we can safely assume that this is a valid download at
+        // this point
+        while let Some(chunk) = response
+            .chunk()
+            .await
+            .map_err(|error| map_timeout(error, &download_link))?
+        {
+            out.write_all(&chunk)?;
+        }
+        out.flush()?;
+        drop(out);
+
+        // Only move the archive to its final location once we know it is complete and correct,
+        // otherwise later runs would consider the package as downloaded
+        let verified_hash = match verify_checksum(&partial_file, &stackable_package.hashes) {
+            Ok(verified_hash) => verified_hash,
+            Err(error) => {
+                fs::remove_file(&partial_file)?;
+                return Err(PackageDownloadError {
+                    package: package.clone(),
+                    download_link,
+                    errormessage: format!("{}", error),
+                });
+            }
+        };
+
+        fs::rename(&partial_file, &target_file)?;
+
+        // The verified hash is cached, so that the archive is not hashed again on installation
+        if let Some(verified_hash) = verified_hash {
+            archive_checksum::store(&target_file, &verified_hash);
+        }
+        Ok(())
+    }
+
+    /// Requests the package from the given download link.
+    ///
+    /// If `offset` is greater than zero, a range request is sent to only retrieve the content
+    /// after the given offset. In this case a response with status code 416 (Range Not
+    /// Satisfiable) is returned as well, so that the caller can decide how to continue.
+    async fn request_package(
+        http_client: &RepositoryClient,
+        package: &Package,
+        download_link: &Url,
+        offset: u64,
+    ) -> Result<Response, StackableError> {
+        let download_link = download_link.clone();
 
         // We set the ACCEPT header field on our request which states that the only content type
         // we are willing to accept is 'application/gzip'
         // If the webserver is unable to provide this content type to us it _SHOULD_ respond with a
         // 406 response code, but it seems we can't rely on that.
         // For more details see: https://www.w3.org/Protocols/rfc2616/rfc2616-sec14.html#sec14.1
-        let response = match client
+        let mut request = http_client
             .get(download_link.clone())
-            .header(ACCEPT, "application/gzip")
-            .send()
-            .await
-        {
+            .header(ACCEPT, "application/gzip");
+
+        if offset > 0 {
+            request = request.header(RANGE, format!("bytes={}-", offset));
+        }
+
+        match request.send().await {
             Ok(response) if response.status().is_success() => {
                 // The request was successful, but just to be safe we'll still check the content_type, 
                 // since the webserver is free to ignore the requested content_type
@@ -173,6 +332,8 @@ impl StackableRepoProvider {
                     Ok(response)
                 }
             }
+            Ok(response) if offset > 0 && response.status() == StatusCode::RANGE_NOT_SATISFIABLE =>
+                Ok(response),
             Ok(response) if response.status() == StatusCode::NOT_ACCEPTABLE =>
                 Err(PackageDownloadError {
                     package: package.clone(),
@@ -187,21 +348,13 @@ impl StackableRepoProvider {
                     response.status()
                 ),
             }),
+            Err(error) if error.is_timeout() => Err(RepositoryTimeout { url: download_link }),
             Err(error) => Err(PackageDownloadError {
                 package: package.clone(),
                 download_link,
                 errormessage: format!("{}", error),
             }),
-        }?;
-
-        // All error cases return above, so we can safely assume that this is a valid download at
-        // this point
-        let mut content = Cursor::new(response.bytes().await?);
-
-        let mut out = File::create(target_path.join(package.get_file_name()))?;
-        copy(&mut content, &mut out)?;
-        out.flush()?;
-        Ok(())
+        }
     }
 
     async fn get_repo_metadata(&mut self) -> Result<RepositoryContent, StackableError> {
@@ -209,14 +362,15 @@ impl StackableRepoProvider {
 
         debug!("Retrieving repository metadata from {}", self.metadata_url);
 
-        let repo_data = match reqwest::get(self.metadata_url.clone()).await {
+        let response = self.http_client.get(self.metadata_url.clone()).send().await;
+        let repo_data = match response {
             Ok(repo_data) => repo_data,
             Err(error) => {
                 warn!(
                     "Failed to retrieve metadata from {} due to {:?}",
                     self.metadata_url, error
                 );
-                return Err(error.into());
+                return Err(map_timeout(error, &self.metadata_url));
             }
         };
         let repo_data = match repo_data.json::<RepoData>().await {
@@ -226,7 +380,7 @@ impl StackableRepoProvider {
                     "Error parsing metadata from repository {}: {:?}",
                     self.name, error
                 );
-                return Err(error.into());
+                return Err(map_timeout(error, &self.metadata_url));
             }
         };
 
@@ -274,6 +428,70 @@ impl StackableRepoProvider {
     }
 }
 
+/// Verifies the given file against the SHA256 hash in `hashes` and returns the verified hash.
+///
+/// The hash algorithm is matched case-insensitively. If the repository does not provide a
+/// SHA256 hash for the package then the verification is skipped, a warning is logged, and
+/// [`None`] is returned.
+fn verify_checksum(
+    file: &Path,
+    hashes: &HashMap<String, String>,
+) -> anyhow::Result<Option<String>> {
+    if let Some(expected_hash) = sha256_hash(hashes) {
+        let actual_hash = calculate_sha256(&mut File::open(file)?)?;
+        if actual_hash.eq_ignore_ascii_case(expected_hash) {
+            debug!("Checksum of [{:?}] successfully verified", file);
+            Ok(Some(actual_hash))
+        } else {
+            Err(anyhow!(
+                "Checksum of the downloaded file does not match, expected SHA256 [{}] but got [{}].",
+                expected_hash,
+                actual_hash
+            ))
+        }
+    } else {
+        warn!(
+            "No SHA256 hash provided by the repository for [{:?}], skipping checksum verification",
+            file
+        );
+        Ok(None)
+    }
+}
+
+/// Returns the SHA256 hash in `hashes` whose algorithm is matched case-insensitively.
+fn sha256_hash(hashes: &HashMap<String, String>) -> Option<&str> {
+    hashes
+        .iter()
+        .find(|(algorithm, _)| algorithm.eq_ignore_ascii_case("sha256"))
+        .map(|(_, hash)| hash.as_str())
+}
+
+/// Converts the given error into a [`RepositoryTimeout`] if the request to the given URL timed
+/// out, so that the caller can distinguish timeouts from other errors.
+fn map_timeout(error: reqwest::Error, url: &Url) -> StackableError {
+    if error.is_timeout() {
+        RepositoryTimeout {
+            url: url.to_owned(),
+        }
+    } else {
+        StackableError::from(error)
+    }
+}
+
+/// Calculates the SHA256 hash of the given content and returns it as lowercase hex string.
+pub fn calculate_sha256<R: Read>(reader: &mut R) -> io::Result<String> {
+    let mut hasher = Sha256::new();
+    let mut buffer = [0; 8192];
+    loop {
+        let read = reader.read(&mut buffer)?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..read]);
+    }
+    Ok(hex::encode(hasher.finalize()))
+}
+
 impl fmt::Display for StackableRepoProvider {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(f, "{}", self.name)
@@ -293,7 +511,8 @@ impl TryFrom<&Repository> for StackableRepoProvider {
             .and_then(|url| Url::parse(url).ok())
             .ok_or(StackableError::RepositoryConversionError)?;
 
-        let stackable_repo_provider = StackableRepoProvider::new(&name, &base_url)?;
+        let stackable_repo_provider = StackableRepoProvider::new(&name, &base_url)?
+            .with_priority(value.spec.priority.unwrap_or(DEFAULT_PRIORITY));
 
         Ok(stackable_repo_provider)
     }
@@ -317,7 +536,10 @@ impl Hash for StackableRepoProvider {
 mod tests {
     use super::*;
 
+    use std::time::Duration;
+
     use crate::provider::repository::repository_spec::RepositorySpec;
+    use crate::provider::repository::RequestTimeouts;
 
     #[test]
     fn stackable_repo_provider_should_be_created_from_a_valid_url_with_a_trailing_slash() {
@@ -356,6 +578,31 @@ mod tests {
         .is_err());
     }
 
+    #[tokio::test]
+    async fn metadata_request_should_time_out_if_the_repository_does_not_respond() {
+        // The listener accepts connections but never sends a response
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = Url::parse(&format!("http://{}/", listener.local_addr().unwrap())).unwrap();
+
+        let mut repo = StackableRepoProvider::new("test", &url)
+            .unwrap()
+            .with_http_client(
+                RepositoryClient::new(
+                    RequestTimeouts {
+                        connect: Some(Duration::from_secs(1)),
+                        request: Some(Duration::from_millis(100)),
+                    },
+                    Duration::from_secs(90),
+                )
+                .unwrap(),
+            );
+
+        assert!(matches!(
+            repo.get_repo_metadata().await,
+            Err(StackableError::RepositoryTimeout { url }) if url == repo.metadata_url
+        ));
+    }
+
     #[test]
     fn test_url_functions() {
         let repo =
@@ -376,6 +623,16 @@ mod tests {
         );
     }
 
+    #[test]
+    fn sha256_should_be_calculated_as_lowercase_hex_string() {
+        let mut content = io::Cursor::new("stackable");
+
+        assert_eq!(
+            "a9d6588562ee0b848327e28eae94f872cd1b01d46377038078165b7be9bf5f5c",
+            calculate_sha256(&mut content).unwrap()
+        );
+    }
+
     #[test]
     fn test_repository_try_from() {
         let mut props = HashMap::new();
@@ -388,10 +645,12 @@ mod tests {
             RepositorySpec {
                 repo_type: Default::default(),
                 properties: props,
+                priority: Some(10),
             },
         );
         let converted_repo = StackableRepoProvider::try_from(&test_repo_crd).unwrap();
         assert_eq!(converted_repo.name, "test");
+        assert_eq!(converted_repo.priority, 10);
         assert_eq!(
             converted_repo.metadata_url.as_str(),
             "http://monitoring.stackable.demo:8000/metadata.json"
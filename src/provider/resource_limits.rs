@@ -0,0 +1,182 @@
+//! Resource limits of containers
+//!
+//! The CPU and memory limits of a container are enforced by systemd with the resource control
+//! directives `CPUQuota` and `MemoryMax` of the service unit. If the limits of a running pod are
+//! changed then they are applied to the running unit with `SetUnitProperties`, so that the
+//! service does not have to be restarted.
+use kubelet::container::Container;
+
+use crate::config::{parse_cpu_quantity, parse_memory_quantity};
+use crate::provider::error::StackableError;
+use crate::provider::error::StackableError::PodValidationError;
+use crate::provider::systemdmanager::systemd1_api::Property;
+
+/// Value of the resource control properties on D-Bus which removes the limit
+const UNLIMITED: u64 = u64::MAX;
+
+/// Directives of the service section which are derived from the limits
+const LIMIT_DIRECTIVES: &[&str] = &["CPUQuota", "MemoryMax"];
+
+/// CPU and memory limits of a container
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct ResourceLimits {
+    /// CPU limit in millicores
+    pub cpu_millis: Option<u64>,
+    /// Memory limit in bytes
+    pub memory_bytes: Option<u64>,
+}
+
+impl ResourceLimits {
+    /// Reads the limits of the given container.
+    ///
+    /// An error is returned if a limit is not a valid quantity.
+    pub fn from_container(container: &Container) -> Result<Self, StackableError> {
+        let limit = |resource: &str, parse: fn(&str) -> Option<u64>| {
+            container
+                .resources()
+                .and_then(|resources| resources.limits.as_ref())
+                .and_then(|limits| limits.get(resource))
+                .map(|quantity| {
+                    parse(&quantity.0).ok_or_else(|| PodValidationError {
+                        msg: format!(
+                            "The {} limit [{}] of the container [{}] is not a valid quantity.",
+                            resource,
+                            quantity.0,
+                            container.name()
+                        ),
+                    })
+                })
+                .transpose()
+        };
+
+        Ok(ResourceLimits {
+            cpu_millis: limit("cpu", parse_cpu_quantity)?,
+            memory_bytes: limit("memory", parse_memory_quantity)?,
+        })
+    }
+
+    /// Returns the CPU quota in percent of one core.
+    ///
+    /// systemd only accepts whole percents, so the quota is rounded up.
+    fn cpu_quota_percent(&self) -> Option<u64> {
+        self.cpu_millis.map(|millis| ((millis + 9) / 10).max(1))
+    }
+
+    /// Returns the directives of the service section which enforce these limits.
+    pub fn unit_directives(&self) -> Vec<(&'static str, String)> {
+        let mut directives = Vec::new();
+
+        if let Some(percent) = self.cpu_quota_percent() {
+            directives.push(("CPUQuota", format!("{}%", percent)));
+        }
+        if let Some(bytes) = self.memory_bytes {
+            directives.push(("MemoryMax", bytes.to_string()));
+        }
+
+        directives
+    }
+
+    /// Returns the D-Bus properties which set these limits on a running unit.
+    ///
+    /// Limits which are not set are removed from the unit.
+    pub fn dbus_properties(&self) -> Vec<Property> {
+        vec![
+            Property::new(
+                "CPUQuotaPerSecUSec",
+                self.cpu_quota_percent()
+                    .map_or(UNLIMITED, |percent| percent * 10_000),
+            ),
+            Property::new("MemoryMax", self.memory_bytes.unwrap_or(UNLIMITED)),
+        ]
+    }
+}
+
+/// Checks if the given unit file contents are equal apart from the directives which enforce the
+/// resource limits.
+///
+/// Such units do not have to be restarted because the limits can be applied to the running
+/// units.
+pub fn differ_only_in_limits(content: &str, other_content: &str) -> bool {
+    let without_limits = |content: &str| {
+        content
+            .lines()
+            .filter(|line| {
+                !LIMIT_DIRECTIVES
+                    .iter()
+                    .any(|directive| line.starts_with(&format!("{}=", directive)))
+            })
+            .collect::<Vec<_>>()
+    };
+
+    without_limits(content) == without_limits(other_content)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::provider::test::TestPod;
+    use rstest::rstest;
+
+    #[test]
+    fn changed_limits_should_be_detected() {
+        let content = "[Service]\nCPUQuota=50%\nExecStart=start.sh\nMemoryMax=1024";
+
+        assert!(differ_only_in_limits(
+            content,
+            "[Service]\nCPUQuota=100%\nExecStart=start.sh"
+        ));
+        assert!(!differ_only_in_limits(
+            content,
+            "[Service]\nCPUQuota=50%\nExecStart=stop.sh\nMemoryMax=1024"
+        ));
+    }
+
+    #[rstest]
+    #[case::without_limits("{}", Some(ResourceLimits::default()), vec![])]
+    #[case::with_limits(
+        "{limits: {cpu: 1250m, memory: 512Mi}}",
+        Some(ResourceLimits { cpu_millis: Some(1250), memory_bytes: Some(512 << 20) }),
+        vec![("CPUQuota", "125%"), ("MemoryMax", "536870912")]
+    )]
+    #[case::cpu_rounded_up(
+        "{limits: {cpu: '0.001'}}",
+        Some(ResourceLimits { cpu_millis: Some(1), memory_bytes: None }),
+        vec![("CPUQuota", "1%")]
+    )]
+    #[case::invalid_quantity("{limits: {memory: '1e3'}}", None, vec![])]
+    fn limits_should_be_read_from_container(
+        #[case] resources: &str,
+        #[case] expected_limits: Option<ResourceLimits>,
+        #[case] expected_directives: Vec<(&str, &str)>,
+    ) {
+        let pod = format!(
+            "
+            apiVersion: v1
+            kind: Pod
+            metadata:
+              name: test
+            spec:
+              containers:
+                - name: test-container
+                  resources: {}
+            ",
+            resources
+        )
+        .parse::<TestPod>()
+        .unwrap();
+        let container = pod.containers().first().unwrap().to_owned();
+
+        let limits = ResourceLimits::from_container(&container).ok();
+
+        assert_eq!(expected_limits, limits);
+        if let Some(limits) = limits {
+            assert_eq!(
+                expected_directives
+                    .into_iter()
+                    .map(|(key, value)| (key, String::from(value)))
+                    .collect::<Vec<_>>(),
+                limits.unit_directives()
+            );
+        }
+    }
+}
@@ -0,0 +1,206 @@
+//! Resolution of secrets from an external backend
+//!
+//! Config files can reference secrets with placeholders in the form `{{secret:<path>}}`, e.g.
+//! `{{secret:kafka/ssl/keystore-password}}`, so that credentials do not have to be stored in
+//! ConfigMaps. The placeholders are resolved in the `CreatingConfig` stage by requesting
+//! `<backend url>/<namespace>/<path>` from the backend which is configured with the option
+//! `secrets-backend-url`. The body of the response is used as value. If a token file is
+//! configured then its content is sent as bearer token.
+//!
+//! The paths are scoped by the namespace of the pod because everyone who may create ConfigMaps
+//! and pods in a namespace can read the resolved secrets from the config files. Otherwise the
+//! pods of one namespace could retrieve the secrets of all other namespaces.
+//!
+//! The resolved values are added to the template data under the names returned by
+//! [`variable_name`] and the placeholders are replaced with references to these variables by
+//! [`substitute_placeholders`], so that unresolved secrets fail the rendering in strict mode.
+use std::collections::BTreeSet;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use lazy_static::lazy_static;
+use log::debug;
+use regex::{Captures, Regex};
+use reqwest::header::AUTHORIZATION;
+use reqwest::{Client, Url};
+use tokio::fs::read_to_string;
+
+use crate::provider::error::StackableError;
+use crate::provider::error::StackableError::SecretResolutionError;
+
+lazy_static! {
+    // Placeholder of a secret, e.g. `{{secret:kafka/ssl/keystore-password}}`
+    static ref SECRET_PLACEHOLDER_PATTERN: Regex =
+        Regex::new(r"\{\{\s*secret:([A-Za-z0-9_.\-/]+)\s*\}\}").unwrap();
+}
+
+/// Timeout for a request to the secrets backend
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Client for the secrets backend
+#[derive(Clone, Debug)]
+pub struct SecretsBackend {
+    url: Url,
+    token_file: Option<PathBuf>,
+    client: Client,
+}
+
+impl SecretsBackend {
+    /// Creates a client for the backend at the given URL.
+    ///
+    /// The token file is read for every request, so that the token can be rotated without
+    /// restarting the agent.
+    pub fn new(url: Url, token_file: Option<PathBuf>) -> Result<Self, StackableError> {
+        let client = Client::builder().timeout(REQUEST_TIMEOUT).build()?;
+
+        Ok(SecretsBackend {
+            url,
+            token_file,
+            client,
+        })
+    }
+
+    /// Retrieves the secret with the given path in the given namespace from the backend.
+    pub async fn resolve(&self, namespace: &str, path: &str) -> Result<String, StackableError> {
+        let error = |msg: String| SecretResolutionError {
+            path: path.to_owned(),
+            msg,
+        };
+
+        let url = secret_url(&self.url, namespace, path)
+            .map_err(|_| error(String::from("Invalid path")))?;
+        debug!("Requesting secret [{}] from [{}]", path, url);
+
+        let mut request = self.client.get(url);
+        if let Some(token_file) = &self.token_file {
+            let token = read_to_string(token_file).await.map_err(|e| {
+                error(format!(
+                    "The token file [{}] could not be read: {}",
+                    token_file.to_string_lossy(),
+                    e
+                ))
+            })?;
+            request = request.header(AUTHORIZATION, format!("Bearer {}", token.trim()));
+        }
+
+        let response = request.send().await.map_err(|e| error(e.to_string()))?;
+        let status = response.status();
+        if !status.is_success() {
+            return Err(error(format!("The backend responded with [{}]", status)));
+        }
+
+        response.text().await.map_err(|e| error(e.to_string()))
+    }
+}
+
+/// Returns the URL of the secret with the given path in the given namespace.
+///
+/// Paths with empty, `.`, or `..` segments are rejected, so that secrets cannot escape the
+/// path of the namespace in the backend.
+fn secret_url(base_url: &Url, namespace: &str, path: &str) -> Result<Url, ()> {
+    let is_invalid_segment =
+        |segment: &str| segment.is_empty() || segment == "." || segment == "..";
+
+    if namespace.contains('/')
+        || is_invalid_segment(namespace)
+        || path.split('/').any(is_invalid_segment)
+    {
+        return Err(());
+    }
+
+    Url::parse(&format!(
+        "{}/{}/{}",
+        base_url.as_str().trim_end_matches('/'),
+        namespace,
+        path
+    ))
+    .map_err(|_| ())
+}
+
+/// Returns the paths of all secrets which are referenced in the given template.
+pub fn secret_paths(template: &str) -> BTreeSet<String> {
+    SECRET_PLACEHOLDER_PATTERN
+        .captures_iter(template)
+        .map(|captures| String::from(&captures[1]))
+        .collect()
+}
+
+/// Returns the name of the template variable which contains the secret with the given path.
+pub fn variable_name(path: &str) -> String {
+    format!("secret_{}", hex::encode(path))
+}
+
+/// Checks if the template variable with the given name contains a secret.
+pub fn is_secret_variable(name: &str) -> bool {
+    name.starts_with("secret_")
+}
+
+/// Replaces the secret placeholders in the given template with references to the template
+/// variables which contain the secrets.
+///
+/// The values are not HTML-escaped.
+pub fn substitute_placeholders(template: &str) -> String {
+    SECRET_PLACEHOLDER_PATTERN
+        .replace_all(template, |captures: &Captures| {
+            format!("{{{{{{{}}}}}}}", variable_name(&captures[1]))
+        })
+        .into_owned()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use rstest::rstest;
+
+    #[test]
+    fn placeholders_should_be_substituted() {
+        let template = "password={{secret:kafka/password}}\nuser={{ secret:kafka/user }}";
+
+        assert_eq!(
+            vec!["kafka/password", "kafka/user"],
+            secret_paths(template).into_iter().collect::<Vec<_>>()
+        );
+        assert_eq!(
+            format!(
+                "password={{{{{{{}}}}}}}\nuser={{{{{{{}}}}}}}",
+                variable_name("kafka/password"),
+                variable_name("kafka/user")
+            ),
+            substitute_placeholders(template)
+        );
+        assert_eq!("{{configroot}}", substitute_placeholders("{{configroot}}"));
+    }
+
+    #[rstest]
+    #[case(
+        "default",
+        "kafka/password",
+        Some("https://vault.example.com/v1/default/kafka/password")
+    )]
+    #[case(
+        "default",
+        "kube-system/password",
+        Some("https://vault.example.com/v1/default/kube-system/password")
+    )]
+    #[case("default", "kafka/../password", None)]
+    #[case("default", "../kube-system/password", None)]
+    #[case("default", "kafka//password", None)]
+    #[case("default", "./password", None)]
+    #[case("..", "kafka/password", None)]
+    #[case("", "kafka/password", None)]
+    #[case("default/..", "kafka/password", None)]
+    fn secret_urls_should_be_scoped_by_namespace(
+        #[case] namespace: &str,
+        #[case] path: &str,
+        #[case] expected_url: Option<&str>,
+    ) {
+        let base_url = Url::parse("https://vault.example.com/v1/").unwrap();
+
+        assert_eq!(
+            expected_url.map(String::from),
+            secret_url(&base_url, namespace, path)
+                .ok()
+                .map(String::from)
+        );
+    }
+}
@@ -0,0 +1,212 @@
+//! Graceful shutdown
+//!
+//! When the agent is terminated, e.g. because the node is shut down, the in-flight downloads are
+//! cancelled. Cancelled downloads leave their partial files behind and are resumed on the next
+//! start. The services keep running by default and are adopted again on the next start.
+//!
+//! If the option `stop-units-on-shutdown` is set, then the systemd units of the pods are also
+//! stopped so that the services can shut down cleanly instead of being killed together with the
+//! node, and the stopped containers are reported as waiting. The units are neither disabled nor
+//! removed, so the agent adopts them again in the `Starting` stage on its next start and starts
+//! them again.
+use std::sync::Arc;
+use std::time::Duration;
+
+use futures_util::future::{self, join_all};
+use k8s_openapi::api::core::v1::Pod as KubePod;
+use kube::{Api, Client};
+use kubelet::pod::{Pod, PodKey};
+use log::{info, warn};
+use tokio::sync::watch;
+
+use super::kubernetes::status::patch_waiting_state;
+use super::systemdmanager::manager::SystemdManager;
+use super::systemdmanager::systemd1_api::StopMode;
+use super::{ContainerHandle, PodHandle, ProviderState};
+
+/// Reason of the waiting state of containers which were stopped during the shutdown
+const SHUTDOWN_REASON: &str = "AgentShutdown";
+
+/// Signal which is triggered when the agent shuts down
+///
+/// Long-running operations like downloads wait for it to be cancelled before the agent exits.
+#[derive(Clone, Debug)]
+pub struct ShutdownSignal {
+    sender: Arc<watch::Sender<bool>>,
+    receiver: watch::Receiver<bool>,
+}
+
+impl Default for ShutdownSignal {
+    fn default() -> Self {
+        let (sender, receiver) = watch::channel(false);
+        ShutdownSignal {
+            sender: Arc::new(sender),
+            receiver,
+        }
+    }
+}
+
+impl ShutdownSignal {
+    /// Triggers the signal.
+    fn trigger(&self) {
+        // Sending fails only if there are no receivers but the signal holds one itself.
+        let _ = self.sender.send(true);
+    }
+
+    /// Checks if the signal was triggered.
+    pub fn is_triggered(&self) -> bool {
+        *self.receiver.borrow()
+    }
+
+    /// Waits until the signal is triggered. The returned future resolves immediately if the
+    /// signal was already triggered.
+    pub async fn triggered(&self) {
+        let mut receiver = self.receiver.clone();
+        while !*receiver.borrow() {
+            if receiver.changed().await.is_err() {
+                // The sender is gone, so the signal cannot be triggered anymore.
+                future::pending::<()>().await;
+            }
+        }
+    }
+}
+
+/// Handle to stop the managed units which stays usable after the provider was passed to the
+/// Kubelet
+#[derive(Clone)]
+pub struct ShutdownHandle {
+    pub(super) provider_state: ProviderState,
+}
+
+impl ShutdownHandle {
+    /// Cancels the in-flight downloads and prevents that new ones are started.
+    pub fn cancel_downloads(&self) {
+        info!("Cancelling the in-flight downloads");
+        self.provider_state.shutdown_signal.trigger();
+    }
+
+    /// Stops all units of the pods on this node and waits at most for the given grace period.
+    ///
+    /// The pods are stopped concurrently. When the units of a pod are stopped, its containers
+    /// are reported as waiting, so that the pod status does not claim that the services are
+    /// still running. Errors are only logged because the agent terminates anyway.
+    pub async fn stop_units(&self, grace_period: Duration) {
+        let (systemd_manager, client, pod_handles) = {
+            let handles = self.provider_state.handles.read().await;
+            (
+                self.provider_state.systemd_manager.clone(),
+                self.provider_state.client.clone(),
+                handles
+                    .iter()
+                    .map(|(pod_key, pod_handle)| (pod_key.to_owned(), pod_handle.to_owned()))
+                    .collect::<Vec<_>>(),
+            )
+        };
+
+        info!(
+            "Stopping [{}] systemd units within [{}] seconds",
+            pod_handles
+                .iter()
+                .map(|(_, pod_handle)| pod_handle.len())
+                .sum::<usize>(),
+            grace_period.as_secs()
+        );
+
+        let stop_jobs =
+            join_all(pod_handles.iter().map(|(pod_key, pod_handle)| {
+                stop_pod(&systemd_manager, &client, pod_key, pod_handle)
+            }));
+
+        if tokio::time::timeout(grace_period, stop_jobs).await.is_err() {
+            warn!(
+                "Not all systemd units were stopped within the grace period of [{}] seconds",
+                grace_period.as_secs()
+            );
+        }
+    }
+}
+
+/// Stops the units of the given pod and reports its app containers as waiting afterwards.
+async fn stop_pod(
+    systemd_manager: &SystemdManager,
+    client: &Client,
+    pod_key: &PodKey,
+    pod_handle: &PodHandle,
+) {
+    join_all(
+        pod_handle
+            .values()
+            .map(|container_handle| stop_container(systemd_manager, container_handle)),
+    )
+    .await;
+
+    let api: Api<KubePod> = Api::namespaced(client.to_owned(), &pod_key.namespace());
+    let pod = match api.get(&pod_key.name()).await {
+        Ok(pod) => Pod::from(pod),
+        Err(error) => {
+            warn!(
+                "The status of pod [{}/{}] could not be updated: {}",
+                pod_key.namespace(),
+                pod_key.name(),
+                error
+            );
+            return;
+        }
+    };
+
+    for container_key in pod_handle.keys().filter(|key| key.is_app()) {
+        if let Err(error) = patch_waiting_state(
+            client,
+            &pod,
+            container_key,
+            SHUTDOWN_REASON,
+            "The service was stopped because the agent was shut down.",
+        )
+        .await
+        {
+            warn!("{}", error);
+        }
+    }
+}
+
+/// Stops the trigger unit of the container if there is one and then its service unit, so that
+/// the service is not activated again.
+async fn stop_container(systemd_manager: &SystemdManager, container_handle: &ContainerHandle) {
+    let units = container_handle
+        .trigger_unit
+        .iter()
+        .chain(Some(&container_handle.service_unit));
+
+    for unit in units {
+        info!("Stopping systemd unit [{}]", unit);
+        if let Err(error) = systemd_manager.stop(unit, StopMode::Replace).await {
+            warn!("{}", error);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[tokio::test]
+    async fn waiting_operations_should_be_notified_when_the_signal_is_triggered() {
+        let shutdown_signal = ShutdownSignal::default();
+        let waiting_operation = tokio::spawn({
+            let shutdown_signal = shutdown_signal.clone();
+            async move { shutdown_signal.triggered().await }
+        });
+
+        assert!(!shutdown_signal.is_triggered());
+        shutdown_signal.trigger();
+
+        tokio::time::timeout(Duration::from_secs(1), waiting_operation)
+            .await
+            .expect("The waiting operation was not notified.")
+            .unwrap();
+        assert!(shutdown_signal.is_triggered());
+
+        // Operations which start after the shutdown are cancelled immediately.
+        shutdown_signal.triggered().await;
+    }
+}
@@ -1,8 +1,14 @@
+use std::fs;
+use std::io;
+use std::net::IpAddr;
 use std::path::PathBuf;
+use std::sync::Arc;
 
 use kubelet::backoff::ExponentialBackoffStrategy;
 use kubelet::pod::state::prelude::*;
 use kubelet::pod::{Pod, Status};
+use log::info;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
 
 use crate::provider::repository::package::Package;
 use crate::provider::ProviderState;
@@ -25,9 +31,31 @@ pub struct PodState {
     pub config_directory: PathBuf,
     pub log_directory: PathBuf,
     pub package_download_backoff_strategy: ExponentialBackoffStrategy,
+    /// Number of failed downloads of the package, see [`DownloadingBackoff`]
+    ///
+    /// [`DownloadingBackoff`]: downloading_backoff::DownloadingBackoff
+    pub download_failures: u32,
     pub service_name: String,
     pub service_uid: String,
     pub package: Package,
+    /// Template for the name of the package directory, see [`Package::get_directory_name`]
+    pub package_directory_template: String,
+    /// User which runs the services if the security context of the pod specifies none
+    pub default_service_user: Option<String>,
+    /// Journal namespace to which the services log or [`None`] for the default journal
+    pub log_namespace: Option<String>,
+    /// Name of the node which runs the pod
+    pub node_name: String,
+    /// IP address of the node which runs the pod
+    pub node_ip: IpAddr,
+    /// Maximum length of the unit names, see [`SystemDUnit::pod_slice_name`]
+    ///
+    /// [`SystemDUnit::pod_slice_name`]: crate::provider::systemdmanager::systemdunit::SystemDUnit::pod_slice_name
+    pub unit_name_max_length: usize,
+    /// Config files which contain resolved secrets, see [`CreatingConfig`]
+    ///
+    /// [`CreatingConfig`]: creating_config::CreatingConfig
+    pub secret_files: Vec<PathBuf>,
 }
 
 impl PodState {
@@ -37,14 +65,31 @@ impl PodState {
     }
 
     pub fn get_service_package_directory(&self) -> PathBuf {
-        self.parcel_directory
-            .join(&self.package.get_directory_name())
+        self.parcel_directory.join(
+            &self
+                .package
+                .get_directory_name(&self.package_directory_template),
+        )
     }
 
     pub fn get_service_log_directory(&self) -> PathBuf {
         self.log_directory.join(&self.service_name)
     }
 
+    /// Returns the image ID of the installed package in the form
+    /// `<product>:<version>@sha256:<hash>`.
+    ///
+    /// The hash of the archive is read from the checksum file which is written on installation.
+    pub fn get_package_image_id(&self) -> io::Result<String> {
+        let checksum = fs::read_to_string(
+            self.parcel_directory.join(
+                self.package
+                    .get_checksum_file_name(&self.package_directory_template),
+            ),
+        )?;
+        Ok(format!("{}@sha256:{}", self.package, checksum.trim()))
+    }
+
     /// Resolve the directory in which the systemd unit files will be placed for this
     /// service.
     /// This defaults to "{{config_root}}/_service"
@@ -56,6 +101,29 @@ impl PodState {
     }
 }
 
+/// Acquires a permit to download or install a package.
+///
+/// The number of permits is limited by the configuration option `max-concurrent-installs`. The
+/// permit is released when it is dropped.
+pub async fn acquire_install_permit(
+    install_permits: Arc<Semaphore>,
+    service_name: &str,
+) -> OwnedSemaphorePermit {
+    match install_permits.clone().try_acquire_owned() {
+        Ok(permit) => permit,
+        Err(_) => {
+            info!(
+                "Service {} is waiting for other downloads or installations to finish",
+                service_name
+            );
+            install_permits
+                .acquire_owned()
+                .await
+                .expect("The semaphore for installations is never closed")
+        }
+    }
+}
+
 // No cleanup state needed, we clean up when dropping PodState.
 #[async_trait::async_trait]
 impl ObjectState for PodState {
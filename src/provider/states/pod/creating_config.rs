@@ -1,27 +1,96 @@
-use std::collections::{BTreeMap, HashMap};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
 use std::fs;
-use std::fs::read_to_string;
-use std::path::{Path, PathBuf};
+use std::fs::{OpenOptions, Permissions};
+use std::io::Write;
+use std::os::unix::fs::{symlink, FileTypeExt, OpenOptionsExt, PermissionsExt};
+use std::path::{Component, Path, PathBuf};
 
 use handlebars::Handlebars;
-use k8s_openapi::api::core::v1::ConfigMap;
+use k8s_openapi::api::core::v1::{ConfigMap, KeyToPath, VolumeMount};
+use k8s_openapi::ByteString;
 use kube::{Api, Client};
 use kubelet::pod::state::prelude::*;
 use kubelet::pod::Pod;
+use lazy_static::lazy_static;
 use log::{debug, error, info, trace, warn};
+use regex::{Captures, Regex};
 
 use super::creating_service::CreatingService;
 use super::setup_failed::SetupFailed;
 use super::waiting_config_map::WaitingConfigMap;
 use crate::fail_fatal;
+use crate::fsext;
 use crate::provider::error::StackableError;
 use crate::provider::error::StackableError::{
-    ConfigFileWriteError, DirectoryParseError, MissingConfigMapsError, PodValidationError,
-    RuntimeError,
+    ConfigFileWriteError, DirectoryParseError, HostPathError, MissingConfigMapsError,
+    PodValidationError, RuntimeError,
 };
+use crate::provider::kubernetes::accessor::boolean_annotation;
+use crate::provider::secrets::{self, SecretsBackend};
+use crate::provider::systemdmanager::systemdunit::{ServiceUser, SystemDUnit};
+use crate::provider::transitions::record_transition;
 use crate::provider::{PodState, ProviderState};
 use kube::error::ErrorResponse;
 
+lazy_static! {
+    // Pattern for the UIDs of pods which are part of the names of the config directories
+    static ref POD_UID_PATTERN: Regex =
+        Regex::new("^[0-9a-f]{8}-[0-9a-f]{4}-[0-9a-f]{4}-[0-9a-f]{4}-[0-9a-f]{12}$").unwrap();
+
+    // Pattern for simple references to variables in templates, e.g. `{{name}}` or `{{{name}}}`
+    static ref VARIABLE_REFERENCE_PATTERN: Regex =
+        Regex::new(r"\{\{\{?\s*([A-Za-z_][A-Za-z0-9_.\-]*)\s*\}?\}\}").unwrap();
+}
+
+/// Annotation which disables the strict rendering of the ConfigMaps of the pod, either `true`
+/// (default) or `false`, see [`CreatingConfig::render_template`]
+pub const STRICT_TEMPLATES_ANNOTATION: &str = "stackable.tech/strict-templates";
+
+/// Delimiter of the placeholders for unknown references in non-strict templates. It is a
+/// character of the private use area of Unicode, so that it does not occur in regular content.
+const UNKNOWN_REFERENCE_DELIMITER: char = '\u{e000}';
+
+/// The mode of config files if neither `defaultMode` nor the `mode` of an item is set, like in
+/// Kubernetes
+const DEFAULT_FILE_MODE: u32 = 0o644;
+
+/// The mode of config files which contain resolved secrets if neither `defaultMode` nor the
+/// `mode` of an item is set, so that the secrets can only be read by the owner
+const SECRET_FILE_MODE: u32 = 0o600;
+
+/// Prefix of the keys in the template data which contain the environment variables of the
+/// containers. The variables are nested under `env` in the templates, e.g. `{{env.FOO}}`.
+const ENV_VARIABLE_PREFIX: &str = "env.";
+
+/// A mount of a ConfigMap volume in the container
+#[derive(Clone, Debug)]
+struct ConfigMapMount {
+    config_map: String,
+    items: Option<Vec<KeyToPath>>,
+    default_mode: Option<i32>,
+    sub_path: Option<String>,
+    /// User of the container which mounts the volume who becomes the owner of the files with
+    /// secrets
+    secret_file_owner: ServiceUser,
+}
+
+/// Content of a key of a ConfigMap
+#[derive(Clone, Debug)]
+enum ConfigMapContent {
+    /// Content of `data` which is rendered as template
+    Text(String),
+    /// Content of `binaryData` which is written unchanged
+    Binary(Vec<u8>),
+}
+
+/// A mount of a hostPath volume in the container
+#[derive(Clone, Debug)]
+struct HostPathMount {
+    path: String,
+    type_: Option<String>,
+    sub_path: Option<String>,
+}
+
 #[derive(Default, Debug, TransitionTo)]
 #[transition_to(CreatingService, SetupFailed, WaitingConfigMap)]
 pub struct CreatingConfig {
@@ -32,22 +101,117 @@ impl CreatingConfig {
     pub fn render_config_template(
         data: &BTreeMap<String, String>,
         template: &str,
+    ) -> Result<String, StackableError> {
+        CreatingConfig::render_template(data, template, true)
+    }
+
+    /// Renders the given template with the given data.
+    ///
+    /// In strict mode, references to unknown variables are rejected, so that typos do not remain
+    /// unnoticed. Otherwise simple references to unknown variables like `{{name}}` are left
+    /// untouched, so that templates of third-party tools which use the same syntax can be
+    /// rendered, and unknown variables in other expressions are treated as empty.
+    pub fn render_template(
+        data: &BTreeMap<String, String>,
+        template: &str,
+        strict: bool,
     ) -> Result<String, StackableError> {
         let mut handlebars = Handlebars::new();
-        debug!("Rendering template with context: {:?}", data);
+        debug!(
+            "Rendering template with context: {:?}",
+            data.iter()
+                .map(|(key, value)| if secrets::is_secret_variable(key) {
+                    (key, "<redacted>")
+                } else {
+                    (key, value.as_str())
+                })
+                .collect::<BTreeMap<_, _>>()
+        );
+
+        let context = CreatingConfig::render_context(data);
+        let template = secrets::substitute_placeholders(template);
+        let (template, unknown_references) = if strict {
+            (template, Vec::new())
+        } else {
+            CreatingConfig::protect_unknown_references(&template, &context)
+        };
 
         // register the template. The template string will be verified and compiled.
         handlebars.register_template_string("t1", template)?;
 
         // Set strict mode, so that we fail with an error if any non-existent fields are accessed
-        handlebars.set_strict_mode(true);
+        handlebars.set_strict_mode(strict);
 
         // Render the template with the provided data and return the resulting String
-        Ok(handlebars.render("t1", &data)?)
+        let mut rendered = handlebars.render("t1", &context)?;
+        for (index, reference) in unknown_references.iter().enumerate() {
+            rendered = rendered.replace(&unknown_reference_placeholder(index), reference);
+        }
+        Ok(rendered)
+    }
+
+    /// Replaces the simple references to variables which are not contained in the given context
+    /// with placeholders which are not interpreted by Handlebars. The template with the
+    /// placeholders and the replaced references in the order of their placeholders are
+    /// returned.
+    fn protect_unknown_references(
+        template: &str,
+        context: &serde_json::Value,
+    ) -> (String, Vec<String>) {
+        let mut unknown_references = Vec::new();
+
+        let template = VARIABLE_REFERENCE_PATTERN
+            .replace_all(template, |captures: &Captures| {
+                let path = &captures[1];
+                let known = path == "this"
+                    || path == "else"
+                    || path
+                        .split('.')
+                        .try_fold(context, |value, key| value.get(key))
+                        .is_some();
+
+                if known {
+                    String::from(&captures[0])
+                } else {
+                    unknown_references.push(String::from(&captures[0]));
+                    unknown_reference_placeholder(unknown_references.len() - 1)
+                }
+            })
+            .into_owned();
+
+        (template, unknown_references)
+    }
+
+    /// Converts the template data into the context of Handlebars.
+    ///
+    /// The environment variables are nested under the key `env`, all other variables are
+    /// provided at the top level.
+    fn render_context(data: &BTreeMap<String, String>) -> serde_json::Value {
+        let mut context = serde_json::Map::new();
+        let mut env = serde_json::Map::new();
+
+        for (key, value) in data {
+            match key.strip_prefix(ENV_VARIABLE_PREFIX) {
+                Some(name) => env.insert(String::from(name), value.as_str().into()),
+                None => context.insert(key.to_owned(), value.as_str().into()),
+            };
+        }
+
+        if !env.is_empty() {
+            context.insert(String::from("env"), serde_json::Value::Object(env));
+        }
+
+        serde_json::Value::Object(context)
     }
 
+    /// Creates the variables which can be used in the templates of config files and commands.
+    ///
+    /// Besides the directories of the service, the name and IP address of the node as well as
+    /// the name and namespace of the pod are provided, so that config files can be adapted to
+    /// the node they are deployed on.
     pub fn create_render_data(
         pod_state: &PodState,
+        pod: &Pod,
     ) -> Result<BTreeMap<String, String>, StackableError> {
         let mut render_data = BTreeMap::new();
 
@@ -72,11 +236,83 @@ impl CreatingConfig {
                 pod_state.get_service_log_directory(),
             )?,
         );
+        render_data.insert(String::from("nodename"), pod_state.node_name.to_owned());
+        render_data.insert(String::from("nodeip"), pod_state.node_ip.to_string());
+        render_data.insert(String::from("podname"), pod.name().to_string());
+        render_data.insert(String::from("namespace"), pod.namespace().to_string());
 
         // Return all template data
         Ok(render_data)
     }
 
+    /// Adds the environment variables of the containers to the given template data, so that
+    /// they can be referenced in config files with `{{env.<name>}}`.
+    ///
+    /// The values are rendered with the given template data like the environment of the
+    /// systemd unit, so they can reference the directories but no other environment variables.
+    /// If several containers define the same variable then the first definition is used.
+    fn add_environment_variables(
+        pod: &Pod,
+        template_data: &mut BTreeMap<String, String>,
+    ) -> Result<(), StackableError> {
+        let mut env_variables = BTreeMap::new();
+
+        for env_var in pod
+            .containers()
+            .iter()
+            .flat_map(|container| container.env().to_owned().unwrap_or_default())
+        {
+            let key = format!("{}{}", ENV_VARIABLE_PREFIX, env_var.name);
+            if !env_variables.contains_key(&key) {
+                let value = CreatingConfig::render_config_template(
+                    template_data,
+                    env_var.value.as_deref().unwrap_or_default(),
+                )?;
+                env_variables.insert(key, value);
+            }
+        }
+
+        template_data.append(&mut env_variables);
+        Ok(())
+    }
+
+    /// Resolves the secrets which are referenced in the given config maps with the given backend
+    /// and adds them to the template data.
+    ///
+    /// The secrets are resolved in the given namespace of the pod, see [`SecretsBackend::resolve`].
+    /// An error is returned if secrets are referenced but no backend is configured.
+    async fn resolve_secrets(
+        secrets_backend: Option<&SecretsBackend>,
+        namespace: &str,
+        config_maps: &HashMap<String, ConfigMap>,
+        template_data: &mut BTreeMap<String, String>,
+    ) -> Result<(), StackableError> {
+        let secret_paths = config_maps
+            .values()
+            .flat_map(|config_map| config_map.data.iter().flat_map(|data| data.values()))
+            .map(String::as_str)
+            .flat_map(secrets::secret_paths)
+            .collect::<BTreeSet<_>>();
+
+        if secret_paths.is_empty() {
+            return Ok(());
+        }
+
+        let secrets_backend = secrets_backend.ok_or_else(|| RuntimeError {
+            msg: format!(
+                "The secrets {:?} are referenced but no secrets backend is configured.",
+                secret_paths
+            ),
+        })?;
+
+        for path in secret_paths {
+            let secret = secrets_backend.resolve(namespace, &path).await?;
+            template_data.insert(secrets::variable_name(&path), secret);
+        }
+
+        Ok(())
+    }
+
     // Public for testing
     pub fn pathbuf_to_string(target_field: &str, path: PathBuf) -> Result<String, StackableError> {
         let path_as_string = path.into_os_string().into_string();
@@ -150,13 +386,15 @@ impl CreatingConfig {
         get_config_maps
     }
 
-    ///
-    ///
+    /// Writes the selected keys of the given ConfigMap to the target path, see
+    /// [`CreatingConfig::config_files`], and returns the files which contain secrets.
     fn apply_config_map(
         map: &ConfigMap,
-        target_directory: &Path,
+        mount: &ConfigMapMount,
+        target_path: &Path,
         template_data: &BTreeMap<String, String>,
-    ) -> Result<(), StackableError> {
+        strict_templates: bool,
+    ) -> Result<Vec<PathBuf>, StackableError> {
         if map.metadata.name.is_none() {
             return Err(RuntimeError {
                 msg: String::from(
@@ -167,59 +405,562 @@ impl CreatingConfig {
         let map = map.clone();
         let config_map_name = &map.metadata.name.expect("Got object with no name from K8s, even though we checked for this one line ago - something went seriously wrong!");
         debug!(
-            "applying configmap {} to directory {:?}",
-            &config_map_name, target_directory
+            "applying configmap {} to path {:?}",
+            &config_map_name, target_path
         );
-        if !(&target_directory.is_dir()) {
-            info!("creating config directory {:?}", target_directory);
-            fs::create_dir_all(&target_directory)?;
-        }
-        if let Some(data) = map.data {
-            debug!("Map contained keys: {:?}", &data.keys());
-            for key in data.keys() {
-                debug!("found key: {} in configmap {}", key, &config_map_name);
-                if let Some(content) = data.get(key) {
+        // Without a subPath the mount path is a directory which contains the files of the
+        // ConfigMap, otherwise it is the file itself.
+        if mount.sub_path.is_none() && !(&target_path.is_dir()) {
+            info!("creating config directory {:?}", target_path);
+            fs::create_dir_all(&target_path)?;
+        }
+        let data = CreatingConfig::config_map_contents(map.data, map.binary_data);
+        if data.is_empty() {
+            debug!("No data found in ConfigMap..");
+        }
+        debug!("Map contained keys: {:?}", &data.keys());
+
+        let mut secret_files = Vec::new();
+        for (key, target_file) in CreatingConfig::config_files(
+            config_map_name,
+            &data,
+            target_path,
+            mount.items.as_deref(),
+            mount.sub_path.as_deref(),
+        )? {
+            debug!("found key: {} in configmap {}", key, &config_map_name);
+            let contains_secrets = match &data[&key] {
+                ConfigMapContent::Text(content) => !secrets::secret_paths(content).is_empty(),
+                ConfigMapContent::Binary(_) => false,
+            };
+            let mode = CreatingConfig::file_mode(
+                config_map_name,
+                &key,
+                mount.items.as_deref(),
+                mount.default_mode,
+                contains_secrets,
+            )?;
+            let rendered_content = match &data[&key] {
+                ConfigMapContent::Text(content) => {
                     trace!("content of key: {}", &content);
                     debug!("rendering");
                     let rendered_content =
-                        CreatingConfig::render_config_template(template_data, content)?;
+                        CreatingConfig::render_template(template_data, content, strict_templates)?;
                     debug!("done rendering");
-                    let target_file = target_directory.join(&key);
+                    rendered_content.into_bytes()
+                }
+                ConfigMapContent::Binary(content) => {
+                    debug!("binary content of key with {} bytes", content.len());
+                    content.to_owned()
+                }
+            };
 
-                    if CreatingConfig::needs_update(&target_file, &rendered_content)? {
-                        debug!(
-                            "writing content of map entry {} to file {:?}",
-                            key, target_file
-                        );
-                        let write_result = fs::write(target_directory.join(&key), rendered_content);
-                        match write_result {
-                            Ok(()) => debug!("write of file {:?} successful!", target_file),
-                            Err(e) => {
-                                error!("write of file {:?} failed: {}", target_file, e);
-                                return Err(ConfigFileWriteError {
-                                    target_file: target_file.to_str().unwrap().to_string(),
-                                    config_map: config_map_name.clone(),
-                                });
-                            }
-                        }
+            if let Some(target_directory) = target_file.parent() {
+                if !(&target_directory.is_dir()) {
+                    info!("creating config directory {:?}", target_directory);
+                    fs::create_dir_all(&target_directory)?;
+                }
+            }
+
+            if CreatingConfig::needs_update(&target_file, &rendered_content)? {
+                debug!(
+                    "writing content of map entry {} to file {:?}",
+                    key, target_file
+                );
+                let write_result =
+                    CreatingConfig::write_file(&target_file, &rendered_content, mode);
+                match write_result {
+                    Ok(()) => debug!("write of file {:?} successful!", target_file),
+                    Err(e) => {
+                        error!("write of file {:?} failed: {}", target_file, e);
+                        return Err(ConfigFileWriteError {
+                            target_file: target_file.to_str().unwrap().to_string(),
+                            config_map: config_map_name.clone(),
+                        });
+                    }
+                }
+            } else {
+                debug!("No update needed for {:?}", target_file);
+                // The mode could have been changed without changing the content
+                fs::set_permissions(&target_file, Permissions::from_mode(mode))?;
+            }
+
+            if contains_secrets {
+                CreatingConfig::change_secret_file_owner(&target_file, &mount.secret_file_owner)?;
+                secret_files.push(target_file);
+            }
+        }
+        Ok(secret_files)
+    }
+
+    /// Makes the given file with secrets readable by the given user which runs the service.
+    ///
+    /// Files with secrets can only be read by their owner by default, see [`SECRET_FILE_MODE`].
+    /// They are owned by the static user of the service and its primary group. Files for a
+    /// dynamic user are left to root because the user does not exist yet; they are passed to
+    /// the service with `LoadCredential` instead. The files of services which run as the user of
+    /// the agent are already owned by this user.
+    fn change_secret_file_owner(
+        secret_file: &Path,
+        owner: &ServiceUser,
+    ) -> Result<(), StackableError> {
+        match owner {
+            ServiceUser::Static(user_name) => fsext::change_owner_to_user(secret_file, user_name)
+                .map_err(|error| RuntimeError {
+                    msg: format!(
+                        "The owner of the file {:?} could not be changed to [{}]: {}",
+                        secret_file, user_name, error
+                    ),
+                }),
+            ServiceUser::Dynamic | ServiceUser::Agent => Ok(()),
+        }
+    }
+
+    /// Merges the text and the binary data of a ConfigMap.
+    ///
+    /// Kubernetes ensures that the keys of `data` and `binaryData` do not overlap. The binary
+    /// data is already decoded from base64 when the ConfigMap is deserialized.
+    fn config_map_contents(
+        data: Option<BTreeMap<String, String>>,
+        binary_data: Option<BTreeMap<String, ByteString>>,
+    ) -> BTreeMap<String, ConfigMapContent> {
+        let text_contents = data
+            .unwrap_or_default()
+            .into_iter()
+            .map(|(key, content)| (key, ConfigMapContent::Text(content)));
+        let binary_contents = binary_data
+            .unwrap_or_default()
+            .into_iter()
+            .map(|(key, ByteString(content))| (key, ConfigMapContent::Binary(content)));
+
+        binary_contents.chain(text_contents).collect()
+    }
+
+    /// Writes the content to the given file and sets its mode.
+    ///
+    /// A new file is already created with the given mode, so that the content is never readable
+    /// by other users if the mode forbids it. The mode is set explicitly afterwards because the
+    /// mode of an existing file is not changed on opening and the umask applies on creation.
+    fn write_file(target_file: &Path, content: &[u8], mode: u32) -> std::io::Result<()> {
+        let mut file = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .mode(mode)
+            .open(target_file)?;
+        fs::set_permissions(target_file, Permissions::from_mode(mode))?;
+        file.write_all(content)
+    }
+
+    /// Determines the mode of the file for the given key of a ConfigMap.
+    ///
+    /// Like in Kubernetes, the `mode` of the item takes precedence over the `defaultMode` of the
+    /// volume. If neither is set then [`DEFAULT_FILE_MODE`] is used, or [`SECRET_FILE_MODE`] if
+    /// the content contains secrets.
+    fn file_mode(
+        config_map_name: &str,
+        key: &str,
+        items: Option<&[KeyToPath]>,
+        default_mode: Option<i32>,
+        contains_secrets: bool,
+    ) -> Result<u32, StackableError> {
+        let mode = items
+            .and_then(|items| items.iter().find(|item| item.key == key))
+            .and_then(|item| item.mode)
+            .or(default_mode);
+
+        match mode {
+            None if contains_secrets => Ok(SECRET_FILE_MODE),
+            None => Ok(DEFAULT_FILE_MODE),
+            Some(mode) if (0..=0o777).contains(&mode) => Ok(mode as u32),
+            Some(mode) => Err(PodValidationError {
+                msg: format!(
+                    "The mode [{}] of the key [{}] in ConfigMap [{}] must be between 0 and 0777 (octal).",
+                    mode, key, config_map_name
+                ),
+            }),
+        }
+    }
+
+    /// Determines the keys of a ConfigMap which are written and the files they are written to.
+    ///
+    /// Like in Kubernetes, every key is written to a file with the name of the key in the
+    /// directory of the mount, unless `items` are specified in the volume. In this case only the
+    /// listed keys are written to their relative paths. If the mount specifies a `subPath` then
+    /// only the file or directory at this path of the volume is written to the mount path.
+    fn config_files<T>(
+        config_map_name: &str,
+        data: &BTreeMap<String, T>,
+        target_path: &Path,
+        items: Option<&[KeyToPath]>,
+        sub_path: Option<&str>,
+    ) -> Result<Vec<(String, PathBuf)>, StackableError> {
+        let validate_path = |path: &str| {
+            let relative_path = Path::new(path);
+            if relative_path.is_relative()
+                && relative_path
+                    .components()
+                    .all(|component| matches!(component, Component::Normal(_)))
+            {
+                Ok(relative_path.to_owned())
+            } else {
+                Err(PodValidationError {
+                    msg: format!(
+                        "The path [{}] for ConfigMap [{}] must be relative and must not contain '..'.",
+                        path, config_map_name
+                    ),
+                })
+            }
+        };
+
+        let volume_files = match items {
+            Some(items) => items
+                .iter()
+                .map(|item| {
+                    if data.contains_key(&item.key) {
+                        Ok((item.key.to_owned(), validate_path(&item.path)?))
                     } else {
-                        debug!("No update needed for {:?}", target_file);
+                        Err(PodValidationError {
+                            msg: format!(
+                                "The key [{}] does not exist in ConfigMap [{}].",
+                                item.key, config_map_name
+                            ),
+                        })
                     }
+                })
+                .collect::<Result<Vec<_>, _>>()?,
+            None => data
+                .keys()
+                .map(|key| Ok((key.to_owned(), validate_path(key)?)))
+                .collect::<Result<Vec<_>, StackableError>>()?,
+        };
+
+        match sub_path.filter(|sub_path| !sub_path.is_empty()) {
+            Some(sub_path) => {
+                let sub_path = validate_path(sub_path)?;
+                let files = volume_files
+                    .into_iter()
+                    .filter_map(|(key, path)| {
+                        path.strip_prefix(&sub_path).ok().map(|relative_path| {
+                            if relative_path.as_os_str().is_empty() {
+                                (key, target_path.to_owned())
+                            } else {
+                                (key, target_path.join(relative_path))
+                            }
+                        })
+                    })
+                    .collect::<Vec<_>>();
+
+                if files.is_empty() {
+                    Err(PodValidationError {
+                        msg: format!(
+                            "The subPath [{}] does not exist in ConfigMap [{}].",
+                            sub_path.to_string_lossy(),
+                            config_map_name
+                        ),
+                    })
                 } else {
-                    info!("No content found for key {}", key);
+                    Ok(files)
                 }
             }
+            None => Ok(volume_files
+                .into_iter()
+                .map(|(key, path)| (key, target_path.join(path)))
+                .collect()),
+        }
+    }
+
+    /// Removes the oldest config directories of the given service so that at most `generations`
+    /// directories remain including the one of the current pod.
+    ///
+    /// Every pod gets its own config directory named `<service name>-<pod uid>`, see
+    /// [`PodState::get_service_config_directory`]. The age of a directory is determined by its
+    /// modification time. The removed directories are returned.
+    pub fn prune_config_generations(
+        config_directory: &Path,
+        service_name: &str,
+        current_directory: &Path,
+        generations: usize,
+    ) -> Result<Vec<PathBuf>, StackableError> {
+        if !config_directory.is_dir() {
+            return Ok(Vec::new());
+        }
+
+        let mut previous_generations = Vec::new();
+        for entry in fs::read_dir(config_directory)? {
+            let entry = entry?;
+            let path = entry.path();
+            let is_previous_generation = path != current_directory
+                && entry.file_type()?.is_dir()
+                && entry
+                    .file_name()
+                    .to_str()
+                    .map(|name| CreatingConfig::is_config_generation(name, service_name))
+                    .unwrap_or_default();
+            if is_previous_generation {
+                previous_generations.push((entry.metadata()?.modified()?, path));
+            }
+        }
+
+        // Sort the newest directories first
+        previous_generations.sort_by(|(modified_a, _), (modified_b, _)| modified_b.cmp(modified_a));
+
+        let obsolete_generations = previous_generations
+            .into_iter()
+            .skip(generations.saturating_sub(1))
+            .map(|(_, path)| path)
+            .collect::<Vec<_>>();
+
+        for directory in &obsolete_generations {
+            info!(
+                "Removing obsolete config directory {:?} of service {}",
+                directory, service_name
+            );
+            fs::remove_dir_all(directory)?;
+        }
+
+        Ok(obsolete_generations)
+    }
+
+    /// Checks if the given directory name denotes a config directory of the given service.
+    ///
+    /// The pod UID must follow the service name, otherwise the directories of services whose
+    /// names start with the given service name would match too.
+    fn is_config_generation(directory_name: &str, service_name: &str) -> bool {
+        directory_name
+            .strip_prefix(service_name)
+            .and_then(|suffix| suffix.strip_prefix('-'))
+            .map(|pod_uid| POD_UID_PATTERN.is_match(pod_uid))
+            .unwrap_or_default()
+    }
+
+    /// Checks that the path of a hostPath volume is an absolute path below one of the allowed
+    /// directories and returns the path on the host which is mounted.
+    ///
+    /// The check is performed on the path as given, so that paths like `/data/../etc` are
+    /// rejected independent of the state of the file system. Symbolic links are resolved later in
+    /// [`CreatingConfig::prepare_host_path`].
+    fn validate_host_path(
+        mount: &HostPathMount,
+        allowed_directories: &[PathBuf],
+    ) -> Result<PathBuf, StackableError> {
+        let path = Path::new(&mount.path);
+        let sub_path = Path::new(mount.sub_path.as_deref().unwrap_or_default());
+
+        let is_normal = |path: &Path| {
+            path.components().all(|component| {
+                matches!(
+                    component,
+                    Component::RootDir | Component::CurDir | Component::Normal(_)
+                )
+            })
+        };
+
+        if !path.is_absolute() || !is_normal(path) || sub_path.is_absolute() || !is_normal(sub_path)
+        {
+            return Err(PodValidationError {
+                msg: format!(
+                    "The hostPath [{}] must be absolute, its subPath [{}] must be relative, and both must not contain '..'.",
+                    mount.path,
+                    sub_path.to_string_lossy()
+                ),
+            });
+        }
+
+        let host_path = path.join(sub_path).components().collect::<PathBuf>();
+
+        if CreatingConfig::is_allowed_host_path(&host_path, allowed_directories) {
+            Ok(host_path)
         } else {
-            debug!("No data found in ConfigMap..");
+            Err(PodValidationError {
+                msg: format!(
+                    "The hostPath [{}] is not below one of the allowed directories {:?}, see the option --host-path-allowed.",
+                    host_path.to_string_lossy(),
+                    allowed_directories
+                ),
+            })
         }
+    }
+
+    fn is_allowed_host_path(host_path: &Path, allowed_directories: &[PathBuf]) -> bool {
+        allowed_directories
+            .iter()
+            .any(|allowed_directory| host_path.starts_with(allowed_directory))
+    }
+
+    /// Resolves the symbolic links in the given path.
+    ///
+    /// Parts of the path which do not exist yet are appended unchanged to the resolved path of
+    /// the longest existing prefix.
+    fn resolve_path(path: &Path) -> PathBuf {
+        path.ancestors()
+            .find_map(|ancestor| {
+                ancestor.canonicalize().ok().map(|resolved_ancestor| {
+                    resolved_ancestor.join(path.strip_prefix(ancestor).unwrap_or(path))
+                })
+            })
+            .unwrap_or_else(|| path.to_owned())
+    }
+
+    /// Ensures that the given host path exists according to the `type` of the hostPath volume.
+    ///
+    /// Before anything is created, the symbolic links in the path and in the allowed directories
+    /// are resolved and the path is checked again, so that symbolic links cannot be used to
+    /// escape from the allowed directories. Like in Kubernetes, `DirectoryOrCreate` and
+    /// `FileOrCreate` create an empty directory or file if nothing exists at the path, whereas
+    /// the other types require an existing file of the given type. If no type is set then no
+    /// checks are performed.
+    fn prepare_host_path(
+        host_path: &Path,
+        type_: Option<&str>,
+        allowed_directories: &[PathBuf],
+    ) -> Result<(), StackableError> {
+        let resolved_path = CreatingConfig::resolve_path(host_path);
+        let resolved_allowed_directories = allowed_directories
+            .iter()
+            .map(|directory| CreatingConfig::resolve_path(directory))
+            .collect::<Vec<_>>();
+        if !CreatingConfig::is_allowed_host_path(&resolved_path, &resolved_allowed_directories) {
+            return Err(PodValidationError {
+                msg: format!(
+                    "The hostPath [{}] resolves to [{}] which is not below one of the allowed directories {:?}.",
+                    host_path.to_string_lossy(),
+                    resolved_path.to_string_lossy(),
+                    allowed_directories
+                ),
+            });
+        }
+
+        let type_ = type_.unwrap_or_default();
+        let file_type = fs::metadata(host_path)
+            .ok()
+            .map(|metadata| metadata.file_type());
+
+        let matches_type = match (type_, file_type) {
+            ("", _) => true,
+            ("DirectoryOrCreate", None) => {
+                info!("Creating host directory {:?}", host_path);
+                fs::create_dir_all(host_path)?;
+                true
+            }
+            ("FileOrCreate", None) => {
+                info!("Creating host file {:?}", host_path);
+                OpenOptions::new()
+                    .write(true)
+                    .create(true)
+                    .mode(DEFAULT_FILE_MODE)
+                    .open(host_path)?;
+                true
+            }
+            ("DirectoryOrCreate", Some(file_type)) | ("Directory", Some(file_type)) => {
+                file_type.is_dir()
+            }
+            ("FileOrCreate", Some(file_type)) | ("File", Some(file_type)) => file_type.is_file(),
+            ("Socket", Some(file_type)) => file_type.is_socket(),
+            ("CharDevice", Some(file_type)) => file_type.is_char_device(),
+            ("BlockDevice", Some(file_type)) => file_type.is_block_device(),
+            ("Directory", None)
+            | ("File", None)
+            | ("Socket", None)
+            | ("CharDevice", None)
+            | ("BlockDevice", None) => false,
+            (type_, _) => {
+                return Err(PodValidationError {
+                    msg: format!(
+                        "The type [{}] of the hostPath [{}] is not supported.",
+                        type_,
+                        host_path.to_string_lossy()
+                    ),
+                })
+            }
+        };
+
+        if !matches_type {
+            return Err(HostPathError {
+                path: host_path.to_owned(),
+                msg: format!("must exist with the type [{}]", type_),
+            });
+        }
+
         Ok(())
     }
 
-    fn needs_update(target_file: &Path, content: &str) -> Result<bool, StackableError> {
+    /// Resolves the mount path of a volume against the config directory of the service.
+    ///
+    /// A leading `/` is ignored, so that absolute mount paths like `/data` are placed in the
+    /// config directory as well. Only absolute paths below one of the given base directories are
+    /// kept, e.g. ConfigMaps mounted at `{{packageroot}}/conf`. Mount paths which contain `..` are
+    /// rejected, so that volumes cannot be mounted outside of these directories.
+    fn resolve_mount_path(
+        mount_path: &Path,
+        config_directory: &Path,
+        absolute_base_directories: &[PathBuf],
+    ) -> Result<PathBuf, StackableError> {
+        if mount_path
+            .components()
+            .any(|component| component == Component::ParentDir)
+        {
+            return Err(PodValidationError {
+                msg: format!(
+                    "The mount path [{}] must not contain [..].",
+                    mount_path.to_string_lossy()
+                ),
+            });
+        }
+
+        if absolute_base_directories
+            .iter()
+            .any(|directory| mount_path.starts_with(directory))
+        {
+            return Ok(mount_path.to_owned());
+        }
+
+        let relative_path = mount_path
+            .components()
+            .filter(|component| matches!(component, Component::Normal(_)))
+            .collect::<PathBuf>();
+        Ok(config_directory.join(relative_path))
+    }
+
+    /// Makes the host path available at the target path by creating a symbolic link.
+    ///
+    /// An existing symbolic link is replaced, so that a changed hostPath takes effect. Other
+    /// files at the target path are not touched.
+    fn link_host_path(host_path: &Path, target_path: &Path) -> Result<(), StackableError> {
+        match fs::symlink_metadata(target_path) {
+            Ok(metadata) if metadata.file_type().is_symlink() => {
+                if fs::read_link(target_path)? == host_path {
+                    debug!("No update needed for {:?}", target_path);
+                    return Ok(());
+                }
+                fs::remove_file(target_path)?;
+            }
+            Ok(_) => {
+                return Err(HostPathError {
+                    path: host_path.to_owned(),
+                    msg: format!("cannot be mounted because {:?} already exists", target_path),
+                })
+            }
+            Err(_) => {}
+        }
+
+        if let Some(target_directory) = target_path.parent() {
+            if !target_directory.is_dir() {
+                info!("creating config directory {:?}", target_directory);
+                fs::create_dir_all(target_directory)?;
+            }
+        }
+
+        debug!("Linking host path {:?} to {:?}", host_path, target_path);
+        symlink(host_path, target_path)?;
+        Ok(())
+    }
+
+    fn needs_update(target_file: &Path, content: &[u8]) -> Result<bool, StackableError> {
         if target_file.is_file() {
-            let current_content = read_to_string(target_file)?;
+            let current_content = fs::read(target_file)?;
             debug!("Compared config file {:?} with result of", target_file);
-            return Ok(current_content.ne(content));
+            return Ok(current_content != content);
         }
         debug!(
             "Target config file {:?} doesn't exist, no need to compare.",
@@ -229,6 +970,15 @@ impl CreatingConfig {
     }
 }
 
+/// Returns the placeholder for the unknown reference with the given index, see
+/// [`CreatingConfig::protect_unknown_references`].
+fn unknown_reference_placeholder(index: usize) -> String {
+    format!(
+        "{}{}{}",
+        UNKNOWN_REFERENCE_DELIMITER, index, UNKNOWN_REFERENCE_DELIMITER
+    )
+}
+
 #[async_trait::async_trait]
 impl State<PodState> for CreatingConfig {
     async fn next(
@@ -238,43 +988,104 @@ impl State<PodState> for CreatingConfig {
         pod: Manifest<Pod>,
     ) -> Transition<PodState> {
         let pod = pod.latest();
+        record_transition(&provider_state, &pod, "CreatingConfig", None).await;
 
         let name = pod.name();
-        let client = {
+        let (
+            client,
+            config_generations,
+            host_path_allowed,
+            secrets_backend,
+            allow_multiple_containers,
+            user_mode,
+        ) = {
             let provider_state = provider_state.read().await;
-            provider_state.client.clone()
+            (
+                provider_state.client.clone(),
+                provider_state.config_generations,
+                provider_state.host_path_allowed.clone(),
+                provider_state.secrets_backend.clone(),
+                provider_state.allow_multiple_containers,
+                provider_state.systemd_manager.is_user_mode(),
+            )
         };
 
-        // Check size of containers array, we currently only allow one container to be present, this
-        // might change in the future
+        // The config of this pod is written in this state, so it is a good time to remove the
+        // config directories of previous pods with the same name.
+        if let Err(error) = CreatingConfig::prune_config_generations(
+            &pod_state.config_directory,
+            &pod_state.service_name,
+            &pod_state.get_service_config_directory(),
+            config_generations,
+        ) {
+            warn!(
+                "Obsolete config directories of service {} could not be removed: {}",
+                pod_state.service_name, error
+            );
+        }
+
         debug!(
             "Found all relevant config maps for service for service {}, writing config files.",
             name
         );
-        let container = if pod.containers().len().ne(&1) {
+
+        // Check size of containers array, we currently only allow one container to be present
+        // unless multiple containers are explicitly allowed, this might change in the future
+        let containers = pod.containers();
+        if containers.is_empty() || (containers.len() > 1 && !allow_multiple_containers) {
             let e = PodValidationError {
                 msg: "Only pods containing exactly one container element are supported!"
                     .to_string(),
             };
             fail_fatal!(e);
-        } else {
-            pod.containers().get(0).unwrap().clone()
-        };
+        }
 
-        // Check if the container has mounts defined
-        let mounts = if let Some(mounts) = container.volume_mounts() {
-            // At least one mount is defined which is fine for now
-            mounts
-        } else {
+        // Collect the mounts of all containers, all of them are written into the config
+        // directory of the service. The files of a mount belong to the user of the container
+        // which mounts it first.
+        let mut mounts: Vec<VolumeMount> = Vec::new();
+        let mut mount_users: HashMap<String, ServiceUser> = HashMap::new();
+        for container in &containers {
+            let service_user =
+                match SystemDUnit::service_user(user_mode, pod_state, &pod, container) {
+                    Ok(service_user) => service_user,
+                    Err(e) => fail_fatal!(e),
+                };
+            for mount in container.volume_mounts().into_iter().flatten() {
+                match mounts
+                    .iter()
+                    .find(|existing| existing.mount_path == mount.mount_path)
+                {
+                    Some(existing) if existing.name != mount.name => warn!(
+                        "The volume [{}] of container [{}] is not mounted at [{}] in service {} \
+                        because the volume [{}] of another container is already mounted there.",
+                        mount.name,
+                        container.name(),
+                        mount.mount_path,
+                        pod_state.service_name,
+                        existing.name
+                    ),
+                    Some(_) => {}
+                    None => {
+                        mount_users.insert(mount.mount_path.to_owned(), service_user.to_owned());
+                        mounts.push(mount.to_owned());
+                    }
+                }
+            }
+        }
+
+        // Check if the containers have mounts defined
+        if mounts.is_empty() {
             // No mount defined, nothing to do for us
             info!(
                 "No mounts defined for service {} - skipping create config step",
                 pod_state.service_name
             );
             return Transition::next(self, CreatingService);
-        };
+        }
 
         // Check if there are volumes defined for every mount
+        let mut host_path_mounts = Vec::new();
         let volume_mounts = if let Some(volumes) = pod.volumes() {
             debug!("Found {} volumes in pod {}", volumes.len(), name);
             let mut result = HashMap::new();
@@ -283,8 +1094,31 @@ impl State<PodState> for CreatingConfig {
                     if mount.name.eq(&volume.name) {
                         // This mount references this volume, check if it is a config map volume
                         if let Some(map) = volume.config_map.clone() {
-                            let map_name = map.name.unwrap().clone();
-                            result.insert(mount.mount_path.clone(), map_name);
+                            let config_map_mount = ConfigMapMount {
+                                config_map: map.name.unwrap(),
+                                items: map.items,
+                                default_mode: map.default_mode,
+                                sub_path: mount
+                                    .sub_path
+                                    .clone()
+                                    .filter(|sub_path| !sub_path.is_empty()),
+                                secret_file_owner: mount_users
+                                    .get(&mount.mount_path)
+                                    .cloned()
+                                    .unwrap_or(ServiceUser::Agent),
+                            };
+                            result.insert(mount.mount_path.clone(), config_map_mount);
+                        }
+                        if let Some(host_path) = volume.host_path.clone() {
+                            let host_path_mount = HostPathMount {
+                                path: host_path.path,
+                                type_: host_path.type_,
+                                sub_path: mount
+                                    .sub_path
+                                    .clone()
+                                    .filter(|sub_path| !sub_path.is_empty()),
+                            };
+                            host_path_mounts.push((mount.mount_path.clone(), host_path_mount));
                         }
                     }
                 }
@@ -347,53 +1181,156 @@ impl State<PodState> for CreatingConfig {
         // error cases in the above match statement would have moved the pod to the waiting for
         // configmap state already
 
-        let template_data = if let Ok(data) = CreatingConfig::create_render_data(pod_state) {
-            data
-        } else {
-            error!("Unable to parse directories for command template as UTF8");
+        let strict_templates = match boolean_annotation(&pod, STRICT_TEMPLATES_ANNOTATION) {
+            Ok(strict_templates) => strict_templates.unwrap_or(true),
+            Err(e) => fail_fatal!(e),
+        };
+
+        let mut template_data =
+            if let Ok(data) = CreatingConfig::create_render_data(pod_state, &pod) {
+                data
+            } else {
+                error!("Unable to parse directories for command template as UTF8");
+                return Transition::next(
+                    self,
+                    SetupFailed {
+                        message: "DirectoryParseError".to_string(),
+                    },
+                );
+            };
+
+        if let Err(error) = CreatingConfig::add_environment_variables(&pod, &mut template_data) {
+            error!(
+                "Environment variables of service {} could not be rendered: {}",
+                pod_state.service_name, error
+            );
             return Transition::next(
                 self,
                 SetupFailed {
-                    message: "DirectoryParseError".to_string(),
+                    message: "EnvironmentRenderError".to_string(),
                 },
             );
-        };
+        }
 
-        for (target_path, volume) in volume_mounts {
+        if let Err(error) = CreatingConfig::resolve_secrets(
+            secrets_backend.as_ref(),
+            pod.namespace(),
+            &config_map_data,
+            &mut template_data,
+        )
+        .await
+        {
+            error!(
+                "Secrets of service {} could not be resolved: {}",
+                pod_state.service_name, error
+            );
+            return Transition::next(
+                self,
+                SetupFailed {
+                    message: "SecretResolutionError".to_string(),
+                },
+            );
+        }
+
+        let config_directory = pod_state.get_service_config_directory();
+
+        let mut secret_files = Vec::new();
+        for (target_path, config_map_mount) in volume_mounts {
             // This is a hack for the NiFi operator. We need the volume mounts for NiFi to point to
             // the package root, not the config root.
             // TODO: remove if a better solution for NiFi is implemented.
-            let joined_target_path = pod_state.get_service_config_directory().join(
-                &CreatingConfig::render_config_template(&template_data, &target_path).unwrap(),
-            );
+            let joined_target_path = match CreatingConfig::resolve_mount_path(
+                Path::new(
+                    &CreatingConfig::render_config_template(&template_data, &target_path).unwrap(),
+                ),
+                &config_directory,
+                &[
+                    config_directory.to_owned(),
+                    pod_state.get_service_package_directory(),
+                ],
+            ) {
+                Ok(joined_target_path) => joined_target_path,
+                Err(e) => fail_fatal!(e),
+            };
             // end hack
 
-            let volume = volume.clone();
+            let volume = &config_map_mount.config_map;
 
             debug!("Applying config map {} to {}", volume, target_path);
-            if let Some(volume_content) = config_map_data.get(&volume) {
-                if let Err(e) = CreatingConfig::apply_config_map(
+            if let Some(volume_content) = config_map_data.get(volume) {
+                match CreatingConfig::apply_config_map(
                     volume_content,
+                    &config_map_mount,
                     &joined_target_path,
                     &template_data,
+                    strict_templates,
                 ) {
+                    Ok(files) => secret_files.extend(files),
                     // Creation of config file failed!
+                    Err(e) => {
+                        error!(
+                            "Failed to create config file [{:?}] from config map [{}] due to: {:?}",
+                            &joined_target_path.to_str(),
+                            volume,
+                            e
+                        );
+                        return Transition::next(
+                            self,
+                            SetupFailed {
+                                message: "FailedToCreateConfigFile".to_string(),
+                            },
+                        );
+                    }
+                }
+            }
+            // Creation went well, carry on
+        }
+        pod_state.secret_files = secret_files;
+
+        for (target_path, host_path_mount) in host_path_mounts {
+            let host_path =
+                match CreatingConfig::validate_host_path(&host_path_mount, &host_path_allowed) {
+                    Ok(host_path) => host_path,
+                    Err(e) => fail_fatal!(e),
+                };
+
+            // Host paths are always linked into the config directory because the link replaces
+            // whatever is at the target path.
+            let joined_target_path = match CreatingConfig::resolve_mount_path(
+                Path::new(&target_path),
+                &config_directory,
+                &[],
+            ) {
+                Ok(joined_target_path) => joined_target_path,
+                Err(e) => fail_fatal!(e),
+            };
+
+            debug!("Mounting host path {:?} to {}", host_path, target_path);
+            let result = CreatingConfig::prepare_host_path(
+                &host_path,
+                host_path_mount.type_.as_deref(),
+                &host_path_allowed,
+            )
+            .and_then(|()| CreatingConfig::link_host_path(&host_path, &joined_target_path));
+
+            match result {
+                Ok(()) => {}
+                Err(e @ PodValidationError { .. }) => fail_fatal!(e),
+                Err(e) => {
                     error!(
-                        "Failed to create config file [{:?}] from config map [{}] due to: {:?}",
-                        &joined_target_path.to_str(),
-                        volume,
-                        e
+                        "Failed to mount host path [{:?}] to [{:?}] due to: {:?}",
+                        host_path, joined_target_path, e
                     );
                     return Transition::next(
                         self,
                         SetupFailed {
-                            message: "FailedToCreateConfigFile".to_string(),
+                            message: "FailedToMountHostPath".to_string(),
                         },
                     );
                 }
             }
-            // Creation went well, carry on
         }
+
         debug!("Transitioning to service creation");
         Transition::next(self, CreatingService)
     }
@@ -406,8 +1343,12 @@ impl State<PodState> for CreatingConfig {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::provider::test::{test_pod_state, TestPod};
+    use nix::unistd::{Uid, User};
     use rstest::rstest;
     use std::collections::BTreeMap;
+    use std::net::Ipv4Addr;
+    use std::os::unix::fs::MetadataExt;
     use std::path::PathBuf;
     use std::str::FromStr;
 
@@ -435,6 +1376,116 @@ mod tests {
         );
     }
 
+    #[rstest]
+    #[case::unknown_variable("{{unknown}}", "{{unknown}}")]
+    #[case::unknown_variable_with_spaces("{{ unknown }}", "{{ unknown }}")]
+    #[case::unknown_unescaped_variable("{{{unknown}}}", "{{{unknown}}}")]
+    #[case::unknown_nested_variable("{{env.HOME}} {{var1.length}}", "{{env.HOME}} {{var1.length}}")]
+    #[case::known_variable("{{var1}}-{{{var1}}}", "test-test")]
+    #[case::unknown_variable_in_block("{{#if unknown}}yes{{else}}no{{/if}}", "no")]
+    fn unknown_variables_should_be_kept_in_non_strict_templates(
+        #[case] template: &str,
+        #[case] expected_result: &str,
+    ) {
+        let mut context = BTreeMap::new();
+        context.insert(String::from("var1"), String::from("test"));
+
+        assert_eq!(
+            expected_result,
+            CreatingConfig::render_template(&context, template, false).unwrap()
+        );
+        assert!(CreatingConfig::render_template(&context, "{{unknown}}", true).is_err());
+    }
+
+    #[test]
+    fn render_data_should_contain_node_and_pod_values() {
+        let pod = "
+            apiVersion: v1
+            kind: Pod
+            metadata:
+              name: kafka-0
+              namespace: kafka
+            spec:
+              containers:
+                - name: kafka
+            "
+        .parse::<TestPod>()
+        .unwrap();
+
+        let pod_state = PodState {
+            node_ip: Ipv4Addr::new(10, 0, 0, 1).into(),
+            ..test_pod_state(&pod)
+        };
+
+        let render_data = CreatingConfig::create_render_data(&pod_state, &pod).unwrap();
+
+        assert_eq!(
+            "broker.rack=node-1@10.0.0.1 client.id=kafka/kafka-0",
+            CreatingConfig::render_config_template(
+                &render_data,
+                "broker.rack={{nodename}}@{{nodeip}} client.id={{namespace}}/{{podname}}"
+            )
+            .unwrap()
+        );
+        assert!(CreatingConfig::render_config_template(&render_data, "{{nodname}}").is_err());
+    }
+
+    #[test]
+    fn environment_variables_should_be_available_under_env() {
+        let pod = "
+            apiVersion: v1
+            kind: Pod
+            metadata:
+              name: kafka-0
+            spec:
+              containers:
+                - name: kafka
+                  env:
+                    - name: LOG_DIR
+                      value: '{{logroot}}/kafka'
+                    - name: logroot
+                      value: shadowed
+                - name: sidecar
+                  env:
+                    - name: LOG_DIR
+                      value: ignored
+            "
+        .parse::<TestPod>()
+        .unwrap();
+
+        let mut template_data = BTreeMap::new();
+        template_data.insert(String::from("logroot"), String::from("/var/log/kafka-0"));
+
+        CreatingConfig::add_environment_variables(&pod, &mut template_data).unwrap();
+
+        assert_eq!(
+            "/var/log/kafka-0/kafka /var/log/kafka-0 shadowed",
+            CreatingConfig::render_config_template(
+                &template_data,
+                "{{env.LOG_DIR}} {{logroot}} {{env.logroot}}"
+            )
+            .unwrap()
+        );
+        assert!(CreatingConfig::render_config_template(&template_data, "{{env.HOME}}").is_err());
+        assert!(CreatingConfig::render_config_template(&template_data, "{{LOG_DIR}}").is_err());
+    }
+
+    #[test]
+    fn secret_placeholders_should_be_rendered_without_escaping() {
+        let mut context = BTreeMap::new();
+        context.insert(
+            secrets::variable_name("kafka/password"),
+            String::from("<p&ss>"),
+        );
+
+        assert_eq!(
+            "password=<p&ss>",
+            CreatingConfig::render_config_template(&context, "password={{secret:kafka/password}}")
+                .unwrap()
+        );
+        assert!(CreatingConfig::render_config_template(&context, "{{secret:kafka/user}}").is_err());
+    }
+
     #[test]
     fn test_pathbuf_string_conversion() {
         let input_path_string = "/home/test/.kube/config";
@@ -464,4 +1515,318 @@ mod tests {
         let output = output.to_string_lossy();
         assert_eq!(output, expected_output);
     }
+
+    #[rstest]
+    #[case(
+        "default-kafka-b3ca9d08-b97d-45bc-9da1-7b0156712ef1",
+        "default-kafka",
+        true
+    )]
+    #[case(
+        "default-kafka-1-b3ca9d08-b97d-45bc-9da1-7b0156712ef1",
+        "default-kafka",
+        false
+    )]
+    #[case("default-kafka-b3ca9d08", "default-kafka", false)]
+    #[case("default-kafka", "default-kafka", false)]
+    #[case(
+        "default-zookeeper-b3ca9d08-b97d-45bc-9da1-7b0156712ef1",
+        "default-kafka",
+        false
+    )]
+    fn test_is_config_generation(
+        #[case] directory_name: &str,
+        #[case] service_name: &str,
+        #[case] expected_result: bool,
+    ) {
+        assert_eq!(
+            expected_result,
+            CreatingConfig::is_config_generation(directory_name, service_name)
+        );
+    }
+
+    #[rstest]
+    #[case::all_keys(
+        None,
+        None,
+        Ok(vec![
+            ("config.properties", "/etc/config/config.properties"),
+            ("log4j.properties", "/etc/config/log4j.properties"),
+        ])
+    )]
+    #[case::items_with_custom_paths(
+        Some(vec![("config.properties", "server.properties"), ("log4j.properties", "log/log4j.properties")]),
+        None,
+        Ok(vec![
+            ("config.properties", "/etc/config/server.properties"),
+            ("log4j.properties", "/etc/config/log/log4j.properties"),
+        ])
+    )]
+    #[case::subset_of_items(
+        Some(vec![("log4j.properties", "log4j.properties")]),
+        None,
+        Ok(vec![("log4j.properties", "/etc/config/log4j.properties")])
+    )]
+    #[case::sub_path_to_a_key(
+        None,
+        Some("config.properties"),
+        Ok(vec![("config.properties", "/etc/config")])
+    )]
+    #[case::sub_path_to_an_item_path(
+        Some(vec![("config.properties", "server.properties")]),
+        Some("server.properties"),
+        Ok(vec![("config.properties", "/etc/config")])
+    )]
+    #[case::sub_path_to_a_directory(
+        Some(vec![("config.properties", "server.properties"), ("log4j.properties", "log/log4j.properties")]),
+        Some("log"),
+        Ok(vec![("log4j.properties", "/etc/config/log4j.properties")])
+    )]
+    #[case::missing_key(Some(vec![("missing", "missing")]), None, Err(()))]
+    #[case::missing_sub_path(None, Some("missing"), Err(()))]
+    #[case::absolute_item_path(Some(vec![("config.properties", "/etc/passwd")]), None, Err(()))]
+    #[case::item_path_with_parent_directory(
+        Some(vec![("config.properties", "../config.properties")]),
+        None,
+        Err(())
+    )]
+    fn test_config_files(
+        #[case] items: Option<Vec<(&str, &str)>>,
+        #[case] sub_path: Option<&str>,
+        #[case] expected_files: Result<Vec<(&str, &str)>, ()>,
+    ) {
+        let mut data = BTreeMap::new();
+        data.insert(String::from("config.properties"), String::new());
+        data.insert(String::from("log4j.properties"), String::new());
+
+        let items = items.map(|items| {
+            items
+                .into_iter()
+                .map(|(key, path)| KeyToPath {
+                    key: String::from(key),
+                    path: String::from(path),
+                    ..KeyToPath::default()
+                })
+                .collect::<Vec<_>>()
+        });
+
+        let files = CreatingConfig::config_files(
+            "test",
+            &data,
+            Path::new("/etc/config"),
+            items.as_deref(),
+            sub_path,
+        )
+        .map_err(|_| ());
+
+        let expected_files = expected_files.map(|files| {
+            files
+                .into_iter()
+                .map(|(key, path)| (String::from(key), PathBuf::from(path)))
+                .collect::<Vec<_>>()
+        });
+
+        assert_eq!(expected_files, files);
+    }
+
+    #[rstest]
+    #[case::default(None, None, false, Ok(0o644))]
+    #[case::default_with_secrets(None, None, true, Ok(0o600))]
+    #[case::default_mode(None, Some(0o600), false, Ok(0o600))]
+    #[case::default_mode_with_secrets(None, Some(0o640), true, Ok(0o640))]
+    #[case::item_mode(Some(0o400), Some(0o600), false, Ok(0o400))]
+    #[case::item_mode_with_secrets(Some(0o644), None, true, Ok(0o644))]
+    #[case::invalid_mode(Some(0o1777), None, false, Err(()))]
+    #[case::negative_mode(Some(-1), None, false, Err(()))]
+    fn test_file_mode(
+        #[case] item_mode: Option<i32>,
+        #[case] default_mode: Option<i32>,
+        #[case] contains_secrets: bool,
+        #[case] expected_mode: Result<u32, ()>,
+    ) {
+        let items = vec![KeyToPath {
+            key: String::from("config.properties"),
+            path: String::from("config.properties"),
+            mode: item_mode,
+        }];
+
+        let mode = CreatingConfig::file_mode(
+            "test",
+            "config.properties",
+            Some(&items),
+            default_mode,
+            contains_secrets,
+        )
+        .map_err(|_| ());
+
+        assert_eq!(expected_mode, mode);
+    }
+
+    #[rstest]
+    #[case::allowed_directory("/data", None, Ok("/data"))]
+    #[case::below_allowed_directory("/data/kafka/", None, Ok("/data/kafka"))]
+    #[case::sub_path("/data", Some("kafka/logs"), Ok("/data/kafka/logs"))]
+    #[case::other_directory("/etc", None, Err(()))]
+    #[case::common_prefix("/database", None, Err(()))]
+    #[case::parent_directory("/data/../etc", None, Err(()))]
+    #[case::parent_directory_in_sub_path("/data", Some("../etc"), Err(()))]
+    #[case::relative_path("data", None, Err(()))]
+    fn test_validate_host_path(
+        #[case] path: &str,
+        #[case] sub_path: Option<&str>,
+        #[case] expected_path: Result<&str, ()>,
+    ) {
+        let mount = HostPathMount {
+            path: String::from(path),
+            type_: None,
+            sub_path: sub_path.map(String::from),
+        };
+
+        assert_eq!(
+            expected_path.map(PathBuf::from),
+            CreatingConfig::validate_host_path(&mount, &[PathBuf::from("/data")]).map_err(|_| ())
+        );
+    }
+
+    #[rstest]
+    #[case::relative_path("conf", Ok("/config/conf"))]
+    #[case::absolute_path("/data", Ok("/config/data"))]
+    #[case::config_directory("/", Ok("/config"))]
+    #[case::below_base_directory("/packages/kafka-2.7/conf", Ok("/packages/kafka-2.7/conf"))]
+    #[case::common_prefix("/packages2/conf", Ok("/config/packages2/conf"))]
+    #[case::parent_directory("../etc", Err(()))]
+    #[case::parent_directory_below_base_directory("/packages/kafka-2.7/../../etc", Err(()))]
+    fn test_resolve_mount_path(#[case] mount_path: &str, #[case] expected_path: Result<&str, ()>) {
+        assert_eq!(
+            expected_path.map(PathBuf::from),
+            CreatingConfig::resolve_mount_path(
+                Path::new(mount_path),
+                Path::new("/config"),
+                &[PathBuf::from("/packages/kafka-2.7")]
+            )
+            .map_err(|_| ())
+        );
+    }
+
+    #[test]
+    fn binary_data_should_be_written_without_rendering() {
+        let directory = std::env::temp_dir().join(format!(
+            "stackable-agent-binary-data-{}",
+            std::process::id()
+        ));
+        let config_map: ConfigMap = serde_yaml::from_str(
+            "
+            apiVersion: v1
+            kind: ConfigMap
+            metadata:
+              name: kerberos
+            data:
+              krb5.conf: 'log={{logroot}}'
+            binaryData:
+              # The bytes 0x00 0x01 0x7b 0x7b 0xff contain `{{` and are not valid UTF-8
+              keytab: AAF7e/8=
+            ",
+        )
+        .unwrap();
+        let mount = ConfigMapMount {
+            config_map: String::from("kerberos"),
+            items: None,
+            default_mode: None,
+            sub_path: None,
+            secret_file_owner: ServiceUser::Agent,
+        };
+        let mut template_data = BTreeMap::new();
+        template_data.insert(String::from("logroot"), String::from("/var/log/kerberos"));
+
+        let result =
+            CreatingConfig::apply_config_map(&config_map, &mount, &directory, &template_data, true);
+        let keytab = fs::read(directory.join("keytab"));
+        let krb5_conf = fs::read_to_string(directory.join("krb5.conf"));
+        fs::remove_dir_all(&directory).unwrap();
+
+        assert!(result.is_ok());
+        assert_eq!(vec![0x00, 0x01, 0x7b, 0x7b, 0xff], keytab.unwrap());
+        assert_eq!("log=/var/log/kerberos", krb5_conf.unwrap());
+    }
+
+    #[test]
+    fn secret_files_should_be_owned_by_the_service_user() {
+        let directory = std::env::temp_dir().join(format!(
+            "stackable-agent-secret-owner-{}",
+            std::process::id()
+        ));
+        let config_map: ConfigMap = serde_yaml::from_str(
+            "
+            apiVersion: v1
+            kind: ConfigMap
+            metadata:
+              name: kafka
+            data:
+              client.properties: 'password={{secret:kafka/password}}'
+              server.properties: 'port=9092'
+            ",
+        )
+        .unwrap();
+        let user = User::from_uid(Uid::current()).unwrap().unwrap();
+        let mount = ConfigMapMount {
+            config_map: String::from("kafka"),
+            items: None,
+            default_mode: None,
+            sub_path: None,
+            secret_file_owner: ServiceUser::Static(user.name.to_owned()),
+        };
+        let mut template_data = BTreeMap::new();
+        template_data.insert(
+            secrets::variable_name("kafka/password"),
+            String::from("secret"),
+        );
+
+        let result =
+            CreatingConfig::apply_config_map(&config_map, &mount, &directory, &template_data, true);
+        let metadata = fs::metadata(directory.join("client.properties"));
+        fs::remove_dir_all(&directory).unwrap();
+
+        let metadata = metadata.unwrap();
+        assert_eq!(vec![directory.join("client.properties")], result.unwrap());
+        assert_eq!(
+            (user.uid.as_raw(), user.gid.as_raw(), SECRET_FILE_MODE),
+            (metadata.uid(), metadata.gid(), metadata.mode() & 0o777)
+        );
+    }
+
+    #[test]
+    fn host_paths_should_be_created_and_linked() {
+        let directory =
+            std::env::temp_dir().join(format!("stackable-agent-host-path-{}", std::process::id()));
+        let allowed_directory = directory.join("allowed");
+        fs::create_dir_all(&allowed_directory).unwrap();
+        symlink("/etc", allowed_directory.join("escape")).unwrap();
+        let allowed_directories = vec![allowed_directory.to_owned()];
+
+        let host_path = allowed_directory.join("data");
+        let target_path = directory.join("config").join("data");
+
+        let missing_directory =
+            CreatingConfig::prepare_host_path(&host_path, Some("Directory"), &allowed_directories);
+        let created_directory = CreatingConfig::prepare_host_path(
+            &host_path,
+            Some("DirectoryOrCreate"),
+            &allowed_directories,
+        );
+        let escaped_directory = CreatingConfig::prepare_host_path(
+            &allowed_directory.join("escape").join("data"),
+            Some("DirectoryOrCreate"),
+            &allowed_directories,
+        );
+        let linked_directory = CreatingConfig::link_host_path(&host_path, &target_path)
+            .and_then(|()| CreatingConfig::link_host_path(&host_path, &target_path));
+        let link = fs::read_link(&target_path);
+        fs::remove_dir_all(&directory).unwrap();
+
+        assert!(matches!(missing_directory, Err(HostPathError { .. })));
+        assert!(created_directory.is_ok());
+        assert!(matches!(escaped_directory, Err(PodValidationError { .. })));
+        assert!(linked_directory.is_ok());
+        assert_eq!(host_path, link.unwrap());
+    }
 }
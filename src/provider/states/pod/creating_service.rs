@@ -8,7 +8,10 @@ use log::{debug, error, info};
 
 use super::setup_failed::SetupFailed;
 use super::starting::Starting;
+use crate::provider::dns::write_resolv_conf;
+use crate::provider::systemdmanager::manager::SystemdManager;
 use crate::provider::systemdmanager::systemdunit::SystemDUnit;
+use crate::provider::transitions::record_transition;
 use crate::provider::{ContainerHandle, PodState, ProviderState};
 
 #[derive(Default, Debug, TransitionTo)]
@@ -24,6 +27,7 @@ impl State<PodState> for CreatingService {
         pod: Manifest<Pod>,
     ) -> Transition<PodState> {
         let pod = pod.latest();
+        record_transition(&shared, &pod, "CreatingService", None).await;
 
         let (systemd_manager, kubeconfig_path) = {
             let provider_state = shared.read().await;
@@ -33,7 +37,6 @@ impl State<PodState> for CreatingService {
             )
         };
 
-        let service_name: &str = pod_state.service_name.as_ref();
         info!(
             "Creating service unit for service {}",
             &pod_state.service_name
@@ -49,61 +52,70 @@ impl State<PodState> for CreatingService {
             }
         }
 
+        // The resolver configuration is mounted into the service units, so it must exist before
+        // they are started
+        if let Err(error) = write_resolv_conf(&pod, service_directory) {
+            error!(
+                "The DNS settings of service [{}] could not be written: {}",
+                pod_state.service_name, error
+            );
+            return Transition::Complete(Err(Error::from(error)));
+        }
+
+        // All units of the pod are placed in a dedicated slice, so the slice must exist before
+        // the units are created
+        let slice = SystemDUnit::new_pod_slice(&pod, pod_state.unit_name_max_length);
+        if let Err(e) = systemd_manager
+            .create_unit(&slice, None, true, false)
+            .await
+            .with_context(|| format!("Slice unit file [{}] could not be created", slice))
+        {
+            return Transition::Complete(Err(e));
+        }
+
         // Each pod can map to multiple systemd units/services as each container will get its own
-        // systemd unit file/service.
-        // Map every container from the pod object to a systemdunit
-        for container in &pod.containers() {
-            let unit = match SystemDUnit::new(
-                systemd_manager.is_user_mode(),
-                pod_state,
-                &kubeconfig_path,
+        // systemd unit file/service. Init containers are mapped to oneshot units which run
+        // before the services are started.
+        let units = match SystemDUnit::new_all(
+            systemd_manager.is_user_mode(),
+            pod_state,
+            &kubeconfig_path,
+            &pod,
+        ) {
+            Ok(units) => units,
+            Err(err) => return Transition::Complete(Err(Error::from(err))),
+        };
+
+        // The package is installed at this point, so a missing binary is reported as setup
+        // error instead of a failing service.
+        for (_, unit) in &units {
+            if let Err(err) = unit.verify_executables() {
+                error!("{}", err);
+                return Transition::Complete(Err(Error::from(err)));
+            }
+        }
+
+        let transient = match SystemDUnit::is_transient(&pod) {
+            Ok(transient) => transient,
+            Err(err) => return Transition::Complete(Err(Error::from(err))),
+        };
+
+        for (container_key, unit) in &units {
+            if let Err(e) = create_unit(
+                &shared,
+                &systemd_manager,
                 &pod,
-                container,
-            ) {
-                Ok(unit) => unit,
-                Err(err) => return Transition::Complete(Err(Error::from(err))),
-            };
-
-            // Create the service
-            // As per ADR005 we currently write the unit files directly in the systemd
-            // unit directory (by passing None as [unit_file_path]).
-            if let Err(e) = systemd_manager
-                .create_unit(&unit, None, true, true)
-                .await
-                .with_context(|| format!("Unit file [{}] could not be created", unit))
+                container_key,
+                unit,
+                transient,
+            )
+            .await
             {
                 // TODO: We need to discuss what to do here, in theory we could have loaded
                 // other services already, do we want to stop those?
                 return Transition::Complete(Err(e));
             }
 
-            let systemd_service = match systemd_manager
-                .create_systemd_service(&unit.get_name())
-                .await
-            {
-                Ok(systemd_service) => systemd_service,
-                Err(error) => {
-                    error!(
-                        "Proxy for the systemd service [{}] could not be created: {}",
-                        service_name, error
-                    );
-                    return Transition::Complete(Err(error));
-                }
-            };
-
-            {
-                let provider_state = shared.write().await;
-                let mut handles = provider_state.handles.write().await;
-                handles.insert_container_handle(
-                    &PodKey::from(&pod),
-                    &ContainerKey::App(String::from(container.name())),
-                    &ContainerHandle {
-                        service_unit: unit.get_name(),
-                        systemd_service,
-                    },
-                )
-            };
-
             // Done for now, if the service was created successfully we are happy
             // Starting and enabling comes in a later state after all service have been createddy
         }
@@ -116,3 +128,77 @@ impl State<PodState> for CreatingService {
         Ok(make_status(Phase::Pending, "CreatingService"))
     }
 }
+
+/// Creates the given unit and registers it as handle for the given container.
+///
+/// No unit file is written for transient units. They are passed to `systemd-run` in the
+/// `Starting` stage instead.
+async fn create_unit(
+    shared: &SharedState<ProviderState>,
+    systemd_manager: &SystemdManager,
+    pod: &Pod,
+    container_key: &ContainerKey,
+    unit: &SystemDUnit,
+    transient: bool,
+) -> anyhow::Result<()> {
+    if !transient {
+        // Create the service
+        // As per ADR005 we currently write the unit files directly in the systemd
+        // unit directory (by passing None as [unit_file_path]).
+        systemd_manager
+            .create_unit(unit, None, true, true)
+            .await
+            .with_context(|| format!("Unit file [{}] could not be created", unit))?;
+    }
+
+    let systemd_service = match systemd_manager
+        .create_systemd_service(&unit.get_name())
+        .await
+    {
+        Ok(systemd_service) => systemd_service,
+        Err(error) => {
+            error!(
+                "Proxy for the systemd service [{}] could not be created: {}",
+                unit, error
+            );
+            return Err(error);
+        }
+    };
+
+    // Socket and timer units are only created for app containers
+    let trigger = match pod
+        .containers()
+        .iter()
+        .find(|container| container_key.is_app() && container.name() == container_key.name())
+    {
+        Some(container) => SystemDUnit::new_trigger(pod, container, unit)?,
+        None => None,
+    };
+
+    if let Some(trigger) = &trigger {
+        systemd_manager
+            .create_unit(trigger, None, true, true)
+            .await
+            .with_context(|| format!("Unit file [{}] could not be created", trigger))?;
+    }
+
+    let provider_state = shared.write().await;
+    let mut handles = provider_state.handles.write().await;
+    handles.insert_container_handle(
+        &PodKey::from(pod),
+        container_key,
+        &ContainerHandle {
+            service_unit: unit.get_name(),
+            systemd_service,
+            trigger_unit: trigger.as_ref().map(SystemDUnit::get_name),
+            previous_invocation_id: None,
+            transient_unit: if transient {
+                Some(unit.to_owned())
+            } else {
+                None
+            },
+        },
+    );
+
+    Ok(())
+}
@@ -1,6 +1,7 @@
 use std::path::Path;
 
 use anyhow::Context;
+use futures_util::future;
 use kubelet::pod::state::prelude::*;
 use kubelet::pod::Pod;
 use log::{debug, error, info, warn};
@@ -8,8 +9,12 @@ use tokio::fs::create_dir_all;
 
 use super::downloading_backoff::DownloadingBackoff;
 use super::installing::Installing;
-use crate::provider::repository::find_repository;
+use crate::fail_fatal;
+use crate::provider::error::StackableError;
 use crate::provider::repository::package::Package;
+use crate::provider::repository::{find_repositories, force_reinstall, required_repository};
+use crate::provider::states::pod::acquire_install_permit;
+use crate::provider::transitions::record_transition;
 use crate::provider::{PodState, ProviderState};
 
 #[derive(Default, Debug, TransitionTo)]
@@ -26,6 +31,23 @@ impl Downloading {
         );
         Path::new(&package_file_name).exists()
     }
+
+    /// Removes the downloaded and partially downloaded archives of the given package, so that
+    /// the package is downloaded from scratch.
+    fn remove_download(package: &Package, download_directory: &Path) -> std::io::Result<()> {
+        for file_name in &[
+            package.get_file_name(),
+            package.get_partial_file_name(),
+            package.get_archive_checksum_file_name(),
+        ] {
+            let file = download_directory.join(file_name);
+            if file.exists() {
+                debug!("Removing cached archive [{}]", file.to_string_lossy());
+                std::fs::remove_file(file)?;
+            }
+        }
+        Ok(())
+    }
 }
 
 #[async_trait::async_trait]
@@ -34,16 +56,50 @@ impl State<PodState> for Downloading {
         self: Box<Self>,
         provider_state: SharedState<ProviderState>,
         pod_state: &mut PodState,
-        _pod: Manifest<Pod>,
+        pod: Manifest<Pod>,
     ) -> Transition<PodState> {
+        let pod = pod.latest();
+        record_transition(&provider_state, &pod, "Downloading", None).await;
         let package = pod_state.package.clone();
 
-        let client = {
+        let (client, repository_client, install_permits, shutdown_signal) = {
             let provider_state = provider_state.read().await;
-            provider_state.client.clone()
+            (
+                provider_state.client.clone(),
+                provider_state.repository_client.clone(),
+                provider_state.install_permits.clone(),
+                provider_state.shutdown_signal.clone(),
+            )
+        };
+
+        let force_reinstall = match force_reinstall(&pod) {
+            Ok(force_reinstall) => force_reinstall,
+            Err(e) => fail_fatal!(e),
         };
 
         info!("Looking for package: {} in known repositories", &package);
+        if force_reinstall {
+            warn!(
+                "Package {} is downloaded again even if it is already present because the pod \
+                requests a reinstallation",
+                package
+            );
+            if let Err(error) =
+                Downloading::remove_download(&package, &pod_state.download_directory)
+            {
+                error!(
+                    "The cached archive of package {} could not be removed: {}",
+                    package, error
+                );
+                return Transition::next(
+                    self,
+                    DownloadingBackoff {
+                        package: package.clone(),
+                    },
+                );
+            }
+        }
+
         debug!(
             "Checking if package {} has already been downloaded.",
             package
@@ -59,19 +115,21 @@ impl State<PodState> for Downloading {
                     download_directory: pod_state.download_directory.clone(),
                     parcel_directory: pod_state.parcel_directory.clone(),
                     package: package.clone(),
+                    package_directory_template: pod_state.package_directory_template.clone(),
                 },
             );
         }
-        let repo = find_repository(client, &package).await;
-        return match repo {
-            Ok(Some(mut repo)) => {
-                // We found a repository providing the package, proceed with download
-                // The repository has already downloaded its metadata at this time, as that
-                // was used to check whether it provides the package
-                info!(
-                    "Starting download of package {} from repository {}",
-                    &package, &repo
-                );
+        let _permit = acquire_install_permit(install_permits, &pod_state.service_name).await;
+
+        let repos = find_repositories(
+            client,
+            &package,
+            required_repository(&pod),
+            &repository_client,
+        )
+        .await;
+        return match repos {
+            Ok(repos) if !repos.is_empty() => {
                 let download_directory = pod_state.download_directory.clone();
 
                 if !(download_directory.is_dir()) {
@@ -80,37 +138,99 @@ impl State<PodState> for Downloading {
                     }
                 };
 
-                let download_result = repo
-                    .download_package(&package, download_directory.clone())
-                    .await;
-                match download_result {
-                    Ok(()) => {
+                // The repositories are tried in the order of their priority, so that a single
+                // failing repository does not prevent the download if others provide the
+                // package as well. The metadata of the repositories has already been
+                // downloaded at this time, as it was used to check whether they provide the
+                // package.
+                let repository_count = repos.len();
+                for (index, mut repo) in repos.into_iter().enumerate() {
+                    info!(
+                        "Starting download of package {} from repository {}",
+                        &package, &repo
+                    );
+
+                    // The download is cancelled if the agent shuts down. The partial file is
+                    // kept, so that the download is resumed on the next start. The pod stays in
+                    // this state until the agent exits, so that its status is not changed.
+                    let download = repo.download_package(&package, download_directory.clone());
+                    let download_result = tokio::select! {
+                        result = download => result,
+                        _ = shutdown_signal.triggered() => {
+                            info!(
+                                "Download of package {} cancelled because the agent shuts down",
+                                package
+                            );
+                            return future::pending().await;
+                        }
+                    };
+                    match download_result {
+                        Ok(()) => {
+                            info!(
+                                "Successfully downloaded package {} to {:?}",
+                                package,
+                                download_directory.clone()
+                            );
+                            return Transition::next(
+                                self,
+                                Installing {
+                                    download_directory: pod_state.download_directory.clone(),
+                                    parcel_directory: pod_state.parcel_directory.clone(),
+                                    package: package.clone(),
+                                    package_directory_template: pod_state
+                                        .package_directory_template
+                                        .clone(),
+                                },
+                            );
+                        }
+                        Err(StackableError::RepositoryTimeout { url }) => warn!(
+                            "Download of package {} from repository {} timed out at [{}]",
+                            package, repo, url
+                        ),
+                        Err(e) => warn!(
+                            "Download of package {} from repository {} failed: {}",
+                            package, repo, e
+                        ),
+                    }
+
+                    if index + 1 < repository_count {
                         info!(
-                            "Successfully downloaded package {} to {:?}",
-                            package,
-                            download_directory.clone()
+                            "Falling back to the next repository which provides package {}",
+                            package
                         );
-                        Transition::next(
-                            self,
-                            Installing {
-                                download_directory: pod_state.download_directory.clone(),
-                                parcel_directory: pod_state.parcel_directory.clone(),
-                                package: package.clone(),
-                            },
-                        )
-                    }
-                    Err(e) => {
-                        warn!("Download of package {} failed: {}", package, e);
-                        Transition::next(
-                            self,
-                            DownloadingBackoff {
-                                package: package.clone(),
-                            },
-                        )
+
+                        // A partial file from this repository must not be resumed from
+                        // another one, because the archives of the repositories may differ.
+                        let partial_file = download_directory.join(package.get_partial_file_name());
+                        if partial_file.exists() {
+                            debug!(
+                                "Removing partially downloaded archive [{}]",
+                                partial_file.to_string_lossy()
+                            );
+                            if let Err(error) = std::fs::remove_file(&partial_file) {
+                                warn!(
+                                    "Partially downloaded archive [{}] could not be removed: {}",
+                                    partial_file.to_string_lossy(),
+                                    error
+                                );
+                            }
+                        }
                     }
                 }
+
+                warn!(
+                    "Download of package {} failed in all repositories which provide it, \
+                    retrying later",
+                    package
+                );
+                Transition::next(
+                    self,
+                    DownloadingBackoff {
+                        package: package.clone(),
+                    },
+                )
             }
-            Ok(None) => {
+            Ok(_) => {
                 // No repository was found that provides this package
                 let message = format!(
                     "Cannot find package {} in any repository, aborting ..",
@@ -1,9 +1,12 @@
 use kubelet::backoff::BackoffStrategy;
 use kubelet::pod::state::prelude::*;
-use log::info;
+use log::{error, info};
 
 use super::downloading::Downloading;
+use crate::provider::error::StackableError::DownloadRetriesExhausted;
+use crate::provider::kubernetes::status::patch_pod_failed;
 use crate::provider::repository::package::Package;
+use crate::provider::transitions::record_transition;
 use crate::provider::{PodState, ProviderState};
 
 #[derive(Debug, TransitionTo)]
@@ -17,10 +20,48 @@ pub struct DownloadingBackoff {
 impl State<PodState> for DownloadingBackoff {
     async fn next(
         self: Box<Self>,
-        _provider_state: SharedState<ProviderState>,
+        provider_state: SharedState<ProviderState>,
         pod_state: &mut PodState,
-        _pod: Manifest<Pod>,
+        pod: Manifest<Pod>,
     ) -> Transition<PodState> {
+        record_transition(
+            &provider_state,
+            &pod.latest(),
+            "DownloadingBackoff",
+            Some(format!(
+                "The download of package [{}] failed.",
+                self.package
+            )),
+        )
+        .await;
+
+        let (client, max_download_retries) = {
+            let provider_state = provider_state.read().await;
+            (
+                provider_state.client.clone(),
+                provider_state.max_download_retries,
+            )
+        };
+
+        // The pod fails permanently if the retries are exhausted, so that it does not cycle
+        // through the download stages forever.
+        pod_state.download_failures += 1;
+        if max_download_retries != 0 && pod_state.download_failures > max_download_retries {
+            let error = DownloadRetriesExhausted {
+                package: self.package.clone(),
+                retries: max_download_retries,
+            };
+            error!("{}", error);
+            patch_pod_failed(
+                &client,
+                &pod.latest(),
+                "DownloadRetriesExhausted",
+                &error.to_string(),
+            )
+            .await;
+            return Transition::Complete(Err(anyhow::Error::from(error)));
+        }
+
         info!(
             "Backing of before retrying download of package {}",
             self.package
@@ -12,6 +12,7 @@ use log::warn;
 use serde_json::json;
 
 use super::downloading::Downloading;
+use crate::provider::transitions::record_transition;
 use crate::provider::{PodState, ProviderState};
 
 #[derive(Default, Debug, TransitionTo)]
@@ -35,6 +36,7 @@ impl State<PodState> for Initializing {
         };
 
         let pod = pod.latest();
+        record_transition(&shared, &pod, "Initializing", None).await;
 
         let api = Api::namespaced(client, pod.namespace());
 
@@ -1,58 +1,421 @@
 use std::fs;
 use std::fs::File;
-use std::path::PathBuf;
+use std::io::{self, Read};
+use std::path::{Component, Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
 
 use flate2::read::GzDecoder;
 use kubelet::pod::state::prelude::*;
 use kubelet::pod::Pod;
-use log::{debug, error, info};
-use tar::Archive;
+use log::{debug, error, info, warn};
+use tar::{Archive, EntryType};
 
 use super::creating_config::CreatingConfig;
 use super::setup_failed::SetupFailed;
+use crate::fail_fatal;
+use crate::fsext;
 use crate::provider::error::StackableError;
+use crate::provider::error::StackableError::{PodValidationError, StripComponentsConflict};
+use crate::provider::kubernetes::accessor::annotation;
+use crate::provider::repository::archive_checksum;
+use crate::provider::repository::force_reinstall;
 use crate::provider::repository::package::Package;
+use crate::provider::states::pod::acquire_install_permit;
+use crate::provider::transitions::record_transition;
 use crate::provider::{PodState, ProviderState};
 
+/// Annotation with the number of leading path components which are stripped from the entries of
+/// the package archive when it is extracted, like `tar --strip-components`
+pub const STRIP_COMPONENTS_ANNOTATION: &str = "stackable.tech/package-strip-components";
+
+/// Reads the number of leading path components to strip from the package archive of the given
+/// pod, see [`STRIP_COMPONENTS_ANNOTATION`].
+///
+/// No components are stripped if the annotation is not set.
+pub fn strip_components(pod: &Pod) -> Result<usize, StackableError> {
+    match annotation(pod, STRIP_COMPONENTS_ANNOTATION) {
+        Some(value) => value.parse::<usize>().map_err(|_| PodValidationError {
+            msg: format!(
+                "The validation of the pod [{}] failed. The value [{}] of the annotation [{}] must be a non-negative integer.",
+                pod.name(),
+                value,
+                STRIP_COMPONENTS_ANNOTATION
+            ),
+        }),
+        None => Ok(0),
+    }
+}
+
+/// Extracts the given archive into the target directory and strips the given number of leading
+/// path components from the entries.
+///
+/// Entries with fewer components, e.g. the top-level directory itself, are skipped. Entries
+/// whose remaining path would leave the target directory are rejected. This includes entries
+/// below symbolic links which were extracted before, like [`Archive::unpack`] does.
+fn unpack<R: Read>(
+    archive: &mut Archive<R>,
+    target_directory: &Path,
+    strip_components: usize,
+) -> Result<(), StackableError> {
+    if strip_components == 0 {
+        return Ok(archive.unpack(target_directory)?);
+    }
+
+    let strip = |path: &Path| -> Result<Option<PathBuf>, StackableError> {
+        let components = path
+            .components()
+            .filter(|component| component != &Component::CurDir)
+            .collect::<Vec<_>>();
+        if components
+            .iter()
+            .any(|component| !matches!(component, Component::Normal(_)))
+        {
+            return Err(StackableError::from(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "The archive contains the invalid path [{}].",
+                    path.to_string_lossy()
+                ),
+            )));
+        }
+        let stripped_path = components
+            .into_iter()
+            .skip(strip_components)
+            .collect::<PathBuf>();
+        Ok(Some(stripped_path).filter(|path| path.components().next().is_some()))
+    };
+
+    fs::create_dir_all(target_directory)?;
+
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let relative_path = match strip(&entry.path()?)? {
+            Some(path) => path,
+            None => continue,
+        };
+        check_no_symlink_in_parents(target_directory, &relative_path)?;
+        let path = target_directory.join(&relative_path);
+
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        // An existing symbolic link is replaced and not followed.
+        if fs::symlink_metadata(&path).map_or(false, |metadata| metadata.file_type().is_symlink()) {
+            fs::remove_file(&path)?;
+        }
+
+        // Hard links refer to other entries of the archive, so their targets must be stripped as
+        // well.
+        if entry.header().entry_type() == EntryType::Link {
+            if let Some(link_name) = entry.link_name()? {
+                if let Some(link_target) = strip(&link_name)? {
+                    check_no_symlink_in_parents(target_directory, &link_target)?;
+                    fs::hard_link(target_directory.join(link_target), &path)?;
+                }
+                continue;
+            }
+        }
+
+        entry.unpack(&path)?;
+    }
+
+    Ok(())
+}
+
+/// Checks that none of the parent directories of the given path below the target directory is a
+/// symbolic link.
+///
+/// Otherwise an archive could write outside of the target directory by extracting a symbolic
+/// link first and then an entry below it.
+fn check_no_symlink_in_parents(
+    target_directory: &Path,
+    relative_path: &Path,
+) -> Result<(), StackableError> {
+    let mut directory = target_directory.to_owned();
+
+    for component in relative_path
+        .parent()
+        .into_iter()
+        .flat_map(Path::components)
+    {
+        directory.push(component);
+        match fs::symlink_metadata(&directory) {
+            Ok(metadata) if metadata.file_type().is_symlink() => {
+                return Err(StackableError::from(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!(
+                        "The archive contains the path [{}] below the symbolic link [{}].",
+                        relative_path.to_string_lossy(),
+                        directory.to_string_lossy()
+                    ),
+                )))
+            }
+            Ok(_) => (),
+            Err(error) if error.kind() == io::ErrorKind::NotFound => break,
+            Err(error) => return Err(error.into()),
+        }
+    }
+
+    Ok(())
+}
+
 #[derive(Debug, TransitionTo)]
 #[transition_to(CreatingConfig, SetupFailed)]
 pub struct Installing {
     pub download_directory: PathBuf,
     pub parcel_directory: PathBuf,
     pub package: Package,
+    /// Template for the name of the package directory, see [`Package::get_directory_name`]
+    pub package_directory_template: String,
 }
 
-impl Installing {
-    fn package_installed<T: Into<Package>>(&self, package: T) -> bool {
-        let package = package.into();
+/// Directory below the parcel directory into which the packages are extracted before they are
+/// moved to their package directories
+const STAGING_DIRECTORY: &str = "_installing";
+
+/// Directory below the parcel directory into which replaced installations are moved
+const SUPERSEDED_DIRECTORY: &str = "_superseded";
+
+/// State of the installation of a package compared to the downloaded archive
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum InstallationState {
+    /// The package is not installed.
+    Missing,
+    /// The package is installed from the downloaded archive.
+    Current,
+    /// The package is installed but the checksum of its archive was not recorded, e.g. because
+    /// it was installed by a previous version of the agent.
+    Unverified,
+    /// The package is installed from a different archive.
+    Outdated,
+}
 
-        let target_directory = self.get_target_directory(&package);
+impl Installing {
+    /// Compares the installation of the given package with the downloaded archive.
+    ///
+    /// The SHA256 hash of the archive is stored next to the package directory when the package
+    /// is installed. The installation is only up to date if the stored hash matches the hash of
+    /// the downloaded archive, otherwise the package was installed from a corrupted or changed
+    /// archive and must be extracted again.
+    fn installation_state(&self, package: &Package, archive_checksum: &str) -> InstallationState {
+        let target_directory = self.get_target_directory(package);
         debug!(
             "Checking if package {:?} has already been installed to {:?}",
             package, target_directory
         );
-        target_directory.exists()
+        if !target_directory.exists() {
+            return InstallationState::Missing;
+        }
+
+        let checksum_file = self.get_checksum_file(package);
+        match fs::read_to_string(&checksum_file) {
+            Ok(installed_checksum) if installed_checksum.trim() == archive_checksum => {
+                InstallationState::Current
+            }
+            Ok(_) => InstallationState::Outdated,
+            Err(error) if error.kind() == io::ErrorKind::NotFound => InstallationState::Unverified,
+            Err(error) => {
+                debug!(
+                    "Checksum of the installed package {:?} could not be read from {:?}: {}",
+                    package, checksum_file, error
+                );
+                InstallationState::Outdated
+            }
+        }
     }
 
     fn get_target_directory(&self, package: &Package) -> PathBuf {
-        self.parcel_directory.join(package.get_directory_name())
+        self.parcel_directory
+            .join(package.get_directory_name(&self.package_directory_template))
+    }
+
+    fn get_checksum_file(&self, package: &Package) -> PathBuf {
+        self.parcel_directory
+            .join(package.get_checksum_file_name(&self.package_directory_template))
+    }
+
+    fn get_strip_components_file(&self, package: &Package) -> PathBuf {
+        self.parcel_directory
+            .join(package.get_strip_components_file_name(&self.package_directory_template))
+    }
+
+    /// Returns the number of leading path components which were stripped when the given
+    /// package was installed.
+    ///
+    /// The number is not recorded for packages which were installed before the annotation
+    /// [`STRIP_COMPONENTS_ANNOTATION`] was supported, so no components were stripped then.
+    fn installed_strip_components(&self, package: &Package) -> Result<usize, StackableError> {
+        match fs::read_to_string(self.get_strip_components_file(package)) {
+            Ok(value) => value.trim().parse().map_err(|_| {
+                StackableError::from(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!(
+                        "The recorded number of stripped path components [{}] of the package \
+                        {} is invalid.",
+                        value.trim(),
+                        package
+                    ),
+                ))
+            }),
+            Err(error) if error.kind() == io::ErrorKind::NotFound => Ok(0),
+            Err(error) => Err(error.into()),
+        }
+    }
+
+    fn get_archive_path(&self, package: &Package) -> PathBuf {
+        self.download_directory.join(package.get_file_name())
+    }
+
+    /// Returns the directory into which the package is extracted for the given service.
+    ///
+    /// The directory is unique per service, so that pods which install the same package
+    /// concurrently do not interfere.
+    fn get_staging_directory(&self, package: &Package, service_name: &str) -> PathBuf {
+        self.parcel_directory.join(STAGING_DIRECTORY).join(format!(
+            "{}.{}",
+            package.get_directory_name(&self.package_directory_template),
+            service_name
+        ))
+    }
+
+    /// Returns a new directory into which the replaced installation of the given package is
+    /// moved.
+    fn get_superseded_directory(&self, package: &Package) -> PathBuf {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        self.parcel_directory
+            .join(SUPERSEDED_DIRECTORY)
+            .join(format!(
+                "{}.{}",
+                package.get_directory_name(&self.package_directory_template),
+                timestamp
+            ))
+    }
+
+    /// Returns the SHA256 hash of the downloaded archive which is cached as long as the archive
+    /// is unchanged, see [`archive_checksum::sha256`].
+    fn archive_checksum(&self, package: &Package) -> Result<String, StackableError> {
+        Ok(archive_checksum::sha256(&self.get_archive_path(package))?)
+    }
+
+    /// Records the checksum of the given archive for an installation which was not verified
+    /// yet.
+    ///
+    /// Packages which were installed before the checksums were recorded are assumed to be
+    /// installed from the downloaded archive. They are adopted instead of being extracted
+    /// again because running services could still use them.
+    fn adopt_installation(
+        &self,
+        package: &Package,
+        archive_checksum: &str,
+    ) -> Result<(), StackableError> {
+        Ok(fs::write(
+            self.get_checksum_file(package),
+            archive_checksum,
+        )?)
     }
 
-    fn install_package<T: Into<Package>>(&self, package: T) -> Result<(), StackableError> {
-        let package: Package = package.into();
+    /// Extracts the given package into a staging directory and moves it to the package
+    /// directory afterwards.
+    ///
+    /// An existing package directory is never deleted because running services of other pods
+    /// could still use it. Instead, it is exchanged atomically with the new installation and
+    /// moved to the directory `_superseded` in the parcel directory.
+    fn install_package(
+        &self,
+        package: &Package,
+        archive_checksum: &str,
+        strip_components: usize,
+        service_name: &str,
+    ) -> Result<(), StackableError> {
+        let staging_directory = self.get_staging_directory(package, service_name);
 
-        let archive_path = self.download_directory.join(package.get_file_name());
+        let result = self.extract_and_move(
+            package,
+            archive_checksum,
+            strip_components,
+            &staging_directory,
+        );
+
+        // The staging directory is only left over if the installation failed. It is not used by
+        // any service, so it can be deleted.
+        if staging_directory.exists() {
+            debug!(
+                "Deleting staging directory [{}]",
+                staging_directory.to_string_lossy()
+            );
+            if let Err(error) = fs::remove_dir_all(&staging_directory) {
+                warn!(
+                    "Staging directory [{}] could not be deleted: {}",
+                    staging_directory.to_string_lossy(),
+                    error
+                );
+            }
+        }
+
+        result
+    }
+
+    fn extract_and_move(
+        &self,
+        package: &Package,
+        archive_checksum: &str,
+        strip_components: usize,
+        staging_directory: &Path,
+    ) -> Result<(), StackableError> {
+        let archive_path = self.get_archive_path(package);
         let tar_gz = File::open(&archive_path)?;
         let tar = GzDecoder::new(tar_gz);
         let mut archive = Archive::new(tar);
 
-        let target_directory = self.get_target_directory(&package);
+        let target_directory = self.get_target_directory(package);
 
         info!(
             "Installing package: {:?} from {:?} into {:?}",
             package, archive_path, target_directory
         );
-        archive.unpack(target_directory)?;
+
+        // Left over from an interrupted installation
+        if staging_directory.exists() {
+            fs::remove_dir_all(staging_directory)?;
+        }
+        fs::create_dir_all(staging_directory)?;
+
+        if strip_components > 0 {
+            debug!(
+                "Stripping {} leading path components from the entries of the archive {:?}",
+                strip_components, archive_path
+            );
+        }
+        unpack(&mut archive, staging_directory, strip_components)?;
+
+        if target_directory.exists() {
+            fsext::exchange(staging_directory, &target_directory)?;
+
+            let superseded_directory = self.get_superseded_directory(package);
+            if let Some(parent) = superseded_directory.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            fs::rename(staging_directory, &superseded_directory)?;
+            warn!(
+                "The previous installation of package {:?} was moved to [{}] because running \
+                services could still use it. It can be deleted when these services are stopped.",
+                package,
+                superseded_directory.to_string_lossy()
+            );
+        } else {
+            fs::rename(staging_directory, &target_directory)?;
+        }
+
+        fs::write(
+            self.get_strip_components_file(package),
+            strip_components.to_string(),
+        )?;
+        // The checksum is written last, so that it is only present if the installation succeeded
+        fs::write(self.get_checksum_file(package), archive_checksum)?;
         Ok(())
     }
 }
@@ -61,60 +424,319 @@ impl Installing {
 impl State<PodState> for Installing {
     async fn next(
         self: Box<Self>,
-        _provider_state: SharedState<ProviderState>,
-        _pod_state: &mut PodState,
-        _pod: Manifest<Pod>,
+        provider_state: SharedState<ProviderState>,
+        pod_state: &mut PodState,
+        pod: Manifest<Pod>,
     ) -> Transition<PodState> {
+        let pod = pod.latest();
+        record_transition(&provider_state, &pod, "Installing", None).await;
+        let strip_components = match strip_components(&pod) {
+            Ok(strip_components) => strip_components,
+            Err(e) => fail_fatal!(e),
+        };
+        let force_reinstall = match force_reinstall(&pod) {
+            Ok(force_reinstall) => force_reinstall,
+            Err(e) => fail_fatal!(e),
+        };
+
+        let install_permits = provider_state.read().await.install_permits.clone();
+        let _permit = acquire_install_permit(install_permits, &pod_state.service_name).await;
+
         let package = self.package.clone();
-        let package_name = &package.get_directory_name();
-        return if self.package_installed(package.clone()) {
-            info!("Package {} has already been installed", package);
-            return Transition::next(
-                self,
-                CreatingConfig {
-                    target_directory: None,
-                },
-            );
-        } else {
-            info!("Installing package {}", package);
-            match self.install_package(package.clone()) {
-                Ok(()) => Transition::next(
+        let package_name = &package.get_directory_name(&self.package_directory_template);
+
+        let archive_checksum = match self.archive_checksum(&package) {
+            Ok(archive_checksum) => archive_checksum,
+            Err(e) => {
+                error!(
+                    "Failed to calculate the checksum of the archive of package [{}] due to: [{:?}]",
+                    &package_name, e
+                );
+                return Transition::next(
+                    self,
+                    SetupFailed {
+                        message: "PackageInstallationFailed".to_string(),
+                    },
+                );
+            }
+        };
+
+        let installation_state = self.installation_state(&package, &archive_checksum);
+
+        // The package directory is shared by all pods which use the package, so its layout must
+        // not depend on the pod.
+        if installation_state != InstallationState::Missing {
+            let installed_strip_components = match self.installed_strip_components(&package) {
+                Ok(installed_strip_components) => installed_strip_components,
+                Err(e) => fail_fatal!(e),
+            };
+            if installed_strip_components != strip_components {
+                let e = StripComponentsConflict {
+                    pod: pod.name().to_string(),
+                    package: package.to_string(),
+                    installed: installed_strip_components,
+                    requested: strip_components,
+                };
+                fail_fatal!(e);
+            }
+        }
+
+        // The archive was downloaded again if a reinstallation is forced, so the package only
+        // needs to be extracted again if the archive changed.
+        match installation_state {
+            InstallationState::Current => {
+                if force_reinstall {
+                    warn!(
+                        "Package {} is not installed again although the pod requests a \
+                        reinstallation because the downloaded archive did not change",
+                        package
+                    );
+                } else {
+                    info!("Package {} has already been installed", package);
+                }
+                return Transition::next(
                     self,
                     CreatingConfig {
                         target_directory: None,
                     },
-                ),
-                Err(e) => {
-                    error!(
-                        "Failed to install package [{}] due to: [{:?}]",
+                );
+            }
+            InstallationState::Unverified if force_reinstall => warn!(
+                "Package {} is installed again because it was installed without a checksum and \
+                the pod requests a reinstallation",
+                package
+            ),
+            InstallationState::Unverified => {
+                info!(
+                    "Package {} was installed without a checksum, adopting the installation",
+                    package
+                );
+                if let Err(e) = self.adopt_installation(&package, &archive_checksum) {
+                    warn!(
+                        "The checksum of package [{}] could not be recorded due to: [{:?}]",
                         &package_name, e
                     );
-                    // Clean up partially unpacked directory to avoid later iterations assuming
-                    // this install attempt was successful because the target directory exists.
-                    let installation_directory = self.get_target_directory(&package);
-                    debug!(
-                        "Cleaning up partial installation by deleting directory [{}]",
-                        installation_directory.to_string_lossy()
-                    );
-                    if let Err(error) = fs::remove_dir_all(&installation_directory) {
-                        error!(
-                            "Failed to clean up directory [{}] due to {}",
-                            installation_directory.to_string_lossy(),
-                            error
-                        );
-                    };
-                    Transition::next(
-                        self,
-                        SetupFailed {
-                            message: "PackageInstallationFailed".to_string(),
-                        },
-                    )
                 }
+                return Transition::next(
+                    self,
+                    CreatingConfig {
+                        target_directory: None,
+                    },
+                );
             }
-        };
+            InstallationState::Outdated => info!(
+                "Package {} was installed from a different archive, reinstalling it",
+                package
+            ),
+            InstallationState::Missing => (),
+        }
+
+        info!("Installing package {}", package);
+        match self.install_package(
+            &package,
+            &archive_checksum,
+            strip_components,
+            &pod_state.service_name,
+        ) {
+            Ok(()) => Transition::next(
+                self,
+                CreatingConfig {
+                    target_directory: None,
+                },
+            ),
+            Err(e) => {
+                error!(
+                    "Failed to install package [{}] due to: [{:?}]",
+                    &package_name, e
+                );
+                Transition::next(
+                    self,
+                    SetupFailed {
+                        message: "PackageInstallationFailed".to_string(),
+                    },
+                )
+            }
+        }
     }
 
     async fn status(&self, _pod_state: &mut PodState, _pod: &Pod) -> anyhow::Result<PodStatus> {
         Ok(make_status(Phase::Pending, "Installing"))
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::provider::test::TestPod;
+    use rstest::rstest;
+    use std::env;
+    use std::process;
+
+    #[rstest]
+    #[case::not_set("{}", Some(0))]
+    #[case::set(r#"{"stackable.tech/package-strip-components": "1"}"#, Some(1))]
+    #[case::negative(r#"{"stackable.tech/package-strip-components": "-1"}"#, None)]
+    fn strip_components_should_be_read_from_annotation(
+        #[case] annotations: &str,
+        #[case] expected_strip_components: Option<usize>,
+    ) {
+        let pod = format!(
+            "
+            apiVersion: v1
+            kind: Pod
+            metadata:
+              name: test
+              annotations: {}
+            spec:
+              containers:
+                - name: test-container
+            ",
+            annotations
+        )
+        .parse::<TestPod>()
+        .unwrap();
+
+        assert_eq!(expected_strip_components, strip_components(&pod).ok());
+    }
+
+    #[test]
+    fn leading_path_components_should_be_stripped() {
+        let mut builder = tar::Builder::new(Vec::new());
+        let mut append_file = |path: &str, content: &[u8]| {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(content.len() as u64);
+            header.set_mode(0o755);
+            header.set_cksum();
+            builder.append_data(&mut header, path, content).unwrap();
+        };
+        append_file("vendor-1.0/bin/start.sh", b"#!/bin/sh");
+        append_file("vendor-1.0/README", b"readme");
+        let archive = builder.into_inner().unwrap();
+
+        let target_directory =
+            env::temp_dir().join(format!("stackable-agent-installing-{}", process::id()));
+        let result = unpack(&mut Archive::new(archive.as_slice()), &target_directory, 1);
+
+        let content = |file: &str| fs::read_to_string(target_directory.join(file)).ok();
+        let files = (content("bin/start.sh"), content("README"));
+        fs::remove_dir_all(&target_directory).unwrap();
+
+        assert!(result.is_ok());
+        assert_eq!(
+            (
+                Some(String::from("#!/bin/sh")),
+                Some(String::from("readme"))
+            ),
+            files
+        );
+    }
+
+    #[test]
+    fn previous_installation_should_be_kept_when_package_is_reinstalled() {
+        let parcel_directory =
+            env::temp_dir().join(format!("stackable-agent-reinstalling-{}", process::id()));
+        let installing = Installing {
+            download_directory: parcel_directory.join("_download"),
+            parcel_directory: parcel_directory.to_owned(),
+            package: Package {
+                product: String::from("kafka"),
+                version: String::from("2.7"),
+            },
+            package_directory_template: String::from("{{product}}-{{version}}"),
+        };
+        let package = installing.package.clone();
+
+        let write_archive = |content: &[u8]| {
+            let mut builder = tar::Builder::new(flate2::write::GzEncoder::new(
+                Vec::new(),
+                flate2::Compression::default(),
+            ));
+            let mut header = tar::Header::new_gnu();
+            header.set_size(content.len() as u64);
+            header.set_mode(0o644);
+            header.set_cksum();
+            builder.append_data(&mut header, "README", content).unwrap();
+            let archive = builder.into_inner().unwrap().finish().unwrap();
+            fs::create_dir_all(&installing.download_directory).unwrap();
+            fs::write(installing.get_archive_path(&package), archive).unwrap();
+        };
+
+        write_archive(b"first");
+        let first_result = installing.install_package(&package, "first", 0, "test");
+        write_archive(b"second");
+        let state_before_reinstallation = installing.installation_state(&package, "second");
+        let second_result = installing.install_package(&package, "second", 0, "test");
+
+        let installed_content =
+            fs::read_to_string(installing.get_target_directory(&package).join("README")).ok();
+        let superseded_contents = fs::read_dir(parcel_directory.join(SUPERSEDED_DIRECTORY))
+            .unwrap()
+            .map(|entry| fs::read_to_string(entry.unwrap().path().join("README")).unwrap())
+            .collect::<Vec<_>>();
+        let state_after_reinstallation = installing.installation_state(&package, "second");
+        let staging_directory_exists = parcel_directory
+            .join(STAGING_DIRECTORY)
+            .join("kafka-2.7.test")
+            .exists();
+        fs::remove_dir_all(&parcel_directory).unwrap();
+
+        assert!(first_result.is_ok());
+        assert!(second_result.is_ok());
+        assert_eq!(InstallationState::Outdated, state_before_reinstallation);
+        assert_eq!(InstallationState::Current, state_after_reinstallation);
+        assert_eq!(Some(String::from("second")), installed_content);
+        assert_eq!(vec![String::from("first")], superseded_contents);
+        assert!(!staging_directory_exists);
+    }
+
+    #[test]
+    fn entries_below_symbolic_links_should_be_rejected() {
+        let mut builder = tar::Builder::new(Vec::new());
+        let mut header = tar::Header::new_gnu();
+        header.set_entry_type(EntryType::Symlink);
+        header.set_path("vendor-1.0/escape").unwrap();
+        header.set_link_name(env::temp_dir()).unwrap();
+        header.set_size(0);
+        header.set_cksum();
+        builder.append(&header, io::empty()).unwrap();
+        let mut header = tar::Header::new_gnu();
+        header.set_size(0);
+        header.set_cksum();
+        builder
+            .append_data(&mut header, "vendor-1.0/escape/file", io::empty())
+            .unwrap();
+        let archive = builder.into_inner().unwrap();
+
+        let target_directory = env::temp_dir().join(format!(
+            "stackable-agent-installing-symlink-{}",
+            process::id()
+        ));
+        let result = unpack(&mut Archive::new(archive.as_slice()), &target_directory, 1);
+        fs::remove_dir_all(&target_directory).unwrap();
+
+        assert!(result.is_err());
+        assert!(!env::temp_dir().join("file").exists());
+    }
+
+    #[test]
+    fn paths_leaving_the_target_directory_should_be_rejected() {
+        let mut header = tar::Header::new_gnu();
+        header.set_size(0);
+        header.set_cksum();
+        let mut builder = tar::Builder::new(Vec::new());
+        // `append_data` rejects such paths, so the path is written directly into the header
+        header.as_old_mut().name[..23].copy_from_slice(b"vendor-1.0/../../escape");
+        header.set_cksum();
+        builder.append(&header, io::empty()).unwrap();
+        let archive = builder.into_inner().unwrap();
+
+        let target_directory = env::temp_dir().join(format!(
+            "stackable-agent-installing-escape-{}",
+            process::id()
+        ));
+        let result = unpack(&mut Archive::new(archive.as_slice()), &target_directory, 1);
+        fs::remove_dir_all(&target_directory).unwrap();
+
+        assert!(result.is_err());
+    }
+}
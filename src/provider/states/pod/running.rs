@@ -1,33 +1,58 @@
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+use std::time::{Duration, Instant};
+
 use anyhow::anyhow;
 use k8s_openapi::api::core::v1::PodCondition;
 use k8s_openapi::apimachinery::pkg::apis::meta::v1::Time;
 use k8s_openapi::chrono;
 use krator::ObjectStatus;
 use kubelet::{
-    container::Status,
+    container::{ContainerKey, Status},
     pod::state::prelude::*,
     pod::{Pod, PodKey},
 };
 use log::{debug, info, trace, warn};
-use tokio::time::Duration;
 
 use super::terminated::Terminated;
+use crate::provider::transitions::record_transition;
 use crate::provider::{
-    kubernetes::status::{patch_container_status, patch_restart_count},
+    kubernetes::accessor::{restart_policy, RestartPolicy},
+    kubernetes::status::{
+        patch_container_status, patch_exit_status, patch_restart_count, patch_waiting_state,
+    },
+    log_rotation::LogRotation,
+    resource_limits::{differ_only_in_limits, ResourceLimits},
+    systemdmanager::manager::SystemdManager,
     systemdmanager::service::ServiceState,
-    PodHandle, PodState, ProviderState,
+    systemdmanager::systemd1_api::{ServiceResult, StartMode, StopMode},
+    systemdmanager::systemdunit::{SystemDUnit, MAX_RESTART_DELAY_SECS},
+    ContainerHandle, PodHandle, PodState, ProviderState, StackableProvider,
 };
 
+/// Type of the pod condition which states that the service units are enabled
+pub const ENABLED_CONDITION_TYPE: &str = "stackable.tech/Enabled";
+
+/// Reason of the waiting state of a container whose service waits for its restart, like the
+/// Kubelet reports it for crashing containers
+const CRASH_LOOP_BACK_OFF_REASON: &str = "CrashLoopBackOff";
+
+/// Delay before a unit which hit the start limit is started again
+const START_LIMIT_BACK_OFF_DELAY: Duration = Duration::from_secs(MAX_RESTART_DELAY_SECS as u64);
+
 #[derive(Debug, TransitionTo)]
 #[transition_to(Terminated)]
 pub struct Running {
     pub transition_time: Time,
+    /// Adopted units which were running but had to be enabled again
+    pub reenabled_units: Vec<String>,
 }
 
 impl Default for Running {
     fn default() -> Self {
         Self {
             transition_time: Time(chrono::offset::Utc::now()),
+            reenabled_units: Vec::new(),
         }
     }
 }
@@ -38,46 +63,157 @@ impl State<PodState> for Running {
         mut self: Box<Self>,
         shared: SharedState<ProviderState>,
         pod_state: &mut PodState,
-        pod: Manifest<Pod>,
+        manifest: Manifest<Pod>,
     ) -> Transition<PodState> {
-        let pod = pod.latest();
+        let pod = manifest.latest();
         let pod_key = &PodKey::from(&pod);
+        record_transition(&shared, &pod, "Running", None).await;
 
-        let (client, pod_handle) = {
+        let (
+            client,
+            systemd_manager,
+            kubeconfig_path,
+            pod_handle,
+            service_check_interval,
+            allow_multiple_containers,
+        ) = {
             let provider_state = shared.read().await;
             let handles = provider_state.handles.read().await;
             (
                 provider_state.client.clone(),
+                provider_state.systemd_manager.clone(),
+                provider_state.kubeconfig_path.clone(),
                 handles.get(pod_key).map(PodHandle::to_owned),
+                provider_state.service_check_interval,
+                provider_state.allow_multiple_containers,
             )
         };
 
-        let mut running_containers = match &pod_handle {
-                Some(containers) => containers.to_owned(),
+        // Init containers already completed in the starting state
+        let mut running_containers: PodHandle = match &pod_handle {
+                Some(containers) => containers
+                    .iter()
+                    .filter(|(container_key, _)| container_key.is_app())
+                    .map(|(container_key, container_handle)| {
+                        (container_key.to_owned(), container_handle.to_owned())
+                    })
+                    .collect(),
                 None => return Transition::Complete(Err(anyhow!("No systemd units found for service [{}], this should not happen, please report a bug for this!", pod_state.service_name))),
             };
 
         let mut container_failed = false;
 
+        // The invocation ID changes whenever a service is restarted, so the last known one is
+        // kept to make the logs of the previous run available.
+        let mut invocation_ids: HashMap<ContainerKey, String> = HashMap::new();
+
+        // The restart count is only patched if it changed since the last successful patch.
+        let mut restart_counts: HashMap<ContainerKey, u32> = HashMap::new();
+
+        // Containers whose main process was not alive in the last check although systemd
+        // considers their units active
+        let mut dead_main_processes: HashSet<ContainerKey> = HashSet::new();
+
+        // Containers which were reported as waiting because their services wait for a restart
+        let mut backing_off_containers: HashSet<ContainerKey> = HashSet::new();
+
+        // Containers whose units hit the start limit and since when they are failed
+        let mut start_limited_containers: HashMap<ContainerKey, Instant> = HashMap::new();
+
+        // The resource limits which were applied to the service units
+        let mut resource_limits = container_resource_limits(&pod);
+
+        // The resource version of the pod from which the service units were generated
+        let mut resource_version = pod.as_kube_pod().metadata.resource_version.to_owned();
+
+        // The annotations were already validated before the pod was started, so rotation is
+        // only disabled here if they were changed in the meantime.
+        let log_rotation = LogRotation::from_pod(&pod).unwrap_or_else(|error| {
+            warn!(
+                "Log rotation of service [{}] is disabled: {}",
+                pod_state.service_name, error
+            );
+            None
+        });
+
         // We loop here and "wake up" periodically to check if the service is still
         // up and running
         // Interruption of this loop is triggered externally by the Krustlet code when
         //   - the pod which this state machine refers to gets deleted
         //   - Krustlet shuts down
         while !running_containers.is_empty() {
-            tokio::time::sleep(Duration::from_secs(10)).await;
+            tokio::time::sleep(service_check_interval).await;
             trace!(
                 "Checking if service {} is still running.",
                 &pod_state.service_name
             );
 
+            // Changed resource limits, e.g. of a pod which was resized in place, are applied to
+            // the running units, so that the services do not have to be restarted.
+            let latest_pod = manifest.latest();
+            for (container_key, limits) in container_resource_limits(&latest_pod) {
+                if resource_limits.get(&container_key) == Some(&limits) {
+                    continue;
+                }
+                if let Some(container_handle) = running_containers.get(&container_key) {
+                    info!(
+                        "Applying the changed resource limits {:?} to unit [{}] of service [{}].",
+                        limits, container_handle.service_unit, pod_state.service_name
+                    );
+                    // The changes are only applied at runtime because persistent drop-ins
+                    // in system.control would not be removed together with the unit. The
+                    // unit files are updated with the current limits anyway when they are
+                    // recreated.
+                    match systemd_manager
+                        .set_properties(
+                            &container_handle.service_unit,
+                            true,
+                            &limits.dbus_properties(),
+                        )
+                        .await
+                    {
+                        Ok(()) => {
+                            resource_limits.insert(container_key, limits);
+                        }
+                        Err(error) => warn!("{}", error),
+                    }
+                }
+            }
+
+            // Changes of the pod after it was started, e.g. of its annotations, are applied by
+            // recreating the units which differ from the updated pod.
+            if latest_pod.as_kube_pod().metadata.resource_version != resource_version {
+                resource_version = latest_pod
+                    .as_kube_pod()
+                    .metadata
+                    .resource_version
+                    .to_owned();
+                reconcile_units(
+                    &systemd_manager,
+                    &kubeconfig_path,
+                    pod_state,
+                    &latest_pod,
+                    &running_containers,
+                    allow_multiple_containers,
+                )
+                .await;
+            }
+
             let mut succeeded_containers = Vec::new();
             let mut failed_containers = Vec::new();
+            let mut recreated_containers = Vec::new();
 
             for (container_key, container_handle) in running_containers.iter() {
                 let systemd_service = &container_handle.systemd_service;
 
                 match systemd_service.service_state().await {
+                    Ok(ServiceState::Created) if container_handle.trigger_unit.is_some() => {
+                        trace!(
+                            "The unit [{}] of service [{}] waits for its activation.",
+                            systemd_service.file(),
+                            pod_state.service_name
+                        );
+                    }
                     Ok(ServiceState::Created) => {
                         warn!(
                             "The unit [{}] of service [{}] was not started. \
@@ -86,9 +222,190 @@ impl State<PodState> for Running {
                             pod_state.service_name
                         );
                     }
-                    Ok(ServiceState::Started) => {}
+                    Ok(ServiceState::Started) => {
+                        match systemd_service.is_restart_pending().await {
+                            Ok(true) => {
+                                if backing_off_containers.insert(container_key.to_owned()) {
+                                    info!(
+                                        "The unit [{}] of service [{}] terminated and is restarted after a back-off delay.",
+                                        systemd_service.file(),
+                                        pod_state.service_name
+                                    );
+                                    let message = format!(
+                                        "Back-off restarting the terminated unit [{}]",
+                                        systemd_service.file()
+                                    );
+                                    if let Err(error) = patch_waiting_state(
+                                        &client,
+                                        &pod,
+                                        container_key,
+                                        CRASH_LOOP_BACK_OFF_REASON,
+                                        &message,
+                                    )
+                                    .await
+                                    {
+                                        warn!("Could not patch waiting state: {}", error);
+                                        backing_off_containers.remove(container_key);
+                                    }
+                                }
+                                continue;
+                            }
+                            Ok(false) => {
+                                if backing_off_containers.remove(container_key) {
+                                    patch_container_status(
+                                        &client,
+                                        &pod,
+                                        container_key,
+                                        &Status::running(),
+                                    )
+                                    .await;
+                                }
+                            }
+                            Err(error) => warn!("{}", error),
+                        }
+
+                        match systemd_service.is_main_process_alive().await {
+                            Ok(Some(false)) => {
+                                // The main process may have just terminated and systemd did
+                                // not process this yet, so the unit is only restarted if the
+                                // process is still not alive in the next check.
+                                if dead_main_processes.insert(container_key.to_owned()) {
+                                    debug!(
+                                        "The main process of unit [{}] of service [{}] is not alive although the unit is active.",
+                                        systemd_service.file(),
+                                        pod_state.service_name
+                                    );
+                                } else if container_handle.transient_unit.is_some() {
+                                    // A stopped transient unit is unloaded by systemd and
+                                    // cannot be started again.
+                                    warn!(
+                                        "The main process of the transient unit [{}] of service [{}] is not alive although the unit is active.",
+                                        systemd_service.file(),
+                                        pod_state.service_name
+                                    );
+                                } else {
+                                    dead_main_processes.remove(container_key);
+                                    let unit = systemd_service.file();
+
+                                    // The exit status is unknown if systemd did not reap the
+                                    // process yet, in which case it is considered failed.
+                                    let succeeded = matches!(
+                                        systemd_service.exit_status().await,
+                                        Ok(exit_status)
+                                            if exit_status.exit_code == 0
+                                                && exit_status.signal.is_none()
+                                    );
+                                    let restart = match restart_policy(&pod) {
+                                        RestartPolicy::Always => true,
+                                        RestartPolicy::OnFailure => !succeeded,
+                                        RestartPolicy::Never => false,
+                                    };
+
+                                    if restart {
+                                        warn!(
+                                            "The main process of unit [{}] of service [{}] is not alive although the unit is active. Restarting the unit.",
+                                            unit,
+                                            pod_state.service_name
+                                        );
+                                        if let Err(error) = async {
+                                            systemd_manager.stop(&unit, StopMode::Replace).await?;
+                                            systemd_manager.start(&unit, StartMode::Fail).await
+                                        }
+                                        .await
+                                        {
+                                            warn!("{}", error);
+                                        }
+                                    } else {
+                                        warn!(
+                                            "The main process of unit [{}] of service [{}] is not alive although the unit is active. Stopping the unit according to the restart policy [{}].",
+                                            unit,
+                                            pod_state.service_name,
+                                            restart_policy(&pod)
+                                        );
+                                        if let Err(error) =
+                                            systemd_manager.stop(&unit, StopMode::Replace).await
+                                        {
+                                            warn!("{}", error);
+                                        }
+                                        let terminated_container =
+                                            (container_key.to_owned(), container_handle.to_owned());
+                                        if succeeded {
+                                            succeeded_containers.push(terminated_container);
+                                        } else {
+                                            failed_containers.push(terminated_container);
+                                        }
+                                    }
+                                }
+                            }
+                            Ok(_) => {
+                                dead_main_processes.remove(container_key);
+                            }
+                            Err(error) => warn!("{}", error),
+                        }
+                    }
                     Ok(ServiceState::Succeeded) => succeeded_containers
                         .push((container_key.to_owned(), container_handle.to_owned())),
+                    Ok(ServiceState::Failed)
+                        if container_handle.transient_unit.is_none()
+                            && restart_policy(&pod) != RestartPolicy::Never
+                            && matches!(
+                                systemd_service.result().await,
+                                Ok(ServiceResult::StartLimit)
+                            ) =>
+                    {
+                        // Older systemd versions do not escalate the restart delays, so the
+                        // unit hits the start limit instead and is restarted after the maximum
+                        // back-off delay, see START_LIMIT_INTERVAL_SECS in SystemDUnit.
+                        let unit = systemd_service.file();
+                        match start_limited_containers.get(container_key) {
+                            None => {
+                                info!(
+                                    "The unit [{}] of service [{}] hit the start limit and is restarted after a back-off delay.",
+                                    unit, pod_state.service_name
+                                );
+                                start_limited_containers
+                                    .insert(container_key.to_owned(), Instant::now());
+                                if backing_off_containers.insert(container_key.to_owned()) {
+                                    let message = format!(
+                                        "Back-off restarting the unit [{}] which hit the start limit",
+                                        unit
+                                    );
+                                    if let Err(error) = patch_waiting_state(
+                                        &client,
+                                        &pod,
+                                        container_key,
+                                        CRASH_LOOP_BACK_OFF_REASON,
+                                        &message,
+                                    )
+                                    .await
+                                    {
+                                        warn!("Could not patch waiting state: {}", error);
+                                        backing_off_containers.remove(container_key);
+                                    }
+                                }
+                            }
+                            Some(failure_time)
+                                if failure_time.elapsed() >= START_LIMIT_BACK_OFF_DELAY =>
+                            {
+                                info!(
+                                    "Restarting the unit [{}] of service [{}] after the start limit back-off.",
+                                    unit, pod_state.service_name
+                                );
+                                match async {
+                                    systemd_manager.reset_failed(&unit).await?;
+                                    systemd_manager.start(&unit, StartMode::Fail).await
+                                }
+                                .await
+                                {
+                                    Ok(()) => {
+                                        start_limited_containers.remove(container_key);
+                                    }
+                                    Err(error) => warn!("{}", error),
+                                }
+                            }
+                            Some(_) => {}
+                        }
+                    }
                     Ok(ServiceState::Failed) => failed_containers
                         .push((container_key.to_owned(), container_handle.to_owned())),
                     Err(dbus_error) => {
@@ -98,10 +415,45 @@ impl State<PodState> for Running {
                             pod_state.service_name,
                             dbus_error
                         );
+
+                        // The proxies of the service are bound to the connection they
+                        // were created with. If the connection to systemd was lost then
+                        // the systemd manager reconnects and the proxies must be
+                        // recreated, otherwise all subsequent queries fail.
+                        match systemd_manager
+                            .create_systemd_service(&systemd_service.file())
+                            .await
+                        {
+                            Ok(systemd_service) => recreated_containers.push((
+                                container_key.to_owned(),
+                                ContainerHandle {
+                                    service_unit: container_handle.service_unit.to_owned(),
+                                    systemd_service,
+                                    trigger_unit: container_handle.trigger_unit.to_owned(),
+                                    previous_invocation_id: container_handle
+                                        .previous_invocation_id
+                                        .to_owned(),
+                                    transient_unit: container_handle.transient_unit.to_owned(),
+                                },
+                            )),
+                            Err(error) => warn!(
+                                "Proxy for unit [{}] of service [{}] could not be recreated: [{}].",
+                                container_handle.service_unit, pod_state.service_name, error
+                            ),
+                        }
                     }
                 }
             }
 
+            if !recreated_containers.is_empty() {
+                let provider_state = shared.read().await;
+                let mut handles = provider_state.handles.write().await;
+                for (container_key, container_handle) in recreated_containers {
+                    handles.insert_container_handle(pod_key, &container_key, &container_handle);
+                    running_containers.insert(container_key, container_handle);
+                }
+            }
+
             for (container_key, container_handle) in &succeeded_containers {
                 info!(
                     "Unit [{}] for service [{}] terminated successfully.",
@@ -114,6 +466,17 @@ impl State<PodState> for Running {
                     &Status::terminated("Completed", false),
                 )
                 .await;
+                if let Err(error) = patch_exit_status(
+                    &client,
+                    &pod,
+                    container_key,
+                    "Completed",
+                    &container_handle.systemd_service,
+                )
+                .await
+                {
+                    warn!("Could not patch exit status: {}", error);
+                }
                 running_containers.remove(container_key);
             }
 
@@ -129,10 +492,23 @@ impl State<PodState> for Running {
                     &Status::terminated("Error", true),
                 )
                 .await;
+                if let Err(error) = patch_exit_status(
+                    &client,
+                    &pod,
+                    container_key,
+                    "Error",
+                    &container_handle.systemd_service,
+                )
+                .await
+                {
+                    warn!("Could not patch exit status: {}", error);
+                }
                 running_containers.remove(container_key);
                 container_failed = true;
             }
 
+            let mut restarted_containers = Vec::new();
+
             for (container_key, container_handle) in running_containers.iter() {
                 trace!(
                     "Unit [{}] of service [{}] still running ...",
@@ -141,11 +517,15 @@ impl State<PodState> for Running {
                 );
 
                 match container_handle.systemd_service.restart_count().await {
+                    Ok(restart_count)
+                        if restart_counts.get(container_key) == Some(&restart_count) => {}
                     Ok(restart_count) => {
-                        if let Err(error) =
-                            patch_restart_count(&client, &pod, container_key, restart_count).await
+                        match patch_restart_count(&client, &pod, container_key, restart_count).await
                         {
-                            warn!("Could not patch restart count: {}", error);
+                            Ok(()) => {
+                                restart_counts.insert(container_key.to_owned(), restart_count);
+                            }
+                            Err(error) => warn!("Could not patch restart count: {}", error),
                         }
                     }
                     Err(error) => warn!(
@@ -153,6 +533,49 @@ impl State<PodState> for Running {
                         container_handle.service_unit, error
                     ),
                 }
+
+                if let Ok(invocation_id) = container_handle.systemd_service.invocation_id().await {
+                    match invocation_ids.insert(container_key.to_owned(), invocation_id.to_owned())
+                    {
+                        Some(previous_invocation_id) if previous_invocation_id != invocation_id => {
+                            restarted_containers.push((
+                                container_key.to_owned(),
+                                ContainerHandle {
+                                    previous_invocation_id: Some(previous_invocation_id),
+                                    ..container_handle.to_owned()
+                                },
+                            ))
+                        }
+                        _ => {}
+                    }
+                }
+            }
+
+            if !restarted_containers.is_empty() {
+                let provider_state = shared.read().await;
+                let mut handles = provider_state.handles.write().await;
+                for (container_key, container_handle) in restarted_containers {
+                    debug!(
+                        "Unit [{}] of service [{}] was restarted.",
+                        container_handle.service_unit, pod_state.service_name
+                    );
+                    handles.insert_container_handle(pod_key, &container_key, &container_handle);
+                    running_containers.insert(container_key, container_handle);
+                }
+            }
+
+            if let Some(log_rotation) = &log_rotation {
+                match log_rotation.rotate(&pod_state.get_service_log_directory()) {
+                    Ok(rotated_files) if !rotated_files.is_empty() => info!(
+                        "Rotated log files of service [{}]: {:?}",
+                        pod_state.service_name, rotated_files
+                    ),
+                    Ok(_) => {}
+                    Err(error) => warn!(
+                        "Could not rotate log files of service [{}]: {}",
+                        pod_state.service_name, error
+                    ),
+                }
             }
         }
 
@@ -174,10 +597,33 @@ impl State<PodState> for Running {
             type_: "Ready".to_string(),
         };
 
+        let (enabled_reason, enabled_message) = if self.reenabled_units.is_empty() {
+            (
+                String::from("Enabled"),
+                String::from("Service units are enabled"),
+            )
+        } else {
+            (
+                String::from("Reenabled"),
+                format!(
+                    "Adopted units were not enabled and have been enabled again: {}",
+                    self.reenabled_units.join(", ")
+                ),
+            )
+        };
+        let enabled_condition = PodCondition {
+            last_probe_time: None,
+            last_transition_time: Some(self.transition_time.clone()),
+            message: Some(enabled_message),
+            reason: Some(enabled_reason),
+            status: "True".to_string(),
+            type_: ENABLED_CONDITION_TYPE.to_string(),
+        };
+
         let status = StatusBuilder::new()
             .phase(Phase::Running)
             .reason("Running")
-            .conditions(vec![condition])
+            .conditions(vec![condition, enabled_condition])
             .build();
 
         debug!(
@@ -188,3 +634,118 @@ impl State<PodState> for Running {
         Ok(status)
     }
 }
+
+/// Regenerates the service units of the given updated pod and recreates the units whose content
+/// differs from the unit files.
+///
+/// The content is compared like in the cleanup stage. The services are restarted to apply the
+/// changes unless only the resource limits changed, which are applied to the running units
+/// instead, or the services are activated by a trigger unit. Transient units have no unit file
+/// and are not reconciled. A changed package cannot be applied because it is not installed.
+async fn reconcile_units(
+    systemd_manager: &SystemdManager,
+    kubeconfig_path: &Path,
+    pod_state: &PodState,
+    pod: &Pod,
+    running_containers: &PodHandle,
+    allow_multiple_containers: bool,
+) {
+    match StackableProvider::get_package(pod, allow_multiple_containers) {
+        Ok(package) if package.to_string() == pod_state.package.to_string() => {}
+        Ok(package) => {
+            warn!(
+                "The package of service [{}] was changed from [{}] to [{}]. The pod must be \
+                recreated to apply this change.",
+                pod_state.service_name, pod_state.package, package
+            );
+            return;
+        }
+        Err(error) => {
+            warn!("{}", error);
+            return;
+        }
+    }
+
+    let units = match SystemDUnit::new_all(
+        systemd_manager.is_user_mode(),
+        pod_state,
+        kubeconfig_path,
+        pod,
+    ) {
+        Ok(units) => units,
+        Err(error) => {
+            warn!(
+                "The units of service [{}] could not be regenerated after the pod was updated: {}",
+                pod_state.service_name, error
+            );
+            return;
+        }
+    };
+
+    for (container_key, unit) in units {
+        let container_handle = match running_containers.get(&container_key) {
+            Some(container_handle) if container_handle.transient_unit.is_none() => container_handle,
+            _ => continue,
+        };
+        let service_unit = &container_handle.service_unit;
+
+        let expected_content = unit.get_unit_file_content();
+        let content = match systemd_manager.unit_file_content(service_unit).await {
+            Ok(Some(content)) if content != expected_content => content,
+            Ok(_) => continue,
+            Err(error) => {
+                warn!("{}", error);
+                continue;
+            }
+        };
+
+        if let Err(error) = unit.verify_executables() {
+            warn!("{}", error);
+            continue;
+        }
+
+        let restart = container_handle.trigger_unit.is_none()
+            && !differ_only_in_limits(&content, &expected_content);
+
+        info!(
+            "The unit [{}] of service [{}] differs from the updated pod specification and is \
+            recreated{}.",
+            service_unit,
+            pod_state.service_name,
+            if restart { " and restarted" } else { "" }
+        );
+
+        if let Err(error) = async {
+            systemd_manager.create_unit(&unit, None, true, true).await?;
+            if restart {
+                systemd_manager
+                    .stop(service_unit, StopMode::Replace)
+                    .await?;
+                systemd_manager.start(service_unit, StartMode::Fail).await?;
+            }
+            Ok::<(), anyhow::Error>(())
+        }
+        .await
+        {
+            warn!(
+                "The unit [{}] of service [{}] could not be recreated: {}",
+                service_unit, pod_state.service_name, error
+            );
+        }
+    }
+}
+
+/// Returns the resource limits of the app containers of the given pod.
+///
+/// Containers with invalid limits are omitted because the limits were already validated when
+/// the units were created.
+fn container_resource_limits(pod: &Pod) -> HashMap<ContainerKey, ResourceLimits> {
+    pod.containers()
+        .iter()
+        .filter_map(|container| {
+            ResourceLimits::from_container(container)
+                .ok()
+                .map(|limits| (ContainerKey::App(String::from(container.name())), limits))
+        })
+        .collect()
+}
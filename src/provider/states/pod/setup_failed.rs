@@ -2,6 +2,7 @@ use kubelet::pod::state::prelude::*;
 use log::{error, info};
 
 use super::downloading::Downloading;
+use crate::provider::transitions::record_transition;
 use crate::provider::{PodState, ProviderState};
 
 #[derive(Default, Debug, TransitionTo)]
@@ -19,11 +20,18 @@ pub struct SetupFailed {
 impl State<PodState> for SetupFailed {
     async fn next(
         self: Box<Self>,
-        _provider_state: SharedState<ProviderState>,
+        provider_state: SharedState<ProviderState>,
         _pod_state: &mut PodState,
         pod: Manifest<Pod>,
     ) -> Transition<PodState> {
         let pod = pod.latest();
+        record_transition(
+            &provider_state,
+            &pod,
+            "SetupFailed",
+            Some(self.message.to_owned()),
+        )
+        .await;
 
         error!(
             "setup failed for pod {} due to: {}",
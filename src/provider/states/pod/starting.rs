@@ -1,20 +1,33 @@
 use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::net::{IpAddr, SocketAddr};
 
 use super::running::Running;
+use crate::provider::transitions::record_transition;
 use crate::provider::{
-    kubernetes::status::patch_container_status, systemdmanager::service::ServiceState, PodHandle,
-    PodState, ProviderState,
+    kubernetes::status::{patch_container_status, patch_exit_status, patch_image},
+    systemdmanager::{
+        manager::SystemdManager,
+        service::{ServiceState, SystemdService},
+        systemd1_api::{ActiveState, StartMode},
+    },
+    ContainerHandle, PodHandle, PodState, ProviderState,
 };
 
-use anyhow::Result;
+use anyhow::{anyhow, Context, Result};
 use kube::{
     api::{Patch, PatchParams},
     Api, Client,
 };
 use kubelet::pod::{Pod, PodKey};
-use kubelet::{container::Status, pod::state::prelude::*};
-use log::{debug, error, info};
+use kubelet::{
+    container::{Container, ContainerKey, Status},
+    pod::state::prelude::*,
+};
+use log::{debug, error, info, trace, warn};
 use serde_json::json;
+use tokio::net::TcpStream;
+use tokio::time::{self, Duration, Instant};
 
 #[derive(Default, Debug, TransitionTo)]
 #[transition_to(Running)]
@@ -29,9 +42,16 @@ impl State<PodState> for Starting {
         pod: Manifest<Pod>,
     ) -> Transition<PodState> {
         let pod = pod.latest();
+        record_transition(&shared, &pod, "Starting", None).await;
 
         match start_service_units(shared, pod_state, &pod).await {
-            Ok(()) => Transition::next(self, Running::default()),
+            Ok(reenabled_units) => Transition::next(
+                self,
+                Running {
+                    reenabled_units,
+                    ..Running::default()
+                },
+            ),
             Err(error) => {
                 error!("{}", error);
                 Transition::Complete(Err(error))
@@ -46,39 +66,116 @@ impl State<PodState> for Starting {
 
 /// Starts the service units for the containers of the given pod.
 ///
-/// The units are started and enabled if they were not already started.
+/// The init units are run one after another before the service units are started. If an init
+/// unit does not complete successfully then an error is returned. The service units are started
+/// and enabled if they were not already started. Adopted service units which are not enabled,
+/// e.g. because the agent was interrupted between starting and enabling them, are enabled again
+/// and returned, so that they can be reported in the pod status.
 async fn start_service_units(
     shared: SharedState<ProviderState>,
     pod_state: &PodState,
     pod: &Pod,
-) -> Result<()> {
+) -> Result<Vec<String>> {
     let pod_key = &PodKey::from(pod);
 
-    let (client, systemd_manager, pod_handle) = {
+    let (client, systemd_manager, pod_handle, server_ip_address, adoption_readiness_timeout) = {
         let provider_state = shared.read().await;
         let handles = provider_state.handles.read().await;
         (
             provider_state.client.clone(),
             provider_state.systemd_manager.clone(),
             handles.get(pod_key).map(PodHandle::to_owned),
+            provider_state.server_ip_address,
+            provider_state.adoption_readiness_timeout,
         )
     };
 
-    for (container_key, container_handle) in pod_handle.unwrap_or_default() {
+    let pod_handle = pod_handle.unwrap_or_default();
+    let mut reenabled_units = Vec::new();
+
+    for init_container in pod.init_containers() {
+        let container_key = ContainerKey::Init(String::from(init_container.name()));
+        if let Some(container_handle) = pod_handle.get(&container_key) {
+            run_init_unit(
+                &client,
+                &systemd_manager,
+                pod,
+                &container_key,
+                container_handle,
+            )
+            .await?;
+        }
+    }
+
+    for (container_key, container_handle) in pod_handle.iter().filter(|(key, _)| key.is_app()) {
         let systemd_service = &container_handle.systemd_service;
         let service_unit = &container_handle.service_unit;
 
-        if systemd_service.service_state().await? == ServiceState::Created {
+        if let Some(trigger_unit) = &container_handle.trigger_unit {
+            // The service unit is started by the socket or timer unit.
+            info!("Starting systemd unit [{}]", trigger_unit);
+            systemd_manager.start(trigger_unit, StartMode::Fail).await?;
+
+            info!("Enabling systemd unit [{}]", trigger_unit);
+            systemd_manager.enable(trigger_unit).await?;
+        } else if let Some(transient_unit) = &container_handle.transient_unit {
+            // Transient units cannot be enabled and are not started again after a reboot.
+            if systemd_service.service_state().await? == ServiceState::Created {
+                info!("Starting transient systemd unit [{}]", service_unit);
+                systemd_manager.start_transient(transient_unit).await?;
+                await_activation(systemd_service, service_unit).await?;
+            } else {
+                debug!(
+                    "Transient unit [{}] for service [{}] was already started. Skipping startup.",
+                    service_unit, &pod_state.service_name
+                );
+            }
+        } else if systemd_service.service_state().await? == ServiceState::Created {
             info!("Starting systemd unit [{}]", service_unit);
-            systemd_manager.start(service_unit).await?;
+            systemd_manager.start(service_unit, StartMode::Fail).await?;
 
             info!("Enabling systemd unit [{}]", service_unit);
             systemd_manager.enable(service_unit).await?;
+
+            await_activation(systemd_service, service_unit).await?;
         } else {
             debug!(
                 "Unit [{}] for service [{}] was already started. Skipping startup.",
                 service_unit, &pod_state.service_name
             );
+
+            // The agent could have been restarted while the unit was activating.
+            await_activation(systemd_service, service_unit).await?;
+
+            // The unit would not be started again after a reboot of the node if it is not
+            // enabled.
+            if !systemd_manager.is_enabled(service_unit).await? {
+                warn!(
+                    "The adopted unit [{}] for service [{}] is running but not enabled. Enabling it again.",
+                    service_unit, &pod_state.service_name
+                );
+                systemd_manager.enable(service_unit).await?;
+                reenabled_units.push(service_unit.to_owned());
+            }
+
+            // The unit was adopted, so it is not known if the service is already ready, e.g.
+            // directly after a reboot of the node.
+            if adoption_readiness_timeout > Duration::from_secs(0) {
+                let ports = pod
+                    .containers()
+                    .iter()
+                    .find(|container| container.name() == container_key.name())
+                    .map(tcp_ports)
+                    .unwrap_or_default();
+                await_tcp_readiness(server_ip_address, &ports, adoption_readiness_timeout)
+                    .await
+                    .with_context(|| {
+                        format!(
+                            "The adopted unit [{}] for service [{}] did not become ready.",
+                            service_unit, &pod_state.service_name
+                        )
+                    })?;
+            }
         }
 
         let mut annotations = HashMap::new();
@@ -93,7 +190,170 @@ async fn start_service_units(
 
         add_annotations(&client, pod, &annotations).await?;
 
-        patch_container_status(&client, pod, &container_key, &Status::running()).await;
+        patch_container_status(&client, pod, container_key, &Status::running()).await;
+
+        match pod_state.get_package_image_id() {
+            Ok(image_id) => {
+                if let Err(error) = patch_image(
+                    &client,
+                    pod,
+                    container_key,
+                    &pod_state.package.to_string(),
+                    &image_id,
+                )
+                .await
+                {
+                    warn!("Could not patch image: {}", error);
+                }
+            }
+            Err(error) => warn!(
+                "Checksum of package [{}] of service [{}] could not be read: {}",
+                pod_state.package, pod_state.service_name, error
+            ),
+        }
+    }
+
+    Ok(reenabled_units)
+}
+
+/// Runs the given init unit to completion if it did not run already.
+///
+/// The start job of a oneshot unit only finishes when the process exited. Afterwards the state
+/// of the unit tells if the init container completed successfully.
+async fn run_init_unit(
+    client: &Client,
+    systemd_manager: &SystemdManager,
+    pod: &Pod,
+    container_key: &ContainerKey,
+    container_handle: &ContainerHandle,
+) -> Result<()> {
+    let systemd_service = &container_handle.systemd_service;
+    let service_unit = &container_handle.service_unit;
+
+    if systemd_service.service_state().await? == ServiceState::Created {
+        info!("Running init unit [{}]", service_unit);
+        patch_container_status(client, pod, container_key, &Status::running()).await;
+
+        if let Err(error) = systemd_manager.start(service_unit, StartMode::Fail).await {
+            warn!("{}", error);
+        }
+    } else {
+        debug!("Init unit [{}] was already run. Skipping it.", service_unit);
+    }
+
+    let succeeded = systemd_service.service_state().await? == ServiceState::Succeeded;
+    let reason = if succeeded { "Completed" } else { "Error" };
+
+    patch_container_status(
+        client,
+        pod,
+        container_key,
+        &Status::terminated(reason, !succeeded),
+    )
+    .await;
+    if let Err(error) = patch_exit_status(client, pod, container_key, reason, systemd_service).await
+    {
+        warn!("Could not patch exit status: {}", error);
+    }
+
+    if succeeded {
+        Ok(())
+    } else {
+        Err(anyhow!(
+            "Init unit [{}] did not complete successfully",
+            service_unit
+        ))
+    }
+}
+
+/// Waits until the given service unit left the "activating" state.
+///
+/// [`ServiceState::Started`] comprises all states of a started unit, so a service which does
+/// not finish its startup, e.g. a service of `Type=notify` which exits before it sends
+/// `READY=1`, would be reported as running before it fails. The wait is bounded by
+/// `TimeoutStartSec` of the unit, after which systemd fails the unit. A unit which waits for
+/// its automatic restart is also in the "activating" state but it was already up, so the
+/// restart is left to the [`Running`] state.
+///
+/// A unit which fails or stops during its activation is not treated as an error but also left
+/// to the [`Running`] state, so that its termination is reported in the container status and the
+/// restart policy of the pod is applied.
+async fn await_activation(systemd_service: &SystemdService, service_unit: &str) -> Result<()> {
+    loop {
+        let active_state = systemd_service.active_state().await?;
+
+        if active_state == ActiveState::Activating && systemd_service.is_restart_pending().await? {
+            debug!(
+                "Unit [{}] is waiting for its restart and is not awaited",
+                service_unit
+            );
+            return Ok(());
+        }
+
+        if is_activation_finished(&active_state) {
+            if is_active(&active_state) {
+                debug!("Unit [{}] is active", service_unit);
+            } else {
+                warn!(
+                    "The unit [{}] did not become active but is [{}]",
+                    service_unit, active_state
+                );
+            }
+            return Ok(());
+        }
+
+        trace!("Unit [{}] is still activating", service_unit);
+        time::sleep(Duration::from_secs(1)).await;
+    }
+}
+
+/// Checks if a unit in the given state finished its activation, successfully or not.
+fn is_activation_finished(active_state: &ActiveState) -> bool {
+    *active_state != ActiveState::Activating
+}
+
+/// Checks if a unit in the given state is active.
+fn is_active(active_state: &ActiveState) -> bool {
+    matches!(active_state, ActiveState::Active | ActiveState::Reloading)
+}
+
+/// Returns the TCP ports declared in the given container.
+fn tcp_ports(container: &Container) -> Vec<u16> {
+    container
+        .ports()
+        .into_iter()
+        .flatten()
+        .filter(|port| port.protocol.as_deref().unwrap_or("TCP") == "TCP")
+        .filter_map(|port| u16::try_from(port.container_port).ok())
+        .collect()
+}
+
+/// Waits until all given TCP ports accept connections.
+///
+/// The connection attempts are repeated every second. An error is returned if not all ports
+/// accept connections within the given timeout.
+async fn await_tcp_readiness(address: IpAddr, ports: &[u16], timeout: Duration) -> Result<()> {
+    let deadline = Instant::now() + timeout;
+
+    for &port in ports {
+        let socket_address = SocketAddr::new(address, port);
+
+        let connect = async {
+            while let Err(error) = TcpStream::connect(socket_address).await {
+                trace!("Port [{}] is not ready yet: {}", socket_address, error);
+                time::sleep(Duration::from_secs(1)).await;
+            }
+        };
+
+        time::timeout_at(deadline, connect).await.map_err(|_| {
+            anyhow!(
+                "Port [{}] did not accept connections within {} seconds",
+                socket_address,
+                timeout.as_secs()
+            )
+        })?;
+
+        debug!("Port [{}] accepts connections", socket_address);
     }
 
     Ok(())
@@ -131,3 +391,66 @@ async fn add_annotations(
     )
     .await
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::provider::test::TestPod;
+    use rstest::rstest;
+    use std::net::Ipv4Addr;
+    use tokio::net::TcpListener;
+
+    #[test]
+    fn should_return_only_tcp_ports() {
+        let pod = "
+            apiVersion: v1
+            kind: Pod
+            metadata:
+              name: test
+            spec:
+              containers:
+                - name: test-container
+                  ports:
+                    - containerPort: 8080
+                    - containerPort: 9090
+                      protocol: TCP
+                    - containerPort: 5353
+                      protocol: UDP
+            "
+        .parse::<TestPod>()
+        .unwrap();
+
+        let container = pod.containers().first().cloned().unwrap();
+
+        assert_eq!(vec![8080, 9090], tcp_ports(&container));
+    }
+
+    #[rstest]
+    #[case(ActiveState::Active, true, true)]
+    #[case(ActiveState::Reloading, true, true)]
+    #[case(ActiveState::Activating, false, false)]
+    #[case(ActiveState::Inactive, true, false)]
+    #[case(ActiveState::Failed, true, false)]
+    #[case(ActiveState::Deactivating, true, false)]
+    fn activation_should_be_finished_unless_activating(
+        #[case] active_state: ActiveState,
+        #[case] expected_finished: bool,
+        #[case] expected_active: bool,
+    ) {
+        assert_eq!(expected_finished, is_activation_finished(&active_state));
+        assert_eq!(expected_active, is_active(&active_state));
+    }
+
+    #[tokio::test]
+    async fn readiness_check_should_succeed_for_listening_port() {
+        let address = IpAddr::V4(Ipv4Addr::LOCALHOST);
+        let listener = TcpListener::bind(SocketAddr::new(address, 0))
+            .await
+            .unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        let result = await_tcp_readiness(address, &[port], Duration::from_secs(5)).await;
+
+        assert!(result.is_ok());
+    }
+}
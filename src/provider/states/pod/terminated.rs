@@ -1,8 +1,17 @@
+use k8s_openapi::api::core::v1::Pod as KubePod;
+use kube::Api;
 use kubelet::pod::{state::prelude::*, PodKey};
 use log::{debug, info, warn};
+use tokio::time::{self, Duration};
 
+use crate::provider::systemdmanager::systemd1_api::StopMode;
+use crate::provider::systemdmanager::systemdunit::SystemDUnit;
+use crate::provider::transitions::record_transition;
 use crate::provider::{PodState, ProviderState};
 
+/// Interval in which it is checked if a failed pod with kept units was deleted
+const DELETION_CHECK_INTERVAL: Duration = Duration::from_secs(10);
+
 #[derive(Default, Debug)]
 /// The pod object was deleted in Kubernetes
 pub struct Terminated {
@@ -20,53 +29,28 @@ impl State<PodState> for Terminated {
         info!("Pod {} was terminated", &pod_state.service_name);
 
         let pod = pod.latest();
-        let pod_key = &PodKey::from(pod);
-
-        let (systemd_manager, pod_handle) = {
-            let provider_state = shared.write().await;
-            let mut handles = provider_state.handles.write().await;
-            (
-                provider_state.systemd_manager.clone(),
-                handles.remove(pod_key),
-            )
-        };
-
-        // TODO: We need some additional error handling here, wait for the services to actually
-        //  shut down and try to remove the rest of the services if one fails (tbd, do we want that?)
-        if let Some(containers) = pod_handle {
-            for container_handle in containers.values() {
-                let service_unit = &container_handle.service_unit;
-
-                debug!("Stopping systemd unit [{}]", service_unit);
-                if let Err(stop_error) = systemd_manager.stop(service_unit).await {
-                    warn!(
-                        "Error occurred stopping systemd unit [{}]: [{}]",
-                        service_unit, stop_error
-                    );
-                    return Transition::Complete(Err(stop_error));
-                }
+        let pod_key = &PodKey::from(&pod);
 
-                // Daemon reload is false here, we'll do that once after all units have been removed
-                debug!("Removing systemd unit [{}]", service_unit);
-                if let Err(remove_error) = systemd_manager.remove_unit(service_unit, false).await {
-                    warn!(
-                        "Error occurred removing systemd unit [{}]: [{}]",
-                        service_unit, remove_error
-                    );
-                    return Transition::Complete(Err(remove_error));
-                }
-            }
+        let keep_failed_services = shared.read().await.keep_failed_services;
+        let pod_deleted = pod.as_kube_pod().metadata.deletion_timestamp.is_some();
 
-            debug!("Performing daemon-reload");
-            if let Err(reload_error) = systemd_manager.reload().await {
-                warn!("Failed to perform daemon-reload: [{}]", reload_error);
-                return Transition::Complete(Err(reload_error));
-            };
+        // The transitions of a pod are kept until the pod is deleted.
+        if pod_deleted {
+            let transitions = shared.read().await.transitions.clone();
+            transitions.write().await.remove(pod_key);
         } else {
-            debug!("Pod [{}] was already terminated", pod_state.service_name);
+            record_transition(&shared, &pod, "Terminated", None).await;
         }
 
-        Transition::Complete(Ok(()))
+        if !self.successful && keep_failed_services && !pod_deleted {
+            keep_failed_units(&shared, &pod).await;
+            return Transition::Complete(Ok(()));
+        }
+
+        match remove_pod_units(&shared, &pod).await {
+            Ok(()) => Transition::Complete(Ok(())),
+            Err(error) => Transition::Complete(Err(error)),
+        }
     }
 
     async fn status(&self, _pod_state: &mut PodState, pod: &Pod) -> anyhow::Result<PodStatus> {
@@ -90,3 +74,230 @@ impl State<PodState> for Terminated {
         Ok(status)
     }
 }
+
+/// Keeps the units of the given failed pod for inspection until the pod is deleted.
+///
+/// The container handles are kept as well, so that the invocation IDs remain available in the
+/// admin interface and for `kubectl logs`. Only the trigger units are stopped, so that the failed
+/// services are not activated again. All kept units are disabled, so that they are not started
+/// again after a reboot of the node. They are removed by a background task as soon as the pod
+/// is deleted, see [`remove_kept_units_after_deletion`].
+async fn keep_failed_units(shared: &SharedState<ProviderState>, pod: &Pod) {
+    let (systemd_manager, trigger_units, service_units) = {
+        let provider_state = shared.read().await;
+        let handles = provider_state.handles.read().await;
+        let containers = handles
+            .get(&PodKey::from(pod))
+            .map(|containers| containers.values().cloned().collect::<Vec<_>>())
+            .unwrap_or_default();
+        let trigger_units = containers
+            .iter()
+            .filter_map(|container_handle| container_handle.trigger_unit.to_owned())
+            .collect::<Vec<_>>();
+        // Transient units cannot be enabled, so they need not be disabled.
+        let service_units = containers
+            .iter()
+            .filter(|container_handle| container_handle.transient_unit.is_none())
+            .map(|container_handle| container_handle.service_unit.to_owned())
+            .collect::<Vec<_>>();
+        (
+            provider_state.systemd_manager.clone(),
+            trigger_units,
+            service_units,
+        )
+    };
+
+    for trigger_unit in &trigger_units {
+        debug!("Stopping systemd unit [{}]", trigger_unit);
+        if let Err(stop_error) = systemd_manager.stop(trigger_unit, StopMode::Fail).await {
+            warn!(
+                "Error occurred stopping systemd unit [{}]: [{}]",
+                trigger_unit, stop_error
+            );
+        }
+    }
+
+    for unit in trigger_units.iter().chain(service_units.iter()) {
+        if let Err(disable_error) = systemd_manager.disable(unit).await {
+            warn!(
+                "Error occurred disabling systemd unit [{}]: [{}]",
+                unit, disable_error
+            );
+        }
+    }
+
+    info!(
+        "The systemd units of the failed pod [{}/{}] are kept for inspection until the pod is \
+        deleted",
+        pod.namespace(),
+        pod.name()
+    );
+
+    tokio::spawn(remove_kept_units_after_deletion(
+        shared.to_owned(),
+        pod.to_owned(),
+    ));
+}
+
+/// Waits until the given pod is deleted and removes its kept units afterwards.
+///
+/// The state machine of the pod is already completed when the units are kept, so the deletion
+/// is detected by polling the pod. If the pod was replaced by a new pod with the same name in
+/// the meantime then the units are left to the new pod which recreates them.
+async fn remove_kept_units_after_deletion(shared: SharedState<ProviderState>, pod: Pod) {
+    let client = shared.read().await.client.clone();
+    let api: Api<KubePod> = Api::namespaced(client, pod.namespace());
+    let uid = pod.as_kube_pod().metadata.uid.to_owned();
+
+    loop {
+        time::sleep(DELETION_CHECK_INTERVAL).await;
+
+        match api.get(pod.name()).await {
+            Ok(current_pod) if current_pod.metadata.uid != uid => {
+                debug!(
+                    "The failed pod [{}/{}] was replaced. Its kept units are taken over by the \
+                    new pod.",
+                    pod.namespace(),
+                    pod.name()
+                );
+                return;
+            }
+            Ok(current_pod) if current_pod.metadata.deletion_timestamp.is_none() => {}
+            Ok(_) => break,
+            Err(kube::Error::Api(error)) if error.code == 404 => break,
+            Err(error) => debug!(
+                "The failed pod [{}/{}] could not be retrieved: {}",
+                pod.namespace(),
+                pod.name(),
+                error
+            ),
+        }
+    }
+
+    info!(
+        "Removing the kept systemd units of the deleted pod [{}/{}]",
+        pod.namespace(),
+        pod.name()
+    );
+    if let Err(error) = remove_pod_units(&shared, &pod).await {
+        warn!(
+            "The kept systemd units of the deleted pod [{}/{}] could not be removed: {}",
+            pod.namespace(),
+            pod.name(),
+            error
+        );
+    }
+}
+
+/// Stops and removes the systemd units of the given pod together with its slice.
+async fn remove_pod_units(shared: &SharedState<ProviderState>, pod: &Pod) -> anyhow::Result<()> {
+    let (systemd_manager, pod_handle, unit_name_max_length) = {
+        let provider_state = shared.write().await;
+        let mut handles = provider_state.handles.write().await;
+        (
+            provider_state.systemd_manager.clone(),
+            handles.remove(&PodKey::from(pod)),
+            provider_state.unit_name_max_length,
+        )
+    };
+
+    // TODO: We need some additional error handling here, wait for the services to actually
+    //  shut down and try to remove the rest of the services if one fails (tbd, do we want that?)
+    if let Some(containers) = pod_handle {
+        for container_handle in containers.values() {
+            // The trigger is stopped first, so that it does not activate the service again
+            if let Some(trigger_unit) = &container_handle.trigger_unit {
+                debug!("Stopping systemd unit [{}]", trigger_unit);
+                if let Err(stop_error) = systemd_manager.stop(trigger_unit, StopMode::Fail).await {
+                    warn!(
+                        "Error occurred stopping systemd unit [{}]: [{}]",
+                        trigger_unit, stop_error
+                    );
+                    return Err(stop_error);
+                }
+
+                debug!("Removing systemd unit [{}]", trigger_unit);
+                if let Err(remove_error) = systemd_manager.remove_unit(trigger_unit, false).await {
+                    warn!(
+                        "Error occurred removing systemd unit [{}]: [{}]",
+                        trigger_unit, remove_error
+                    );
+                    return Err(remove_error);
+                }
+            }
+
+            let service_unit = &container_handle.service_unit;
+
+            // Transient units have no unit file and are unloaded by systemd when they are
+            // inactive. Failed ones are kept until their failed state is reset.
+            if container_handle.transient_unit.is_some() {
+                debug!("Stopping transient systemd unit [{}]", service_unit);
+                if let Err(stop_error) = systemd_manager.stop(service_unit, StopMode::Fail).await {
+                    // The unit is possibly already unloaded, e.g. after a reboot.
+                    warn!(
+                        "Error occurred stopping systemd unit [{}]: [{}]",
+                        service_unit, stop_error
+                    );
+                }
+
+                if let Err(reset_error) = systemd_manager.reset_failed(service_unit).await {
+                    debug!(
+                        "Failed state of transient unit [{}] was not reset: [{}]",
+                        service_unit, reset_error
+                    );
+                }
+                continue;
+            }
+
+            debug!("Stopping systemd unit [{}]", service_unit);
+            if let Err(stop_error) = systemd_manager.stop(service_unit, StopMode::Fail).await {
+                warn!(
+                    "Error occurred stopping systemd unit [{}]: [{}]",
+                    service_unit, stop_error
+                );
+                return Err(stop_error);
+            }
+
+            // Daemon reload is false here, we'll do that once after all units have been removed
+            debug!("Removing systemd unit [{}]", service_unit);
+            if let Err(remove_error) = systemd_manager.remove_unit(service_unit, false).await {
+                warn!(
+                    "Error occurred removing systemd unit [{}]: [{}]",
+                    service_unit, remove_error
+                );
+                return Err(remove_error);
+            }
+        }
+
+        // The slice is stopped after all its units were stopped, so it does not stop any
+        // running units implicitly
+        let slice = SystemDUnit::pod_slice_name(pod, unit_name_max_length);
+        debug!("Stopping and removing slice [{}]", slice);
+        if let Err(stop_error) = systemd_manager.stop(&slice, StopMode::Fail).await {
+            warn!(
+                "Error occurred stopping slice [{}]: [{}]",
+                slice, stop_error
+            );
+        }
+        if let Err(remove_error) = systemd_manager.remove_unit(&slice, false).await {
+            warn!(
+                "Error occurred removing slice [{}]: [{}]",
+                slice, remove_error
+            );
+        }
+
+        debug!("Performing daemon-reload");
+        if let Err(reload_error) = systemd_manager.reload().await {
+            warn!("Failed to perform daemon-reload: [{}]", reload_error);
+            return Err(reload_error);
+        };
+    } else {
+        debug!(
+            "Pod [{}/{}] was already terminated",
+            pod.namespace(),
+            pod.name()
+        );
+    }
+
+    Ok(())
+}
@@ -3,6 +3,7 @@ use kubelet::pod::state::prelude::*;
 use log::info;
 
 use super::creating_config::CreatingConfig;
+use crate::provider::transitions::record_transition;
 use crate::provider::{PodState, ProviderState};
 
 #[derive(Debug, TransitionTo)]
@@ -18,10 +19,20 @@ pub struct WaitingConfigMap {
 impl State<PodState> for WaitingConfigMap {
     async fn next(
         self: Box<Self>,
-        _provider_state: SharedState<ProviderState>,
+        provider_state: SharedState<ProviderState>,
         pod_state: &mut PodState,
-        _pod: Manifest<Pod>,
+        pod: Manifest<Pod>,
     ) -> Transition<PodState> {
+        record_transition(
+            &provider_state,
+            &pod.latest(),
+            "WaitingConfigMap",
+            Some(format!(
+                "Missing config maps: {}",
+                self.missing_config_maps.join(", ")
+            )),
+        )
+        .await;
         info!(
             "Delaying execution due to missing configmaps: {:?}",
             &self.missing_config_maps
@@ -0,0 +1,224 @@
+//! Fake systemd manager on a private D-Bus for tests
+//!
+//! [`FakeSystemd::start`] launches a private `dbus-daemon` and serves a minimal
+//! `org.freedesktop.systemd1.Manager` object on it which implements `LoadUnit`, `StartUnit`,
+//! `StartTransientUnit`, and `StopUnit`. Every job is removed immediately and the `JobRemoved` signal is sent with the
+//! result which was configured for the unit, so that [`SystemdManager`] can be tested
+//! deterministically. A `JobRemoved` signal of an unrelated job is sent beforehand to verify that
+//! the signals are correlated with the jobs.
+//!
+//! [`SystemdManager`]: super::manager::SystemdManager
+use std::collections::HashMap;
+use std::convert::{TryFrom, TryInto};
+use std::io::{BufRead, BufReader};
+use std::process::{Child, Command, Stdio};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use zbus::{dbus_interface, fdo};
+use zvariant::{ObjectPath, OwnedObjectPath, OwnedValue};
+
+/// Well-known name of systemd on the bus
+const SYSTEMD_SERVICE_NAME: &str = "org.freedesktop.systemd1";
+
+/// Path of the manager object
+const MANAGER_PATH: &str = "/org/freedesktop/systemd1";
+
+/// Interface of the manager object
+const MANAGER_INTERFACE: &str = "org.freedesktop.systemd1.Manager";
+
+/// Calls and job results which are shared between the test and the fake
+#[derive(Debug, Default)]
+struct FakeState {
+    /// Received method calls in the form `<method> <unit> <mode>`
+    calls: Vec<String>,
+    /// Results of the jobs by unit; jobs of other units are `done`
+    job_results: HashMap<String, String>,
+    /// ID of the last enqueued job
+    last_job_id: u32,
+}
+
+/// Private bus with a fake systemd manager
+///
+/// The bus is terminated when the fake is dropped.
+pub struct FakeSystemd {
+    bus: Child,
+    address: String,
+    state: Arc<Mutex<FakeState>>,
+}
+
+impl FakeSystemd {
+    /// Starts a private bus and serves the fake manager on it.
+    ///
+    /// `dbus-daemon` must be installed, therefore the tests which use the fake are ignored by
+    /// default and run with `cargo test -- --ignored`.
+    pub fn start() -> FakeSystemd {
+        let mut bus = Command::new("dbus-daemon")
+            .args(&["--session", "--nofork", "--print-address=1"])
+            .stdout(Stdio::piped())
+            .spawn()
+            .expect("dbus-daemon could not be started, it is required by this test.");
+
+        let mut address = String::new();
+        BufReader::new(bus.stdout.take().expect("stdout is piped"))
+            .read_line(&mut address)
+            .expect("The address of the private bus could not be read.");
+        let address = address.trim().to_owned();
+
+        let state = Arc::new(Mutex::new(FakeState::default()));
+
+        let connection = zbus::Connection::new_for_address(&address, true)
+            .expect("The fake systemd could not connect to the private bus.");
+        fdo::DBusProxy::new(&connection)
+            .expect("The proxy of the private bus could not be created.")
+            .request_name(
+                SYSTEMD_SERVICE_NAME
+                    .try_into()
+                    .expect("The name of systemd is valid."),
+                fdo::RequestNameFlags::ReplaceExisting.into(),
+            )
+            .expect("The name of systemd could not be requested on the private bus.");
+
+        let manager = FakeManager {
+            connection: connection.clone(),
+            state: state.clone(),
+        };
+
+        thread::spawn(move || {
+            let mut object_server = zbus::ObjectServer::new(&connection);
+            object_server
+                .at(MANAGER_PATH, manager)
+                .expect("The fake manager could not be served.");
+
+            // The loop ends when the bus is terminated.
+            while object_server.try_handle_next().is_ok() {}
+        });
+
+        FakeSystemd {
+            bus,
+            address,
+            state,
+        }
+    }
+
+    /// Returns the address of the private bus.
+    pub fn address(&self) -> &str {
+        &self.address
+    }
+
+    /// Sets the result of the jobs of the given unit, e.g. `failed`.
+    pub fn set_job_result(&self, unit: &str, result: &str) {
+        self.state
+            .lock()
+            .unwrap()
+            .job_results
+            .insert(String::from(unit), String::from(result));
+    }
+
+    /// Returns the received method calls in the form `<method> <unit> <mode>`.
+    pub fn calls(&self) -> Vec<String> {
+        self.state.lock().unwrap().calls.to_owned()
+    }
+}
+
+impl Drop for FakeSystemd {
+    fn drop(&mut self) {
+        let _ = self.bus.kill();
+        let _ = self.bus.wait();
+    }
+}
+
+/// Object which implements the subset of the manager interface
+struct FakeManager {
+    connection: zbus::Connection,
+    state: Arc<Mutex<FakeState>>,
+}
+
+impl FakeManager {
+    /// Records the call, removes the job immediately, and returns its path.
+    fn enqueue_job(&self, method: &str, unit: &str, mode: &str) -> fdo::Result<OwnedObjectPath> {
+        let (job_id, result) = {
+            let mut state = self.state.lock().unwrap();
+            state.calls.push(format!("{} {} {}", method, unit, mode));
+            state.last_job_id += 2;
+            let result = state
+                .job_results
+                .get(unit)
+                .map_or("done", String::as_str)
+                .to_owned();
+            (state.last_job_id, result)
+        };
+
+        // The signal of another job must be ignored by the caller.
+        self.job_removed(job_id - 1, "other.service", "failed")?;
+        self.job_removed(job_id, unit, &result)?;
+
+        Ok(job_path(job_id))
+    }
+
+    /// Sends the `JobRemoved` signal for the given job.
+    fn job_removed(&self, job_id: u32, unit: &str, result: &str) -> fdo::Result<()> {
+        let job = job_path(job_id);
+        self.connection
+            .emit_signal(
+                None::<&str>,
+                MANAGER_PATH,
+                MANAGER_INTERFACE,
+                "JobRemoved",
+                &(job_id, &job, unit, result),
+            )
+            .map_err(|error| fdo::Error::Failed(error.to_string()))?;
+        Ok(())
+    }
+}
+
+#[dbus_interface(name = "org.freedesktop.systemd1.Manager")]
+impl FakeManager {
+    fn load_unit(&self, name: &str) -> OwnedObjectPath {
+        unit_path(name)
+    }
+
+    fn start_unit(&self, name: &str, mode: &str) -> fdo::Result<OwnedObjectPath> {
+        self.enqueue_job("StartUnit", name, mode)
+    }
+
+    fn start_transient_unit(
+        &self,
+        name: &str,
+        mode: &str,
+        _properties: Vec<(String, OwnedValue)>,
+        _aux: Vec<(String, Vec<(String, OwnedValue)>)>,
+    ) -> fdo::Result<OwnedObjectPath> {
+        self.enqueue_job("StartTransientUnit", name, mode)
+    }
+
+    fn stop_unit(&self, name: &str, mode: &str) -> fdo::Result<OwnedObjectPath> {
+        self.enqueue_job("StopUnit", name, mode)
+    }
+}
+
+/// Returns the path of the job with the given ID like systemd does.
+fn job_path(job_id: u32) -> OwnedObjectPath {
+    ObjectPath::try_from(format!("{}/job/{}", MANAGER_PATH, job_id))
+        .expect("The job path is valid.")
+        .into()
+}
+
+/// Returns the path of the given unit like systemd does, i.e. with all characters except
+/// alphanumeric ones escaped as `_<hex>`.
+fn unit_path(name: &str) -> OwnedObjectPath {
+    let escaped_name = name
+        .bytes()
+        .map(|byte| {
+            if byte.is_ascii_alphanumeric() {
+                char::from(byte).to_string()
+            } else {
+                format!("_{:02x}", byte)
+            }
+        })
+        .collect::<String>();
+
+    ObjectPath::try_from(format!("{}/unit/{}", MANAGER_PATH, escaped_name))
+        .expect("The unit path is valid.")
+        .into()
+}
@@ -1,13 +1,34 @@
 //! This module provides functions for reading from the journal.
 
-use anyhow::{Error, Result};
+use anyhow::{anyhow, Error, Result};
 use kubelet::log::Sender;
+use std::fs;
+use std::path::Path;
 use std::str;
-use systemd::{journal, journal::JournalRef};
+use std::time::{Duration, UNIX_EPOCH};
+use systemd::{journal, journal::Journal, journal::JournalRef};
+
+/// Interval in which a follower of the exported journal checks if the receiver is still
+/// interested in further entries
+const EXPORT_WAIT_INTERVAL: Duration = Duration::from_secs(1);
+
+/// File which contains the ID of the machine which is part of the names of the journal
+/// directories
+const MACHINE_ID_FILE: &str = "/etc/machine-id";
+
+/// Directories of the persistent and the volatile journal
+const JOURNAL_DIRECTORIES: &[&str] = &["/var/log/journal", "/run/log/journal"];
 
 /// Reads journal entries with the given invocation ID and sends the
 /// contained messages.
 ///
+/// If a syslog identifier is given then only the entries with this
+/// identifier are sent, so that messages which are written to the journal
+/// by other processes on behalf of the service are omitted.
+///
+/// If a log namespace is given then the journal of this namespace is read
+/// instead of the default one, see `LogNamespace=` in systemd.exec(5).
+///
 /// The options `tail` and `follow` in `sender` are taken into account.
 ///
 /// If `tail` is set with `Some(line_count)` then only the last
@@ -17,9 +38,14 @@ use systemd::{journal, journal::JournalRef};
 /// If `follow` is `true` then additionally all new messages are sent
 /// until the channel of `sender` is closed. In this case an
 /// [`Err(kubelet::log::SendError::ChannelClosed)`] will be returned.
-pub async fn send_messages(sender: &mut Sender, invocation_id: &str) -> Result<()> {
-    let mut journal = journal::OpenOptions::default().open()?;
-    let journal = journal.match_add("_SYSTEMD_INVOCATION_ID", invocation_id)?;
+pub async fn send_messages(
+    sender: &mut Sender,
+    invocation_id: &str,
+    syslog_identifier: Option<&str>,
+    log_namespace: Option<&str>,
+) -> Result<()> {
+    let mut journal = open_journal(invocation_id, syslog_identifier, log_namespace)?;
+    let journal = &mut *journal;
 
     if let Some(line_count) = sender.tail() {
         seek_journal_backwards(journal, line_count)?;
@@ -41,6 +67,157 @@ pub async fn send_messages(sender: &mut Sender, invocation_id: &str) -> Result<(
     Ok(())
 }
 
+/// Reads journal entries with the given invocation ID and passes them in the
+/// [journal export format](https://systemd.io/JOURNAL_EXPORT_FORMATS/) to
+/// `send`, one entry per call.
+///
+/// In contrast to [`send_messages`], all fields of the entries are
+/// preserved including the cursor and the timestamps, so that the entries
+/// can be ingested by log shippers like the output of
+/// `journalctl --output=export`. The entries are filtered and the log
+/// namespace is selected like in [`send_messages`].
+///
+/// If `tail` is set with `Some(entry_count)` then only the last
+/// `entry_count` entries are passed, otherwise all available entries.
+///
+/// If `follow` is `true` then additionally all new entries are passed until
+/// `send` or `is_closed` indicate that the receiver is gone.
+pub fn export_entries<F, C>(
+    invocation_id: &str,
+    syslog_identifier: Option<&str>,
+    log_namespace: Option<&str>,
+    tail: Option<usize>,
+    follow: bool,
+    mut send: F,
+    is_closed: C,
+) -> Result<()>
+where
+    F: FnMut(Vec<u8>) -> Result<()>,
+    C: Fn() -> bool,
+{
+    let mut journal = open_journal(invocation_id, syslog_identifier, log_namespace)?;
+    let journal = &mut *journal;
+
+    let mut remaining = match tail {
+        Some(entry_count) => {
+            seek_journal_backwards(journal, entry_count)?;
+            Some(entry_count)
+        }
+        None => None,
+    };
+
+    loop {
+        while remaining != Some(0) && journal.next()? != 0 {
+            send(export_entry(journal)?)?;
+            remaining = remaining.map(|count| count - 1);
+        }
+
+        if !follow || is_closed() {
+            return Ok(());
+        }
+
+        // Only the entries which existed at the start are limited by `tail`.
+        remaining = None;
+        journal.wait(Some(EXPORT_WAIT_INTERVAL))?;
+    }
+}
+
+/// Opens the journal of the given namespace or the default one and adds the
+/// matches for the given invocation ID and syslog identifier.
+fn open_journal(
+    invocation_id: &str,
+    syslog_identifier: Option<&str>,
+    log_namespace: Option<&str>,
+) -> Result<Journal> {
+    let mut journal = match log_namespace {
+        Some(log_namespace) => journal::OpenDirectoryOptions::default()
+            .open_directory(namespace_directory(log_namespace)?)?,
+        None => journal::OpenOptions::default().open()?,
+    };
+    journal.match_add("_SYSTEMD_INVOCATION_ID", invocation_id)?;
+    // Matches for different fields are combined with a logical AND
+    if let Some(syslog_identifier) = syslog_identifier {
+        journal.match_add("SYSLOG_IDENTIFIER", syslog_identifier)?;
+    }
+    Ok(journal)
+}
+
+/// Returns the directory which contains the journal files of the given namespace.
+///
+/// The journal is opened by its directory instead of with
+/// `sd_journal_open_namespace`, so that the agent still runs with versions of
+/// libsystemd which do not support namespaces. The persistent journal is
+/// preferred over the volatile one.
+fn namespace_directory(log_namespace: &str) -> Result<String> {
+    let machine_id = fs::read_to_string(MACHINE_ID_FILE)?;
+    let directory_name = format!("{}.{}", machine_id.trim(), log_namespace);
+
+    JOURNAL_DIRECTORIES
+        .iter()
+        .map(|journal_directory| format!("{}/{}", journal_directory, directory_name))
+        .find(|directory| Path::new(directory).is_dir())
+        .ok_or_else(|| {
+            anyhow!(
+                "The journal of the namespace [{}] was not found in {:?}.",
+                log_namespace,
+                JOURNAL_DIRECTORIES
+            )
+        })
+}
+
+/// Serializes the current entry of the journal in the export format
+/// including the terminating empty line.
+fn export_entry(journal: &mut JournalRef) -> Result<Vec<u8>> {
+    let mut entry = Vec::new();
+
+    let realtime = journal.timestamp()?.duration_since(UNIX_EPOCH)?.as_micros();
+    let (monotonic, _) = journal.monotonic_timestamp()?;
+
+    write_export_field(&mut entry, b"__CURSOR", journal.cursor()?.as_bytes());
+    write_export_field(
+        &mut entry,
+        b"__REALTIME_TIMESTAMP",
+        realtime.to_string().as_bytes(),
+    );
+    write_export_field(
+        &mut entry,
+        b"__MONOTONIC_TIMESTAMP",
+        monotonic.to_string().as_bytes(),
+    );
+
+    journal.restart_data();
+    while let Some(field) = journal.enumerate_data()? {
+        write_export_field(&mut entry, field.name(), field.value().unwrap_or_default());
+    }
+
+    entry.push(b'\n');
+    Ok(entry)
+}
+
+/// Writes a field in the export format.
+///
+/// Values which contain control characters or are not valid UTF-8 are
+/// written in the binary form: the name, a newline, the length of the value
+/// as little-endian 64-bit integer, the value, and a newline. All other
+/// values are written in the form `NAME=value` followed by a newline.
+fn write_export_field(entry: &mut Vec<u8>, name: &[u8], value: &[u8]) {
+    let binary = str::from_utf8(value).map_or(true, |value| {
+        value
+            .chars()
+            .any(|character| character.is_control() && character != '\t')
+    });
+
+    entry.extend_from_slice(name);
+    if binary {
+        entry.push(b'\n');
+        entry.extend_from_slice(&(value.len() as u64).to_le_bytes());
+    } else {
+        entry.push(b'=');
+    }
+    entry.extend_from_slice(value);
+    entry.push(b'\n');
+}
+
 /// Sets the cursor of the journal to the position before the last `count`
 /// entries so that the next entry is the first of `count` remaining
 /// entries. If the beginning of the journal is reached then the cursor is
@@ -118,3 +295,22 @@ async fn send_message(sender: &mut Sender, message: &str) -> Result<()> {
     line.push('\n');
     sender.send(line).await.map_err(Error::new)
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn export_fields_should_be_serialized_as_text_or_binary() {
+        let mut entry = Vec::new();
+
+        write_export_field(&mut entry, b"MESSAGE", b"Started\tservice");
+        write_export_field(&mut entry, b"MESSAGE", b"line 1\nline 2");
+
+        let mut expected = b"MESSAGE=Started\tservice\nMESSAGE\n".to_vec();
+        expected.extend_from_slice(&13u64.to_le_bytes());
+        expected.extend_from_slice(b"line 1\nline 2\n");
+
+        assert_eq!(expected, entry);
+    }
+}
@@ -6,90 +6,285 @@
 use super::service::SystemdService;
 use super::systemd1_api::{
     AsyncJobProxy, AsyncManagerProxy, AsyncUnitProxy, JobRemovedResult, JobRemovedSignal,
-    ManagerSignals, StartMode, StopMode,
+    ManagerSignals, Property, StartMode, StopMode,
 };
 use crate::provider::systemdmanager::systemdunit::SystemDUnit;
 use crate::provider::StackableError;
-use crate::provider::StackableError::RuntimeError;
+use crate::provider::StackableError::{DbusConnectionError, RuntimeError};
 use anyhow::{anyhow, Context};
 use futures_util::{future, stream::StreamExt};
-use log::debug;
+use kubelet::backoff::{BackoffStrategy, ExponentialBackoffStrategy};
+use log::{debug, info, trace, warn};
 use std::fs;
 use std::fs::File;
 use std::future::Future;
 use std::io::Write;
 use std::path::PathBuf;
+use std::sync::RwLock;
+use std::time::{Duration, Instant};
+use tokio::fs::read_to_string;
+use tokio::process::Command;
+use tokio::sync::Mutex;
+use tokio::time::timeout;
 use zbus::azync::Connection;
 
 /// Enum that lists the supported unit types
 #[derive(Clone, Debug, Eq, PartialEq)]
 pub enum UnitTypes {
     Service,
+    Slice,
+    Socket,
+    Timer,
+}
+
+/// Number of attempts to reconnect to the bus before giving up
+const RECONNECT_ATTEMPTS: usize = 5;
+
+/// Time after which a connection is considered lost if the bus does not respond
+const CONNECTION_CHECK_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Awaits the given D-Bus call and logs its duration at trace level together with the unit
+/// and the method, so that slow calls can be correlated with the affected services.
+pub(super) async fn timed<T, Fut>(unit: &str, method: &str, call: Fut) -> T
+where
+    Fut: Future<Output = T>,
+{
+    let start = Instant::now();
+    let result = call.await;
+    trace!(
+        "D-Bus call [{}] for unit [{}] took {:?}",
+        method,
+        unit,
+        start.elapsed()
+    );
+    result
 }
 
 /// The main way of interacting with this module, this struct offers
 /// the public methods for managing service units.
 ///
 /// Use [`SystemdManager::new`] to create a new instance.
+///
+/// If the connection to the bus is lost, e.g. because the D-Bus daemon
+/// or systemd was restarted, then the manager reconnects transparently,
+/// see [`SystemdManager::with_reconnect`].
 pub struct SystemdManager {
     units_directory: PathBuf,
-    proxy: AsyncManagerProxy<'static>,
+    connection: RwLock<ManagerConnection>,
+    reconnect_lock: Mutex<()>,
+    max_queued: usize,
     user_mode: bool, // TODO Use the same naming (user_mode or session_mode) everywhere
+    /// Address of the bus if neither the system nor the session bus is used, e.g. the private
+    /// bus of the fake systemd in the tests
+    bus_address: Option<String>,
+}
+
+/// Proxy of the systemd manager and the generation of the underlying
+/// connection which is incremented on every reconnect
+struct ManagerConnection {
+    proxy: AsyncManagerProxy<'static>,
+    generation: u64,
 }
 
 impl SystemdManager {
     /// Creates a new instance, takes a flag whether to run within the
-    /// user session or manage services system-wide.
-    pub async fn new(user_mode: bool, max_pods: u16) -> Result<Self, StackableError> {
-        // Connect to session or system bus depending on the value of [user_mode]
-        let mut connection = if user_mode {
-            Connection::session().await.map_err(|e| RuntimeError {
-                msg: format!(
-                    "Could not create a connection to the systemd session bus: {}",
-                    e
-                ),
-            })?
-        } else {
-            Connection::system().await.map_err(|e| RuntimeError {
-                msg: format!(
-                    "Could not create a connection to the systemd system-wide bus: {}",
-                    e
-                ),
-            })?
-        };
-
+    /// user session or manage services system-wide and the directory
+    /// in which the unit files are written.
+    pub async fn new(
+        user_mode: bool,
+        units_directory: PathBuf,
+        max_pods: u16,
+    ) -> Result<Self, StackableError> {
         // The maximum number of queued DBus messages must be higher
         // than the number of containers which can be started and
         // stopped simultaneously.
-        connection.set_max_queued(max_pods as usize * 2);
+        let max_queued = max_pods as usize * 2;
 
-        let proxy = AsyncManagerProxy::new(&connection)
-            .await
-            .map_err(|e| RuntimeError {
-                msg: format!(
-                    "Proxy for org.freedesktop.systemd1.Manager could not be created: {}",
-                    e
-                ),
-            })?;
+        let proxy = SystemdManager::connect(user_mode, None, max_queued).await?;
 
-        // Depending on whether we are supposed to run in user space or system-wide
-        // we'll pick the default directory to initialize the systemd manager with
+        // The units directory depends on whether we are supposed to run in user space or
+        // system-wide, see [`crate::config::AgentConfig::unit_directory`].
         // This allows creating unit files either directly in the systemd folder by
         // passing in just a filename, or symlink them by passing in an absolute
         // path
-        let units_directory = if user_mode {
-            PathBuf::from(shellexpand::tilde("~/.config/systemd/user").to_string())
-        } else {
-            PathBuf::from("/lib/systemd/system")
-        };
-
         Ok(SystemdManager {
             units_directory,
-            proxy,
+            connection: RwLock::new(ManagerConnection {
+                proxy,
+                generation: 0,
+            }),
+            reconnect_lock: Mutex::new(()),
+            max_queued,
             user_mode,
+            bus_address: None,
+        })
+    }
+
+    /// Creates a new instance which is connected to the bus with the
+    /// given address instead of the system bus.
+    #[cfg(test)]
+    pub(crate) async fn for_bus_address(
+        bus_address: &str,
+        units_directory: PathBuf,
+    ) -> Result<Self, StackableError> {
+        let max_queued = 16;
+        let proxy = SystemdManager::connect(false, Some(bus_address), max_queued).await?;
+
+        Ok(SystemdManager {
+            units_directory,
+            connection: RwLock::new(ManagerConnection {
+                proxy,
+                generation: 0,
+            }),
+            reconnect_lock: Mutex::new(()),
+            max_queued,
+            user_mode: false,
+            bus_address: Some(String::from(bus_address)),
         })
     }
 
+    /// Connects to the session or system bus depending on the value of
+    /// `user_mode`, or to the bus with the given address if there is
+    /// one, and creates a proxy for the systemd manager.
+    async fn connect(
+        user_mode: bool,
+        bus_address: Option<&str>,
+        max_queued: usize,
+    ) -> Result<AsyncManagerProxy<'static>, StackableError> {
+        let mut connection = if let Some(bus_address) = bus_address {
+            Connection::new_for_address(bus_address, true)
+                .await
+                .map_err(|source| DbusConnectionError {
+                    bus: "private",
+                    source,
+                })?
+        } else if user_mode {
+            Connection::session()
+                .await
+                .map_err(|source| DbusConnectionError {
+                    bus: "session",
+                    source,
+                })?
+        } else {
+            Connection::system()
+                .await
+                .map_err(|source| DbusConnectionError {
+                    bus: "system-wide",
+                    source,
+                })?
+        };
+
+        connection.set_max_queued(max_queued);
+
+        // Caching of properties is disabled, so that the connection
+        // check always queries the bus.
+        AsyncManagerProxy::builder(&connection)
+            .cache_properties(false)
+            .build()
+            .await
+            .map_err(|e| RuntimeError {
+                msg: format!(
+                    "Proxy for org.freedesktop.systemd1.Manager could not be created: {}",
+                    e
+                ),
+            })
+    }
+
+    /// Returns the current proxy and the generation of its connection.
+    fn proxy(&self) -> (AsyncManagerProxy<'static>, u64) {
+        let connection = self
+            .connection
+            .read()
+            .expect("The lock on the systemd connection is poisoned.");
+        (connection.proxy.clone(), connection.generation)
+    }
+
+    /// Runs the given operation with the current proxy.
+    ///
+    /// If the operation fails and the connection to the bus turns out to
+    /// be lost then a new connection is established and the operation is
+    /// run once more. All operations must therefore be idempotent.
+    async fn with_reconnect<T, F, Fut>(&self, operation: F) -> anyhow::Result<T>
+    where
+        F: Fn(AsyncManagerProxy<'static>) -> Fut,
+        Fut: Future<Output = anyhow::Result<T>>,
+    {
+        let (proxy, generation) = self.proxy();
+
+        match operation(proxy).await {
+            Ok(value) => Ok(value),
+            Err(error) => {
+                if self.reconnect_if_lost(generation).await? {
+                    let (proxy, _) = self.proxy();
+                    operation(proxy).await
+                } else {
+                    Err(error)
+                }
+            }
+        }
+    }
+
+    /// Reconnects to the bus if the connection with the given generation
+    /// is lost.
+    ///
+    /// Returns `true` if a new connection is available, either because it
+    /// was established by this call or concurrently by another caller,
+    /// and `false` if the connection is still intact. Failed attempts are
+    /// retried with an exponential backoff and an error is returned if no
+    /// connection could be established after [`RECONNECT_ATTEMPTS`].
+    async fn reconnect_if_lost(&self, generation: u64) -> anyhow::Result<bool> {
+        let _reconnect_guard = self.reconnect_lock.lock().await;
+
+        let (proxy, current_generation) = self.proxy();
+        if current_generation != generation {
+            return Ok(true);
+        }
+
+        if let Ok(Ok(_)) = timeout(CONNECTION_CHECK_TIMEOUT, proxy.version()).await {
+            return Ok(false);
+        }
+
+        warn!("The connection to systemd was lost, reconnecting");
+
+        let mut backoff_strategy = ExponentialBackoffStrategy::default();
+        let mut attempt = 1;
+        loop {
+            match SystemdManager::connect(
+                self.user_mode,
+                self.bus_address.as_deref(),
+                self.max_queued,
+            )
+            .await
+            {
+                Ok(proxy) => {
+                    let mut connection = self
+                        .connection
+                        .write()
+                        .expect("The lock on the systemd connection is poisoned.");
+                    connection.proxy = proxy;
+                    connection.generation += 1;
+                    info!("Reconnected to systemd");
+                    return Ok(true);
+                }
+                Err(error) if attempt < RECONNECT_ATTEMPTS => {
+                    warn!(
+                        "Reconnection attempt {} of {} to systemd failed: {}",
+                        attempt, RECONNECT_ATTEMPTS, error
+                    );
+                    backoff_strategy.wait().await;
+                    attempt += 1;
+                }
+                Err(error) => {
+                    return Err(anyhow!(
+                        "Could not reconnect to systemd after {} attempts: {}",
+                        RECONNECT_ATTEMPTS,
+                        error
+                    ))
+                }
+            }
+        }
+    }
+
     pub fn is_user_mode(&self) -> bool {
         self.user_mode
     }
@@ -115,7 +310,7 @@ impl SystemdManager {
     /// The location of the unit file is determined by the value of `unit_file_path`:
     ///
     /// * None, the unit file will be created in the base directory that this manager was initialized
-    /// with, which is either /lib/systemd/system or ~/.config/systemd/user depending on the value of
+    /// with, which is either the system or the user unit directory depending on the value of
     /// `session`.
     /// * Some<PathBuf>, the unit file will be created at this location and linked into the proper
     /// systemd unit directory
@@ -221,8 +416,17 @@ impl SystemdManager {
     /// * if a unit name is passed an attempt is made to unlink the unit via a dbus call
     ///
     /// Calling this function means an implicit disabling of the service, if it was enabled.
+    /// The drop-ins which were created by [`SystemdManager::set_properties`] are removed as well.
     ///
     pub async fn remove_unit(&self, unit: &str, daemon_reload: bool) -> anyhow::Result<()> {
+        let drop_in_paths = self
+            .control_drop_in_paths(unit)
+            .await
+            .unwrap_or_else(|error| {
+                debug!("{}", error);
+                Vec::new()
+            });
+
         debug!("Disabling unit [{}]", unit);
         if let Err(disable_error) = self.disable(unit).await {
             debug!(
@@ -241,12 +445,91 @@ impl SystemdManager {
             self.delete_unit_file(unit)?;
         }
 
+        for drop_in_path in drop_in_paths {
+            debug!("Removing drop-in [{}] of unit [{}]", drop_in_path, unit);
+            if let Err(error) = fs::remove_file(&drop_in_path) {
+                if error.kind() != std::io::ErrorKind::NotFound {
+                    warn!("File [{}] could not be removed: {}", drop_in_path, error);
+                }
+            }
+        }
+
         if daemon_reload {
             self.reload().await?;
         }
         Ok(())
     }
 
+    /// Stops, disables, and removes the given units with as few D-Bus calls as possible.
+    ///
+    /// The stop jobs are enqueued concurrently and all units are disabled in a single
+    /// `DisableUnitFiles` call. If this call fails, e.g. because one of the unit files is already
+    /// gone, then the units are disabled one by one. Afterwards the unit files are deleted and
+    /// the daemon is reloaded once.
+    ///
+    /// Errors of individual units are only logged, so that the remaining units are removed
+    /// nevertheless. An error is returned if the final daemon reload fails.
+    pub async fn remove_units(&self, units: &[&str]) -> anyhow::Result<()> {
+        if units.is_empty() {
+            return Ok(());
+        }
+
+        debug!("Removing units {:?}", units);
+
+        for result in
+            future::join_all(units.iter().map(|unit| self.stop(unit, StopMode::Replace))).await
+        {
+            if let Err(error) = result {
+                warn!("{}", error);
+            }
+        }
+
+        // The fragment paths must be determined before the units are disabled because linked
+        // unit files are unknown to systemd afterwards. The drop-ins which were created by
+        // [`SystemdManager::set_properties`] are removed together with the unit files, so that
+        // they are not applied to a new unit with the same name.
+        let mut unit_files = Vec::new();
+        for unit in units {
+            match self.fragment_path(unit).await {
+                Ok(Some(file_path)) => unit_files.push(file_path),
+                Ok(None) => {}
+                Err(error) => warn!("{}", error),
+            }
+            match self.control_drop_in_paths(unit).await {
+                Ok(drop_in_paths) => unit_files.extend(drop_in_paths),
+                Err(error) => warn!("{}", error),
+            }
+        }
+
+        let result = self
+            .with_reconnect(
+                |proxy| async move { Ok(proxy.disable_unit_files(units, false).await?) },
+            )
+            .await;
+        if let Err(error) = result {
+            debug!(
+                "Units could not be disabled at once, disabling them one by one: {}",
+                error
+            );
+            for unit in units {
+                if let Err(error) = self.disable(unit).await {
+                    warn!("{}", error);
+                }
+            }
+        }
+
+        for unit_file in unit_files {
+            debug!("Removing file [{}].", unit_file);
+            if let Err(error) = fs::remove_file(&unit_file) {
+                if error.kind() != std::io::ErrorKind::NotFound {
+                    warn!("File [{}] could not be removed: {}", unit_file, error);
+                }
+            }
+        }
+
+        self.reload().await
+    }
+
     /// Enables a systemd unit to be stared automatically at system boot - expects a fully named
     /// unit (which means: including the .service or other unit type).
     /// This either requires that the unit is known to systemd or an absolute path to a unit file
@@ -259,7 +542,13 @@ impl SystemdManager {
         // of an existing and linked service was provided or this is an absolute path
         debug!("Trying to enable systemd unit [{}]", unit);
 
-        match self.proxy.enable_unit_files(&[unit], false, true).await {
+        let result = self
+            .with_reconnect(|proxy| async move {
+                Ok(proxy.enable_unit_files(&[unit], false, true).await?)
+            })
+            .await;
+
+        match result {
             Ok(_) => {
                 debug!("Successfully enabled service [{}]", unit);
                 Ok(())
@@ -268,11 +557,135 @@ impl SystemdManager {
         }
     }
 
+    /// Checks if the given unit is enabled persistently, i.e. if it is started at system boot.
+    ///
+    /// Units which are only enabled at runtime or only linked are not considered enabled
+    /// because they do not survive a reboot.
+    pub async fn is_enabled(&self, unit: &str) -> anyhow::Result<bool> {
+        let unit_file_state = self
+            .with_reconnect(|proxy| async move {
+                Ok(timed(unit, "GetUnitFileState", proxy.get_unit_file_state(unit)).await?)
+            })
+            .await
+            .with_context(|| format!("Enablement of unit [{}] could not be determined", unit))?;
+
+        trace!("Unit file state of [{}] is [{}]", unit, unit_file_state);
+
+        Ok(unit_file_state == "enabled")
+    }
+
+    /// Starts the given unit as transient unit with `systemd-run` without writing a unit file.
+    ///
+    /// The command returns after the start job finished. Transient units are unloaded by systemd
+    /// when they become inactive, failed units only after [`SystemdManager::reset_failed`].
+    ///
+    /// In contrast to [`SystemdManager::start_transient_unit`], the properties of the unit are
+    /// given in the syntax of unit files. `systemd-run` converts them into their D-Bus types,
+    /// e.g. `TimeoutStartSec` into `TimeoutStartUSec` or the capability names into bit masks.
+    pub async fn start_transient(&self, unit: &SystemDUnit) -> anyhow::Result<()> {
+        debug!("Trying to start transient unit [{}]", unit);
+
+        let mut command = Command::new("systemd-run");
+        if self.is_user_mode() {
+            command.arg("--user");
+        }
+        command.args(unit.systemd_run_arguments());
+
+        let output = command
+            .output()
+            .await
+            .with_context(|| format!("systemd-run could not be executed for unit [{}]", unit))?;
+
+        if output.status.success() {
+            debug!("Successfully started transient unit [{}]", unit);
+            Ok(())
+        } else {
+            Err(anyhow!(
+                "Error starting transient unit [{}]: {}",
+                unit,
+                String::from_utf8_lossy(&output.stderr).trim()
+            ))
+        }
+    }
+
+    /// Creates a transient unit with the given properties and starts it.
+    ///
+    /// The function returns when the start job is finished, see [`SystemdManager::start`].
+    /// No auxiliary units are created.
+    pub async fn start_transient_unit(
+        &self,
+        unit: &str,
+        mode: StartMode,
+        properties: &[Property],
+    ) -> anyhow::Result<()> {
+        debug!(
+            "Trying to start transient unit [{}] in mode [{}] with properties {:?}",
+            unit, mode, properties
+        );
+
+        let mode = &mode;
+        let result = self
+            .call_method(unit, "StartTransientUnit", |proxy| async move {
+                proxy
+                    .start_transient_unit(unit, mode.to_owned(), properties, &[])
+                    .await
+            })
+            .await;
+
+        if result.is_ok() {
+            debug!("Successfully started transient unit [{}]", unit);
+        }
+
+        result.map_err(|e| anyhow!("Error starting transient unit [{}]: {}", unit, e))
+    }
+
+    /// Changes the properties of the given unit without restarting it.
+    ///
+    /// Only properties which can be changed at runtime are accepted by systemd, e.g. the
+    /// resource control properties. The changes are persisted in drop-in files unless `runtime`
+    /// is set.
+    pub async fn set_properties(
+        &self,
+        unit: &str,
+        runtime: bool,
+        properties: &[Property],
+    ) -> anyhow::Result<()> {
+        debug!("Setting properties {:?} of unit [{}]", properties, unit);
+
+        self.with_reconnect(|proxy| async move {
+            Ok(timed(
+                unit,
+                "SetUnitProperties",
+                proxy.set_unit_properties(unit, runtime, properties),
+            )
+            .await?)
+        })
+        .await
+        .with_context(|| format!("Properties of unit [{}] could not be set", unit))
+    }
+
+    /// Resets the failed state of the given unit, so that a failed transient unit is unloaded.
+    pub async fn reset_failed(&self, unit: &str) -> anyhow::Result<()> {
+        debug!("Resetting the failed state of unit [{}]", unit);
+
+        self.with_reconnect(|proxy| async move {
+            Ok(timed(unit, "ResetFailedUnit", proxy.reset_failed_unit(unit)).await?)
+        })
+        .await
+        .with_context(|| format!("Failed state of unit [{}] could not be reset", unit))
+    }
+
     // Disable the systemd unit - which effectively means removing the symlink from the
     // multi-user.target subdirectory.
     pub async fn disable(&self, unit: &str) -> anyhow::Result<()> {
         debug!("Trying to disable systemd unit [{}]", unit);
-        match self.proxy.disable_unit_files(&[unit], false).await {
+        let result = self
+            .with_reconnect(
+                |proxy| async move { Ok(proxy.disable_unit_files(&[unit], false).await?) },
+            )
+            .await;
+
+        match result {
             Ok(_) => {
                 debug!("Successfully disabled service [{}]", unit);
                 Ok(())
@@ -285,11 +698,17 @@ impl SystemdManager {
     /// [unit] is expected to be the name (including .<unittype>) of a service that is known to
     /// systemd at the time this is called.
     /// To make a service known please take a look at the [`SystemdManager::enable`] function.
-    pub async fn start(&self, unit: &str) -> anyhow::Result<()> {
-        debug!("Trying to start unit [{}]", unit);
+    ///
+    /// The [mode] determines how conflicting jobs which are already queued are handled. Usually
+    /// [`StartMode::Fail`] is used, so that queued jobs are not changed.
+    pub async fn start(&self, unit: &str, mode: StartMode) -> anyhow::Result<()> {
+        debug!("Trying to start unit [{}] in mode [{}]", unit, mode);
 
+        let mode = &mode;
         let result = self
-            .call_method(|proxy| proxy.start_unit(unit, StartMode::Fail))
+            .call_method(unit, "StartUnit", |proxy| async move {
+                proxy.start_unit(unit, mode.to_owned()).await
+            })
             .await;
 
         if result.is_ok() {
@@ -303,11 +722,18 @@ impl SystemdManager {
     /// [unit] is expected to be the name (including .<unittype>) of a service that is known to
     /// systemd at the time this is called.
     /// To make a service known please take a look at the [`SystemdManager::enable`] function.
-    pub async fn stop(&self, unit: &str) -> anyhow::Result<()> {
-        debug!("Trying to stop systemd unit [{}]", unit);
+    ///
+    /// The [mode] determines how conflicting jobs which are already queued are handled. Usually
+    /// [`StopMode::Fail`] is used, whereas [`StopMode::Replace`] is used when many units are
+    /// stopped at once, so that the stop jobs do not fail because of queued jobs.
+    pub async fn stop(&self, unit: &str, mode: StopMode) -> anyhow::Result<()> {
+        debug!("Trying to stop systemd unit [{}] in mode [{}]", unit, mode);
 
+        let mode = &mode;
         let result = self
-            .call_method(|proxy| proxy.stop_unit(unit, StopMode::Fail))
+            .call_method(unit, "StopUnit", |proxy| async move {
+                proxy.stop_unit(unit, mode.to_owned()).await
+            })
             .await;
 
         if result.is_ok() {
@@ -331,32 +757,59 @@ impl SystemdManager {
     /// If the signal contains another result or no signal is returned
     /// (which should never happen) then an error with a corresponding
     /// message is returned.
-    async fn call_method<'a, F, Fut>(&'a self, method: F) -> anyhow::Result<()>
+    ///
+    /// The whole procedure is repeated if the connection to the bus was
+    /// lost in the meantime, see [`SystemdManager::with_reconnect`].
+    ///
+    /// The durations of the method call and of the whole job are logged
+    /// at trace level with the given unit and method name.
+    async fn call_method<F, Fut>(
+        &self,
+        unit: &str,
+        method_name: &str,
+        method: F,
+    ) -> anyhow::Result<()>
     where
-        F: Fn(&'a AsyncManagerProxy) -> Fut,
-        Fut: Future<Output = zbus::Result<AsyncJobProxy<'a>>>,
+        F: Fn(AsyncManagerProxy<'static>) -> Fut,
+        Fut: Future<Output = zbus::Result<AsyncJobProxy<'static>>>,
     {
-        let signals = self
-            .proxy
-            .receive_signal(ManagerSignals::JobRemoved)
-            .await?
-            .map(|message| message.body::<JobRemovedSignal>().unwrap());
-
-        let job = method(&self.proxy).await?;
-
-        let mut signals = signals
-            .filter(|signal| future::ready(&signal.job.to_owned().into_inner() == job.path()));
-
-        let signal = signals.next().await;
-
-        match signal {
-            Some(message) if message.result == JobRemovedResult::Done => Ok(()),
-            Some(message) => Err(anyhow!("The systemd job failed: {:?}", message)),
-            None => Err(anyhow!(
-                "No signal was returned for the systemd job: {:?}",
-                job
-            )),
-        }
+        let method = &method;
+        let start = Instant::now();
+
+        let result = self
+            .with_reconnect(|proxy| async move {
+                let signals = proxy
+                    .receive_signal(ManagerSignals::JobRemoved)
+                    .await?
+                    .map(|message| message.body::<JobRemovedSignal>().unwrap());
+
+                let job = timed(unit, method_name, method(proxy.clone())).await?;
+
+                let mut signals = signals.filter(|signal| {
+                    future::ready(&signal.job.to_owned().into_inner() == job.path())
+                });
+
+                let signal = signals.next().await;
+
+                match signal {
+                    Some(message) if message.result == JobRemovedResult::Done => Ok(()),
+                    Some(message) => Err(anyhow!("The systemd job failed: {:?}", message)),
+                    None => Err(anyhow!(
+                        "No signal was returned for the systemd job: {:?}",
+                        job
+                    )),
+                }
+            })
+            .await;
+
+        trace!(
+            "Job of the D-Bus call [{}] for unit [{}] finished after {:?}",
+            method_name,
+            unit,
+            start.elapsed()
+        );
+
+        result
     }
 
     // Perform a daemon-reload, this causes systemd to re-read all unit files on disk and
@@ -366,7 +819,11 @@ impl SystemdManager {
     pub async fn reload(&self) -> anyhow::Result<()> {
         debug!("Performing daemon-reload..");
 
-        match self.proxy.reload().await {
+        let result = self
+            .with_reconnect(|proxy| async move { Ok(proxy.reload().await?) })
+            .await;
+
+        match result {
             Ok(_) => {
                 debug!("Successfully performed daemon-reload");
                 Ok(())
@@ -380,18 +837,26 @@ impl SystemdManager {
     // when using this crate
     async fn link_unit_file(&self, unit: &str, force: bool) -> anyhow::Result<()> {
         debug!("Linking [{}]", unit);
-        self.proxy.link_unit_files(&[unit], false, force).await?;
-        Ok(())
+        self.with_reconnect(|proxy| async move {
+            proxy.link_unit_files(&[unit], false, force).await?;
+            Ok(())
+        })
+        .await
     }
 
     pub async fn create_systemd_service(&self, unit: &str) -> anyhow::Result<SystemdService> {
-        SystemdService::new(unit, &self.proxy).await
+        self.with_reconnect(|proxy| async move { SystemdService::new(unit, &proxy).await })
+            .await
     }
 
     /// Returns the file path of the given unit if there is one.
     pub async fn fragment_path(&self, unit: &str) -> anyhow::Result<Option<String>> {
-        let unit_proxy = self.create_unit_proxy(unit).await?;
-        let fragment_path = unit_proxy.fragment_path().await?;
+        let fragment_path = self
+            .with_reconnect(|proxy| async move {
+                let unit_proxy = SystemdManager::create_unit_proxy(&proxy, unit).await?;
+                Ok(unit_proxy.fragment_path().await?)
+            })
+            .await?;
 
         let file_path = if fragment_path.is_empty() {
             None
@@ -402,17 +867,64 @@ impl SystemdManager {
         Ok(file_path)
     }
 
+    /// Returns the paths of the drop-in files of the given unit which were created by
+    /// [`SystemdManager::set_properties`].
+    pub async fn control_drop_in_paths(&self, unit: &str) -> anyhow::Result<Vec<String>> {
+        let drop_in_paths = self
+            .with_reconnect(|proxy| async move {
+                let unit_proxy = SystemdManager::create_unit_proxy(&proxy, unit).await?;
+                Ok(unit_proxy.drop_in_paths().await?)
+            })
+            .await
+            .with_context(|| {
+                format!(
+                    "The drop-in paths of the unit [{}] could not be determined.",
+                    unit
+                )
+            })?;
+
+        Ok(drop_in_paths
+            .into_iter()
+            .filter(|path| path.contains(".control/"))
+            .collect())
+    }
+
+    /// Returns the content of the file of the given unit if there is one.
+    pub async fn unit_file_content(&self, unit: &str) -> anyhow::Result<Option<String>> {
+        let file_path = self.fragment_path(unit).await.with_context(|| {
+            format!(
+                "The file path of the unit [{}] could not be determined.",
+                unit
+            )
+        })?;
+
+        match file_path {
+            Some(file_path) => {
+                let file_content = read_to_string(&file_path)
+                    .await
+                    .with_context(|| format!("The file [{}] could not be read.", file_path))?;
+                Ok(Some(file_content))
+            }
+            None => Ok(None),
+        }
+    }
+
     /// Returns the names of the units assigned to the given slice.
     pub async fn slice_content(&self, slice: &str) -> anyhow::Result<Vec<String>> {
-        let unit_proxy = self.create_unit_proxy(slice).await?;
-        let content = unit_proxy.required_by().await?;
-        Ok(content)
+        self.with_reconnect(|proxy| async move {
+            let unit_proxy = SystemdManager::create_unit_proxy(&proxy, slice).await?;
+            Ok(unit_proxy.required_by().await?)
+        })
+        .await
     }
 
-    async fn create_unit_proxy(&self, unit: &str) -> anyhow::Result<AsyncUnitProxy<'_>> {
-        let unit_object_path = self.proxy.load_unit(unit).await?;
+    async fn create_unit_proxy(
+        proxy: &AsyncManagerProxy<'static>,
+        unit: &str,
+    ) -> anyhow::Result<AsyncUnitProxy<'static>> {
+        let unit_object_path = proxy.load_unit(unit).await?;
 
-        let unit_proxy = AsyncUnitProxy::builder(self.proxy.connection())
+        let unit_proxy = AsyncUnitProxy::builder(proxy.connection())
             .cache_properties(false)
             .path(unit_object_path)
             .unwrap() // safe because load_unit always returns a valid path
@@ -433,6 +945,9 @@ impl SystemdManager {
         // Append proper extension for unit type to file name
         let extension = match unit_type {
             UnitTypes::Service => ".service",
+            UnitTypes::Slice => ".slice",
+            UnitTypes::Socket => ".socket",
+            UnitTypes::Timer => ".timer",
         };
 
         let mut result = String::from(name);
@@ -442,3 +957,96 @@ impl SystemdManager {
         Ok(result)
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::provider::systemdmanager::fake_systemd::FakeSystemd;
+    use std::env;
+
+    async fn connect(fake_systemd: &FakeSystemd) -> SystemdManager {
+        SystemdManager::for_bus_address(fake_systemd.address(), env::temp_dir())
+            .await
+            .expect("The fake systemd is not reachable.")
+    }
+
+    #[tokio::test]
+    #[ignore = "requires dbus-daemon"]
+    async fn jobs_should_be_awaited_until_their_signals_arrive() {
+        let fake_systemd = FakeSystemd::start();
+        let systemd_manager = connect(&fake_systemd).await;
+
+        // The fake sends a failed result for another job before every job, so the calls only
+        // succeed if the signals are correlated with the jobs.
+        systemd_manager
+            .start("test.service", StartMode::Fail)
+            .await
+            .unwrap();
+        systemd_manager
+            .stop("test.service", StopMode::Replace)
+            .await
+            .unwrap();
+
+        assert_eq!(
+            vec![
+                "StartUnit test.service fail",
+                "StopUnit test.service replace"
+            ],
+            fake_systemd.calls()
+        );
+    }
+
+    #[tokio::test]
+    #[ignore = "requires dbus-daemon"]
+    async fn transient_units_should_be_started() {
+        let fake_systemd = FakeSystemd::start();
+        let systemd_manager = connect(&fake_systemd).await;
+
+        systemd_manager
+            .start_transient_unit(
+                "test.service",
+                StartMode::Fail,
+                &[Property::new("Description", "test")],
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(
+            vec!["StartTransientUnit test.service fail"],
+            fake_systemd.calls()
+        );
+    }
+
+    #[tokio::test]
+    #[ignore = "requires dbus-daemon"]
+    async fn failed_jobs_should_be_reported() {
+        let fake_systemd = FakeSystemd::start();
+        let systemd_manager = connect(&fake_systemd).await;
+        fake_systemd.set_job_result("test.service", "failed");
+
+        let error = systemd_manager
+            .start("test.service", StartMode::Fail)
+            .await
+            .unwrap_err();
+
+        assert!(
+            error.to_string().contains("result: Failed"),
+            "Unexpected error: {}",
+            error
+        );
+    }
+
+    #[tokio::test]
+    #[ignore = "requires dbus-daemon"]
+    async fn units_should_be_loaded() {
+        let fake_systemd = FakeSystemd::start();
+        let systemd_manager = connect(&fake_systemd).await;
+
+        let systemd_service = systemd_manager
+            .create_systemd_service("default-test.service")
+            .await
+            .unwrap();
+
+        assert_eq!("default-test.service", systemd_service.file());
+    }
+}
@@ -1,3 +1,5 @@
+#[cfg(test)]
+pub mod fake_systemd;
 pub mod journal_reader;
 pub mod manager;
 pub mod service;
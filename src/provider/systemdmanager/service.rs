@@ -1,8 +1,12 @@
 //! Exposes methods from the systemd unit and service interfaces.
+use super::manager::timed;
 use super::systemd1_api::{
-    ActiveState, AsyncManagerProxy, AsyncServiceProxy, AsyncUnitProxy, SUB_STATE_SERVICE_EXITED,
+    ActiveState, AsyncManagerProxy, AsyncServiceProxy, AsyncUnitProxy, ServiceResult,
+    SUB_STATE_SERVICE_AUTO_RESTART, SUB_STATE_SERVICE_EXITED,
 };
 use anyhow::anyhow;
+use k8s_openapi::chrono::{DateTime, TimeZone, Utc};
+use std::fs;
 
 /// Represents the state of a service unit object.
 #[derive(Clone, Debug, Eq, PartialEq)]
@@ -17,6 +21,54 @@ pub enum ServiceState {
     Failed,
 }
 
+/// `si_code` of a process which exited normally
+const CLD_EXITED: i32 = 1;
+
+/// States in `/proc/<pid>/stat` of processes which terminated, i.e. zombie and dead
+const TERMINATED_PROCESS_STATES: &[char] = &['Z', 'X'];
+
+/// Exit status of the last run of the main process of a service
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ExitStatus {
+    /// Exit code of the process, or `128 + signal` if the process was killed by a signal like it
+    /// is reported by a shell
+    pub exit_code: i32,
+    /// Signal which killed the process
+    pub signal: Option<i32>,
+    pub started_at: Option<DateTime<Utc>>,
+    pub finished_at: Option<DateTime<Utc>>,
+}
+
+impl ExitStatus {
+    /// Creates the exit status from the `ExecMain*` properties of a service.
+    fn new(code: i32, status: i32, start_timestamp: u64, exit_timestamp: u64) -> Self {
+        let signal = if code == CLD_EXITED {
+            None
+        } else {
+            Some(status)
+        };
+
+        ExitStatus {
+            exit_code: signal.map_or(status, |signal| 128 + signal),
+            signal,
+            started_at: ExitStatus::to_date_time(start_timestamp),
+            finished_at: ExitStatus::to_date_time(exit_timestamp),
+        }
+    }
+
+    /// Converts a systemd timestamp in microseconds since the epoch. systemd uses 0 for unset
+    /// timestamps.
+    fn to_date_time(timestamp: u64) -> Option<DateTime<Utc>> {
+        if timestamp == 0 {
+            None
+        } else {
+            let seconds = (timestamp / 1_000_000) as i64;
+            let nanoseconds = (timestamp % 1_000_000) as u32 * 1_000;
+            Some(Utc.timestamp(seconds, nanoseconds))
+        }
+    }
+}
+
 /// Stores proxies of a systemd unit and service
 #[derive(Clone, Debug)]
 pub struct SystemdService {
@@ -70,7 +122,7 @@ impl SystemdService {
     /// possible to distinguish between "inactive and never run" and
     /// "inactive and terminated successfully".
     pub async fn service_state(&self) -> anyhow::Result<ServiceState> {
-        let active_state = self.unit_proxy.active_state().await?;
+        let active_state = timed(&self.file, "ActiveState", self.unit_proxy.active_state()).await?;
 
         let service_state = match active_state {
             ActiveState::Inactive => {
@@ -84,7 +136,7 @@ impl SystemdService {
                 ServiceState::Created
             }
             ActiveState::Active => {
-                let sub_state = self.unit_proxy.sub_state().await?;
+                let sub_state = timed(&self.file, "SubState", self.unit_proxy.sub_state()).await?;
                 if sub_state == SUB_STATE_SERVICE_EXITED {
                     // The service terminated successfully (otherwise
                     // ActiveState would be set to "failed") and will
@@ -112,22 +164,78 @@ impl SystemdService {
         Ok(service_state)
     }
 
+    /// Returns the fine-grained active state of the service unit object.
+    ///
+    /// In contrast to [`SystemdService::service_state`], a service which is still starting up
+    /// can be distinguished from a service which is up, e.g. a service of `Type=notify` which
+    /// did not yet send `READY=1`.
+    pub async fn active_state(&self) -> anyhow::Result<ActiveState> {
+        timed(&self.file, "ActiveState", self.unit_proxy.active_state())
+            .await
+            .map_err(|error| {
+                anyhow!(
+                    "ActiveState of systemd unit [{}] cannot be retrieved: {}",
+                    self.file,
+                    error
+                )
+            })
+    }
+
+    /// Checks if the service terminated and waits for its restart.
+    ///
+    /// The service stays in this state until the restart delay elapsed, see `RestartSec` in
+    /// [`super::systemdunit::SystemDUnit`].
+    pub async fn is_restart_pending(&self) -> anyhow::Result<bool> {
+        timed(&self.file, "SubState", self.unit_proxy.sub_state())
+            .await
+            .map(|sub_state| sub_state.starts_with(SUB_STATE_SERVICE_AUTO_RESTART))
+            .map_err(|error| {
+                anyhow!(
+                    "SubState of systemd unit [{}] cannot be retrieved: {}",
+                    self.file,
+                    error
+                )
+            })
+    }
+
     /// Retrieves the current restart count.
     ///
     /// The restart counter was introduced in systemd version 235.
     pub async fn restart_count(&self) -> anyhow::Result<u32> {
-        self.service_proxy
-            .nrestarts()
+        timed(&self.file, "NRestarts", self.service_proxy.nrestarts())
             .await
             .map_err(|e| anyhow!("Error receiving NRestarts of unit [{}]. {}", self.file, e))
     }
 
+    /// Retrieves the exit status of the last run of the main process.
+    pub async fn exit_status(&self) -> anyhow::Result<ExitStatus> {
+        let error = |error: zbus::Error| {
+            anyhow!(
+                "Exit status of systemd unit [{}] cannot be retrieved: {}",
+                self.file,
+                error
+            )
+        };
+
+        let properties = async {
+            Ok::<_, zbus::Error>(ExitStatus::new(
+                self.service_proxy.exec_main_code().await?,
+                self.service_proxy.exec_main_status().await?,
+                self.service_proxy.exec_main_start_timestamp().await?,
+                self.service_proxy.exec_main_exit_timestamp().await?,
+            ))
+        };
+
+        timed(&self.file, "ExecMain*", properties)
+            .await
+            .map_err(error)
+    }
+
     /// Retrieves the current invocation ID.
     ///
     /// The invocation ID was introduced in systemd version 232.
     pub async fn invocation_id(&self) -> anyhow::Result<String> {
-        self.unit_proxy
-            .invocation_id()
+        timed(&self.file, "InvocationID", self.unit_proxy.invocation_id())
             .await
             .map(|invocation_id| invocation_id.to_string())
             .map_err(|error| {
@@ -138,4 +246,155 @@ impl SystemdService {
                 )
             })
     }
+
+    /// Retrieves the result of the last run of the service, e.g. the reason why it failed.
+    pub async fn result(&self) -> anyhow::Result<ServiceResult> {
+        timed(&self.file, "Result", self.service_proxy.result())
+            .await
+            .map_err(|error| {
+                anyhow!(
+                    "Result of systemd unit [{}] cannot be retrieved: {}",
+                    self.file,
+                    error
+                )
+            })
+    }
+
+    /// Checks if the main process of the service is still alive.
+    ///
+    /// systemd may consider a service active although its main process already terminated, e.g.
+    /// if the process is a zombie. [`None`] is returned if the service has no main process, e.g.
+    /// because it is waiting for a restart.
+    pub async fn is_main_process_alive(&self) -> anyhow::Result<Option<bool>> {
+        let main_pid = timed(&self.file, "MainPID", self.service_proxy.main_pid())
+            .await
+            .map_err(|error| {
+                anyhow!(
+                    "MainPID of systemd unit [{}] cannot be retrieved: {}",
+                    self.file,
+                    error
+                )
+            })?;
+
+        Ok(Some(main_pid).filter(|pid| *pid != 0).map(is_process_alive))
+    }
+
+    /// Retrieves the syslog identifier which is set in the unit.
+    ///
+    /// [`None`] is returned if the unit does not set one, e.g. if it was
+    /// created by a previous version of the agent.
+    pub async fn syslog_identifier(&self) -> anyhow::Result<Option<String>> {
+        timed(
+            &self.file,
+            "SyslogIdentifier",
+            self.service_proxy.syslog_identifier(),
+        )
+        .await
+        .map(|syslog_identifier| Some(syslog_identifier).filter(|id| !id.is_empty()))
+        .map_err(|error| {
+            anyhow!(
+                "SyslogIdentifier of systemd unit [{}] cannot be retrieved: {}",
+                self.file,
+                error
+            )
+        })
+    }
+
+    /// Retrieves the journal namespace to which the service logs.
+    ///
+    /// [`None`] is returned if the service logs to the default namespace.
+    /// Versions of systemd before 245 do not support namespaces, so an
+    /// error is returned in this case.
+    pub async fn log_namespace(&self) -> anyhow::Result<Option<String>> {
+        timed(
+            &self.file,
+            "LogNamespace",
+            self.service_proxy.log_namespace(),
+        )
+        .await
+        .map(|log_namespace| Some(log_namespace).filter(|namespace| !namespace.is_empty()))
+        .map_err(|error| {
+            anyhow!(
+                "LogNamespace of systemd unit [{}] cannot be retrieved: {}",
+                self.file,
+                error
+            )
+        })
+    }
+}
+
+/// Checks if a process with the given ID exists and did not terminate yet.
+fn is_process_alive(pid: u32) -> bool {
+    fs::read_to_string(format!("/proc/{}/stat", pid))
+        .ok()
+        .and_then(|stat| process_state(&stat))
+        .map_or(false, |state| !TERMINATED_PROCESS_STATES.contains(&state))
+}
+
+/// Returns the state of a process from the content of its file `/proc/<pid>/stat`.
+///
+/// The state follows the command name which is enclosed in parentheses and can contain spaces
+/// and parentheses itself, see proc(5).
+fn process_state(stat: &str) -> Option<char> {
+    stat.rsplit_once(')')
+        .and_then(|(_, fields)| fields.trim_start().chars().next())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use rstest::rstest;
+
+    #[rstest]
+    #[case::exited_successfully(1, 0, ExitStatus {
+        exit_code: 0,
+        signal: None,
+        started_at: Some(Utc.timestamp(1_600_000_000, 0)),
+        finished_at: Some(Utc.timestamp(1_600_000_060, 500_000_000)),
+    })]
+    #[case::exited_with_error(1, 2, ExitStatus {
+        exit_code: 2,
+        signal: None,
+        started_at: Some(Utc.timestamp(1_600_000_000, 0)),
+        finished_at: Some(Utc.timestamp(1_600_000_060, 500_000_000)),
+    })]
+    #[case::killed_by_signal(2, 9, ExitStatus {
+        exit_code: 137,
+        signal: Some(9),
+        started_at: Some(Utc.timestamp(1_600_000_000, 0)),
+        finished_at: Some(Utc.timestamp(1_600_000_060, 500_000_000)),
+    })]
+    fn exit_status_should_be_derived_from_service_properties(
+        #[case] code: i32,
+        #[case] status: i32,
+        #[case] expected_exit_status: ExitStatus,
+    ) {
+        assert_eq!(
+            expected_exit_status,
+            ExitStatus::new(code, status, 1_600_000_000_000_000, 1_600_000_060_500_000)
+        );
+    }
+
+    #[test]
+    fn unset_timestamps_should_be_ignored() {
+        let exit_status = ExitStatus::new(CLD_EXITED, 0, 0, 0);
+
+        assert_eq!(None, exit_status.started_at);
+        assert_eq!(None, exit_status.finished_at);
+    }
+
+    #[rstest]
+    #[case::running("1234 (java) S 1 1234 1234 0 -1", Some('S'))]
+    #[case::zombie("1234 (java) Z 1 1234 1234 0 -1", Some('Z'))]
+    #[case::name_with_parentheses("1234 (a) (b) R 1 1234", Some('R'))]
+    #[case::invalid("", None)]
+    fn test_process_state(#[case] stat: &str, #[case] expected_state: Option<char>) {
+        assert_eq!(expected_state, process_state(stat));
+    }
+
+    #[test]
+    fn own_process_should_be_alive() {
+        assert!(is_process_alive(std::process::id()));
+        assert!(!is_process_alive(u32::MAX));
+    }
 }
@@ -12,7 +12,7 @@ use std::{
 };
 use strum::{AsRefStr, Display, EnumString, EnumVariantNames, IntoStaticStr, VariantNames};
 use zbus::{dbus_proxy, names::MemberName};
-use zvariant::{derive::Type, OwnedObjectPath, OwnedValue, Signature, Type};
+use zvariant::{derive::Type, OwnedObjectPath, OwnedValue, Signature, Type, Value};
 
 /// Implements [`Serialize`] for an enum.
 ///
@@ -146,6 +146,12 @@ pub enum StartMode {
     IgnoreRequirements,
 }
 
+impl Default for StartMode {
+    fn default() -> Self {
+        StartMode::Fail
+    }
+}
+
 impl_serialize_for_enum!(StartMode);
 impl_type_for_enum!(StartMode);
 
@@ -174,9 +180,47 @@ pub enum StopMode {
     IgnoreRequirements,
 }
 
+impl Default for StopMode {
+    fn default() -> Self {
+        StopMode::Fail
+    }
+}
+
 impl_serialize_for_enum!(StopMode);
 impl_type_for_enum!(StopMode);
 
+/// Property of a transient unit, e.g. `Description` or `ExecStart`
+///
+/// The names and types of the properties are documented in the
+/// [manual](https://www.freedesktop.org/software/systemd/man/org.freedesktop.systemd1.html#Properties2)
+/// of the unit and service objects.
+#[derive(Debug, Serialize, Type)]
+pub struct Property {
+    pub name: String,
+    pub value: OwnedValue,
+}
+
+impl Property {
+    /// Creates a property with the given name and value.
+    pub fn new<'a, V>(name: &str, value: V) -> Self
+    where
+        V: Into<Value<'a>>,
+    {
+        Property {
+            name: String::from(name),
+            value: OwnedValue::from(value.into()),
+        }
+    }
+}
+
+/// Auxiliary unit which is created together with a transient unit, e.g.
+/// a socket unit for a transient service
+#[derive(Debug, Serialize, Type)]
+pub struct AuxiliaryUnit {
+    pub name: String,
+    pub properties: Vec<Property>,
+}
+
 /// The manager object is the central entry point for clients.
 ///
 /// Currently not all methods of the systemd object are exposed.
@@ -220,6 +264,31 @@ trait Manager {
     #[dbus_proxy(object = "Job")]
     fn stop_unit(&self, name: &str, mode: StopMode);
 
+    /// Creates a transient unit with the given properties and enqueues
+    /// a start job for it.
+    ///
+    /// The auxiliary units are created together with the transient unit
+    /// but not started.
+    #[dbus_proxy(object = "Job")]
+    fn start_transient_unit(
+        &self,
+        name: &str,
+        mode: StartMode,
+        properties: &[Property],
+        aux: &[AuxiliaryUnit],
+    );
+
+    /// Changes the properties of the given unit while it is running.
+    ///
+    /// If `runtime` is `true` then the changes are lost on reboot,
+    /// otherwise they are persisted in drop-in files.
+    fn set_unit_properties(
+        &self,
+        name: &str,
+        runtime: bool,
+        properties: &[Property],
+    ) -> zbus::Result<()>;
+
     /// Reloads all unit files.
     fn reload(&self) -> zbus::Result<()>;
 
@@ -254,6 +323,15 @@ trait Manager {
     /// `/etc/`).
     fn disable_unit_files(&self, files: &[&str], runtime: bool) -> zbus::Result<Changes>;
 
+    /// Resets the "failed" state of the given unit.
+    ///
+    /// Failed transient units are unloaded afterwards.
+    fn reset_failed_unit(&self, name: &str) -> zbus::Result<()>;
+
+    /// Returns the current enablement status of the given unit file,
+    /// e.g. `enabled`, `disabled`, `linked`, or `static`.
+    fn get_unit_file_state(&self, file: &str) -> zbus::Result<String>;
+
     /// Links unit files (that are located outside of the usual unit
     /// search paths) into the unit search path.
     ///
@@ -262,6 +340,10 @@ trait Manager {
     /// `force` controls whether symlinks pointing to other units shall
     /// be replaced if necessary.
     fn link_unit_files(&self, files: &[&str], runtime: bool, force: bool) -> zbus::Result<Changes>;
+
+    /// Short version string of the systemd package
+    #[dbus_proxy(property)]
+    fn version(&self) -> zbus::Result<String>;
 }
 
 /// Signals of the manager object.
@@ -376,6 +458,12 @@ impl_tryfrom_ownedvalue_for_enum!(ActiveState);
 /// RemainAfterExit setting.
 pub const SUB_STATE_SERVICE_EXITED: &str = "exited";
 
+/// Sub state of a service unit object which is set if the service
+/// terminated and waits for the restart delay to elapse. systemd
+/// version 254 additionally uses the sub state `auto-restart-queued`
+/// when the restart job is enqueued.
+pub const SUB_STATE_SERVICE_AUTO_RESTART: &str = "auto-restart";
+
 /// Unique ID for a runtime cycle of a unit
 #[derive(Clone, Debug, Eq, PartialEq)]
 pub struct InvocationId(Vec<u8>);
@@ -440,6 +528,12 @@ trait Unit {
     #[dbus_proxy(property)]
     fn fragment_path(&self) -> zbus::Result<String>;
 
+    /// `DropInPaths` contains the paths of the drop-in files which are
+    /// applied to this unit, including the ones in the `.control`
+    /// directories which are created by `SetUnitProperties`.
+    #[dbus_proxy(property)]
+    fn drop_in_paths(&self) -> zbus::Result<Vec<String>>;
+
     /// Unique ID for a runtime cycle of a unit
     #[dbus_proxy(property, name = "InvocationID")]
     fn invocation_id(&self) -> zbus::Result<InvocationId>;
@@ -481,6 +575,7 @@ pub enum ServiceResult {
     /// Indicates that a service has been started too frequently in a
     /// specific time frame (as configured in `StartLimitInterval`,
     /// `StartLimitBurst`).
+    #[strum(serialize = "start-limit-hit")]
     StartLimit,
 }
 
@@ -522,6 +617,41 @@ trait Service {
     /// Number of restarts
     #[dbus_proxy(property, name = "NRestarts")]
     fn nrestarts(&self) -> zbus::Result<u32>;
+
+    /// Exit code or signal number of the last run of the main process,
+    /// depending on [`ServiceProxy::exec_main_code`]
+    #[dbus_proxy(property)]
+    fn exec_main_status(&self) -> zbus::Result<i32>;
+
+    /// Reason why the main process exited, i.e. one of the `si_code`
+    /// values of `waitid` like `CLD_EXITED` or `CLD_KILLED`
+    #[dbus_proxy(property)]
+    fn exec_main_code(&self) -> zbus::Result<i32>;
+
+    /// Time in microseconds since the epoch when the main process was
+    /// started, or 0 if it was not started yet
+    #[dbus_proxy(property)]
+    fn exec_main_start_timestamp(&self) -> zbus::Result<u64>;
+
+    /// Time in microseconds since the epoch when the main process
+    /// exited, or 0 if it did not exit yet
+    #[dbus_proxy(property)]
+    fn exec_main_exit_timestamp(&self) -> zbus::Result<u64>;
+
+    /// Identifier of the journal entries written by the service, or an
+    /// empty string if it is not set in the unit
+    #[dbus_proxy(property)]
+    fn syslog_identifier(&self) -> zbus::Result<String>;
+
+    /// Journal namespace to which the service logs, or an empty string
+    /// if it logs to the default namespace
+    #[dbus_proxy(property)]
+    fn log_namespace(&self) -> zbus::Result<String>;
+
+    /// Process ID of the main process of the service, or 0 if there is
+    /// none
+    #[dbus_proxy(property, name = "MainPID")]
+    fn main_pid(&self) -> zbus::Result<u32>;
 }
 
 /// A systemd job object
@@ -539,7 +669,7 @@ trait Job {}
 mod test {
     use super::*;
     use byteorder::LE;
-    use zvariant::{EncodingContext, Value};
+    use zvariant::EncodingContext;
 
     #[test]
     fn deserialize_change_type() {
@@ -583,6 +713,20 @@ mod test {
         );
     }
 
+    #[test]
+    fn signatures_of_transient_unit_types() {
+        assert_eq!("(sv)", Property::signature().as_str());
+        assert_eq!("(sa(sv))", AuxiliaryUnit::signature().as_str());
+    }
+
+    #[test]
+    fn serialize_property() {
+        assert_eq!(
+            serialize(&("Description", Value::from("test"))),
+            serialize(&Property::new("Description", "test"))
+        );
+    }
+
     #[test]
     fn display_manager_signals() {
         assert_eq!("JobRemoved", ManagerSignals::JobRemoved.to_string());
@@ -610,6 +754,14 @@ mod test {
         assert_eq!("done", JobRemovedResult::Done.to_string());
     }
 
+    #[test]
+    fn try_service_result_from_owned_value() {
+        assert_eq!(
+            ServiceResult::StartLimit,
+            ServiceResult::try_from(OwnedValue::from(Value::from("start-limit-hit"))).unwrap()
+        );
+    }
+
     #[test]
     fn try_active_state_from_owned_value() {
         assert_eq!(
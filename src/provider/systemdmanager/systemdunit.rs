@@ -2,19 +2,27 @@ use std::collections::{BTreeMap, HashMap};
 use std::fmt;
 use std::fmt::{Display, Formatter};
 use std::iter::{self, repeat};
-use std::path::Path;
+use std::ops::RangeInclusive;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
 
-use kubelet::container::Container;
+use k8s_openapi::api::core::v1::Sysctl;
+use kubelet::container::{Container, ContainerKey};
 use kubelet::pod::Pod;
 use lazy_static::lazy_static;
 use log::{debug, error, info, trace, warn};
 use multimap::MultiMap;
 use regex::Regex;
+use sha2::{Digest, Sha256};
 use strum::{Display, EnumIter, IntoEnumIterator};
 
+use crate::provider::dns::{has_dns_override, RESOLV_CONF_FILE_NAME};
 use crate::provider::error::StackableError;
 use crate::provider::error::StackableError::PodValidationError;
-use crate::provider::kubernetes::accessor::{restart_policy, RestartPolicy};
+use crate::provider::kubernetes::accessor::{
+    annotation, boolean_annotation, qos_class, restart_policy, QosClass, RestartPolicy,
+};
+use crate::provider::resource_limits::ResourceLimits;
 use crate::provider::states::pod::creating_config::CreatingConfig;
 use crate::provider::states::pod::PodState;
 use crate::provider::systemdmanager::manager::UnitTypes;
@@ -23,9 +31,161 @@ use crate::provider::systemdmanager::manager::UnitTypes;
 /// the process
 const DEFAULT_TERMINATION_TIMEOUT_SECS: i64 = 30;
 
+/// The delay before the first restart of a failed service
+///
+/// A moderate value is chosen so that tests are not slowed down too much.
+const RESTART_DELAY_SECS: u32 = 2;
+
+/// The maximum delay between restarts of a failed service which is also the maximum back-off
+/// delay of the Kubelet
+pub const MAX_RESTART_DELAY_SECS: u32 = 300;
+
+/// The number of restarts after which the maximum delay is reached
+///
+/// The delay roughly doubles with every restart like the back-off of the Kubelet.
+const RESTART_STEPS: u32 = 7;
+
+/// The time span in which at most [`START_LIMIT_BURST`] starts of a service are permitted
+///
+/// With the escalating delays of systemd 254 and later, a crashing service is started at most
+/// five times within this span, so the limit is never hit. Older versions ignore
+/// `RestartSteps` and restart with the fixed initial delay, so a crashing service hits the
+/// limit after a few seconds and is restarted by the agent after [`MAX_RESTART_DELAY_SECS`].
+const START_LIMIT_INTERVAL_SECS: u32 = 60;
+
+/// The number of starts of a service which are permitted within [`START_LIMIT_INTERVAL_SECS`]
+const START_LIMIT_BURST: u32 = 7;
+
+/// Key in the unit section which records a port of the container in the form
+/// `<port>/<protocol>` optionally followed by the name of the port, e.g. `8080/TCP http`
+///
+/// systemd ignores keys with the prefix `X-`, so that they can be used as metadata.
+pub const PORT_PROPERTY: &str = "X-Stackable-Port";
+
+/// Properties which are not passed to `systemd-run` because it rejects properties which are
+/// unknown to the installed version of systemd whereas they are ignored in unit files
+const UNIT_FILE_ONLY_PROPERTIES: &[&str] = &["RestartMaxDelaySec", "RestartSteps", PORT_PROPERTY];
+
 /// The slice all service units will be placed in.
+///
+/// The units are not placed directly in this slice but in a nested slice per pod, see
+/// [`SystemDUnit::pod_slice_name`].
 pub const STACKABLE_SLICE: &str = "system-stackable.slice";
 
+/// Annotation to set the `OOMScoreAdjust` of the service units explicitly, overriding the value
+/// derived from the quality of service class of the pod
+pub const OOM_SCORE_ADJUST_ANNOTATION: &str = "stackable.tech/oom-score-adjust";
+
+/// Annotation to set the `Nice` level of the service units
+pub const NICE_ANNOTATION: &str = "stackable.tech/nice";
+
+/// Annotation to select the type of the unit which activates the services of the pod, either
+/// `service` (default), `socket`, or `timer`
+pub const UNIT_TYPE_ANNOTATION: &str = "stackable.tech/unit-type";
+
+/// Annotation to set the `Type` of the service units, e.g. `notify` for services which signal
+/// their readiness with `sd_notify`, see [`ServiceType`]
+pub const SERVICE_TYPE_ANNOTATION: &str = "stackable.tech/service-type";
+
+/// Annotation with the `OnCalendar` expression of timer units
+pub const ON_CALENDAR_ANNOTATION: &str = "stackable.tech/on-calendar";
+
+/// Annotation to run the services of the pod as a transient user which is allocated by systemd,
+/// either `true` or `false` (default)
+pub const DYNAMIC_USER_ANNOTATION: &str = "stackable.tech/dynamic-user";
+
+/// Annotation to set the `TimeoutStartSec` of the service units in seconds, overriding the
+/// value derived from the startup probes of the containers
+pub const START_TIMEOUT_ANNOTATION: &str = "stackable.tech/start-timeout";
+
+/// Directory in which systemd places the credentials of the units, see `LoadCredential` in
+/// systemd.exec(5)
+const CREDENTIALS_DIRECTORY: &str = "/run/credentials";
+
+/// Annotation to start the service units of the pod as transient units with `systemd-run`
+/// instead of writing unit files, either `true` or `false` (default)
+///
+/// Only pods which are not restarted by systemd, i.e. with the restart policy `OnFailure` or
+/// `Never`, can be run transiently.
+pub const TRANSIENT_UNIT_ANNOTATION: &str = "stackable.tech/transient-unit";
+
+/// Binary which sets the kernel parameters of the pods, see [`SystemDUnit::get_sysctls`]
+const SYSCTL_BINARY: &str = "/sbin/sysctl";
+
+/// Default value of `failureThreshold` in probes, see the Kubernetes API reference
+const DEFAULT_PROBE_FAILURE_THRESHOLD: i32 = 3;
+
+/// Default value of `periodSeconds` in probes, see the Kubernetes API reference
+const DEFAULT_PROBE_PERIOD_SECONDS: i32 = 10;
+
+/// Maximum length of unit names including the type suffix, see `UNIT_NAME_MAX` in systemd
+///
+/// Shorter names can be configured with the option `unit-name-max-length`.
+pub const UNIT_NAME_MAX: usize = 255;
+
+/// Number of hexadecimal digits of the hash which is appended to shortened unit names
+const UNIT_NAME_HASH_LENGTH: usize = 8;
+
+/// Length of an escape sequence like `\x2d` in unit names
+const UNIT_NAME_ESCAPE_LENGTH: usize = 4;
+
+/// The `OOMScoreAdjust` for guaranteed and critical pods, this is the same value the Kubelet uses
+const GUARANTEED_OOM_SCORE_ADJUST: i32 = -997;
+
+/// The `OOMScoreAdjust` for best-effort pods, this is the same value the Kubelet uses
+const BEST_EFFORT_OOM_SCORE_ADJUST: i32 = 1000;
+
+/// Priority classes of pods which must not be killed by the OOM killer if possible
+const CRITICAL_PRIORITY_CLASSES: &[&str] = &["system-node-critical", "system-cluster-critical"];
+
+/// Linux capabilities without the prefix `CAP_`, see capabilities(7)
+const CAPABILITIES: &[&str] = &[
+    "AUDIT_CONTROL",
+    "AUDIT_READ",
+    "AUDIT_WRITE",
+    "BLOCK_SUSPEND",
+    "BPF",
+    "CHECKPOINT_RESTORE",
+    "CHOWN",
+    "DAC_OVERRIDE",
+    "DAC_READ_SEARCH",
+    "FOWNER",
+    "FSETID",
+    "IPC_LOCK",
+    "IPC_OWNER",
+    "KILL",
+    "LEASE",
+    "LINUX_IMMUTABLE",
+    "MAC_ADMIN",
+    "MAC_OVERRIDE",
+    "MKNOD",
+    "NET_ADMIN",
+    "NET_BIND_SERVICE",
+    "NET_BROADCAST",
+    "NET_RAW",
+    "PERFMON",
+    "SETFCAP",
+    "SETGID",
+    "SETPCAP",
+    "SETUID",
+    "SYSLOG",
+    "SYS_ADMIN",
+    "SYS_BOOT",
+    "SYS_CHROOT",
+    "SYS_MODULE",
+    "SYS_NICE",
+    "SYS_PACCT",
+    "SYS_PTRACE",
+    "SYS_RAWIO",
+    "SYS_RESOURCE",
+    "SYS_TIME",
+    "SYS_TTY_CONFIG",
+    "WAKE_ALARM",
+];
+
+/// Value in `securityContext.capabilities.drop` which drops all capabilities
+const ALL_CAPABILITIES: &str = "ALL";
+
 /// List of sections in the systemd unit
 ///
 /// The sections are written in the same order as listed here into the unit file.
@@ -33,6 +193,8 @@ pub const STACKABLE_SLICE: &str = "system-stackable.slice";
 pub enum Section {
     Unit,
     Service,
+    Socket,
+    Timer,
     Install,
 }
 
@@ -41,6 +203,20 @@ lazy_static! {
     // see https://systemd.io/USER_NAMES/
     static ref USER_NAME_PATTERN: Regex =
         Regex::new("^[a-zA-Z_][a-zA-Z0-9_-]{0,30}$").unwrap();
+    // Pattern for journal namespaces which are accepted by systemd, i.e. valid unit instance
+    // names which do not start with a dot
+    static ref LOG_NAMESPACE_PATTERN: Regex =
+        Regex::new("^[a-zA-Z0-9_:-][a-zA-Z0-9_:.-]*$").unwrap();
+}
+
+/// Checks if the given user name complies with the strict mode of systemd.
+pub fn is_valid_user_name(user_name: &str) -> bool {
+    USER_NAME_PATTERN.is_match(user_name)
+}
+
+/// Checks if the given name can be used as journal namespace in `LogNamespace=`.
+pub fn is_valid_log_namespace(log_namespace: &str) -> bool {
+    LOG_NAMESPACE_PATTERN.is_match(log_namespace)
 }
 
 /// Boolean arguments used in unit files
@@ -134,6 +310,39 @@ impl From<RestartPolicy> for RestartOption {
     }
 }
 
+/// Process start-up types of service units which can be selected for containers with the
+/// annotation [`SERVICE_TYPE_ANNOTATION`]
+///
+/// The type `oneshot` is not offered because it is reserved for init containers and the type
+/// `dbus` is not offered because it requires a bus name.
+#[derive(Clone, Copy, Debug, Display, EnumIter, Eq, PartialEq)]
+#[strum(serialize_all = "kebab-case")]
+pub enum ServiceType {
+    /// The service is considered started as soon as the main process is forked off (default).
+    Simple,
+    /// The service is considered started as soon as the main binary was executed.
+    Exec,
+    /// The main process forks and its parent exits when the service is started.
+    Forking,
+    /// The service signals its readiness with `sd_notify`.
+    Notify,
+    /// Like [`ServiceType::Simple`] but the process is delayed until all active jobs are
+    /// dispatched.
+    Idle,
+}
+
+/// User which runs the service of a container, see [`SystemDUnit::service_user`]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum ServiceUser {
+    /// The service runs as the user of the agent, i.e. as root or as the user of the session.
+    Agent,
+    /// The service runs as the given user.
+    Static(String),
+    /// The service runs as a transient user which is allocated by systemd, see
+    /// [`DYNAMIC_USER_ANNOTATION`].
+    Dynamic,
+}
+
 /// A struct that represents an individual systemd unit
 #[derive(Clone, Debug)]
 pub struct SystemDUnit {
@@ -158,11 +367,12 @@ impl SystemDUnit {
         // Naming schema
         //  Service name: `namespace-podname`
         //  SystemdUnit: `namespace-podname-containername`
+        //  Names which exceed the maximum length are shortened, see `shorten_unit_name`
         // TODO: add this to the docs in more detail
         let name_prefix = format!("{}-{}-", pod.namespace(), pod.name());
 
         // Create template data to be used when rendering template strings
-        let template_data = if let Ok(data) = CreatingConfig::create_render_data(pod_state) {
+        let template_data = if let Ok(data) = CreatingConfig::create_render_data(pod_state, pod) {
             data
         } else {
             error!("Unable to parse directories for command template as UTF8");
@@ -184,9 +394,140 @@ impl SystemDUnit {
             &template_data,
             &package_root,
             user_mode,
+            pod_state.unit_name_max_length,
         )?;
 
-        unit.set_property(Section::Service, "Slice", STACKABLE_SLICE);
+        unit.set_property(
+            Section::Service,
+            "Slice",
+            &SystemDUnit::pod_slice_name(pod, pod_state.unit_name_max_length),
+        );
+        unit.set_property(
+            Section::Service,
+            "SyslogIdentifier",
+            &SystemDUnit::syslog_identifier(pod.namespace(), pod.name(), container.name()),
+        );
+
+        if has_dns_override(pod) {
+            if !user_mode {
+                let resolv_conf = pod_state
+                    .get_service_service_directory()
+                    .join(RESOLV_CONF_FILE_NAME);
+                unit.set_property(
+                    Section::Service,
+                    "BindReadOnlyPaths",
+                    &format!("{}:/etc/resolv.conf", resolv_conf.to_string_lossy()),
+                );
+            } else {
+                info!("The DNS settings of the pod are not applied to the container [{}] because the agent runs in session mode.", container.name());
+            }
+        }
+
+        if let Some(security_context) = container.security_context() {
+            if security_context.allow_privilege_escalation == Some(false) {
+                unit.set_property(
+                    Section::Service,
+                    "NoNewPrivileges",
+                    &Boolean::Yes.to_string(),
+                );
+            }
+
+            if security_context.read_only_root_filesystem == Some(true) {
+                if !user_mode {
+                    unit.set_read_only_root_filesystem(&SystemDUnit::get_writable_paths(
+                        pod_state, pod, container,
+                    ));
+                } else {
+                    info!("The setting readOnlyRootFilesystem of the container [{}] is ignored because the agent runs in session mode.", container.name());
+                }
+            }
+        }
+
+        let read_only_host_paths = SystemDUnit::get_host_paths(pod, container)
+            .into_iter()
+            .filter(|(_, read_only)| *read_only)
+            .map(|(host_path, _)| host_path)
+            .collect::<Vec<_>>();
+        if !read_only_host_paths.is_empty() {
+            if !user_mode {
+                unit.set_read_only_paths(&read_only_host_paths);
+            } else {
+                info!("The hostPath volumes of the container [{}] are not mounted read-only because the agent runs in session mode.", container.name());
+            }
+        }
+
+        if let Some(log_namespace) = &pod_state.log_namespace {
+            if !user_mode {
+                unit.set_property(Section::Service, "LogNamespace", log_namespace);
+            } else {
+                info!("The log namespace [{}] is not set for the container [{}] because the agent runs in session mode.", log_namespace, container.name());
+            }
+        }
+
+        let sysctls = SystemDUnit::get_sysctls(pod);
+        if !sysctls.is_empty() {
+            if !user_mode {
+                for sysctl in sysctls {
+                    unit.add_property(
+                        Section::Service,
+                        "ExecStartPre",
+                        &SystemDUnit::sysctl_command(pod, sysctl)?,
+                    );
+                }
+            } else {
+                info!("The sysctls of the pod [{}] are not set for the container [{}] because the agent runs in session mode.", pod.name(), container.name());
+            }
+        }
+
+        if let Some(start_timeout) = SystemDUnit::get_start_timeout(pod, container)? {
+            unit.set_property(
+                Section::Service,
+                "TimeoutStartSec",
+                &start_timeout.to_string(),
+            );
+        }
+
+        for port in container.ports().iter().flatten() {
+            let protocol = port.protocol.as_deref().unwrap_or("TCP");
+            let value = match &port.name {
+                Some(name) => format!("{}/{} {}", port.container_port, protocol, name),
+                None => format!("{}/{}", port.container_port, protocol),
+            };
+            unit.add_property(Section::Unit, PORT_PROPERTY, &value);
+        }
+
+        for (directive, value) in ResourceLimits::from_container(container)?.unit_directives() {
+            unit.set_property(Section::Service, directive, &value);
+        }
+
+        if boolean_annotation(pod, DYNAMIC_USER_ANNOTATION)?.unwrap_or_default() {
+            if SystemDUnit::get_user_name_from_pod_security_context(pod)?.is_some()
+                || SystemDUnit::get_user_name_from_security_context(container, pod.name())?
+                    .is_some()
+            {
+                return Err(PodValidationError {
+                    msg: format!(
+                        "The validation of the pod [{}] failed. The annotation [{}] must not be enabled if a user name is set in spec.securityContext.windowsOptions.runAsUserName or spec.containers[name = {}].securityContext.windowsOptions.runAsUserName.",
+                        pod.name(),
+                        DYNAMIC_USER_ANNOTATION,
+                        container.name()
+                    ),
+                });
+            }
+
+            if !user_mode {
+                unit.set_dynamic_user();
+                unit.load_secret_files(&pod_state.secret_files);
+            } else {
+                info!("The annotation [{}] is ignored for the container [{}] because the agent runs in session mode.", DYNAMIC_USER_ANNOTATION, container.name());
+            }
+        } else if let Some(default_service_user) = &pod_state.default_service_user {
+            // The default user is only applied if neither the pod nor the container specify a
+            // user. In session mode, all services run as the user of the agent.
+            if !user_mode && !unit.has_property(Section::Service, "User") {
+                unit.set_property(Section::Service, "User", default_service_user);
+            }
+        }
 
         const UNIT_ENV_KEY: &str = "KUBECONFIG";
         if let Some(kubeconfig_path) = kubeconfig_path.to_str() {
@@ -204,6 +545,255 @@ impl SystemDUnit {
         Ok(unit)
     }
 
+    /// Creates the units for all init containers and containers of the given pod.
+    ///
+    /// The units of the init containers are chained, so that they run one after another in the
+    /// given order, and the service units of the containers depend on the last init unit.
+    pub fn new_all(
+        user_mode: bool,
+        pod_state: &PodState,
+        kubeconfig_path: &Path,
+        pod: &Pod,
+    ) -> Result<Vec<(ContainerKey, SystemDUnit)>, StackableError> {
+        let mut units = Vec::new();
+
+        let mut previous_init_unit: Option<String> = None;
+        for init_container in pod.init_containers() {
+            let unit = SystemDUnit::new_init(
+                user_mode,
+                pod_state,
+                kubeconfig_path,
+                pod,
+                &init_container,
+                previous_init_unit.as_deref(),
+            )?;
+            previous_init_unit = Some(unit.get_name());
+            units.push((
+                ContainerKey::Init(String::from(init_container.name())),
+                unit,
+            ));
+        }
+
+        let trigger_type = SystemDUnit::trigger_type(pod)?;
+
+        for container in pod.containers() {
+            let mut unit =
+                SystemDUnit::new(user_mode, pod_state, kubeconfig_path, pod, &container)?;
+            if let Some(last_init_unit) = &previous_init_unit {
+                unit.add_dependency(last_init_unit);
+            }
+            if trigger_type.is_some() {
+                unit.set_triggered_by(restart_policy(pod));
+            }
+            units.push((ContainerKey::App(String::from(container.name())), unit));
+        }
+
+        Ok(units)
+    }
+
+    /// Returns the type of the units which activate the service units of the given pod, see
+    /// [`UNIT_TYPE_ANNOTATION`].
+    ///
+    /// [`None`] is returned if the service units are started directly.
+    pub fn trigger_type(pod: &Pod) -> Result<Option<UnitTypes>, StackableError> {
+        match annotation(pod, UNIT_TYPE_ANNOTATION) {
+            None | Some("service") => Ok(None),
+            Some("socket") => Ok(Some(UnitTypes::Socket)),
+            Some("timer") => Ok(Some(UnitTypes::Timer)),
+            Some(unit_type) => Err(PodValidationError {
+                msg: format!(
+                    "The unit type [{}] in the annotation [{}] is not supported. Supported are \
+                    [service], [socket], and [timer].",
+                    unit_type, UNIT_TYPE_ANNOTATION
+                ),
+            }),
+        }
+    }
+
+    /// Checks if the service units of the given pod are started as transient units, see
+    /// [`TRANSIENT_UNIT_ANNOTATION`].
+    ///
+    /// Transient units cannot be enabled or activated by other units, therefore pods with the
+    /// restart policy `Always`, init containers, or a trigger unit are rejected.
+    pub fn is_transient(pod: &Pod) -> Result<bool, StackableError> {
+        if !boolean_annotation(pod, TRANSIENT_UNIT_ANNOTATION)?.unwrap_or_default() {
+            return Ok(false);
+        }
+
+        let reason = if restart_policy(pod) == RestartPolicy::Always {
+            Some(String::from("the restart policy is [Always]"))
+        } else if !pod.init_containers().is_empty() {
+            Some(String::from("it contains init containers"))
+        } else if SystemDUnit::trigger_type(pod)?.is_some() {
+            Some(format!(
+                "a trigger unit is selected in the annotation [{}]",
+                UNIT_TYPE_ANNOTATION
+            ))
+        } else {
+            None
+        };
+
+        match reason {
+            Some(reason) => Err(PodValidationError {
+                msg: format!(
+                    "The pod [{}] cannot be run with transient units as requested in the \
+                    annotation [{}] because {}.",
+                    pod.name(),
+                    TRANSIENT_UNIT_ANNOTATION,
+                    reason
+                ),
+            }),
+            None => Ok(true),
+        }
+    }
+
+    /// Adapts this service unit so that it is activated by a unit of the given type.
+    ///
+    /// The service unit is not enabled itself but pulled in by the socket or timer unit.
+    /// Triggered services must become inactive after they terminated, otherwise they would not
+    /// be started again on the next connection or when the timer elapses. Therefore they are
+    /// not restarted after a successful termination, even with the restart policy `Always`,
+    /// and only restarted on failure if the restart policy permits it.
+    fn set_triggered_by(&mut self, restart_policy: RestartPolicy) {
+        self.sections.remove(&Section::Install);
+
+        let restart_option = match restart_policy {
+            RestartPolicy::Always | RestartPolicy::OnFailure => RestartOption::OnFailure,
+            RestartPolicy::Never => RestartOption::No,
+        };
+        self.set_restart_option(&restart_option);
+        self.set_remain_after_exit_option(Boolean::No);
+    }
+
+    /// Creates the socket or timer unit which activates the given service unit of the container
+    /// if the pod selects one, see [`SystemDUnit::trigger_type`].
+    ///
+    /// A socket unit listens on the ports of the container and starts the service on the first
+    /// incoming connection. A timer unit starts the service according to the calendar event in
+    /// the annotation [`ON_CALENDAR_ANNOTATION`].
+    pub fn new_trigger(
+        pod: &Pod,
+        container: &Container,
+        service_unit: &SystemDUnit,
+    ) -> Result<Option<SystemDUnit>, StackableError> {
+        let unit_type = match SystemDUnit::trigger_type(pod)? {
+            Some(unit_type) => unit_type,
+            None => return Ok(None),
+        };
+
+        let mut unit = SystemDUnit {
+            name: service_unit.name.to_owned(),
+            unit_type,
+            sections: Default::default(),
+        };
+
+        unit.set_property(Section::Unit, "Description", &service_unit.name);
+
+        match unit.unit_type {
+            UnitTypes::Socket => {
+                let mut listening = false;
+                for port in container.ports().iter().flatten() {
+                    let directive = match port.protocol.as_deref().unwrap_or("TCP") {
+                        "TCP" => "ListenStream",
+                        "UDP" => "ListenDatagram",
+                        protocol => {
+                            return Err(PodValidationError {
+                                msg: format!(
+                                    "The protocol [{}] of the port [{}] in container [{}] is \
+                                    not supported for socket units.",
+                                    protocol,
+                                    port.container_port,
+                                    container.name()
+                                ),
+                            })
+                        }
+                    };
+                    unit.add_property(Section::Socket, directive, &port.container_port.to_string());
+                    listening = true;
+                }
+
+                if !listening {
+                    return Err(PodValidationError {
+                        msg: format!(
+                            "The container [{}] must declare at least one port to be \
+                            activated by a socket unit.",
+                            container.name()
+                        ),
+                    });
+                }
+
+                unit.set_property(Section::Socket, "Service", &service_unit.get_name());
+                unit.set_property(Section::Install, "WantedBy", "sockets.target");
+            }
+            UnitTypes::Timer => {
+                let on_calendar =
+                    annotation(pod, ON_CALENDAR_ANNOTATION).ok_or_else(|| PodValidationError {
+                        msg: format!(
+                            "The annotation [{}] is required for timer units.",
+                            ON_CALENDAR_ANNOTATION
+                        ),
+                    })?;
+
+                unit.set_property(Section::Timer, "OnCalendar", on_calendar);
+                unit.set_property(Section::Timer, "Unit", &service_unit.get_name());
+                unit.set_property(Section::Install, "WantedBy", "timers.target");
+            }
+            _ => unreachable!("Only socket and timer units activate services"),
+        }
+
+        Ok(Some(unit))
+    }
+
+    /// Creates the unit for an init container of the given pod.
+    ///
+    /// Init containers must run to completion before the service units of the pod are
+    /// started. Therefore the unit is of type `oneshot` and remains active after the process
+    /// exited, so that it is not run again when a dependent service unit is restarted. If
+    /// `previous_init_unit` is given then this unit is only started after the previous one
+    /// completed successfully, see [`SystemDUnit::add_dependency`].
+    pub fn new_init(
+        user_mode: bool,
+        pod_state: &PodState,
+        kubeconfig_path: &Path,
+        pod: &Pod,
+        init_container: &Container,
+        previous_init_unit: Option<&str>,
+    ) -> Result<SystemDUnit, StackableError> {
+        let mut unit =
+            SystemDUnit::new(user_mode, pod_state, kubeconfig_path, pod, init_container)?;
+
+        unit.set_property(Section::Service, "Type", "oneshot");
+        unit.remove_property(Section::Service, "NotifyAccess");
+        unit.set_remain_after_exit_option(Boolean::Yes);
+
+        // A failed init container fails the whole pod, furthermore systemd does not allow
+        // oneshot services to be restarted always.
+        unit.set_restart_option(&RestartOption::No);
+        unit.remove_property(Section::Service, "RestartSec");
+        unit.remove_property(Section::Service, "RestartSteps");
+        unit.remove_property(Section::Service, "RestartMaxDelaySec");
+
+        // The start job of a oneshot service only finishes when the process exited, so the
+        // default timeout of 90 seconds must not apply.
+        unit.set_property(Section::Service, "TimeoutStartSec", "infinity");
+
+        // Init units are not enabled but pulled in by the service units which require them.
+        unit.sections.remove(&Section::Install);
+
+        if let Some(previous_init_unit) = previous_init_unit {
+            unit.add_dependency(previous_init_unit);
+        }
+
+        Ok(unit)
+    }
+
+    /// Adds a dependency on the given unit, so that this unit is only started after the given
+    /// unit was started successfully and is stopped if the given unit is stopped.
+    pub fn add_dependency(&mut self, unit: &str) {
+        self.add_property(Section::Unit, "After", unit);
+        self.add_property(Section::Unit, "Requires", unit);
+    }
+
     fn new_from_container(
         common_properties: &SystemDUnit,
         name_prefix: &str,
@@ -212,6 +802,7 @@ impl SystemDUnit {
         template_data: &BTreeMap<String, String>,
         package_root: &Path,
         user_mode: bool,
+        max_length: usize,
     ) -> Result<Self, StackableError> {
         let mut unit = common_properties.clone();
 
@@ -223,7 +814,12 @@ impl SystemDUnit {
             Some(name_without_suffix) => name_without_suffix.to_string(),
         };
 
-        unit.name = format!("{}{}", name_prefix, trimmed_name);
+        unit.name = SystemDUnit::shorten_unit_name(
+            &format!("{}{}", name_prefix, trimmed_name),
+            common_properties.get_type_string(),
+            "-",
+            max_length,
+        );
 
         unit.set_property(Section::Unit, "Description", &unit.name.clone());
 
@@ -233,6 +829,12 @@ impl SystemDUnit {
             &SystemDUnit::get_command(container, template_data, package_root)?,
         );
 
+        for (directive, command) in
+            SystemDUnit::get_lifecycle_commands(container, template_data, package_root)?
+        {
+            unit.add_property(Section::Service, directive, &command);
+        }
+
         let env_vars = SystemDUnit::get_environment(container, service_name, template_data)?;
         for (key, value) in env_vars {
             unit.add_env_var(&key, &value);
@@ -252,6 +854,14 @@ impl SystemDUnit {
             }
         }
 
+        if let Some((added, dropped)) = SystemDUnit::get_capabilities(container, &unit.name)? {
+            if !user_mode {
+                unit.set_capabilities(&added, &dropped);
+            } else {
+                info!("The capabilities in spec.containers[name = {}].securityContext.capabilities are not set in the systemd unit because the agent runs in session mode.", container.name());
+            }
+        }
+
         // This one is mandatory, as otherwise enabling the unit fails
         unit.set_property(
             Section::Install,
@@ -294,6 +904,92 @@ impl SystemDUnit {
             .transpose()
     }
 
+    /// Returns the added and dropped capabilities of the given container with the prefix `CAP_`.
+    ///
+    /// The capabilities may be given with or without the prefix. [`ALL_CAPABILITIES`] is only
+    /// accepted in the dropped capabilities and returned unchanged. [`None`] is returned if the
+    /// container does not specify capabilities.
+    fn get_capabilities(
+        container: &Container,
+        pod_name: &str,
+    ) -> Result<Option<(Vec<String>, Vec<String>)>, StackableError> {
+        let capabilities = match container
+            .security_context()
+            .and_then(|security_context| security_context.capabilities.as_ref())
+        {
+            Some(capabilities) => capabilities,
+            None => return Ok(None),
+        };
+
+        let normalize = |field: &str, capability: &str, all_allowed: bool| {
+            let name = capability.strip_prefix("CAP_").unwrap_or(capability);
+            if all_allowed && capability == ALL_CAPABILITIES {
+                Ok(String::from(ALL_CAPABILITIES))
+            } else if CAPABILITIES.contains(&name) {
+                Ok(format!("CAP_{}", name))
+            } else {
+                Err(PodValidationError {
+                    msg: format!(
+                        "The validation of the pod [{}] failed. The capability [{}] in spec.containers[name = {}].securityContext.capabilities.{} is not a known Linux capability.",
+                        pod_name,
+                        capability,
+                        container.name(),
+                        field
+                    ),
+                })
+            }
+        };
+
+        let added = capabilities
+            .add
+            .iter()
+            .flatten()
+            .map(|capability| normalize("add", capability, false))
+            .collect::<Result<Vec<_>, _>>()?;
+        let dropped = capabilities
+            .drop
+            .iter()
+            .flatten()
+            .map(|capability| normalize("drop", capability, true))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(Some((added, dropped)))
+    }
+
+    /// Maps the given capabilities to `AmbientCapabilities` and `CapabilityBoundingSet`.
+    ///
+    /// The added capabilities are granted to the service also if it does not run as root. The
+    /// dropped capabilities are removed from the bounding set, so that neither the service nor
+    /// its child processes can acquire them. Added capabilities take precedence over dropped
+    /// ones like in other container runtimes, i.e. if all capabilities are dropped then the
+    /// bounding set contains only the added ones.
+    fn set_capabilities(&mut self, added: &[String], dropped: &[String]) {
+        if !added.is_empty() {
+            self.set_property(Section::Service, "AmbientCapabilities", &added.join(" "));
+        }
+
+        if dropped
+            .iter()
+            .any(|capability| capability == ALL_CAPABILITIES)
+        {
+            // An empty bounding set removes all capabilities.
+            self.set_property(Section::Service, "CapabilityBoundingSet", &added.join(" "));
+        } else {
+            let dropped = dropped
+                .iter()
+                .filter(|capability| !added.contains(capability))
+                .map(String::as_str)
+                .collect::<Vec<_>>();
+            if !dropped.is_empty() {
+                self.set_property(
+                    Section::Service,
+                    "CapabilityBoundingSet",
+                    &format!("~{}", dropped.join(" ")),
+                );
+            }
+        }
+    }
+
     /// Parse a pod object and retrieve the generic settings which will be the same across
     /// all service units created for containers in this pod.
     /// This is designed to then be used as `common_properties` parameter when calling
@@ -323,24 +1019,40 @@ impl SystemDUnit {
         // If it was not specified we use the default value for 'terminationGracePeriodSeconds' of
         // 30 seconds, as this differs from the systemd default for 'TimeOutStopSec` which is 90
         // seconds.
-        let termination_timeout = match pod_spec.termination_grace_period_seconds {
-            None => DEFAULT_TERMINATION_TIMEOUT_SECS,
-            Some(specified_timeout) => specified_timeout,
+        match pod_spec.termination_grace_period_seconds {
+            // A grace period of 0 means that the processes are killed immediately, whereas
+            // systemd interprets `TimeoutStopSec=0` as disabling the timeout. The shortest
+            // possible timeout is used instead and the remaining processes of the control group
+            // are killed directly with SIGKILL, see `KillMode=mixed`.
+            Some(0) => {
+                unit.set_property(Section::Service, "TimeoutStopSec", "1s");
+                unit.set_property(Section::Service, "KillMode", "mixed");
+            }
+            specified_timeout => {
+                let termination_timeout = specified_timeout
+                    .unwrap_or(DEFAULT_TERMINATION_TIMEOUT_SECS)
+                    .to_string();
+                unit.set_property(Section::Service, "TimeoutStopSec", &termination_timeout);
+            }
         }
-        .to_string();
-
-        unit.set_property(Section::Service, "TimeoutStopSec", &termination_timeout);
 
         let restart_option = RestartOption::from(restart_policy(pod));
         unit.set_restart_option(&restart_option);
 
-        // Relieve the machine a little bit on restart loops but choose
-        // a moderate value so that tests are not slowed down too much.
-        unit.set_restart_sec_option(2);
-
-        // Adhere to the given restart policy and do not limit the
-        // number of restarts.
-        unit.set_start_limit_interval_sec_option(0);
+        // Escalate the delay between restarts like the crash loop
+        // back-off of the Kubelet. RestartSteps and RestartMaxDelaySec
+        // were introduced in systemd version 254 and are ignored by
+        // older versions which restart with the fixed initial delay.
+        unit.set_restart_sec_option(RESTART_DELAY_SECS);
+        unit.set_restart_steps_option(RESTART_STEPS, MAX_RESTART_DELAY_SECS);
+
+        // Older systemd versions restart a crashing service with the
+        // fixed initial delay, so the start rate is limited as a
+        // fallback. A unit which hits the start limit fails and is
+        // restarted by the agent after the maximum back-off delay,
+        // because Kubernetes restarts the container forever. The limit
+        // is not reached with escalating restart delays.
+        unit.set_start_limit_options(START_LIMIT_INTERVAL_SECS, START_LIMIT_BURST);
 
         // If the service can terminate successfully then
         // RemainAfterExit must be set to "yes" so that the state of the
@@ -362,40 +1074,479 @@ impl SystemDUnit {
             }
         }
 
+        if let Some(oom_score_adjust) = SystemDUnit::get_oom_score_adjust(pod)? {
+            unit.set_property(
+                Section::Service,
+                "OOMScoreAdjust",
+                &oom_score_adjust.to_string(),
+            );
+        }
+
+        if let Some(nice) = SystemDUnit::get_integer_annotation(pod, NICE_ANNOTATION, -20..=19)? {
+            unit.set_property(Section::Service, "Nice", &nice.to_string());
+        }
+
+        if let Some(service_type) = SystemDUnit::get_service_type(pod)? {
+            unit.set_service_type(service_type);
+        }
+
         Ok(unit)
     }
 
-    /// Configures whether the service shall be restarted when the
-    /// service process exits, is killed, or a timeout is reached.
-    fn set_restart_option(&mut self, setting: &RestartOption) {
-        self.set_property(Section::Service, "Restart", &setting.to_string());
-    }
+    /// Determines the `OOMScoreAdjust` for the service units of the given pod.
+    ///
+    /// The value can be set explicitly with the annotation [`OOM_SCORE_ADJUST_ANNOTATION`].
+    /// Otherwise pods with a critical priority class or the quality of service class
+    /// `Guaranteed` are protected from the OOM killer and pods with the class `BestEffort` are
+    /// killed first. No value is set for `Burstable` pods, so they keep the default of systemd.
+    fn get_oom_score_adjust(pod: &Pod) -> Result<Option<i32>, StackableError> {
+        if let Some(value) =
+            SystemDUnit::get_integer_annotation(pod, OOM_SCORE_ADJUST_ANNOTATION, -1000..=1000)?
+        {
+            return Ok(Some(value));
+        }
 
-    /// Configures the time to sleep in seconds before restarting a
-    /// service (as configured with [`Self::set_restart_option`]).
-    /// Defaults to 100ms.
-    fn set_restart_sec_option(&mut self, seconds: u32) {
-        self.set_property(Section::Service, "RestartSec", &seconds.to_string());
-    }
+        let critical = pod
+            .as_kube_pod()
+            .spec
+            .as_ref()
+            .and_then(|spec| spec.priority_class_name.as_ref())
+            .map(|priority_class| CRITICAL_PRIORITY_CLASSES.contains(&priority_class.as_str()))
+            .unwrap_or_default();
+
+        let oom_score_adjust = match qos_class(pod) {
+            _ if critical => Some(GUARANTEED_OOM_SCORE_ADJUST),
+            QosClass::Guaranteed => Some(GUARANTEED_OOM_SCORE_ADJUST),
+            QosClass::Burstable => None,
+            QosClass::BestEffort => Some(BEST_EFFORT_OOM_SCORE_ADJUST),
+        };
 
-    /// Configures unit start rate limiting. Units which are started too
-    /// often within the given time span are not permitted to start any
-    /// more. The allowed number of restarts can be set with
-    /// "StartLimitBurst". May be set to 0 to disable any kind of rate
-    /// limiting.
-    fn set_start_limit_interval_sec_option(&mut self, seconds: u32) {
-        self.set_property(Section::Unit, "StartLimitIntervalSec", &seconds.to_string());
+        Ok(oom_score_adjust)
     }
 
-    /// Causes systemd to consider the unit to be active if the start
-    /// action exited successfully.
-    fn set_remain_after_exit_option(&mut self, setting: Boolean) {
-        self.set_property(Section::Service, "RemainAfterExit", &setting.to_string());
+    /// Determines the `TimeoutStartSec` in seconds for the service unit of the given container.
+    ///
+    /// The value can be set explicitly with the annotation [`START_TIMEOUT_ANNOTATION`].
+    /// Otherwise it is derived from the startup probe of the container, i.e. the time
+    /// Kubernetes would wait until the container is started:
+    /// `initialDelaySeconds + failureThreshold × periodSeconds`. If neither is given then no
+    /// value is returned and the default of systemd applies.
+    fn get_start_timeout(pod: &Pod, container: &Container) -> Result<Option<i32>, StackableError> {
+        if let Some(value) =
+            SystemDUnit::get_integer_annotation(pod, START_TIMEOUT_ANNOTATION, 1..=i32::MAX)?
+        {
+            return Ok(Some(value));
+        }
+
+        let start_timeout = container.startup_probe().map(|probe| {
+            let initial_delay = probe.initial_delay_seconds.unwrap_or_default().max(0);
+            let failure_threshold = probe
+                .failure_threshold
+                .unwrap_or(DEFAULT_PROBE_FAILURE_THRESHOLD)
+                .max(1);
+            let period = probe
+                .period_seconds
+                .unwrap_or(DEFAULT_PROBE_PERIOD_SECONDS)
+                .max(1);
+
+            initial_delay.saturating_add(failure_threshold.saturating_mul(period))
+        });
+
+        Ok(start_timeout)
     }
 
-    fn get_user_name_from_pod_security_context(pod: &Pod) -> Result<Option<&str>, StackableError> {
-        let validate = |user_name| {
-            if USER_NAME_PATTERN.is_match(user_name) {
+    /// Returns the service type which is selected in the annotation [`SERVICE_TYPE_ANNOTATION`].
+    ///
+    /// [`None`] is returned if the annotation is not set, so that the default of systemd, i.e.
+    /// [`ServiceType::Simple`], applies.
+    fn get_service_type(pod: &Pod) -> Result<Option<ServiceType>, StackableError> {
+        annotation(pod, SERVICE_TYPE_ANNOTATION)
+            .map(|value| {
+                ServiceType::iter()
+                    .find(|service_type| service_type.to_string() == value)
+                    .ok_or_else(|| PodValidationError {
+                        msg: format!(
+                            "The validation of the pod [{}] failed. The service type [{}] in the annotation [{}] is not supported. Supported are {}.",
+                            pod.name(),
+                            value,
+                            SERVICE_TYPE_ANNOTATION,
+                            ServiceType::iter()
+                                .map(|service_type| format!("[{}]", service_type))
+                                .collect::<Vec<_>>()
+                                .join(", ")
+                        ),
+                    })
+            })
+            .transpose()
+    }
+
+    /// Parses the annotation with the given key as integer and checks that it lies within the
+    /// given range.
+    fn get_integer_annotation(
+        pod: &Pod,
+        key: &str,
+        range: RangeInclusive<i32>,
+    ) -> Result<Option<i32>, StackableError> {
+        annotation(pod, key)
+            .map(|value| match value.parse::<i32>() {
+                Ok(number) if range.contains(&number) => Ok(number),
+                _ => Err(PodValidationError {
+                    msg: format!(
+                        "The validation of the pod [{}] failed. The value [{}] of the annotation [{}] must be an integer between {} and {}.",
+                        pod.name(),
+                        value,
+                        key,
+                        range.start(),
+                        range.end()
+                    ),
+                }),
+            })
+            .transpose()
+    }
+
+    /// Returns the identifier of the journal entries of the given container, i.e.
+    /// `<namespace>/<pod>/<container>`.
+    ///
+    /// Unlike the unit names, the identifier is never shortened, so that the journal can be
+    /// filtered reliably with `journalctl -t`.
+    pub fn syslog_identifier(namespace: &str, pod_name: &str, container_name: &str) -> String {
+        format!("{}/{}/{}", namespace, pod_name, container_name)
+    }
+
+    /// Runs the service as a transient user which is allocated by systemd when the unit is
+    /// started.
+    ///
+    /// The dynamic user cannot write to directories owned by other users, so a runtime and a
+    /// state directory named after the unit are created and chowned by systemd. Their paths are
+    /// passed in the environment variables `RUNTIME_DIRECTORY` and `STATE_DIRECTORY`.
+    fn set_dynamic_user(&mut self) {
+        let directory_name = self.name.to_owned();
+        self.set_property(Section::Service, "DynamicUser", &Boolean::Yes.to_string());
+        self.set_property(Section::Service, "RuntimeDirectory", &directory_name);
+        self.set_property(Section::Service, "StateDirectory", &directory_name);
+    }
+
+    /// Passes the given config files which contain secrets to a service with a dynamic user.
+    ///
+    /// The files are owned by root and only readable by the owner because the dynamic user does
+    /// not exist before the service is started. systemd copies them into the credentials
+    /// directory of the unit where they are owned by the dynamic user, see `LoadCredential` in
+    /// systemd.exec(5), and the copies are mounted over the original files.
+    fn load_secret_files(&mut self, secret_files: &[PathBuf]) {
+        let credentials_directory = Path::new(CREDENTIALS_DIRECTORY).join(self.get_name());
+        for (index, secret_file) in secret_files.iter().enumerate() {
+            let credential = format!("secret-{}", index);
+            let secret_file = secret_file.to_string_lossy();
+            self.add_property(
+                Section::Service,
+                "LoadCredential",
+                &format!("{}:{}", credential, secret_file),
+            );
+            self.add_property(
+                Section::Service,
+                "BindReadOnlyPaths",
+                &format!(
+                    "{}:{}",
+                    credentials_directory.join(&credential).to_string_lossy(),
+                    secret_file
+                ),
+            );
+        }
+    }
+
+    /// Mounts the file system read-only for this service except for the given paths.
+    ///
+    /// The service gets a private and writable `/tmp` and `/var/tmp` and the home directories
+    /// are mounted read-only.
+    fn set_read_only_root_filesystem(&mut self, writable_paths: &[PathBuf]) {
+        self.set_property(Section::Service, "ProtectSystem", "strict");
+        self.set_property(Section::Service, "ProtectHome", "read-only");
+        self.set_property(Section::Service, "PrivateTmp", &Boolean::Yes.to_string());
+
+        self.remove_property(Section::Service, "ReadWritePaths");
+        for path in writable_paths {
+            // Paths prefixed with "-" are ignored by systemd if they do not exist.
+            self.add_property(
+                Section::Service,
+                "ReadWritePaths",
+                &format!("-{}", path.to_string_lossy()),
+            );
+        }
+    }
+
+    /// Returns the paths which stay writable for the given container if its root file system
+    /// is read-only.
+    ///
+    /// These are the log directory of the service and the host paths of the volumes which are
+    /// mounted writable into the container.
+    fn get_writable_paths(pod_state: &PodState, pod: &Pod, container: &Container) -> Vec<PathBuf> {
+        let mut writable_paths = vec![pod_state.get_service_log_directory()];
+        writable_paths.extend(
+            SystemDUnit::get_host_paths(pod, container)
+                .into_iter()
+                .filter(|(_, read_only)| !read_only)
+                .map(|(host_path, _)| host_path),
+        );
+        writable_paths
+    }
+
+    /// Returns the host paths of the hostPath volumes which are mounted into the given container
+    /// and whether they are mounted read-only.
+    ///
+    /// The `subPath` of a volume mount is appended to the path of the volume, so that only the
+    /// mounted part of the volume is returned like in the `CreatingConfig` stage.
+    fn get_host_paths(pod: &Pod, container: &Container) -> Vec<(PathBuf, bool)> {
+        let volumes = pod.volumes().map(Vec::as_slice).unwrap_or_default();
+        container
+            .volume_mounts()
+            .iter()
+            .flatten()
+            .filter_map(|volume_mount| {
+                let host_path = volumes
+                    .iter()
+                    .find(|volume| volume.name == volume_mount.name)
+                    .and_then(|volume| volume.host_path.as_ref())?;
+                let sub_path = volume_mount.sub_path.as_deref().unwrap_or_default();
+                let path = Path::new(&host_path.path)
+                    .join(sub_path)
+                    .components()
+                    .collect::<PathBuf>();
+                Some((path, volume_mount.read_only == Some(true)))
+            })
+            .collect()
+    }
+
+    /// Mounts the given paths read-only for this service.
+    fn set_read_only_paths(&mut self, read_only_paths: &[PathBuf]) {
+        self.remove_property(Section::Service, "ReadOnlyPaths");
+        for path in read_only_paths {
+            // Paths prefixed with "-" are ignored by systemd if they do not exist.
+            self.add_property(
+                Section::Service,
+                "ReadOnlyPaths",
+                &format!("-{}", path.to_string_lossy()),
+            );
+        }
+    }
+
+    /// Creates the slice unit for the given pod in which all service units of the pod are
+    /// placed.
+    ///
+    /// The slice does not need to be enabled or started, systemd activates it as soon as one
+    /// of its units is started.
+    pub fn new_pod_slice(pod: &Pod, max_length: usize) -> Self {
+        let slice_name = SystemDUnit::pod_slice_name(pod, max_length);
+
+        let mut unit = SystemDUnit {
+            name: slice_name
+                .strip_suffix(".slice")
+                .unwrap_or(&slice_name)
+                .to_string(),
+            unit_type: UnitTypes::Slice,
+            sections: Default::default(),
+        };
+
+        unit.set_property(
+            Section::Unit,
+            "Description",
+            &format!("Slice of pod {}/{}", pod.namespace(), pod.name()),
+        );
+
+        unit
+    }
+
+    /// Returns the name of the slice in which the units of the given pod are placed.
+    ///
+    /// Every pod gets its own slice nested directly in [`STACKABLE_SLICE`], e.g.
+    /// `system-stackable-default_kafka\x2d0.slice` for the pod `kafka-0` in the namespace
+    /// `default`. Dashes denote the hierarchy of slices in systemd, therefore they are escaped
+    /// in the namespace and pod name, and both are separated by an underscore which cannot occur
+    /// in Kubernetes names. Otherwise systemd would create a parent slice per namespace which
+    /// contains the units of all pods in this namespace.
+    ///
+    /// Names which exceed the given maximum length are shortened without introducing another
+    /// level in the hierarchy, see [`SystemDUnit::shorten_unit_name`].
+    pub fn pod_slice_name(pod: &Pod, max_length: usize) -> String {
+        let escape = |name: &str| name.replace('-', "\\x2d");
+
+        let name = format!(
+            "{}-{}_{}",
+            STACKABLE_SLICE
+                .strip_suffix(".slice")
+                .unwrap_or(STACKABLE_SLICE),
+            escape(pod.namespace()),
+            escape(pod.name())
+        );
+
+        format!(
+            "{}.slice",
+            SystemDUnit::shorten_unit_name(&name, ".slice", "\\x2d", max_length)
+        )
+    }
+
+    /// Shortens the given unit name if it exceeds the given maximum length together with the
+    /// given type suffix.
+    ///
+    /// The name is truncated and the first [`UNIT_NAME_HASH_LENGTH`] digits of the SHA256 hash
+    /// of the full name are appended with the given separator. The shortened name is therefore
+    /// still unique and stays the same across restarts of the agent, so that existing units are
+    /// adopted again. Escape sequences like `\x2d` are not split, otherwise systemd would
+    /// reject the name or interpret the remaining characters literally.
+    fn shorten_unit_name(name: &str, suffix: &str, separator: &str, max_length: usize) -> String {
+        if name.len() + suffix.len() <= max_length {
+            return name.to_owned();
+        }
+
+        let hash = hex::encode(Sha256::digest(name.as_bytes()));
+
+        let mut length = max_length - suffix.len() - separator.len() - UNIT_NAME_HASH_LENGTH;
+        while !name.is_char_boundary(length) {
+            length -= 1;
+        }
+        if let Some(escape_start) = name[..length].rfind('\\') {
+            if escape_start + UNIT_NAME_ESCAPE_LENGTH > length {
+                length = escape_start;
+            }
+        }
+
+        format!(
+            "{}{}{}",
+            &name[..length],
+            separator,
+            &hash[..UNIT_NAME_HASH_LENGTH]
+        )
+    }
+
+    /// Configures whether the service shall be restarted when the
+    /// service process exits, is killed, or a timeout is reached.
+    fn set_restart_option(&mut self, setting: &RestartOption) {
+        self.set_property(Section::Service, "Restart", &setting.to_string());
+    }
+
+    /// Configures the time to sleep in seconds before restarting a
+    /// service (as configured with [`Self::set_restart_option`]).
+    /// Defaults to 100ms.
+    fn set_restart_sec_option(&mut self, seconds: u32) {
+        self.set_property(Section::Service, "RestartSec", &seconds.to_string());
+    }
+
+    /// Configures the number of restarts after which the time to sleep
+    /// before restarting a service reaches the given maximum. The delay
+    /// grows exponentially from the one which is configured with
+    /// [`Self::set_restart_sec_option`].
+    fn set_restart_steps_option(&mut self, steps: u32, max_delay_seconds: u32) {
+        self.set_property(Section::Service, "RestartSteps", &steps.to_string());
+        self.set_property(
+            Section::Service,
+            "RestartMaxDelaySec",
+            &max_delay_seconds.to_string(),
+        );
+    }
+
+    /// Configures unit start rate limiting. Units which are started more
+    /// often than the given burst within the given time span are not
+    /// permitted to start any more until their failed state is reset.
+    /// The interval may be set to 0 to disable any kind of rate
+    /// limiting.
+    fn set_start_limit_options(&mut self, interval_seconds: u32, burst: u32) {
+        self.set_property(
+            Section::Unit,
+            "StartLimitIntervalSec",
+            &interval_seconds.to_string(),
+        );
+        self.set_property(Section::Unit, "StartLimitBurst", &burst.to_string());
+    }
+
+    /// Sets the process start-up type of this service unit.
+    ///
+    /// Services of type `notify` are often started by wrapper scripts, so that the
+    /// notifications are accepted from all processes of the service and not only from the main
+    /// process.
+    fn set_service_type(&mut self, service_type: ServiceType) {
+        self.set_property(Section::Service, "Type", &service_type.to_string());
+        if service_type == ServiceType::Notify {
+            self.set_property(Section::Service, "NotifyAccess", "all");
+        }
+    }
+
+    /// Causes systemd to consider the unit to be active if the start
+    /// action exited successfully.
+    fn set_remain_after_exit_option(&mut self, setting: Boolean) {
+        self.set_property(Section::Service, "RemainAfterExit", &setting.to_string());
+    }
+
+    /// Returns the kernel parameters in `spec.securityContext.sysctls` of the given pod.
+    ///
+    /// The parameters are not namespaced by systemd, so they apply to the whole node. The agent
+    /// only accepts pods whose sysctls are allowed in its configuration.
+    pub fn get_sysctls(pod: &Pod) -> &[Sysctl] {
+        pod.as_kube_pod()
+            .spec
+            .as_ref()
+            .and_then(|spec| spec.security_context.as_ref())
+            .and_then(|security_context| security_context.sysctls.as_deref())
+            .unwrap_or_default()
+    }
+
+    /// Returns the command which sets the given sysctl before the service is started.
+    ///
+    /// The command is prefixed with `+`, so that it runs with full privileges even if the
+    /// service runs as another user. The specifiers `%` and `$` are escaped, so that the value
+    /// is passed unchanged, and values which contain quotes, backslashes, or control characters
+    /// are rejected because they cannot be quoted reliably.
+    fn sysctl_command(pod: &Pod, sysctl: &Sysctl) -> Result<String, StackableError> {
+        let assignment = format!("{}={}", sysctl.name, sysctl.value);
+
+        if assignment
+            .chars()
+            .any(|c| c == '"' || c == '\\' || c.is_control())
+        {
+            return Err(PodValidationError {
+                msg: format!(
+                    "The validation of the pod [{}] failed. The sysctl [{}] in spec.securityContext.sysctls must not contain quotes, backslashes, or control characters.",
+                    pod.name(),
+                    sysctl.name
+                ),
+            });
+        }
+
+        Ok(format!(
+            "+{} --write \"{}\"",
+            SYSCTL_BINARY,
+            assignment.replace('%', "%%").replace('$', "$$")
+        ))
+    }
+
+    /// Returns the user which runs the service of the given container.
+    ///
+    /// The user is determined like in [`SystemDUnit::new`]: The user name of the container takes
+    /// precedence over the one of the pod and the default service user only applies if neither
+    /// is set. In session mode, all services run as the user of the agent.
+    pub fn service_user(
+        user_mode: bool,
+        pod_state: &PodState,
+        pod: &Pod,
+        container: &Container,
+    ) -> Result<ServiceUser, StackableError> {
+        if user_mode {
+            return Ok(ServiceUser::Agent);
+        }
+        if boolean_annotation(pod, DYNAMIC_USER_ANNOTATION)?.unwrap_or_default() {
+            return Ok(ServiceUser::Dynamic);
+        }
+
+        let user_name = SystemDUnit::get_user_name_from_security_context(container, pod.name())?
+            .or(SystemDUnit::get_user_name_from_pod_security_context(pod)?)
+            .or_else(|| pod_state.default_service_user.as_deref());
+
+        Ok(user_name.map_or(ServiceUser::Agent, |user_name| {
+            ServiceUser::Static(String::from(user_name))
+        }))
+    }
+
+    fn get_user_name_from_pod_security_context(pod: &Pod) -> Result<Option<&str>, StackableError> {
+        let validate = |user_name| {
+            if USER_NAME_PATTERN.is_match(user_name) {
                 Ok(user_name)
             } else {
                 Err(PodValidationError {
@@ -426,6 +1577,50 @@ impl SystemDUnit {
         format!("{}.{}", self.name, lower_type)
     }
 
+    /// Verifies that the binaries of all commands in the unit exist and are executable.
+    ///
+    /// Otherwise systemd would fail with the rather cryptic exit code `203/EXEC` when starting
+    /// the service. Binaries without an absolute path are looked up by systemd and therefore
+    /// not checked.
+    pub fn verify_executables(&self) -> Result<(), StackableError> {
+        let service_section = match self.sections.get(&Section::Service) {
+            Some(section) => section,
+            None => return Ok(()),
+        };
+
+        for directive in &["ExecStart", "ExecStartPost", "ExecStop"] {
+            let binaries = service_section
+                .get_vec(*directive)
+                .into_iter()
+                .flatten()
+                .filter_map(|command| command.split_whitespace().next())
+                .map(Path::new)
+                .filter(|binary| binary.is_absolute());
+
+            for binary in binaries {
+                let executable = binary
+                    .metadata()
+                    .map(|metadata| {
+                        metadata.is_file() && metadata.permissions().mode() & 0o111 != 0
+                    })
+                    .unwrap_or_default();
+
+                if !executable {
+                    return Err(PodValidationError {
+                        msg: format!(
+                            "The binary [{}] of the {} command in the unit [{}] does not exist or is not executable.",
+                            binary.to_string_lossy(),
+                            directive,
+                            self.get_name()
+                        ),
+                    });
+                }
+            }
+        }
+
+        Ok(())
+    }
+
     /// Adds an environment variable to the service section of the unit file
     pub fn add_env_var(&mut self, key: &str, value: &str) {
         self.add_property(
@@ -444,6 +1639,20 @@ impl SystemDUnit {
         *section.entry(String::from(key)).or_insert_vec(Vec::new()) = vec![String::from(value)];
     }
 
+    /// Checks if a property with the given key is set in the given section
+    fn has_property(&self, section: Section, key: &str) -> bool {
+        self.sections
+            .get(&section)
+            .map_or(false, |section| section.contains_key(key))
+    }
+
+    /// Removes all properties with the given key from the given section
+    fn remove_property(&mut self, section: Section, key: &str) {
+        if let Some(section) = self.sections.get_mut(&section) {
+            section.remove(key);
+        }
+    }
+
     /// Adds a property to the given section
     ///
     /// Properties with the same key remain untouched.
@@ -462,15 +1671,75 @@ impl SystemDUnit {
             .join("\n\n")
     }
 
+    /// Returns the ports which are recorded with [`PORT_PROPERTY`] in the given unit file.
+    pub fn ports_from_unit_file(unit_file_content: &str) -> Vec<String> {
+        let prefix = format!("{}=", PORT_PROPERTY);
+        unit_file_content
+            .lines()
+            .filter_map(|line| line.trim().strip_prefix(&prefix))
+            .map(String::from)
+            .collect()
+    }
+
+    /// Returns the arguments for `systemd-run` to start this unit as transient unit.
+    ///
+    /// The properties of the unit and service sections are passed with `--property` in the
+    /// order of the unit file and the command of `ExecStart` is passed as command line, split
+    /// with the quoting rules of unit files, see [`split_command_line`]. The install section is
+    /// omitted because transient units cannot be enabled, and so are the
+    /// [`UNIT_FILE_ONLY_PROPERTIES`].
+    pub fn systemd_run_arguments(&self) -> Vec<String> {
+        let mut arguments = vec![
+            format!("--unit={}", self.get_name()),
+            String::from("--quiet"),
+        ];
+
+        for section in [Section::Unit, Section::Service].iter() {
+            if let Some(entries) = self.sections.get(section) {
+                let mut entries = entries.iter_all().collect::<Vec<_>>();
+                entries.sort_by_key(|(key, _)| *key);
+
+                arguments.extend(
+                    entries
+                        .into_iter()
+                        .filter(|(key, _)| {
+                            *key != "ExecStart"
+                                && !UNIT_FILE_ONLY_PROPERTIES.contains(&key.as_str())
+                        })
+                        .flat_map(|(key, values)| repeat(key).zip(values))
+                        .map(|(key, value)| format!("--property={}={}", key, value)),
+                );
+            }
+        }
+
+        arguments.push(String::from("--"));
+        arguments.extend(
+            self.sections
+                .get(&Section::Service)
+                .and_then(|section| section.get("ExecStart"))
+                .into_iter()
+                .flat_map(|command| split_command_line(command)),
+        );
+
+        arguments
+    }
+
+    /// Writes the given section with its properties sorted by key.
+    ///
+    /// The order of the keys in the multimap is not deterministic, so the properties are sorted
+    /// by key to produce the same content for the same unit, which is required for the comparison
+    /// in the cleanup stage. Properties with the same key, e.g. `Environment`, keep the order in
+    /// which they were added.
     fn write_section(section: &Section, entries: &MultiMap<String, String>) -> String {
         let header = format!("[{}]", section);
 
-        let mut body = entries
-            .iter_all()
+        let mut entries = entries.iter_all().collect::<Vec<_>>();
+        entries.sort_by_key(|(key, _)| *key);
+
+        let body = entries
+            .into_iter()
             .flat_map(|(key, values)| repeat(key).zip(values))
-            .map(|(key, value)| format!("{}={}", key, value))
-            .collect::<Vec<_>>();
-        body.sort();
+            .map(|(key, value)| format!("{}={}", key, value));
 
         iter::once(header)
             .chain(body)
@@ -481,6 +1750,9 @@ impl SystemDUnit {
     fn get_type_string(&self) -> &str {
         match &self.unit_type {
             UnitTypes::Service => ".service",
+            UnitTypes::Slice => ".slice",
+            UnitTypes::Socket => ".socket",
+            UnitTypes::Timer => ".timer",
         }
     }
 
@@ -561,6 +1833,80 @@ impl SystemDUnit {
             }
         };
 
+        // Append values from args array to command array
+        // This is necessary as we only have the ExecStart field in a systemd service unit.
+        // There is no specific place to put arguments separate from the command.
+        if let Some(mut args) = container.args().clone() {
+            debug!(
+                "Appending arguments [{:?}] to command for [{}]",
+                args,
+                container.name()
+            );
+            command.append(args.as_mut());
+        }
+
+        SystemDUnit::render_command(command, container, template_data, package_root)
+    }
+
+    /// Maps the exec handlers of the lifecycle hooks of the given container to systemd
+    /// directives.
+    ///
+    /// The `postStart` hook is run with `ExecStartPost` after the service was started and the
+    /// `preStop` hook with `ExecStop` before the service process is terminated. The commands
+    /// are rendered like the main command. Other handlers than exec are not supported and
+    /// ignored.
+    fn get_lifecycle_commands(
+        container: &Container,
+        template_data: &BTreeMap<String, String>,
+        package_root: &Path,
+    ) -> Result<Vec<(&'static str, String)>, StackableError> {
+        let lifecycle = container.lifecycle();
+        let hooks = [
+            (
+                "postStart",
+                "ExecStartPost",
+                lifecycle.and_then(|lifecycle| lifecycle.post_start.as_ref()),
+            ),
+            (
+                "preStop",
+                "ExecStop",
+                lifecycle.and_then(|lifecycle| lifecycle.pre_stop.as_ref()),
+            ),
+        ];
+
+        let mut commands = Vec::new();
+        for (hook, directive, handler) in hooks.iter() {
+            if let Some(handler) = handler {
+                match handler.exec.as_ref().and_then(|exec| exec.command.as_ref()) {
+                    Some(command) => commands.push((
+                        *directive,
+                        SystemDUnit::render_command(
+                            command.to_owned(),
+                            container,
+                            template_data,
+                            package_root,
+                        )?,
+                    )),
+                    None => warn!(
+                        "The {} hook of container [{}] is ignored because only exec handlers are supported.",
+                        hook,
+                        container.name()
+                    ),
+                }
+            }
+        }
+
+        Ok(commands)
+    }
+
+    /// Prepends the package root to the binary of the given command and replaces the variables
+    /// in all parts of the command.
+    fn render_command(
+        mut command: Vec<String>,
+        container: &Container,
+        template_data: &BTreeMap<String, String>,
+        package_root: &Path,
+    ) -> Result<String, StackableError> {
         trace!(
             "Command before replacing variables and adding packageroot: {:?}",
             command
@@ -608,18 +1954,6 @@ impl SystemDUnit {
             binary.replace_range(.., &binary_with_path);
         }
 
-        // Append values from args array to command array
-        // This is necessary as we only have the ExecStart field in a systemd service unit.
-        // There is no specific place to put arguments separate from the command.
-        if let Some(mut args) = container.args().clone() {
-            debug!(
-                "Appending arguments [{:?}] to command for [{}]",
-                args,
-                container.name()
-            );
-            command.append(args.as_mut());
-        }
-
         // Replace variables in command array
         let command_render_result = command
             .iter()
@@ -635,6 +1969,37 @@ impl SystemDUnit {
     }
 }
 
+/// Splits the given command line into its arguments like systemd does for `ExecStart`.
+///
+/// Arguments are separated by whitespace unless it is enclosed in double or single quotes or
+/// escaped with a backslash.
+fn split_command_line(command: &str) -> Vec<String> {
+    let mut arguments = Vec::new();
+    let mut argument: Option<String> = None;
+    let mut quote = None;
+
+    let mut chars = command.chars();
+    while let Some(c) = chars.next() {
+        match (c, quote) {
+            ('\\', _) => {
+                if let Some(escaped) = chars.next() {
+                    argument.get_or_insert_with(String::new).push(escaped);
+                }
+            }
+            (c, Some(quote_char)) if c == quote_char => quote = None,
+            ('"', None) | ('\'', None) => {
+                quote = Some(c);
+                argument.get_or_insert_with(String::new);
+            }
+            (c, None) if c.is_whitespace() => arguments.extend(argument.take()),
+            (c, _) => argument.get_or_insert_with(String::new).push(c),
+        }
+    }
+    arguments.extend(argument);
+
+    arguments
+}
+
 impl Display for SystemDUnit {
     fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
         write!(f, "{}", self.get_name())
@@ -644,9 +2009,8 @@ impl Display for SystemDUnit {
 #[cfg(test)]
 mod test {
     use super::*;
-    use crate::provider::{repository::package::Package, test::TestPod};
+    use crate::provider::test::{test_pod_state, TestPod};
     use indoc::indoc;
-    use kubelet::backoff::ExponentialBackoffStrategy;
     use rstest::rstest;
     use std::path::PathBuf;
 
@@ -676,17 +2040,22 @@ mod test {
         indoc! {r#"
             [Unit]
             Description=default-stackable-test-container
-            StartLimitIntervalSec=0
+            StartLimitBurst=7
+            StartLimitIntervalSec=60
 
             [Service]
             Environment="KUBECONFIG=~/.kube/config"
             ExecStart=/run/test-1.0.0/start.sh
+            OOMScoreAdjust=1000
             RemainAfterExit=no
             Restart=always
+            RestartMaxDelaySec=300
             RestartSec=2
-            Slice=system-stackable.slice
+            RestartSteps=7
+            Slice=system-stackable-default_stackable.slice
             StandardError=journal
             StandardOutput=journal
+            SyslogIdentifier=default/stackable/test-container
             TimeoutStopSec=30
             User=pod-user
 
@@ -723,19 +2092,24 @@ mod test {
         indoc! {r#"
             [Unit]
             Description=default-stackable-test-container
-            StartLimitIntervalSec=0
+            StartLimitBurst=7
+            StartLimitIntervalSec=60
 
             [Service]
             Environment="KUBECONFIG=~/.kube/config"
-            Environment="LOG_DIR=/var/log/default-stackable"
             Environment="LOG_LEVEL=INFO"
+            Environment="LOG_DIR=/var/log/default-stackable"
             ExecStart=/run/test-1.0.0/start.sh arg /etc/default-stackable-b3ca9d08-b97d-45bc-9da1-7b0156712ef1
+            OOMScoreAdjust=1000
             RemainAfterExit=no
             Restart=always
+            RestartMaxDelaySec=300
             RestartSec=2
-            Slice=system-stackable.slice
+            RestartSteps=7
+            Slice=system-stackable-default_stackable.slice
             StandardError=journal
             StandardOutput=journal
+            SyslogIdentifier=default/stackable/test-container
             TimeoutStopSec=30
             User=container-user
 
@@ -764,17 +2138,22 @@ mod test {
         indoc! {r#"
             [Unit]
             Description=default-stackable-test-container
-            StartLimitIntervalSec=0
+            StartLimitBurst=7
+            StartLimitIntervalSec=60
 
             [Service]
             Environment="KUBECONFIG=~/.kube/config"
             ExecStart=/run/test-1.0.0/start.sh
+            OOMScoreAdjust=1000
             RemainAfterExit=no
             Restart=always
+            RestartMaxDelaySec=300
             RestartSec=2
-            Slice=system-stackable.slice
+            RestartSteps=7
+            Slice=system-stackable-default_stackable.slice
             StandardError=journal
             StandardOutput=journal
+            SyslogIdentifier=default/stackable/test-container
             TimeoutStopSec=30
 
             [Install]
@@ -797,22 +2176,66 @@ mod test {
         indoc! {r#"
             [Unit]
             Description=default-stackable-test-container
-            StartLimitIntervalSec=0
+            StartLimitBurst=7
+            StartLimitIntervalSec=60
 
             [Service]
             Environment="KUBECONFIG=~/.kube/config"
             ExecStart=/run/test-1.0.0/start.sh
+            OOMScoreAdjust=1000
             RemainAfterExit=no
             Restart=always
+            RestartMaxDelaySec=300
             RestartSec=2
-            Slice=system-stackable.slice
+            RestartSteps=7
+            Slice=system-stackable-default_stackable.slice
             StandardError=journal
             StandardOutput=journal
+            SyslogIdentifier=default/stackable/test-container
             TimeoutStopSec=10
 
             [Install]
             WantedBy=multi-user.target"#}
     )]
+    #[case::kill_immediately_without_termination_grace_period(
+        BusType::System,
+        "
+            apiVersion: v1
+            kind: Pod
+            metadata:
+              name: stackable
+            spec:
+              containers:
+                - name: test-container
+                  command:
+                    - start.sh
+              terminationGracePeriodSeconds: 0",
+        "default-stackable-test-container.service",
+        indoc! {r#"
+            [Unit]
+            Description=default-stackable-test-container
+            StartLimitBurst=7
+            StartLimitIntervalSec=60
+
+            [Service]
+            Environment="KUBECONFIG=~/.kube/config"
+            ExecStart=/run/test-1.0.0/start.sh
+            KillMode=mixed
+            OOMScoreAdjust=1000
+            RemainAfterExit=no
+            Restart=always
+            RestartMaxDelaySec=300
+            RestartSec=2
+            RestartSteps=7
+            Slice=system-stackable-default_stackable.slice
+            StandardError=journal
+            StandardOutput=journal
+            SyslogIdentifier=default/stackable/test-container
+            TimeoutStopSec=1s
+
+            [Install]
+            WantedBy=multi-user.target"#}
+    )]
     #[case::set_restart_policy(
         BusType::System,
         "
@@ -830,43 +2253,212 @@ mod test {
         indoc! {r#"
             [Unit]
             Description=default-stackable-test-container
-            StartLimitIntervalSec=0
+            StartLimitBurst=7
+            StartLimitIntervalSec=60
 
             [Service]
             Environment="KUBECONFIG=~/.kube/config"
             ExecStart=/run/test-1.0.0/start.sh
+            OOMScoreAdjust=1000
             RemainAfterExit=yes
             Restart=on-failure
+            RestartMaxDelaySec=300
             RestartSec=2
-            Slice=system-stackable.slice
+            RestartSteps=7
+            Slice=system-stackable-default_stackable.slice
             StandardError=journal
             StandardOutput=journal
+            SyslogIdentifier=default/stackable/test-container
             TimeoutStopSec=30
 
             [Install]
             WantedBy=multi-user.target"#}
     )]
-    fn create_unit_from_pod(
-        #[case] bus_type: BusType,
-        #[case] pod: TestPod,
-        #[case] expected_unit_file_name: &str,
-        #[case] expected_unit_file_content: &str,
-    ) {
-        let kubeconfig_path = PathBuf::from("~/.kube/config");
+    #[case::add_lifecycle_hooks(
+        BusType::System,
+        "
+            apiVersion: v1
+            kind: Pod
+            metadata:
+              name: stackable
+            spec:
+              containers:
+                - name: test-container
+                  command:
+                    - start.sh
+                  lifecycle:
+                    postStart:
+                      exec:
+                        command:
+                          - register.sh
+                          - '{{configroot}}'
+                    preStop:
+                      exec:
+                        command:
+                          - /bin/sh
+                          - -c
+                          - deregister",
+        "default-stackable-test-container.service",
+        indoc! {r#"
+            [Unit]
+            Description=default-stackable-test-container
+            StartLimitBurst=7
+            StartLimitIntervalSec=60
 
-        let pod_state = PodState {
-            parcel_directory: PathBuf::from("/run"),
-            download_directory: PathBuf::new(),
-            config_directory: PathBuf::from("/etc"),
-            log_directory: PathBuf::from("/var/log"),
-            package_download_backoff_strategy: ExponentialBackoffStrategy::default(),
-            service_name: format!("{}-{}", pod.namespace(), pod.name()),
-            service_uid: String::from("b3ca9d08-b97d-45bc-9da1-7b0156712ef1"),
-            package: Package {
-                product: String::from("test"),
-                version: String::from("1.0.0"),
-            },
-        };
+            [Service]
+            Environment="KUBECONFIG=~/.kube/config"
+            ExecStart=/run/test-1.0.0/start.sh
+            ExecStartPost=/run/test-1.0.0/register.sh /etc/default-stackable-b3ca9d08-b97d-45bc-9da1-7b0156712ef1
+            ExecStop=/bin/sh -c deregister
+            OOMScoreAdjust=1000
+            RemainAfterExit=no
+            Restart=always
+            RestartMaxDelaySec=300
+            RestartSec=2
+            RestartSteps=7
+            Slice=system-stackable-default_stackable.slice
+            StandardError=journal
+            StandardOutput=journal
+            SyslogIdentifier=default/stackable/test-container
+            TimeoutStopSec=30
+
+            [Install]
+            WantedBy=multi-user.target"#}
+    )]
+    #[case::set_oom_score_adjust_and_nice_from_annotations(
+        BusType::System,
+        r#"
+            apiVersion: v1
+            kind: Pod
+            metadata:
+              name: stackable
+              annotations:
+                stackable.tech/oom-score-adjust: "-500"
+                stackable.tech/nice: "5"
+            spec:
+              containers:
+                - name: test-container
+                  command:
+                    - start.sh"#,
+        "default-stackable-test-container.service",
+        indoc! {r#"
+            [Unit]
+            Description=default-stackable-test-container
+            StartLimitBurst=7
+            StartLimitIntervalSec=60
+
+            [Service]
+            Environment="KUBECONFIG=~/.kube/config"
+            ExecStart=/run/test-1.0.0/start.sh
+            Nice=5
+            OOMScoreAdjust=-500
+            RemainAfterExit=no
+            Restart=always
+            RestartMaxDelaySec=300
+            RestartSec=2
+            RestartSteps=7
+            Slice=system-stackable-default_stackable.slice
+            StandardError=journal
+            StandardOutput=journal
+            SyslogIdentifier=default/stackable/test-container
+            TimeoutStopSec=30
+
+            [Install]
+            WantedBy=multi-user.target"#}
+    )]
+    #[case::set_service_type_from_annotation(
+        BusType::System,
+        r#"
+            apiVersion: v1
+            kind: Pod
+            metadata:
+              name: stackable
+              annotations:
+                stackable.tech/service-type: notify
+            spec:
+              containers:
+                - name: test-container
+                  command:
+                    - start.sh"#,
+        "default-stackable-test-container.service",
+        indoc! {r#"
+            [Unit]
+            Description=default-stackable-test-container
+            StartLimitBurst=7
+            StartLimitIntervalSec=60
+
+            [Service]
+            Environment="KUBECONFIG=~/.kube/config"
+            ExecStart=/run/test-1.0.0/start.sh
+            NotifyAccess=all
+            OOMScoreAdjust=1000
+            RemainAfterExit=no
+            Restart=always
+            RestartMaxDelaySec=300
+            RestartSec=2
+            RestartSteps=7
+            Slice=system-stackable-default_stackable.slice
+            StandardError=journal
+            StandardOutput=journal
+            SyslogIdentifier=default/stackable/test-container
+            TimeoutStopSec=30
+            Type=notify
+
+            [Install]
+            WantedBy=multi-user.target"#}
+    )]
+    #[case::set_oom_score_adjust_for_guaranteed_pod(
+        BusType::System,
+        "
+            apiVersion: v1
+            kind: Pod
+            metadata:
+              name: stackable
+            spec:
+              containers:
+                - name: test-container
+                  command:
+                    - start.sh
+                  resources:
+                    limits:
+                      cpu: 500m
+                      memory: 1Gi",
+        "default-stackable-test-container.service",
+        indoc! {r#"
+            [Unit]
+            Description=default-stackable-test-container
+            StartLimitBurst=7
+            StartLimitIntervalSec=60
+
+            [Service]
+            CPUQuota=50%
+            Environment="KUBECONFIG=~/.kube/config"
+            ExecStart=/run/test-1.0.0/start.sh
+            MemoryMax=1073741824
+            OOMScoreAdjust=-997
+            RemainAfterExit=no
+            Restart=always
+            RestartMaxDelaySec=300
+            RestartSec=2
+            RestartSteps=7
+            Slice=system-stackable-default_stackable.slice
+            StandardError=journal
+            StandardOutput=journal
+            SyslogIdentifier=default/stackable/test-container
+            TimeoutStopSec=30
+
+            [Install]
+            WantedBy=multi-user.target"#}
+    )]
+    fn create_unit_from_pod(
+        #[case] bus_type: BusType,
+        #[case] pod: TestPod,
+        #[case] expected_unit_file_name: &str,
+        #[case] expected_unit_file_content: &str,
+    ) {
+        let kubeconfig_path = PathBuf::from("~/.kube/config");
+
+        let pod_state = test_pod_state(&pod);
 
         let result = SystemDUnit::new(
             bus_type == BusType::Session,
@@ -883,4 +2475,1310 @@ mod test {
             panic!("Systemd unit expected but got {:?}", result);
         }
     }
+
+    #[rstest]
+    #[case::without_user_name(BusType::System, "", "", Some("stackable"))]
+    #[case::with_pod_user_name(BusType::System, "pod-user", "", Some("pod-user"))]
+    #[case::with_container_user_name(
+        BusType::System,
+        "pod-user",
+        "container-user",
+        Some("container-user")
+    )]
+    #[case::on_session_bus(BusType::Session, "", "", None)]
+    fn default_service_user_should_only_be_set_without_user_name(
+        #[case] bus_type: BusType,
+        #[case] pod_user_name: &str,
+        #[case] container_user_name: &str,
+        #[case] expected_user: Option<&str>,
+    ) {
+        let security_context = |user_name: &str| {
+            if user_name.is_empty() {
+                String::from("{}")
+            } else {
+                format!("{{windowsOptions: {{runAsUserName: {}}}}}", user_name)
+            }
+        };
+
+        let pod = format!(
+            "
+            apiVersion: v1
+            kind: Pod
+            metadata:
+              name: stackable
+            spec:
+              containers:
+                - name: test-container
+                  command:
+                    - start.sh
+                  securityContext: {}
+              securityContext: {}
+            ",
+            security_context(container_user_name),
+            security_context(pod_user_name)
+        )
+        .parse::<TestPod>()
+        .unwrap();
+
+        let pod_state = PodState {
+            default_service_user: Some(String::from("stackable")),
+            ..test_pod_state(&pod)
+        };
+
+        let unit = SystemDUnit::new(
+            bus_type == BusType::Session,
+            &pod_state,
+            &PathBuf::from("~/.kube/config"),
+            &pod,
+            pod.containers().first().unwrap(),
+        )
+        .unwrap();
+
+        assert_eq!(
+            expected_user,
+            unit.sections
+                .get(&Section::Service)
+                .and_then(|section| section.get("User"))
+                .map(String::as_str)
+        );
+    }
+
+    #[rstest]
+    #[case::enabled(
+        BusType::System,
+        "true",
+        "",
+        Ok(Some((
+            "yes",
+            "default-stackable-test-container",
+            "default-stackable-test-container"
+        )))
+    )]
+    #[case::disabled(BusType::System, "false", "", Ok(None))]
+    #[case::on_session_bus(BusType::Session, "true", "", Ok(None))]
+    #[case::with_user_name(BusType::System, "true", "pod-user", Err(()))]
+    #[case::invalid_value(BusType::System, "yes", "", Err(()))]
+    fn dynamic_user_should_be_set_from_annotation(
+        #[case] bus_type: BusType,
+        #[case] dynamic_user: &str,
+        #[case] pod_user_name: &str,
+        #[case] expected_properties: Result<Option<(&str, &str, &str)>, ()>,
+    ) {
+        let security_context = if pod_user_name.is_empty() {
+            String::from("{}")
+        } else {
+            format!("{{windowsOptions: {{runAsUserName: {}}}}}", pod_user_name)
+        };
+
+        let pod = format!(
+            "
+            apiVersion: v1
+            kind: Pod
+            metadata:
+              name: stackable
+              annotations:
+                stackable.tech/dynamic-user: \"{}\"
+            spec:
+              containers:
+                - name: test-container
+                  command:
+                    - start.sh
+              securityContext: {}
+            ",
+            dynamic_user, security_context
+        )
+        .parse::<TestPod>()
+        .unwrap();
+
+        let pod_state = PodState {
+            default_service_user: Some(String::from("stackable")),
+            ..test_pod_state(&pod)
+        };
+
+        let result = SystemDUnit::new(
+            bus_type == BusType::Session,
+            &pod_state,
+            &PathBuf::from("~/.kube/config"),
+            &pod,
+            pod.containers().first().unwrap(),
+        );
+
+        let properties = result
+            .map(|unit| {
+                let property = |key: &str| {
+                    unit.sections
+                        .get(&Section::Service)
+                        .and_then(|section| section.get(key))
+                        .map(String::to_owned)
+                };
+                property("DynamicUser").map(|dynamic_user| {
+                    (
+                        dynamic_user,
+                        property("RuntimeDirectory").unwrap_or_default(),
+                        property("StateDirectory").unwrap_or_default(),
+                    )
+                })
+            })
+            .map_err(|_| ());
+
+        assert_eq!(
+            expected_properties.map(|properties| properties.map(
+                |(dynamic_user, runtime_directory, state_directory)| (
+                    String::from(dynamic_user),
+                    String::from(runtime_directory),
+                    String::from(state_directory)
+                )
+            )),
+            properties
+        );
+    }
+
+    #[rstest]
+    #[case::root(BusType::System, "", "", "", None, Ok(ServiceUser::Agent))]
+    #[case::default_user(
+        BusType::System,
+        "",
+        "",
+        "",
+        Some("stackable"),
+        Ok(ServiceUser::Static(String::from("stackable")))
+    )]
+    #[case::pod_user(
+        BusType::System,
+        "",
+        "pod-user",
+        "",
+        Some("stackable"),
+        Ok(ServiceUser::Static(String::from("pod-user")))
+    )]
+    #[case::container_user(
+        BusType::System,
+        "",
+        "pod-user",
+        "container-user",
+        Some("stackable"),
+        Ok(ServiceUser::Static(String::from("container-user")))
+    )]
+    #[case::dynamic_user(
+        BusType::System,
+        "true",
+        "",
+        "",
+        Some("stackable"),
+        Ok(ServiceUser::Dynamic)
+    )]
+    #[case::on_session_bus(
+        BusType::Session,
+        "true",
+        "pod-user",
+        "",
+        Some("stackable"),
+        Ok(ServiceUser::Agent)
+    )]
+    #[case::invalid_user_name(BusType::System, "", "-user", "", None, Err(()))]
+    fn service_user_should_match_the_user_of_the_unit(
+        #[case] bus_type: BusType,
+        #[case] dynamic_user: &str,
+        #[case] pod_user_name: &str,
+        #[case] container_user_name: &str,
+        #[case] default_service_user: Option<&str>,
+        #[case] expected_service_user: Result<ServiceUser, ()>,
+    ) {
+        let security_context = |user_name: &str| {
+            if user_name.is_empty() {
+                String::from("{}")
+            } else {
+                format!("{{windowsOptions: {{runAsUserName: {}}}}}", user_name)
+            }
+        };
+
+        let pod = format!(
+            "
+            apiVersion: v1
+            kind: Pod
+            metadata:
+              name: stackable
+              annotations:
+                stackable.tech/dynamic-user: \"{}\"
+            spec:
+              containers:
+                - name: test-container
+                  command:
+                    - start.sh
+                  securityContext: {}
+              securityContext: {}
+            ",
+            if dynamic_user.is_empty() {
+                "false"
+            } else {
+                dynamic_user
+            },
+            security_context(container_user_name),
+            security_context(pod_user_name)
+        )
+        .parse::<TestPod>()
+        .unwrap();
+
+        let pod_state = PodState {
+            default_service_user: default_service_user.map(String::from),
+            ..test_pod_state(&pod)
+        };
+
+        assert_eq!(
+            expected_service_user,
+            SystemDUnit::service_user(
+                bus_type == BusType::Session,
+                &pod_state,
+                &pod,
+                pod.containers().first().unwrap(),
+            )
+            .map_err(|_| ())
+        );
+    }
+
+    #[test]
+    fn secret_files_should_be_loaded_as_credentials_for_dynamic_users() {
+        let pod = "
+            apiVersion: v1
+            kind: Pod
+            metadata:
+              name: stackable
+              annotations:
+                stackable.tech/dynamic-user: \"true\"
+            spec:
+              containers:
+                - name: test-container
+                  command:
+                    - start.sh
+            "
+        .parse::<TestPod>()
+        .unwrap();
+
+        let pod_state = PodState {
+            secret_files: vec![
+                PathBuf::from("/etc/default-stackable/client.properties"),
+                PathBuf::from("/etc/default-stackable/ssl/keystore.password"),
+            ],
+            ..test_pod_state(&pod)
+        };
+
+        let unit = SystemDUnit::new(
+            false,
+            &pod_state,
+            &PathBuf::from("~/.kube/config"),
+            &pod,
+            pod.containers().first().unwrap(),
+        )
+        .unwrap();
+
+        let properties = |key: &str| {
+            unit.sections
+                .get(&Section::Service)
+                .and_then(|section| section.get_vec(key))
+                .cloned()
+                .unwrap_or_default()
+        };
+
+        assert_eq!(
+            (
+                vec![
+                    String::from("secret-0:/etc/default-stackable/client.properties"),
+                    String::from("secret-1:/etc/default-stackable/ssl/keystore.password"),
+                ],
+                vec![
+                    String::from("/run/credentials/default-stackable-test-container.service/secret-0:/etc/default-stackable/client.properties"),
+                    String::from("/run/credentials/default-stackable-test-container.service/secret-1:/etc/default-stackable/ssl/keystore.password"),
+                ]
+            ),
+            (properties("LoadCredential"), properties("BindReadOnlyPaths"))
+        );
+    }
+
+    #[rstest]
+    #[case::added(
+        BusType::System,
+        "{add: [NET_BIND_SERVICE, CAP_SYS_NICE]}",
+        Ok((Some("CAP_NET_BIND_SERVICE CAP_SYS_NICE"), None))
+    )]
+    #[case::dropped(
+        BusType::System,
+        "{add: [NET_ADMIN], drop: [NET_ADMIN, NET_RAW, SYS_ADMIN]}",
+        Ok((Some("CAP_NET_ADMIN"), Some("~CAP_NET_RAW CAP_SYS_ADMIN")))
+    )]
+    #[case::all_dropped(
+        BusType::System,
+        "{add: [NET_BIND_SERVICE], drop: [ALL]}",
+        Ok((Some("CAP_NET_BIND_SERVICE"), Some("CAP_NET_BIND_SERVICE")))
+    )]
+    #[case::all_dropped_without_added(BusType::System, "{drop: [ALL]}", Ok((None, Some(""))))]
+    #[case::on_session_bus(BusType::Session, "{add: [NET_ADMIN]}", Ok((None, None)))]
+    #[case::unknown_capability(BusType::System, "{add: [NET_MAGIC]}", Err(()))]
+    #[case::all_added(BusType::System, "{add: [ALL]}", Err(()))]
+    fn capabilities_should_be_set_from_security_context(
+        #[case] bus_type: BusType,
+        #[case] capabilities: &str,
+        #[case] expected_properties: Result<(Option<&str>, Option<&str>), ()>,
+    ) {
+        let pod = format!(
+            "
+            apiVersion: v1
+            kind: Pod
+            metadata:
+              name: stackable
+            spec:
+              containers:
+                - name: test-container
+                  command:
+                    - start.sh
+                  securityContext:
+                    capabilities: {}
+            ",
+            capabilities
+        )
+        .parse::<TestPod>()
+        .unwrap();
+
+        let pod_state = test_pod_state(&pod);
+
+        let result = SystemDUnit::new(
+            bus_type == BusType::Session,
+            &pod_state,
+            &PathBuf::from("~/.kube/config"),
+            &pod,
+            pod.containers().first().unwrap(),
+        );
+
+        let properties = result
+            .map(|unit| {
+                let property = |key: &str| {
+                    unit.sections
+                        .get(&Section::Service)
+                        .and_then(|section| section.get(key))
+                        .map(String::to_owned)
+                };
+                (
+                    property("AmbientCapabilities"),
+                    property("CapabilityBoundingSet"),
+                )
+            })
+            .map_err(|_| ());
+
+        assert_eq!(
+            expected_properties.map(|(ambient_capabilities, bounding_set)| (
+                ambient_capabilities.map(String::from),
+                bounding_set.map(String::from)
+            )),
+            properties
+        );
+    }
+
+    #[rstest]
+    #[case::without_security_context(BusType::System, "{}", (None, None, vec![]))]
+    #[case::privilege_escalation_allowed(
+        BusType::System,
+        "{allowPrivilegeEscalation: true, readOnlyRootFilesystem: false}",
+        (None, None, vec![])
+    )]
+    #[case::privilege_escalation_disallowed(
+        BusType::System,
+        "{allowPrivilegeEscalation: false}",
+        (Some("yes"), None, vec![])
+    )]
+    #[case::read_only_root_filesystem(
+        BusType::System,
+        "{readOnlyRootFilesystem: true}",
+        (
+            None,
+            Some("strict"),
+            vec!["-/var/log/default-stackable", "-/var/lib/data"]
+        )
+    )]
+    #[case::on_session_bus(
+        BusType::Session,
+        "{allowPrivilegeEscalation: false, readOnlyRootFilesystem: true}",
+        (Some("yes"), None, vec![])
+    )]
+    fn hardening_should_be_derived_from_security_context(
+        #[case] bus_type: BusType,
+        #[case] security_context: &str,
+        #[case] expected_properties: (Option<&str>, Option<&str>, Vec<&str>),
+    ) {
+        let pod = format!(
+            "
+            apiVersion: v1
+            kind: Pod
+            metadata:
+              name: stackable
+            spec:
+              containers:
+                - name: test-container
+                  command:
+                    - start.sh
+                  securityContext: {}
+                  volumeMounts:
+                    - name: data
+                      mountPath: data
+                    - name: certificates
+                      mountPath: certificates
+                      readOnly: true
+              volumes:
+                - name: data
+                  hostPath:
+                    path: /var/lib/data
+                - name: certificates
+                  hostPath:
+                    path: /etc/certificates
+            ",
+            security_context
+        )
+        .parse::<TestPod>()
+        .unwrap();
+
+        let pod_state = test_pod_state(&pod);
+
+        let unit = SystemDUnit::new(
+            bus_type == BusType::Session,
+            &pod_state,
+            &PathBuf::from("~/.kube/config"),
+            &pod,
+            pod.containers().first().unwrap(),
+        )
+        .unwrap();
+
+        let service_section = unit.sections.get(&Section::Service).unwrap();
+        let property = |key: &str| service_section.get(key).map(String::as_str);
+
+        assert_eq!(
+            expected_properties,
+            (
+                property("NoNewPrivileges"),
+                property("ProtectSystem"),
+                service_section
+                    .get_vec("ReadWritePaths")
+                    .map(|paths| paths.iter().map(String::as_str).collect())
+                    .unwrap_or_default()
+            )
+        );
+    }
+
+    #[rstest]
+    #[case::on_system_bus(
+        BusType::System,
+        (
+            vec!["-/var/log/default-stackable", "-/var/lib/data/kafka"],
+            vec!["-/etc/certificates/kafka/truststore.p12"]
+        )
+    )]
+    #[case::on_session_bus(BusType::Session, (vec![], vec![]))]
+    fn host_paths_should_be_mounted_with_sub_path_and_read_only_flag(
+        #[case] bus_type: BusType,
+        #[case] expected_paths: (Vec<&str>, Vec<&str>),
+    ) {
+        let pod = "
+            apiVersion: v1
+            kind: Pod
+            metadata:
+              name: stackable
+            spec:
+              containers:
+                - name: test-container
+                  command:
+                    - start.sh
+                  securityContext:
+                    readOnlyRootFilesystem: true
+                  volumeMounts:
+                    - name: data
+                      mountPath: data
+                      subPath: kafka
+                    - name: certificates
+                      mountPath: truststore.p12
+                      subPath: kafka/truststore.p12
+                      readOnly: true
+                    - name: config
+                      mountPath: config
+                      readOnly: true
+              volumes:
+                - name: data
+                  hostPath:
+                    path: /var/lib/data
+                - name: certificates
+                  hostPath:
+                    path: /etc/certificates/
+                - name: config
+                  configMap:
+                    name: config
+            "
+        .parse::<TestPod>()
+        .unwrap();
+
+        let pod_state = test_pod_state(&pod);
+
+        let unit = SystemDUnit::new(
+            bus_type == BusType::Session,
+            &pod_state,
+            &PathBuf::from("~/.kube/config"),
+            &pod,
+            pod.containers().first().unwrap(),
+        )
+        .unwrap();
+
+        let service_section = unit.sections.get(&Section::Service).unwrap();
+        let paths = |key: &str| {
+            service_section
+                .get_vec(key)
+                .map(|paths| paths.iter().map(String::as_str).collect::<Vec<_>>())
+                .unwrap_or_default()
+        };
+
+        assert_eq!(
+            expected_paths,
+            (paths("ReadWritePaths"), paths("ReadOnlyPaths"))
+        );
+    }
+
+    #[rstest]
+    #[case::on_system_bus(
+        BusType::System,
+        "62%",
+        Ok(vec![
+            r#"+/sbin/sysctl --write "vm.max_map_count=262144""#,
+            r#"+/sbin/sysctl --write "vm.dirty_ratio=62%%""#,
+        ])
+    )]
+    #[case::on_session_bus(BusType::Session, "62%", Ok(vec![]))]
+    #[case::value_with_quotes(BusType::System, "'\"62\"'", Err(()))]
+    fn sysctls_should_be_set_before_the_service_is_started(
+        #[case] bus_type: BusType,
+        #[case] dirty_ratio: &str,
+        #[case] expected_commands: Result<Vec<&str>, ()>,
+    ) {
+        let pod = format!(
+            "
+            apiVersion: v1
+            kind: Pod
+            metadata:
+              name: stackable
+            spec:
+              containers:
+                - name: test-container
+                  command:
+                    - start.sh
+              securityContext:
+                sysctls:
+                  - name: vm.max_map_count
+                    value: '262144'
+                  - name: vm.dirty_ratio
+                    value: {}
+            ",
+            dirty_ratio
+        )
+        .parse::<TestPod>()
+        .unwrap();
+
+        let pod_state = test_pod_state(&pod);
+
+        let result = SystemDUnit::new(
+            bus_type == BusType::Session,
+            &pod_state,
+            &PathBuf::from("~/.kube/config"),
+            &pod,
+            pod.containers().first().unwrap(),
+        );
+
+        let commands = result
+            .map(|unit| {
+                unit.sections
+                    .get(&Section::Service)
+                    .and_then(|section| section.get_vec("ExecStartPre"))
+                    .cloned()
+                    .unwrap_or_default()
+            })
+            .map_err(|_| ());
+
+        assert_eq!(
+            expected_commands
+                .map(|commands| commands.into_iter().map(String::from).collect::<Vec<_>>()),
+            commands
+        );
+    }
+
+    #[rstest]
+    #[case::on_system_bus(BusType::System, Some("stackable"))]
+    #[case::on_session_bus(BusType::Session, None)]
+    fn log_namespace_should_be_set_on_system_bus(
+        #[case] bus_type: BusType,
+        #[case] expected_log_namespace: Option<&str>,
+    ) {
+        let pod = "
+            apiVersion: v1
+            kind: Pod
+            metadata:
+              name: stackable
+            spec:
+              containers:
+                - name: test-container
+                  command:
+                    - start.sh
+        "
+        .parse::<TestPod>()
+        .unwrap();
+
+        let pod_state = PodState {
+            log_namespace: Some(String::from("stackable")),
+            ..test_pod_state(&pod)
+        };
+
+        let unit = SystemDUnit::new(
+            bus_type == BusType::Session,
+            &pod_state,
+            &PathBuf::from("~/.kube/config"),
+            &pod,
+            pod.containers().first().unwrap(),
+        )
+        .unwrap();
+
+        assert_eq!(
+            expected_log_namespace,
+            unit.sections
+                .get(&Section::Service)
+                .and_then(|section| section.get("LogNamespace"))
+                .map(String::as_str)
+        );
+    }
+
+    #[rstest]
+    #[case("stackable", true)]
+    #[case("kafka-logs_2:a.b", true)]
+    #[case("", false)]
+    #[case(".hidden", false)]
+    #[case("kafka/logs", false)]
+    #[case("kafka logs", false)]
+    fn log_namespaces_should_be_validated(#[case] log_namespace: &str, #[case] valid: bool) {
+        assert_eq!(valid, is_valid_log_namespace(log_namespace));
+    }
+
+    #[rstest]
+    #[case::simple_names("default", "stackable", "system-stackable-default_stackable.slice")]
+    #[case::names_with_dashes(
+        "kube-system",
+        "kafka-0",
+        r"system-stackable-kube\x2dsystem_kafka\x2d0.slice"
+    )]
+    fn pod_slice_name_should_be_nested_in_stackable_slice(
+        #[case] namespace: &str,
+        #[case] pod_name: &str,
+        #[case] expected_slice_name: &str,
+    ) {
+        let pod = format!(
+            "
+            apiVersion: v1
+            kind: Pod
+            metadata:
+              name: {}
+              namespace: {}
+            spec:
+              containers:
+                - name: test-container
+            ",
+            pod_name, namespace
+        )
+        .parse::<TestPod>()
+        .unwrap();
+
+        assert_eq!(
+            expected_slice_name,
+            SystemDUnit::pod_slice_name(&pod, UNIT_NAME_MAX)
+        );
+    }
+
+    #[test]
+    fn long_unit_names_should_be_shortened() {
+        let name = "a".repeat(300);
+
+        let shortened_name = SystemDUnit::shorten_unit_name(&name, ".service", "-", UNIT_NAME_MAX);
+
+        assert_eq!(UNIT_NAME_MAX, shortened_name.len() + ".service".len());
+        assert!(shortened_name.starts_with(&"a".repeat(238)));
+        assert_eq!(
+            shortened_name,
+            SystemDUnit::shorten_unit_name(&name, ".service", "-", UNIT_NAME_MAX)
+        );
+        assert_ne!(
+            shortened_name,
+            SystemDUnit::shorten_unit_name(&"a".repeat(299), ".service", "-", UNIT_NAME_MAX)
+        );
+        assert_eq!(
+            "default-kafka-0",
+            SystemDUnit::shorten_unit_name("default-kafka-0", ".service", "-", UNIT_NAME_MAX)
+        );
+    }
+
+    #[test]
+    fn unit_names_should_be_shortened_to_the_given_length() {
+        let shortened_name = SystemDUnit::shorten_unit_name(&"a".repeat(100), ".service", "-", 64);
+
+        assert_eq!(64, shortened_name.len() + ".service".len());
+        assert!(shortened_name.starts_with(&"a".repeat(47)));
+    }
+
+    #[rstest]
+    #[case::cut_after_backslash(45)]
+    #[case::cut_after_x(44)]
+    #[case::cut_in_code(43)]
+    fn escape_sequences_should_not_be_split(#[case] backslash_position: usize) {
+        // The name is cut after 46 characters to leave room for the separator, the hash, and
+        // the suffix.
+        let prefix = "a".repeat(backslash_position);
+        let name = format!(r"{}\x2d{}", prefix, "b".repeat(30));
+
+        let shortened_name = SystemDUnit::shorten_unit_name(&name, ".slice", r"\x2d", 64);
+
+        let hash = &hex::encode(Sha256::digest(name.as_bytes()))[..UNIT_NAME_HASH_LENGTH];
+        assert_eq!(format!(r"{}\x2d{}", prefix, hash), shortened_name);
+    }
+
+    #[test]
+    fn complete_escape_sequences_should_be_kept() {
+        let prefix = format!(r"{}\x2d", "a".repeat(42));
+        let name = format!("{}{}", prefix, "b".repeat(30));
+
+        let shortened_name = SystemDUnit::shorten_unit_name(&name, ".slice", r"\x2d", 64);
+
+        assert!(shortened_name.starts_with(&format!(r"{}\x2d", prefix)));
+        assert_eq!(64, shortened_name.len() + ".slice".len());
+    }
+
+    #[test]
+    fn pod_slice_should_be_created() {
+        let pod = "
+            apiVersion: v1
+            kind: Pod
+            metadata:
+              name: stackable
+            spec:
+              containers:
+                - name: test-container
+            "
+        .parse::<TestPod>()
+        .unwrap();
+
+        let unit = SystemDUnit::new_pod_slice(&pod, UNIT_NAME_MAX);
+
+        assert_eq!("system-stackable-default_stackable.slice", unit.get_name());
+        assert_eq!(
+            indoc! {"
+                [Unit]
+                Description=Slice of pod default/stackable"},
+            unit.get_unit_file_content()
+        );
+    }
+
+    #[rstest]
+    #[case::not_a_number("stackable.tech/oom-score-adjust", "high")]
+    #[case::oom_score_adjust_too_low("stackable.tech/oom-score-adjust", "-1001")]
+    #[case::nice_too_high("stackable.tech/nice", "20")]
+    #[case::start_timeout_zero("stackable.tech/start-timeout", "0")]
+    #[case::unsupported_service_type("stackable.tech/service-type", "oneshot")]
+    fn invalid_annotations_should_be_rejected(#[case] key: &str, #[case] value: &str) {
+        let pod = format!(
+            r#"
+            apiVersion: v1
+            kind: Pod
+            metadata:
+              name: stackable
+              annotations:
+                {}: "{}"
+            spec:
+              containers:
+                - name: test-container
+                  command:
+                    - start.sh
+            "#,
+            key, value
+        )
+        .parse::<TestPod>()
+        .unwrap();
+
+        let result = SystemDUnit::new_from_pod(&pod, false);
+
+        assert!(matches!(result, Err(PodValidationError { .. })));
+    }
+
+    #[rstest]
+    #[case::without_probe_and_annotation("{}", "", None)]
+    #[case::from_startup_probe_defaults("{}", "startupProbe: {}", Some("30"))]
+    #[case::from_startup_probe(
+        "{}",
+        "startupProbe: {initialDelaySeconds: 5, failureThreshold: 30, periodSeconds: 10}",
+        Some("305")
+    )]
+    #[case::from_annotation(
+        r#"{"stackable.tech/start-timeout": "600"}"#,
+        "startupProbe: {failureThreshold: 30, periodSeconds: 10}",
+        Some("600")
+    )]
+    fn start_timeout_should_be_derived_from_startup_probe_or_annotation(
+        #[case] annotations: &str,
+        #[case] container_properties: &str,
+        #[case] expected_start_timeout: Option<&str>,
+    ) {
+        let pod = format!(
+            "
+            apiVersion: v1
+            kind: Pod
+            metadata:
+              name: stackable
+              annotations: {}
+            spec:
+              containers:
+                - name: test-container
+                  command:
+                    - start.sh
+                  {}
+            ",
+            annotations, container_properties
+        )
+        .parse::<TestPod>()
+        .unwrap();
+
+        let pod_state = test_pod_state(&pod);
+
+        let unit = SystemDUnit::new(
+            false,
+            &pod_state,
+            &PathBuf::from("~/.kube/config"),
+            &pod,
+            pod.containers().first().unwrap(),
+        )
+        .unwrap();
+
+        let start_timeout = unit
+            .sections
+            .get(&Section::Service)
+            .and_then(|entries| entries.get("TimeoutStartSec"))
+            .map(String::as_str);
+        assert_eq!(expected_start_timeout, start_timeout);
+    }
+
+    #[test]
+    fn init_units_should_be_chained_before_service_unit() {
+        let pod = "
+            apiVersion: v1
+            kind: Pod
+            metadata:
+              name: stackable
+            spec:
+              initContainers:
+                - name: prepare
+                  command:
+                    - prepare.sh
+                - name: migrate
+                  command:
+                    - migrate.sh
+              containers:
+                - name: test-container
+                  command:
+                    - start.sh
+            "
+        .parse::<TestPod>()
+        .unwrap();
+
+        let pod_state = test_pod_state(&pod);
+
+        let units = SystemDUnit::new_all(false, &pod_state, &PathBuf::from("~/.kube/config"), &pod)
+            .unwrap();
+
+        let container_keys = units
+            .iter()
+            .map(|(container_key, _)| container_key.to_owned())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            vec![
+                ContainerKey::Init(String::from("prepare")),
+                ContainerKey::Init(String::from("migrate")),
+                ContainerKey::App(String::from("test-container")),
+            ],
+            container_keys
+        );
+
+        assert_eq!(
+            indoc! {r#"
+                [Unit]
+                Description=default-stackable-prepare
+                StartLimitBurst=7
+                StartLimitIntervalSec=60
+
+                [Service]
+                Environment="KUBECONFIG=~/.kube/config"
+                ExecStart=/run/test-1.0.0/prepare.sh
+                OOMScoreAdjust=1000
+                RemainAfterExit=yes
+                Restart=no
+                Slice=system-stackable-default_stackable.slice
+                StandardError=journal
+                StandardOutput=journal
+                SyslogIdentifier=default/stackable/prepare
+                TimeoutStartSec=infinity
+                TimeoutStopSec=30
+                Type=oneshot"#},
+            units[0].1.get_unit_file_content()
+        );
+
+        assert_eq!(
+            indoc! {r#"
+                [Unit]
+                After=default-stackable-prepare.service
+                Description=default-stackable-migrate
+                Requires=default-stackable-prepare.service
+                StartLimitBurst=7
+                StartLimitIntervalSec=60
+
+                [Service]
+                Environment="KUBECONFIG=~/.kube/config"
+                ExecStart=/run/test-1.0.0/migrate.sh
+                OOMScoreAdjust=1000
+                RemainAfterExit=yes
+                Restart=no
+                Slice=system-stackable-default_stackable.slice
+                StandardError=journal
+                StandardOutput=journal
+                SyslogIdentifier=default/stackable/migrate
+                TimeoutStartSec=infinity
+                TimeoutStopSec=30
+                Type=oneshot"#},
+            units[1].1.get_unit_file_content()
+        );
+
+        assert_eq!(
+            indoc! {r#"
+                [Unit]
+                After=default-stackable-migrate.service
+                Description=default-stackable-test-container
+                Requires=default-stackable-migrate.service
+                StartLimitBurst=7
+                StartLimitIntervalSec=60
+
+                [Service]
+                Environment="KUBECONFIG=~/.kube/config"
+                ExecStart=/run/test-1.0.0/start.sh
+                OOMScoreAdjust=1000
+                RemainAfterExit=no
+                Restart=always
+                RestartMaxDelaySec=300
+                RestartSec=2
+                RestartSteps=7
+                Slice=system-stackable-default_stackable.slice
+                StandardError=journal
+                StandardOutput=journal
+                SyslogIdentifier=default/stackable/test-container
+                TimeoutStopSec=30
+
+                [Install]
+                WantedBy=multi-user.target"#},
+            units[2].1.get_unit_file_content()
+        );
+    }
+
+    #[test]
+    fn missing_executable_should_be_rejected() {
+        let mut unit = SystemDUnit::new_from_pod(
+            &"
+            apiVersion: v1
+            kind: Pod
+            metadata:
+              name: stackable
+            spec:
+              containers:
+                - name: test-container
+            "
+            .parse::<TestPod>()
+            .unwrap(),
+            false,
+        )
+        .unwrap();
+
+        unit.set_property(Section::Service, "ExecStart", "/bin/sh -c true");
+        assert!(unit.verify_executables().is_ok());
+
+        unit.set_property(Section::Service, "ExecStart", "relative/start.sh");
+        assert!(unit.verify_executables().is_ok());
+
+        unit.set_property(
+            Section::Service,
+            "ExecStart",
+            "/nonexistent/start.sh --help",
+        );
+        assert!(matches!(
+            unit.verify_executables(),
+            Err(PodValidationError { msg }) if msg.contains("/nonexistent/start.sh")
+        ));
+    }
+
+    #[rstest]
+    #[case::socket(
+        &[("stackable.tech/unit-type", "socket")],
+        indoc! {r#"
+            [Unit]
+            Description=default-stackable-test-container
+            StartLimitBurst=7
+            StartLimitIntervalSec=60
+            X-Stackable-Port=8080/TCP
+            X-Stackable-Port=8125/UDP
+
+            [Service]
+            Environment="KUBECONFIG=~/.kube/config"
+            ExecStart=/run/test-1.0.0/start.sh
+            OOMScoreAdjust=1000
+            RemainAfterExit=no
+            Restart=on-failure
+            RestartMaxDelaySec=300
+            RestartSec=2
+            RestartSteps=7
+            Slice=system-stackable-default_stackable.slice
+            StandardError=journal
+            StandardOutput=journal
+            SyslogIdentifier=default/stackable/test-container
+            TimeoutStopSec=30"#},
+        "default-stackable-test-container.socket",
+        indoc! {r#"
+            [Unit]
+            Description=default-stackable-test-container
+
+            [Socket]
+            ListenDatagram=8125
+            ListenStream=8080
+            Service=default-stackable-test-container.service
+
+            [Install]
+            WantedBy=sockets.target"#}
+    )]
+    #[case::timer(
+        &[
+            ("stackable.tech/unit-type", "timer"),
+            ("stackable.tech/on-calendar", "*-*-* 02:00:00")
+        ],
+        indoc! {r#"
+            [Unit]
+            Description=default-stackable-test-container
+            StartLimitBurst=7
+            StartLimitIntervalSec=60
+            X-Stackable-Port=8080/TCP
+            X-Stackable-Port=8125/UDP
+
+            [Service]
+            Environment="KUBECONFIG=~/.kube/config"
+            ExecStart=/run/test-1.0.0/start.sh
+            OOMScoreAdjust=1000
+            RemainAfterExit=no
+            Restart=on-failure
+            RestartMaxDelaySec=300
+            RestartSec=2
+            RestartSteps=7
+            Slice=system-stackable-default_stackable.slice
+            StandardError=journal
+            StandardOutput=journal
+            SyslogIdentifier=default/stackable/test-container
+            TimeoutStopSec=30"#},
+        "default-stackable-test-container.timer",
+        indoc! {r#"
+            [Unit]
+            Description=default-stackable-test-container
+
+            [Timer]
+            OnCalendar=*-*-* 02:00:00
+            Unit=default-stackable-test-container.service
+
+            [Install]
+            WantedBy=timers.target"#}
+    )]
+    fn trigger_unit_should_activate_service_unit(
+        #[case] annotations: &[(&str, &str)],
+        #[case] expected_service_unit_file_content: &str,
+        #[case] expected_trigger_unit_file_name: &str,
+        #[case] expected_trigger_unit_file_content: &str,
+    ) {
+        let pod = format!(
+            "
+            apiVersion: v1
+            kind: Pod
+            metadata:
+              name: stackable
+              annotations:
+                {}
+            spec:
+              containers:
+                - name: test-container
+                  command:
+                    - start.sh
+                  ports:
+                    - containerPort: 8080
+                    - containerPort: 8125
+                      protocol: UDP
+            ",
+            annotations
+                .iter()
+                .map(|(key, value)| format!(r#"{}: "{}""#, key, value))
+                .collect::<Vec<_>>()
+                .join("\n                ")
+        )
+        .parse::<TestPod>()
+        .unwrap();
+
+        let pod_state = test_pod_state(&pod);
+
+        let units = SystemDUnit::new_all(false, &pod_state, &PathBuf::from("~/.kube/config"), &pod)
+            .unwrap();
+        let (_, service_unit) = units.first().unwrap();
+        assert_eq!(
+            expected_service_unit_file_content,
+            service_unit.get_unit_file_content()
+        );
+
+        let container = pod.containers().first().unwrap().to_owned();
+        let trigger_unit = SystemDUnit::new_trigger(&pod, &container, service_unit)
+            .unwrap()
+            .expect("A trigger unit is expected.");
+        assert_eq!(expected_trigger_unit_file_name, trigger_unit.get_name());
+        assert_eq!(
+            expected_trigger_unit_file_content,
+            trigger_unit.get_unit_file_content()
+        );
+    }
+
+    #[rstest]
+    #[case::unknown_unit_type("stackable.tech/unit-type: path")]
+    #[case::timer_without_calendar_event("stackable.tech/unit-type: timer")]
+    #[case::socket_without_ports("stackable.tech/unit-type: socket")]
+    fn invalid_trigger_should_be_rejected(#[case] annotation: &str) {
+        let pod = format!(
+            "
+            apiVersion: v1
+            kind: Pod
+            metadata:
+              name: stackable
+              annotations:
+                {}
+            spec:
+              containers:
+                - name: test-container
+                  command:
+                    - start.sh
+            ",
+            annotation
+        )
+        .parse::<TestPod>()
+        .unwrap();
+
+        let service_unit = SystemDUnit::new_from_pod(&pod, false).unwrap();
+        let container = pod.containers().first().unwrap().to_owned();
+
+        let result = SystemDUnit::new_trigger(&pod, &container, &service_unit);
+
+        assert!(matches!(result, Err(PodValidationError { .. })));
+    }
+
+    #[rstest]
+    #[case::not_annotated("Never", "{}", Some(false))]
+    #[case::transient("OnFailure", "{stackable.tech/transient-unit: 'true'}", Some(true))]
+    #[case::restarted_by_systemd("Always", "{stackable.tech/transient-unit: 'true'}", None)]
+    #[case::with_trigger(
+        "Never",
+        "{stackable.tech/transient-unit: 'true', stackable.tech/unit-type: socket}",
+        None
+    )]
+    fn transient_units_should_only_be_allowed_for_jobs(
+        #[case] restart_policy: &str,
+        #[case] annotations: &str,
+        #[case] expected_transient: Option<bool>,
+    ) {
+        let pod = format!(
+            "
+            apiVersion: v1
+            kind: Pod
+            metadata:
+              name: stackable
+              annotations: {}
+            spec:
+              containers:
+                - name: test-container
+              restartPolicy: {}
+            ",
+            annotations, restart_policy
+        )
+        .parse::<TestPod>()
+        .unwrap();
+
+        assert_eq!(expected_transient, SystemDUnit::is_transient(&pod).ok());
+    }
+
+    #[test]
+    fn ports_should_be_read_from_unit_file() {
+        let unit_file_content = indoc! {"
+            [Unit]
+            Description=default-stackable-test-container
+            X-Stackable-Port=8080/TCP http
+            X-Stackable-Port=8125/UDP
+
+            [Service]
+            ExecStart=/run/test-1.0.0/start.sh --port=8080
+        "};
+
+        assert_eq!(
+            vec!["8080/TCP http", "8125/UDP"],
+            SystemDUnit::ports_from_unit_file(unit_file_content)
+        );
+    }
+
+    #[test]
+    fn systemd_run_arguments_should_contain_properties_and_command() {
+        let mut unit = SystemDUnit::new_from_pod(
+            &"
+            apiVersion: v1
+            kind: Pod
+            metadata:
+              name: stackable
+            spec:
+              containers:
+                - name: test-container
+            "
+            .parse::<TestPod>()
+            .unwrap(),
+            false,
+        )
+        .unwrap();
+        unit.set_property(Section::Service, "ExecStart", "/run/start.sh --config /etc");
+        unit.set_property(Section::Service, "Restart", "no");
+        unit.set_property(Section::Install, "WantedBy", "multi-user.target");
+
+        let arguments = unit.systemd_run_arguments();
+
+        assert_eq!(
+            vec!["--unit=stackable.service", "--quiet"],
+            arguments[..2].to_vec()
+        );
+        assert!(arguments.contains(&String::from("--property=Restart=no")));
+        assert!(!arguments
+            .iter()
+            .any(|argument| argument.contains("WantedBy")
+                || argument.contains("ExecStart")
+                || argument.contains("RestartSteps")));
+        assert_eq!(
+            vec!["--", "/run/start.sh", "--config", "/etc"],
+            arguments[arguments.len() - 4..].to_vec()
+        );
+    }
+
+    #[rstest]
+    #[case::plain("/run/start.sh --config /etc", &["/run/start.sh", "--config", "/etc"])]
+    #[case::repeated_whitespace(" /run/start.sh   -v ", &["/run/start.sh", "-v"])]
+    #[case::double_quotes(r#"/bin/sh -c "echo a  b""#, &["/bin/sh", "-c", "echo a  b"])]
+    #[case::single_quotes(r#"/bin/sh -c 'echo "a"'"#, &["/bin/sh", "-c", r#"echo "a""#])]
+    #[case::escaped_whitespace(r"/run/start.sh a\ b", &["/run/start.sh", "a b"])]
+    #[case::empty_argument(r#"/run/start.sh """#, &["/run/start.sh", ""])]
+    fn command_lines_should_be_split_like_in_unit_files(
+        #[case] command: &str,
+        #[case] expected_arguments: &[&str],
+    ) {
+        assert_eq!(expected_arguments, split_command_line(command));
+    }
 }
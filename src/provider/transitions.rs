@@ -0,0 +1,99 @@
+//! In-memory log of the state transitions of the pods
+//!
+//! Every state of the pod state machine records itself when it is entered, so that the admin
+//! interface can tell in which state a pod is and why it got there without searching the logs
+//! of the node. Only the most recent transitions of each pod are kept and the log of a pod is
+//! discarded when the pod is deleted.
+use std::collections::{HashMap, VecDeque};
+
+use k8s_openapi::apimachinery::pkg::apis::meta::v1::Time;
+use k8s_openapi::chrono::Utc;
+use kubelet::pod::state::prelude::SharedState;
+use kubelet::pod::{Pod, PodKey};
+use serde::Serialize;
+
+use super::ProviderState;
+
+/// Maximum number of transitions which are kept per pod
+const MAX_TRANSITIONS_PER_POD: usize = 20;
+
+/// Transition of a pod into a state of the state machine
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StateTransition {
+    /// Name of the entered state, e.g. `WaitingConfigMap`
+    pub state: String,
+    /// Reason why the state was entered if it is not the regular next step
+    pub reason: Option<String>,
+    /// Time when the state was entered
+    pub time: Time,
+}
+
+/// Recent state transitions of all pods
+#[derive(Debug, Default)]
+pub struct TransitionLog {
+    transitions: HashMap<PodKey, VecDeque<StateTransition>>,
+}
+
+impl TransitionLog {
+    /// Appends a transition of the given pod and discards the oldest one if the maximum number
+    /// of transitions is exceeded.
+    pub fn record(&mut self, pod_key: &PodKey, state: &str, reason: Option<String>) {
+        let transitions = self.transitions.entry(pod_key.to_owned()).or_default();
+        if transitions.len() == MAX_TRANSITIONS_PER_POD {
+            transitions.pop_front();
+        }
+        transitions.push_back(StateTransition {
+            state: String::from(state),
+            reason,
+            time: Time(Utc::now()),
+        });
+    }
+
+    /// Returns the transitions of the given pod in chronological order or [`None`] if no
+    /// transitions were recorded.
+    pub fn get(&self, pod_key: &PodKey) -> Option<&VecDeque<StateTransition>> {
+        self.transitions.get(pod_key)
+    }
+
+    /// Discards the transitions of the given pod.
+    pub fn remove(&mut self, pod_key: &PodKey) {
+        self.transitions.remove(pod_key);
+    }
+}
+
+/// Records that the given pod entered the given state.
+pub async fn record_transition(
+    shared: &SharedState<ProviderState>,
+    pod: &Pod,
+    state: &str,
+    reason: Option<String>,
+) {
+    let transitions = shared.read().await.transitions.clone();
+    transitions
+        .write()
+        .await
+        .record(&PodKey::from(pod), state, reason);
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn only_the_most_recent_transitions_should_be_kept() {
+        let pod_key = PodKey::new("default", "test");
+        let mut transition_log = TransitionLog::default();
+
+        for attempt in 0..=MAX_TRANSITIONS_PER_POD {
+            transition_log.record(&pod_key, "Downloading", Some(attempt.to_string()));
+        }
+
+        let transitions = transition_log.get(&pod_key).unwrap();
+        assert_eq!(MAX_TRANSITIONS_PER_POD, transitions.len());
+        assert_eq!(Some(String::from("1")), transitions[0].reason);
+
+        transition_log.remove(&pod_key);
+        assert!(transition_log.get(&pod_key).is_none());
+    }
+}
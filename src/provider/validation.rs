@@ -0,0 +1,204 @@
+//! Offline validation of pods
+//!
+//! The agent rejects pods which it cannot run, e.g. pods with more than one container, without
+//! an image tag, with an invalid `runAsUserName`, or with templates in the commands which cannot
+//! be resolved. These checks do not require a running agent, so that such pods can already be
+//! detected before they are applied, e.g. in a CI pipeline.
+use std::net::{IpAddr, Ipv4Addr};
+use std::path::PathBuf;
+
+use kubelet::backoff::ExponentialBackoffStrategy;
+use kubelet::pod::Pod;
+use stackable_config::ConfigOption;
+
+use crate::config::AgentConfig;
+use crate::provider::log_rotation::LogRotation;
+use crate::provider::repository::force_reinstall;
+use crate::provider::repository::package::Package;
+use crate::provider::states::pod::installing::strip_components;
+use crate::provider::states::pod::PodState;
+use crate::provider::systemdmanager::systemdunit::{SystemDUnit, UNIT_NAME_MAX};
+use crate::provider::{find_kubeconfig, StackableProvider};
+
+/// Placeholder for the UID of the pod if it is not set in the given manifest
+const PLACEHOLDER_UID: &str = "00000000-0000-0000-0000-000000000000";
+
+/// Placeholder for the name of the node which would run the pod
+const PLACEHOLDER_NODE_NAME: &str = "localhost";
+
+/// Parses a pod from its YAML representation.
+pub fn parse_pod(yaml: &str) -> Result<Pod, serde_yaml::Error> {
+    let kube_pod: k8s_openapi::api::core::v1::Pod = serde_yaml::from_str(yaml)?;
+    Ok(Pod::from(kube_pod))
+}
+
+/// Runs the checks which the agent performs on a pod before it creates the systemd units and
+/// returns all violations.
+///
+/// An empty list is returned if the pod would be accepted by the agent. The directories of the
+/// default configuration are used to resolve the templates. Checks which depend on the node,
+/// like the existence of the binaries, are not performed.
+pub fn validate_pod(pod: &Pod) -> Vec<String> {
+    let mut violations = Vec::new();
+
+    let package = match StackableProvider::get_package(pod, false) {
+        Ok(package) => package,
+        Err(error) => {
+            violations.push(error.to_string());
+            // Continue with a placeholder to check the rest of the pod
+            Package {
+                product: String::from("unknown"),
+                version: String::from("0.0.0"),
+            }
+        }
+    };
+
+    if let Err(error) = SystemDUnit::new_from_pod(pod, false) {
+        // The pod properties are part of every unit, so the units of the containers cannot be
+        // checked without them.
+        violations.push(error.to_string());
+        return violations;
+    }
+
+    if let Err(error) = LogRotation::from_pod(pod) {
+        violations.push(error.to_string());
+    }
+
+    if let Err(error) = strip_components(pod) {
+        violations.push(error.to_string());
+    }
+
+    if let Err(error) = force_reinstall(pod) {
+        violations.push(error.to_string());
+    }
+
+    if let Err(error) = SystemDUnit::is_transient(pod) {
+        violations.push(error.to_string());
+    }
+
+    let pod_state = placeholder_pod_state(pod, package);
+    let kubeconfig_path = find_kubeconfig().unwrap_or_default();
+
+    for container in pod.init_containers() {
+        if let Err(error) = SystemDUnit::new(false, &pod_state, &kubeconfig_path, pod, &container) {
+            violations.push(error.to_string());
+        }
+    }
+
+    for container in pod.containers() {
+        match SystemDUnit::new(false, &pod_state, &kubeconfig_path, pod, &container) {
+            Ok(unit) => {
+                if let Err(error) = SystemDUnit::new_trigger(pod, &container, &unit) {
+                    violations.push(error.to_string());
+                }
+            }
+            Err(error) => violations.push(error.to_string()),
+        }
+    }
+
+    violations
+}
+
+/// Creates the state which the agent would initialize for the given pod with the default
+/// configuration.
+fn placeholder_pod_state(pod: &Pod, package: Package) -> PodState {
+    let default_directory =
+        |option: &ConfigOption| PathBuf::from(option.default.unwrap_or_default());
+
+    let parcel_directory = default_directory(&AgentConfig::PACKAGE_DIR);
+
+    PodState {
+        download_directory: parcel_directory.join("_download"),
+        parcel_directory,
+        config_directory: default_directory(&AgentConfig::CONFIG_DIR),
+        log_directory: default_directory(&AgentConfig::LOG_DIR),
+        package_download_backoff_strategy: ExponentialBackoffStrategy::default(),
+        download_failures: 0,
+        service_name: format!("{}-{}", pod.namespace(), pod.name()),
+        service_uid: pod
+            .as_kube_pod()
+            .metadata
+            .uid
+            .clone()
+            .unwrap_or_else(|| String::from(PLACEHOLDER_UID)),
+        package,
+        package_directory_template: String::from(
+            AgentConfig::PACKAGE_DIR_TEMPLATE
+                .default
+                .unwrap_or_default(),
+        ),
+        default_service_user: None,
+        log_namespace: None,
+        node_name: String::from(PLACEHOLDER_NODE_NAME),
+        node_ip: IpAddr::V4(Ipv4Addr::LOCALHOST),
+        unit_name_max_length: UNIT_NAME_MAX,
+        secret_files: Vec::new(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::provider::test::TestPod;
+    use rstest::rstest;
+
+    #[rstest]
+    #[case::valid_pod(
+        "
+        apiVersion: v1
+        kind: Pod
+        metadata:
+          name: test
+        spec:
+          containers:
+            - name: test-container
+              image: test:0.1
+              command:
+                - start.sh
+                - --config={{configroot}}
+        ",
+        0
+    )]
+    #[case::missing_image_tag_and_invalid_user_name(
+        "
+        apiVersion: v1
+        kind: Pod
+        metadata:
+          name: test
+        spec:
+          containers:
+            - name: test-container
+              image: test
+              command:
+                - start.sh
+              securityContext:
+                windowsOptions:
+                  runAsUserName: invalid/user
+        ",
+        2
+    )]
+    #[case::multiple_containers_and_unresolvable_templates(
+        "
+        apiVersion: v1
+        kind: Pod
+        metadata:
+          name: test
+        spec:
+          containers:
+            - name: first-container
+              image: test:0.1
+              command:
+                - start.sh
+                - --config={{unknown}}
+            - name: second-container
+              image: test:0.1
+              command:
+                - start.sh
+                - --log={{unknown}}
+        ",
+        3
+    )]
+    fn all_violations_should_be_reported(#[case] pod: TestPod, #[case] expected_violations: usize) {
+        assert_eq!(expected_violations, validate_pod(&pod).len());
+    }
+}
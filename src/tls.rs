@@ -0,0 +1,88 @@
+//! Checks of the TLS certificate and private key of the local webserver
+
+use std::fs;
+use std::path::Path;
+
+use anyhow::{anyhow, Context, Result};
+use openssl::pkey::PKey;
+use openssl::x509::X509;
+
+/// Checks if the given PEM encoded certificate and PKCS8 private key form a valid pair.
+///
+/// # Errors
+///
+/// An error is returned if one of the files cannot be read or parsed or if the public key of
+/// the certificate does not belong to the private key.
+pub fn check_key_pair(cert_file: &Path, key_file: &Path) -> Result<()> {
+    let cert_pem = fs::read(cert_file)
+        .with_context(|| format!("Could not read [{}]", cert_file.to_string_lossy()))?;
+    let key_pem = fs::read(key_file)
+        .with_context(|| format!("Could not read [{}]", key_file.to_string_lossy()))?;
+
+    check_pem_key_pair(&cert_pem, &key_pem).with_context(|| {
+        format!(
+            "The certificate [{}] and the private key [{}] do not form a valid pair",
+            cert_file.to_string_lossy(),
+            key_file.to_string_lossy()
+        )
+    })
+}
+
+fn check_pem_key_pair(cert_pem: &[u8], key_pem: &[u8]) -> Result<()> {
+    let cert = X509::from_pem(cert_pem).context("The certificate is not PEM encoded")?;
+    let key = PKey::private_key_from_pem(key_pem)
+        .context("The private key is not a PEM encoded PKCS8 key")?;
+
+    if cert.public_key()?.public_eq(&key) {
+        Ok(())
+    } else {
+        Err(anyhow!(
+            "The public key of the certificate does not belong to the private key"
+        ))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use openssl::ec::{EcGroup, EcKey};
+    use openssl::hash::MessageDigest;
+    use openssl::nid::Nid;
+    use openssl::pkey::Private;
+
+    fn generate_key() -> PKey<Private> {
+        let group = EcGroup::from_curve_name(Nid::X9_62_PRIME256V1).unwrap();
+        PKey::from_ec_key(EcKey::generate(&group).unwrap()).unwrap()
+    }
+
+    fn generate_cert(key: &PKey<Private>) -> X509 {
+        let mut builder = X509::builder().unwrap();
+        builder.set_pubkey(key).unwrap();
+        builder.sign(key, MessageDigest::sha256()).unwrap();
+        builder.build()
+    }
+
+    #[test]
+    fn matching_key_pair_should_be_accepted() {
+        let key = generate_key();
+        let cert = generate_cert(&key);
+
+        assert!(check_pem_key_pair(
+            &cert.to_pem().unwrap(),
+            &key.private_key_to_pem_pkcs8().unwrap()
+        )
+        .is_ok());
+    }
+
+    #[test]
+    fn mismatched_key_pair_should_be_rejected() {
+        let cert = generate_cert(&generate_key());
+        let other_key = generate_key();
+
+        assert!(check_pem_key_pair(
+            &cert.to_pem().unwrap(),
+            &other_key.private_key_to_pem_pkcs8().unwrap()
+        )
+        .is_err());
+    }
+}